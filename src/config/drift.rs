@@ -0,0 +1,95 @@
+use crate::config::Config;
+use crate::error::{GatewayError, Result};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Reads and parses a configuration file.
+pub fn read(path: &str) -> Result<Config> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| GatewayError::config(format!("failed to read {}: {}", path, error)))?;
+    serde_yml::from_str(&content)
+        .map_err(|error| GatewayError::config(format!("failed to parse {}: {}", path, error)))
+}
+
+/// First 12 hex digits of the SHA-256 of the effective configuration, which does not change with
+/// comments, formatting or key order of the file.
+pub fn hash(config: &Config) -> Result<String> {
+    // Converting to a value first sorts the keys of maps.
+    let normalized = serde_json::to_value(config)?.to_string();
+    let hash = format!("{:x}", Sha256::digest(normalized.as_bytes()));
+    Ok(hash[..12].to_string())
+}
+
+/// Hash of the configuration on disk if it differs from the running one.
+fn drifted(path: &str, running: &str) -> Result<Option<String>> {
+    let current = hash(&read(path)?)?;
+    Ok((current != running).then_some(current))
+}
+
+/// Warns in the given interval while the configuration file differs from the running
+/// configuration, i.e. changes wait for a restart.
+pub fn spawn_drift_check(path: String, running: String, interval: Duration) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match drifted(&path, &running) {
+            Ok(Some(current)) => warn!(
+                "{} (hash {}) differs from the running configuration (hash {}), restart to apply it",
+                path, current, running
+            ),
+            Ok(None) => debug!("{} matches the running configuration", path),
+            Err(error) => warn!("failed to check {} for changes: {}", path, error),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+mqttUrl: "mqtt://localhost:1883"
+mqttClientId: "gateway"
+sources:
+  - name: "sensors"
+    type: "sensor"
+    prefix: "sensors"
+"#;
+
+    #[test]
+    fn test_hash_ignores_formatting() -> Result<()> {
+        let config: Config = serde_yml::from_str(CONFIG).unwrap();
+        let reformatted: Config = serde_yml::from_str(&format!(
+            "# gateway\nmqttClientId: gateway\n{}",
+            CONFIG.replace("mqttClientId: \"gateway\"\n", "")
+        ))
+        .unwrap();
+        let changed: Config =
+            serde_yml::from_str(&CONFIG.replace("\"gateway\"", "\"other\"")).unwrap();
+
+        assert_eq!(hash(&config)?.len(), 12);
+        assert_eq!(hash(&config)?, hash(&reformatted)?);
+        assert_ne!(hash(&config)?, hash(&changed)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drifted() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("config-drift-{}.yml", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, CONFIG).unwrap();
+        let running = hash(&read(path)?)?;
+
+        assert_eq!(drifted(path, &running)?, None);
+        fs::write(path, CONFIG.replace("localhost", "broker")).unwrap();
+        assert!(drifted(path, &running)?.is_some());
+        fs::remove_file(path).unwrap();
+        assert!(drifted(path, &running).is_err());
+        Ok(())
+    }
+}
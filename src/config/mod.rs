@@ -1,4 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub(crate) mod drift;
+pub(crate) mod options;
+pub(crate) mod pipeline;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum SourceType {
@@ -10,17 +15,341 @@ pub enum SourceType {
     OpenDTU,
     #[serde(rename = "openmqttgateway")]
     OpenMqttGateway,
+    #[serde(rename = "zwave")]
+    ZWave,
+    #[serde(rename = "senml")]
+    SenML,
     #[serde(rename = "debug")]
     Debug,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Source {
-    name: String,
+    pub(crate) name: String,
     #[serde(rename = "type")]
     pub(crate) source_type: SourceType,
     pub(crate) prefix: String,
+    pub(crate) qos: Option<i32>,
     pub(crate) targets: Option<Vec<Target>>,
+    #[serde(rename = "activeHours")]
+    pub(crate) active_hours: Option<String>,
+    pub(crate) calendar: Option<CalendarConfig>,
+    pub(crate) timestamp: Option<TimestampConfig>,
+    pub(crate) charset: Option<Charset>,
+    pub(crate) lossy: Option<bool>,
+    #[serde(rename = "deviceTags")]
+    pub(crate) device_tags: Option<Vec<String>>,
+    #[serde(rename = "missingFieldThreshold")]
+    pub(crate) missing_field_threshold: Option<u64>,
+    pub(crate) precision: Option<HashMap<String, u32>>,
+    #[serde(rename = "sampleRate")]
+    pub(crate) sample_rate: Option<SampleRate>,
+    /// Layout of the topics like `{prefix}/{location}/{measurement}` (sensor and shelly only).
+    #[serde(rename = "topicSchema")]
+    pub(crate) topic_schema: Option<String>,
+    /// Publish `{prefix}/ack/{location}` once all targets wrote a message (sensor only).
+    pub(crate) ack: Option<bool>,
+    /// Whether messages lacking `aenergy` or `temperature` are dropped (shelly only).
+    pub(crate) parsing: Option<ParseMode>,
+    /// Write the energy of each minute from `aenergy.by_minute` (shelly only).
+    #[serde(rename = "energyByMinute")]
+    pub(crate) energy_by_minute: Option<bool>,
+    /// Splits signed power into `power_import` and `power_export` or tags its `direction`
+    /// (shelly only).
+    #[serde(rename = "powerDirection")]
+    pub(crate) power_direction: Option<PowerDirection>,
+    /// Where the reading fields are found in the payload (sensor only).
+    pub(crate) fields: Option<FieldsConfig>,
+    /// Unix socket, or `-` for stdin, to read NDJSON log events from in addition to the topics
+    /// (sensor only).
+    pub(crate) socket: Option<String>,
+    /// Accept batches of log events via `POST /ingest/<prefix>` of the HTTP API, requires `auth`
+    /// (sensor only).
+    pub(crate) ingest: Option<bool>,
+    /// Devices polled via SNMP, their values are handled like log events (sensor only).
+    pub(crate) snmp: Option<Vec<SnmpConfig>>,
+    /// Devices polled via Modbus TCP, their registers are handled like log events (sensor only).
+    pub(crate) modbus: Option<Vec<ModbusConfig>>,
+    /// Tags with the broker, QoS and/or retain flag of the message, like `["broker", "retain"]`.
+    #[serde(rename = "brokerTags")]
+    pub(crate) broker_tags: Option<Vec<String>>,
+    /// Send a `gateway_message_age` event with each heartbeat.
+    #[serde(rename = "messageAge")]
+    pub(crate) message_age: Option<bool>,
+    /// Send a `device_online` event per device with each heartbeat, 0 once the device sent no
+    /// events for this many seconds.
+    #[serde(rename = "onlineTimeout")]
+    pub(crate) online_timeout: Option<u64>,
+    /// Only record the devices and live values of retained messages the broker sends on
+    /// subscribing, without writing their events to the targets again.
+    #[serde(rename = "skipRetained")]
+    pub(crate) skip_retained: Option<bool>,
+    /// Names of the pipelines providing the settings left unset here, later ones take precedence.
+    pub(crate) pipelines: Option<Vec<String>>,
+    /// Settings of the parser, keyed by the source type they are meant for.
+    #[serde(default, with = "serde_yml::with::singleton_map")]
+    pub(crate) options: Option<SourceOptions>,
+    /// Events written on a schedule without a message, e.g. a daily tariff rate.
+    pub(crate) events: Option<Vec<StaticEventConfig>>,
+}
+
+/// Event written to the targets of a source on a schedule, e.g. as anchor point for queries.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StaticEventConfig {
+    /// Cron expression `minute hour day month weekday` in local time, like `0 0 * * *`.
+    pub(crate) schedule: String,
+    pub(crate) measurement: String,
+    pub(crate) value: f64,
+    pub(crate) tags: Option<HashMap<String, String>>,
+}
+
+/// Circuit breaker of a database target: after `failures` consecutive failed writes no writes
+/// are attempted for `cooldown` seconds, then a single write probes whether the target recovered.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BreakerConfig {
+    /// Default 5.
+    pub(crate) failures: Option<u32>,
+    /// Default 60.
+    pub(crate) cooldown: Option<u64>,
+    /// What happens to the events arriving during the cooldown.
+    pub(crate) policy: Option<BreakerPolicy>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum BreakerPolicy {
+    /// Discards the events.
+    #[default]
+    #[serde(rename = "drop")]
+    Drop,
+    /// Keeps the events in the queue of the target, which applies back pressure to the sources
+    /// once it is full.
+    #[serde(rename = "spool")]
+    Spool,
+}
+
+/// Parser settings of a source. They take precedence over the same settings given at the top
+/// level of the source.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SourceOptions {
+    #[serde(rename = "shelly")]
+    Shelly(ShellySourceConfig),
+    #[serde(rename = "sensor")]
+    Sensor(SensorSourceConfig),
+    #[serde(rename = "openmqttgateway")]
+    OpenMqttGateway(OpenMqttGatewaySourceConfig),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ShellySourceConfig {
+    #[serde(rename = "deviceTags")]
+    pub(crate) device_tags: Option<Vec<String>>,
+    #[serde(rename = "missingFieldThreshold")]
+    pub(crate) missing_field_threshold: Option<u64>,
+    pub(crate) parsing: Option<ParseMode>,
+    #[serde(rename = "energyByMinute")]
+    pub(crate) energy_by_minute: Option<bool>,
+    #[serde(rename = "powerDirection")]
+    pub(crate) power_direction: Option<PowerDirection>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SensorSourceConfig {
+    pub(crate) fields: Option<FieldsConfig>,
+    pub(crate) ack: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OpenMqttGatewaySourceConfig {
+    /// IDs like `283146C17616` of the devices whose events are written, default all.
+    pub(crate) devices: Option<Vec<String>>,
+}
+
+/// Filters and transforms shared by the sources referencing the pipeline by name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PipelineConfig {
+    #[serde(rename = "activeHours")]
+    pub(crate) active_hours: Option<String>,
+    #[serde(rename = "sampleRate")]
+    pub(crate) sample_rate: Option<SampleRate>,
+    pub(crate) precision: Option<HashMap<String, u32>>,
+    pub(crate) calendar: Option<CalendarConfig>,
+    pub(crate) timestamp: Option<TimestampConfig>,
+    pub(crate) charset: Option<Charset>,
+    pub(crate) lossy: Option<bool>,
+    #[serde(rename = "brokerTags")]
+    pub(crate) broker_tags: Option<Vec<String>>,
+}
+
+/// JSON pointers like `"/data/ts"` to the fields of a sensor reading, unset fields are read from
+/// the top level.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FieldsConfig {
+    pub(crate) time: Option<String>,
+    pub(crate) value: Option<String>,
+    pub(crate) sensor: Option<String>,
+}
+
+/// Device polled via SNMP v2c, e.g. a UPS or a PoE switch.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SnmpConfig {
+    /// Address like `ups.local:161`, the port defaults to 161.
+    pub(crate) host: String,
+    /// Default `public`.
+    pub(crate) community: Option<String>,
+    /// Seconds between polls, default 60.
+    pub(crate) interval: Option<u64>,
+    pub(crate) location: String,
+    pub(crate) oids: Vec<OidConfig>,
+}
+
+/// Value of a device polled via SNMP.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct OidConfig {
+    /// Numeric OID like `1.3.6.1.2.1.33.1.4.4.1.5.1`.
+    pub(crate) oid: String,
+    /// Measurement of the value.
+    #[serde(rename = "type")]
+    pub(crate) measurement: String,
+    /// Default the host of the device.
+    pub(crate) sensor: Option<String>,
+    pub(crate) unit: Option<String>,
+    /// Factor the value is multiplied with, e.g. 0.1 for tenths of degrees.
+    pub(crate) scale: Option<f64>,
+}
+
+/// Device polled via Modbus TCP, e.g. a heat pump or an energy meter.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ModbusConfig {
+    /// Address like `heatpump.local:502`, the port defaults to 502.
+    pub(crate) host: String,
+    /// Unit identifier, default 1.
+    pub(crate) unit: Option<u8>,
+    /// Seconds between polls, default 60.
+    pub(crate) interval: Option<u64>,
+    pub(crate) location: String,
+    pub(crate) registers: Vec<RegisterConfig>,
+}
+
+/// Value of a device polled via Modbus TCP.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RegisterConfig {
+    /// Address of the (first) register, starting at 0.
+    pub(crate) address: u16,
+    #[serde(rename = "type")]
+    pub(crate) register_type: RegisterType,
+    /// Holding (default) or input register.
+    pub(crate) kind: Option<RegisterKind>,
+    pub(crate) measurement: String,
+    /// Default the host of the device.
+    pub(crate) sensor: Option<String>,
+    pub(crate) unit: Option<String>,
+    /// Factor the value is multiplied with, e.g. 0.1 for tenths of degrees.
+    pub(crate) scale: Option<f64>,
+}
+
+/// Layout of a register value, 32 bit values span two registers with the high word first.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum RegisterType {
+    #[serde(rename = "u16")]
+    U16,
+    #[serde(rename = "i16")]
+    I16,
+    #[serde(rename = "u32")]
+    U32,
+    #[serde(rename = "i32")]
+    I32,
+    #[serde(rename = "f32")]
+    F32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum RegisterKind {
+    #[default]
+    #[serde(rename = "holding")]
+    Holding,
+    #[serde(rename = "input")]
+    Input,
+}
+
+/// Processes 1 of N messages of each topic or a percentage like `"10%"`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum SampleRate {
+    Every(u32),
+    Percentage(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Charset {
+    #[default]
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "latin-1")]
+    Latin1,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ParseMode {
+    /// Emit the fields present in a message.
+    #[default]
+    #[serde(rename = "tolerant")]
+    Tolerant,
+    /// Drop messages lacking a field, e.g. to detect firmware changes in test environments.
+    #[serde(rename = "strict")]
+    Strict,
+}
+
+/// Handling of the sign of the power of bidirectional meters, negative while feeding in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PowerDirection {
+    /// Writes the consumed power as `power_import` and the fed in power as `power_export`, both
+    /// positive and 0 while power flows the other way.
+    #[serde(rename = "split")]
+    Split,
+    /// Keeps the signed `power` and tags it with `direction` `import` or `export`.
+    #[serde(rename = "tag")]
+    Tag,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum MissingTimestamp {
+    #[serde(rename = "drop")]
+    Drop,
+    #[serde(rename = "now")]
+    Now,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimestampConfig {
+    pub(crate) missing: Option<MissingTimestamp>,
+    #[serde(rename = "maxOffset")]
+    pub(crate) max_offset: Option<i64>,
+    /// Accept payloads marked with `"backfill": true` regardless of `maxOffset`.
+    #[serde(rename = "allowBackfill")]
+    pub(crate) allow_backfill: Option<bool>,
+    /// Truncate timestamps to multiples of this many seconds.
+    pub(crate) align: Option<u64>,
+}
+
+/// Time zone the calendar tags are derived in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum CalendarTimezone {
+    #[default]
+    #[serde(rename = "utc")]
+    Utc,
+    #[serde(rename = "local")]
+    Local,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CalendarConfig {
+    pub(crate) tags: Vec<String>,
+    pub(crate) file: Option<String>,
+    /// Default `utc`, `local` uses the time zone of the gateway host.
+    pub(crate) timezone: Option<CalendarTimezone>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -32,6 +361,19 @@ pub enum Target {
         database: String,
         user: Option<String>,
         password: Option<String>,
+        #[serde(rename = "retentionPolicies")]
+        retention_policies: Option<HashMap<String, String>>,
+        buckets: Option<HashMap<String, String>>,
+        /// Pauses the writes after repeated failures.
+        breaker: Option<BreakerConfig>,
+        /// Points written with a single request at most, default 5000.
+        #[serde(rename = "maxPoints")]
+        max_points: Option<usize>,
+        /// Bytes of line protocol written with a single request at most, default 1 MiB.
+        #[serde(rename = "maxBytes")]
+        max_bytes: Option<usize>,
+        /// Requests in flight at the same time, default 1.
+        concurrency: Option<usize>,
     },
     #[serde(rename = "postgresql")]
     Postgresql {
@@ -40,19 +382,415 @@ pub enum Target {
         user: String,
         password: String,
         database: String,
+        /// Seconds of the windows summarized per series instead of writing the readings.
+        #[serde(rename = "summaryWindow")]
+        summary_window: Option<u64>,
+        /// Statistics written in addition to min, max, mean and count, e.g. `p95` or `stddev`.
+        #[serde(rename = "summaryFields")]
+        summary_fields: Option<Vec<String>>,
+        /// Measurements written immediately instead of summarized.
+        #[serde(rename = "lowLatency")]
+        low_latency: Option<Vec<String>>,
+        /// Channel notified with the key of each written row.
+        notify: Option<String>,
+        /// Pauses the writes after repeated failures.
+        breaker: Option<BreakerConfig>,
+    },
+    #[serde(rename = "redis")]
+    Redis {
+        url: String,
+        stream: String,
+        #[serde(rename = "maxLength")]
+        max_length: Option<usize>,
+    },
+    #[serde(rename = "telegram")]
+    Telegram {
+        token: String,
+        #[serde(rename = "chatId")]
+        chat_id: String,
+        template: Option<String>,
+        #[serde(rename = "minInterval")]
+        min_interval: Option<u64>,
+    },
+    #[serde(rename = "pushover")]
+    Pushover {
+        token: String,
+        user: String,
+        template: Option<String>,
+        #[serde(rename = "minInterval")]
+        min_interval: Option<u64>,
+    },
+    #[serde(rename = "smtp")]
+    Smtp {
+        host: String,
+        port: Option<u16>,
+        user: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: String,
+        template: Option<String>,
+        #[serde(rename = "minInterval")]
+        min_interval: Option<u64>,
+    },
+    #[serde(rename = "history")]
+    History { size: Option<usize> },
+    /// Republishes the events to a broker, the topic may contain `{measurement}` and `{<tag>}`.
+    #[serde(rename = "mqtt")]
+    Mqtt {
+        url: String,
+        #[serde(rename = "clientId")]
+        client_id: Option<String>,
+        topic: String,
+        format: Option<PayloadFormat>,
+        qos: Option<i32>,
+    },
+    /// Streams the events as Arrow record batches to a Flight service, e.g. DataFusion or Dremio.
+    #[serde(rename = "flight")]
+    Flight {
+        url: String,
+        table: String,
+        /// Rows per record batch at most, default 1000.
+        #[serde(rename = "batchSize")]
+        batch_size: Option<usize>,
+        /// Seconds after which a partial batch is sent, default 5.
+        #[serde(rename = "flushInterval")]
+        flush_interval: Option<u64>,
+    },
+    /// Discards the events, counting them for load tests.
+    #[serde(rename = "null")]
+    Null {
+        /// Seconds between throughput reports, default 10.
+        #[serde(rename = "reportInterval")]
+        report_interval: Option<u64>,
+    },
+    /// Writes the events matching the tags to the nested target, e.g. to split databases per room.
+    #[serde(rename = "route")]
+    Route {
+        /// Tags the events must have, `measurement` matches the measurement.
+        when: Option<HashMap<String, String>>,
+        /// Tags of the events left out.
+        unless: Option<HashMap<String, String>>,
+        target: Box<Target>,
+    },
+    /// Transforms the events with a WebAssembly module before writing them to the nested target.
+    #[serde(rename = "wasm")]
+    Wasm {
+        /// File of the module, binary or text format.
+        module: String,
+        /// Roughly the number of instructions per event, default 10 million.
+        fuel: Option<u64>,
+        /// Memory of the module in MiB, default 16.
+        #[serde(rename = "memoryLimit")]
+        memory_limit: Option<usize>,
+        target: Box<Target>,
     },
     // #[serde(rename = "debug")]
     // Debug {
     // },
 }
 
+impl Target {
+    /// Type and endpoint of the target for logs, without credentials.
+    pub fn name(&self) -> String {
+        match self {
+            Target::InfluxDB { url, database, .. } => format!("influxdb {}/{}", url, database),
+            Target::Postgresql {
+                host,
+                port,
+                database,
+                ..
+            } => format!("postgresql {}:{}/{}", host, port, database),
+            Target::Redis { url, stream, .. } => format!("redis {}/{}", url, stream),
+            Target::Telegram { .. } => "telegram".to_string(),
+            Target::Pushover { .. } => "pushover".to_string(),
+            Target::Smtp { host, to, .. } => format!("smtp {} to {}", host, to),
+            Target::History { .. } => "history".to_string(),
+            Target::Mqtt { url, topic, .. } => format!("mqtt {} {}", url, topic),
+            Target::Flight { url, table, .. } => format!("flight {}/{}", url, table),
+            Target::Null { .. } => "null".to_string(),
+            Target::Route { target, .. } => format!("route to {}", target.name()),
+            Target::Wasm { module, target, .. } => format!("wasm {} to {}", module, target.name()),
+        }
+    }
+}
+
+/// Virtual meters combining the events of several devices of any source, e.g. the house
+/// consumption from the mains and the PV power, written to their own targets.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VirtualMetersConfig {
+    pub(crate) meters: Vec<MeterConfig>,
+    pub(crate) targets: Vec<Target>,
+}
+
+/// A virtual meter, emitted whenever one of its inputs changes while the latest values of all
+/// inputs are at most `window` seconds apart.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MeterConfig {
+    pub(crate) measurement: String,
+    /// Tags of the events of the meter.
+    pub(crate) tags: Option<HashMap<String, String>>,
+    pub(crate) operation: Option<MeterOperation>,
+    /// Seconds, default 30.
+    pub(crate) window: Option<i64>,
+    pub(crate) inputs: Vec<MeterInput>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum MeterOperation {
+    #[default]
+    #[serde(rename = "sum")]
+    Sum,
+    #[serde(rename = "average")]
+    Average,
+}
+
+/// Events of a device feeding a virtual meter.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MeterInput {
+    /// Tags the events must have, `measurement` matches the measurement.
+    pub(crate) when: HashMap<String, String>,
+    /// Numeric field of the events, default `value`.
+    pub(crate) field: Option<String>,
+    /// Factor applied to the value, default 1, e.g. -1 to subtract it.
+    pub(crate) factor: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DevicesConfig {
+    pub(crate) file: Option<String>,
+}
+
+/// Payload format of republished events.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PayloadFormat {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "line")]
+    LineProtocol,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(&self) -> &str {
+        match self {
+            Compression::None => "ndjson",
+            Compression::Gzip => "ndjson.gz",
+            Compression::Zstd => "ndjson.zst",
+        }
+    }
+}
+
+/// Rotated files of messages dropped by the sources, sizes in bytes and ages in seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeadLetterConfig {
+    pub(crate) directory: String,
+    pub(crate) compression: Option<Compression>,
+    #[serde(rename = "maxSize")]
+    pub(crate) max_size: Option<u64>,
+    #[serde(rename = "maxAge")]
+    pub(crate) max_age: Option<u64>,
+    pub(crate) retain: Option<usize>,
+    pub(crate) upload: Option<UploadConfig>,
+}
+
+/// S3 compatible object store the completed files are uploaded to, e.g. AWS S3 or MinIO.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UploadConfig {
+    pub(crate) endpoint: String,
+    pub(crate) bucket: String,
+    pub(crate) region: Option<String>,
+    #[serde(rename = "accessKey")]
+    pub(crate) access_key: String,
+    #[serde(rename = "secretKey")]
+    pub(crate) secret_key: String,
+    /// Prepended to the file names to form the object keys.
+    pub(crate) prefix: Option<String>,
+    /// Keeps uploaded files until they are removed by the retention, deletes them otherwise.
+    #[serde(rename = "keepLocal")]
+    pub(crate) keep_local: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpConfig {
+    pub(crate) listen: String,
+    /// Number of values kept per series for the Grafana JSON datasource.
+    #[serde(rename = "liveHistory")]
+    pub(crate) live_history: Option<usize>,
+    /// Advertises the HTTP API via mDNS as `_mqtt-gateway._tcp`.
+    pub(crate) mdns: Option<bool>,
+}
+
+/// Commands of the HTTP API and the control topic a token may issue.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Querying devices, sources, history and live values.
+    #[serde(rename = "read")]
+    Read,
+    #[serde(rename = "enable")]
+    Enable,
+    #[serde(rename = "disable")]
+    Disable,
+    /// Starting and stopping message captures.
+    #[serde(rename = "capture")]
+    Capture,
+    /// Posting events to `/ingest/<prefix>`.
+    #[serde(rename = "ingest")]
+    Ingest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TokenConfig {
+    /// Identifies the client in the log.
+    pub(crate) name: String,
+    pub(crate) token: String,
+    /// Allowed commands, all if not set.
+    pub(crate) allow: Option<Vec<Command>>,
+}
+
+/// Resource profile, `constrained` lowers the defaults of queue sizes, buffers and caches and
+/// limits the memory, e.g. for a Raspberry Pi Zero.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Profile {
+    #[default]
+    #[serde(rename = "default")]
+    Default,
+    #[serde(rename = "constrained")]
+    Constrained,
+}
+
+/// Retained JSON document with the state of the gateway.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatusConfig {
+    pub(crate) topic: Option<String>,
+    /// Seconds between updates.
+    pub(crate) interval: Option<u64>,
+}
+
+/// Where runtime control actions are recorded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditConfig {
+    /// File the actions are appended to as JSON lines.
+    pub(crate) file: Option<String>,
+    pub(crate) topic: Option<String>,
+}
+
+/// Key of the encryption of the dead-letter and capture files.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncryptionConfig {
+    /// File with a 256 bit key as 64 hex digits.
+    #[serde(rename = "keyFile")]
+    pub(crate) key_file: String,
+}
+
+/// Tags and fields hashed or dropped before events are sent to the targets.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RedactionConfig {
+    /// Secret of the deployment mixed into the hashes, required for `hash`.
+    pub(crate) salt: Option<String>,
+    /// Keys of tags and fields whose values are replaced by a salted hash.
+    pub(crate) hash: Option<Vec<String>>,
+    /// Keys of tags and fields removed from the events.
+    pub(crate) drop: Option<Vec<String>>,
+}
+
+/// Where messages captured at runtime via the control topic are written to.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CaptureConfig {
+    /// File the messages are appended to as JSON lines.
+    pub(crate) file: Option<String>,
+    /// Messages are republished below this topic, e.g. `debug/shellies/kitchen/power`.
+    pub(crate) topic: Option<String>,
+}
+
+/// Requires a token for the HTTP API (`Authorization: Bearer <token>`) and control messages
+/// (`"token"` field).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthConfig {
+    pub(crate) tokens: Vec<TokenConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LocationConfig {
+    pub(crate) latitude: Option<f64>,
+    pub(crate) longitude: Option<f64>,
+    pub(crate) floor: Option<String>,
+    pub(crate) room: Option<String>,
+}
+
+/// One broker URI or a list of URIs to fail over between, in order of preference.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum MqttUrl {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl MqttUrl {
+    pub fn urls(&self) -> Vec<String> {
+        match self {
+            MqttUrl::Single(url) => vec![url.clone()],
+            MqttUrl::Multiple(urls) => urls.clone(),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Config {
     pub(crate) sources: Vec<Source>,
     #[serde(rename = "mqttUrl")]
-    pub(crate) mqtt_url: String,
+    pub(crate) mqtt_url: MqttUrl,
     #[serde(rename = "mqttClientId")]
     pub(crate) mqtt_client_id: String,
+    #[serde(rename = "persistentSession")]
+    pub(crate) persistent_session: Option<bool>,
+    #[serde(rename = "sessionExpiry")]
+    pub(crate) session_expiry: Option<u32>,
+    #[serde(rename = "inflightLimit")]
+    pub(crate) inflight_limit: Option<usize>,
+    /// Interval of the per source heartbeat events in seconds.
+    pub(crate) heartbeat: Option<u64>,
+    #[serde(rename = "controlTopic")]
+    pub(crate) control_topic: Option<String>,
+    pub(crate) locations: Option<HashMap<String, LocationConfig>>,
+    pub(crate) pipelines: Option<HashMap<String, PipelineConfig>>,
+    pub(crate) instance: Option<String>,
+    #[serde(rename = "hostTag")]
+    pub(crate) host_tag: Option<bool>,
+    pub(crate) devices: Option<DevicesConfig>,
+    pub(crate) http: Option<HttpConfig>,
+    #[serde(rename = "deadLetter")]
+    pub(crate) dead_letter: Option<DeadLetterConfig>,
+    pub(crate) auth: Option<AuthConfig>,
+    pub(crate) audit: Option<AuditConfig>,
+    pub(crate) capture: Option<CaptureConfig>,
+    pub(crate) encryption: Option<EncryptionConfig>,
+    pub(crate) redaction: Option<RedactionConfig>,
+    pub(crate) status: Option<StatusConfig>,
+    pub(crate) profile: Option<Profile>,
+    /// Heap size in MiB above which reading from the broker is paused.
+    #[serde(rename = "memoryLimit")]
+    pub(crate) memory_limit: Option<usize>,
+    /// Size in MiB of the events queued for all targets above which further events are shed.
+    #[serde(rename = "queueMemoryLimit")]
+    pub(crate) queue_memory_limit: Option<usize>,
+    /// Interval in seconds of the check for changes of the configuration file, 0 disables it.
+    #[serde(rename = "configCheck")]
+    pub(crate) config_check: Option<u64>,
+    #[serde(rename = "virtualMeters")]
+    pub(crate) virtual_meters: Option<VirtualMetersConfig>,
 }
 
 #[cfg(test)]
@@ -69,7 +807,7 @@ mod tests {
         database: "bar"
         "#;
 
-        let result: Target = serde_yml::from_str(&yaml).unwrap();
+        let result: Target = serde_yml::from_str(yaml).unwrap();
 
         if let Target::InfluxDB { url, database, .. } = result {
             assert_eq!(url, "foo");
@@ -90,9 +828,12 @@ mod tests {
         database: "bar"
         user: "baz"
         password: "qux"
+        breaker:
+          failures: 3
+          policy: "spool"
         "#;
 
-        let result: Target = serde_yml::from_str(&yaml).unwrap();
+        let result: Target = serde_yml::from_str(yaml).unwrap();
         debug!("{:?}", result);
 
         if let Target::Postgresql {
@@ -101,6 +842,8 @@ mod tests {
             database,
             user,
             password,
+            breaker,
+            ..
         } = result
         {
             assert_eq!(host, "foo");
@@ -108,6 +851,14 @@ mod tests {
             assert_eq!(database, "bar");
             assert_eq!(user, "baz");
             assert_eq!(password, "qux");
+            assert_eq!(
+                breaker,
+                Some(BreakerConfig {
+                    failures: Some(3),
+                    cooldown: None,
+                    policy: Some(BreakerPolicy::Spool),
+                })
+            );
         } else {
             panic!("wrong type");
         }
@@ -115,6 +866,197 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_deserialize_mqtt_url() -> Result<()> {
+        let single: MqttUrl = serde_yml::from_str(r#""tcp://foo:1883""#)?;
+        let multiple: MqttUrl = serde_yml::from_str(
+            r#"
+        - "tcp://foo:1883"
+        - "tcp://bar:1883"
+        "#,
+        )?;
+
+        assert_eq!(single.urls(), vec!["tcp://foo:1883"]);
+        assert_eq!(multiple.urls(), vec!["tcp://foo:1883", "tcp://bar:1883"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_redis() -> Result<()> {
+        let yaml = r#"
+        type: "redis"
+        url: "redis://foo:6379"
+        stream: "bar"
+        maxLength: 1000
+        "#;
+
+        let result: Target = serde_yml::from_str(yaml).unwrap();
+
+        if let Target::Redis {
+            url,
+            stream,
+            max_length,
+        } = result
+        {
+            assert_eq!(url, "redis://foo:6379");
+            assert_eq!(stream, "bar");
+            assert_eq!(max_length, Some(1000));
+        } else {
+            panic!("wrong type");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_flight() -> Result<()> {
+        let yaml = r#"
+        type: "flight"
+        url: "http://foo:50051"
+        table: "events"
+        batchSize: 500
+        "#;
+
+        let result: Target = serde_yml::from_str(yaml).unwrap();
+
+        assert_eq!(
+            result,
+            Target::Flight {
+                url: "http://foo:50051".to_string(),
+                table: "events".to_string(),
+                batch_size: Some(500),
+                flush_interval: None,
+            }
+        );
+        assert_eq!(result.name(), "flight http://foo:50051/events");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_telegram() -> Result<()> {
+        let yaml = r#"
+        type: "telegram"
+        token: "foo"
+        chatId: "bar"
+        template: "{location}: {value}"
+        minInterval: 300
+        "#;
+
+        let result: Target = serde_yml::from_str(yaml).unwrap();
+
+        if let Target::Telegram {
+            token,
+            chat_id,
+            template,
+            min_interval,
+        } = result
+        {
+            assert_eq!(token, "foo");
+            assert_eq!(chat_id, "bar");
+            assert_eq!(template.unwrap(), "{location}: {value}");
+            assert_eq!(min_interval, Some(300));
+        } else {
+            panic!("wrong type");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_route() -> Result<()> {
+        let yaml = r#"
+        type: "route"
+        when:
+          location: "garage"
+        target:
+          type: "history"
+          size: 10
+        "#;
+
+        let result: Target = serde_yml::from_str(yaml).unwrap();
+
+        if let Target::Route {
+            when,
+            unless,
+            target,
+        } = result
+        {
+            assert_eq!(when.unwrap().get("location").unwrap(), "garage");
+            assert!(unless.is_none());
+            assert_eq!(*target, Target::History { size: Some(10) });
+        } else {
+            panic!("wrong type");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_virtual_meters() -> Result<()> {
+        let yaml = r#"
+        meters:
+          - measurement: "house_power"
+            tags: { location: "house" }
+            inputs:
+              - when: { measurement: "power", location: "mains" }
+              - when: { measurement: "power", location: "pv" }
+                factor: -1
+        targets:
+          - type: "history"
+        "#;
+
+        let result: VirtualMetersConfig = serde_yml::from_str(yaml).unwrap();
+
+        assert_eq!(result.meters[0].measurement, "house_power");
+        assert_eq!(result.meters[0].operation, None);
+        assert_eq!(result.meters[0].inputs[0].factor, None);
+        assert_eq!(result.meters[0].inputs[1].factor, Some(-1.0));
+        assert_eq!(result.targets, vec![Target::History { size: None }]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_locations() -> Result<()> {
+        let yaml = r#"
+        kitchen:
+          latitude: 48.137
+          longitude: 11.575
+          floor: "1"
+          room: "kitchen"
+        garage:
+          room: "garage"
+        "#;
+
+        let result: HashMap<String, LocationConfig> = serde_yml::from_str(yaml).unwrap();
+
+        let kitchen = result.get("kitchen").unwrap();
+        assert_eq!(kitchen.latitude, Some(48.137));
+        assert_eq!(kitchen.longitude, Some(11.575));
+        assert_eq!(kitchen.floor.as_deref(), Some("1"));
+        assert_eq!(kitchen.room.as_deref(), Some("kitchen"));
+        assert!(result.get("garage").unwrap().latitude.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_calendar() -> Result<()> {
+        let yaml = r#"
+        tags: ["weekday", "season"]
+        file: "holidays.yml"
+        "#;
+
+        let result: CalendarConfig = serde_yml::from_str(yaml).unwrap();
+
+        assert_eq!(result.tags, vec!["weekday", "season"]);
+        assert_eq!(result.file.unwrap(), "holidays.yml");
+
+        Ok(())
+    }
+
     #[test]
     fn test_deserialize_source() -> Result<()> {
         let yaml = r#"
@@ -127,11 +1069,12 @@ mod tests {
             database: "qux"
         "#;
 
-        let result: Source = serde_yml::from_str(&yaml).unwrap();
+        let result: Source = serde_yml::from_str(yaml).unwrap();
 
         assert_eq!(result.name, "foo");
         assert_eq!(result.source_type, SourceType::Sensor);
         assert_eq!(result.prefix, "bar");
+        assert!(result.active_hours.is_none());
 
         let targets = result.targets.unwrap();
         assert_eq!(targets.len(), 1);
@@ -146,4 +1089,55 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_deserialize_snmp() -> Result<()> {
+        let yaml = r#"
+        name: "network"
+        type: "sensor"
+        prefix: "network"
+        snmp:
+          - host: "switch.local"
+            location: "rack"
+            oids:
+              - oid: "1.3.6.1.2.1.105.1.3.1.1.4.1"
+                type: "poe_power"
+                unit: "W"
+        "#;
+
+        let result: Source = serde_yml::from_str(yaml)?;
+
+        let devices = result.snmp.unwrap();
+        assert_eq!(devices[0].host, "switch.local");
+        assert!(devices[0].community.is_none());
+        assert_eq!(devices[0].oids[0].measurement, "poe_power");
+        assert_eq!(devices[0].oids[0].unit.as_deref(), Some("W"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_events() -> Result<()> {
+        let yaml = r#"
+        name: "Shelly"
+        type: "shelly"
+        prefix: "shellies"
+        events:
+          - schedule: "0 0 * * *"
+            measurement: "tariff_rate"
+            value: 0.32
+            tags:
+              tariff: "day"
+        "#;
+
+        let result: Source = serde_yml::from_str(yaml)?;
+
+        let events = result.events.unwrap();
+        assert_eq!(events[0].schedule, "0 0 * * *");
+        assert_eq!(events[0].measurement, "tariff_rate");
+        assert_eq!(events[0].value, 0.32);
+        assert_eq!(events[0].tags.as_ref().unwrap()["tariff"], "day");
+
+        Ok(())
+    }
 }
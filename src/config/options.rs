@@ -0,0 +1,126 @@
+use crate::config::{Source, SourceOptions, SourceType};
+use crate::error::{GatewayError, Result};
+
+/// Moves the parser settings of the `options` block to the top level of the source, after
+/// checking that they are meant for its type. The devices of OpenMqttGateway sources stay in the
+/// block, they have no top level setting.
+pub fn apply(mut source: Source) -> Result<Source> {
+    match (source.options.clone(), &source.source_type) {
+        (None, _) | (Some(SourceOptions::OpenMqttGateway(_)), SourceType::OpenMqttGateway) => {}
+        (Some(SourceOptions::Shelly(options)), SourceType::Shelly) => {
+            source.device_tags = options.device_tags.or(source.device_tags);
+            source.missing_field_threshold = options
+                .missing_field_threshold
+                .or(source.missing_field_threshold);
+            source.parsing = options.parsing.or(source.parsing);
+            source.energy_by_minute = options.energy_by_minute.or(source.energy_by_minute);
+            source.power_direction = options.power_direction.or(source.power_direction);
+        }
+        (Some(SourceOptions::Sensor(options)), SourceType::Sensor) => {
+            source.fields = options.fields.or(source.fields);
+            source.ack = options.ack.or(source.ack);
+        }
+        (Some(_), source_type) => {
+            return Err(GatewayError::config(format!(
+                "options of source {} are not meant for its type {:?}",
+                source.name, source_type
+            )));
+        }
+    }
+    Ok(source)
+}
+
+/// Devices of an OpenMqttGateway source whose events are written, all devices if unset.
+#[cfg(feature = "openmqttgateway")]
+pub fn devices(options: Option<&SourceOptions>) -> Option<Vec<String>> {
+    match options {
+        Some(SourceOptions::OpenMqttGateway(options)) => options.devices.clone(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PowerDirection;
+
+    fn source(yaml: &str) -> Source {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let source = apply(source(
+            r#"
+            name: "Shelly"
+            type: "shelly"
+            prefix: "shellies"
+            energyByMinute: false
+            options:
+              shelly:
+                energyByMinute: true
+                powerDirection: "split"
+            "#,
+        ))?;
+
+        assert_eq!(source.energy_by_minute, Some(true));
+        assert_eq!(source.power_direction, Some(PowerDirection::Split));
+        #[cfg(feature = "openmqttgateway")]
+        assert_eq!(devices(source.options.as_ref()), None);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "openmqttgateway")]
+    fn test_devices() -> Result<()> {
+        let source = apply(source(
+            r#"
+            name: "BLE"
+            type: "openmqttgateway"
+            prefix: "blegateway"
+            options:
+              openmqttgateway:
+                devices: ["283146C17616"]
+            "#,
+        ))?;
+
+        assert_eq!(
+            devices(source.options.as_ref()),
+            Some(vec!["283146C17616".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_of_other_type() {
+        let error = apply(source(
+            r#"
+            name: "Sensors"
+            type: "sensor"
+            prefix: "klimalogger"
+            options:
+              shelly:
+                energyByMinute: true
+            "#,
+        ))
+        .unwrap_err();
+
+        assert!(error.to_string().contains("not meant for its type Sensor"));
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let result: serde_yml::Result<Source> = serde_yml::from_str(
+            r#"
+            name: "Sensors"
+            type: "sensor"
+            prefix: "klimalogger"
+            options:
+              sensor:
+                skew: 10
+            "#,
+        );
+
+        assert!(result.is_err());
+    }
+}
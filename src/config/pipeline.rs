@@ -0,0 +1,122 @@
+use crate::config::{PipelineConfig, Source};
+use crate::error::{GatewayError, Result};
+use std::collections::HashMap;
+
+/// Fills the settings a source leaves unset from the pipelines it references. Pipelines listed
+/// later take precedence, precision entries are merged per measurement.
+pub fn apply(mut source: Source, pipelines: &HashMap<String, PipelineConfig>) -> Result<Source> {
+    let referenced = source
+        .pipelines
+        .iter()
+        .flatten()
+        .map(|name| {
+            pipelines.get(name).ok_or_else(|| {
+                GatewayError::config(format!(
+                    "source {} references unknown pipeline '{}'",
+                    source.name, name
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut precision = HashMap::new();
+    for pipeline in &referenced {
+        precision.extend(pipeline.precision.clone().unwrap_or_default());
+    }
+    precision.extend(source.precision.take().unwrap_or_default());
+    source.precision = Some(precision).filter(|precision| !precision.is_empty());
+
+    for pipeline in referenced.into_iter().rev() {
+        let pipeline = pipeline.clone();
+        source.active_hours = source.active_hours.or(pipeline.active_hours);
+        source.sample_rate = source.sample_rate.or(pipeline.sample_rate);
+        source.calendar = source.calendar.or(pipeline.calendar);
+        source.timestamp = source.timestamp.or(pipeline.timestamp);
+        source.charset = source.charset.or(pipeline.charset);
+        source.lossy = source.lossy.or(pipeline.lossy);
+        source.broker_tags = source.broker_tags.or(pipeline.broker_tags);
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SampleRate;
+
+    fn pipelines() -> HashMap<String, PipelineConfig> {
+        serde_yml::from_str(
+            r#"
+            units:
+              precision:
+                power: 1
+                temperature: 1
+              sampleRate: 10
+            office:
+              activeHours: "Mon-Fri 08:00-18:00"
+              sampleRate: 5
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn source(yaml: &str) -> Source {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let source = apply(
+            source(
+                r#"
+                name: "Shelly"
+                type: "shelly"
+                prefix: "shellies"
+                pipelines: ["units", "office"]
+                precision:
+                  power: 0
+                "#,
+            ),
+            &pipelines(),
+        )?;
+
+        assert_eq!(source.active_hours.as_deref(), Some("Mon-Fri 08:00-18:00"));
+        assert_eq!(source.sample_rate, Some(SampleRate::Every(5)));
+        assert_eq!(
+            source.precision,
+            Some(HashMap::from([
+                ("power".to_string(), 0),
+                ("temperature".to_string(), 1)
+            ]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_pipeline() {
+        let source = source(
+            r#"
+            name: "Shelly"
+            type: "shelly"
+            prefix: "shellies"
+            pipelines: ["unknown"]
+            "#,
+        );
+
+        assert!(apply(source.clone(), &pipelines()).is_err());
+        assert_eq!(
+            apply(
+                Source {
+                    pipelines: None,
+                    ..source.clone()
+                },
+                &HashMap::new()
+            )
+            .unwrap(),
+            Source {
+                pipelines: None,
+                ..source
+            }
+        );
+    }
+}
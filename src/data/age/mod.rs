@@ -0,0 +1,194 @@
+use crate::data::enrichment::labels;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+
+/// Upper bounds in seconds of the buckets of the message age histogram. Negative ages are
+/// payload timestamps ahead of the receive time, i.e. device clocks running fast.
+pub const BUCKETS: [i64; 12] = [-60, -10, -1, 0, 1, 2, 5, 10, 30, 60, 300, 3600];
+
+pub const MEASUREMENT: &str = "gateway_message_age";
+
+static AGES: LazyLock<Mutex<BTreeMap<String, Ages>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+static MEASURED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+thread_local! {
+    static SOURCE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Ages observed since the last measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub(crate) count: u64,
+    pub(crate) min: i64,
+    pub(crate) max: i64,
+    pub(crate) mean: f64,
+}
+
+#[derive(Debug, Default)]
+struct Ages {
+    /// Counts per bucket of `BUCKETS`, not cumulative, followed by the count above the last.
+    buckets: [u64; BUCKETS.len() + 1],
+    count: u64,
+    sum: i64,
+    /// Count, sum, minimum and maximum since the last measurement.
+    window: Option<(u64, i64, i64, i64)>,
+}
+
+impl Ages {
+    #[cfg(any(
+        feature = "shelly",
+        feature = "openmqttgateway",
+        feature = "klimalogger"
+    ))]
+    fn observe(&mut self, age: i64, windowed: bool) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| age <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += age;
+        if windowed {
+            let (count, sum, min, max) = self.window.unwrap_or((0, 0, age, age));
+            self.window = Some((count + 1, sum + age, min.min(age), max.max(age)));
+        }
+    }
+
+    fn take_window(&mut self) -> Option<Window> {
+        let (count, sum, min, max) = self.window.take()?;
+        Some(Window {
+            count,
+            min,
+            max,
+            mean: sum as f64 / count as f64,
+        })
+    }
+}
+
+/// Attributes the ages observed and the devices seen while handling a message of a source to it.
+pub fn with_source<R>(source: &str, handle: impl FnOnce() -> R) -> R {
+    SOURCE.set(Some(source.to_string()));
+    let result = handle();
+    SOURCE.set(None);
+    result
+}
+
+/// Prefix of the source handling the current message, see [`with_source`].
+pub fn current_source() -> Option<String> {
+    SOURCE.with_borrow(Clone::clone)
+}
+
+/// Records the age of a message, the receive time minus the payload timestamp in seconds.
+#[cfg(any(
+    feature = "shelly",
+    feature = "openmqttgateway",
+    feature = "klimalogger"
+))]
+pub fn observe(age: i64) {
+    let Some(source) = current_source() else {
+        return;
+    };
+    let windowed = MEASURED.lock().unwrap().contains(&source);
+    AGES.lock()
+        .unwrap()
+        .entry(source)
+        .or_default()
+        .observe(age, windowed);
+}
+
+/// Sends a `gateway_message_age` event with each heartbeat of the source.
+pub fn enable_measurement(source: &str) {
+    MEASURED.lock().unwrap().insert(source.to_string());
+}
+
+/// Ages of the source since the last call, if the measurement is enabled and messages had a
+/// timestamp.
+pub fn take_window(source: &str) -> Option<Window> {
+    AGES.lock().unwrap().get_mut(source)?.take_window()
+}
+
+/// The histograms of all sources in the Prometheus text format.
+pub fn metrics() -> String {
+    let mut metrics = String::from(
+        "# HELP mqtt_gateway_message_age_seconds Receive time minus payload timestamp.\n\
+         # TYPE mqtt_gateway_message_age_seconds histogram\n",
+    );
+    for (source, ages) in AGES.lock().unwrap().iter() {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(ages.buckets) {
+            cumulative += count;
+            writeln!(
+                metrics,
+                "mqtt_gateway_message_age_seconds_bucket{} {}",
+                labels(&[("source", source), ("le", &bound.to_string())]),
+                cumulative
+            )
+            .unwrap();
+        }
+        let source_labels = labels(&[("source", source)]);
+        writeln!(
+            metrics,
+            "mqtt_gateway_message_age_seconds_bucket{} {}\n\
+             mqtt_gateway_message_age_seconds_sum{} {}\n\
+             mqtt_gateway_message_age_seconds_count{} {}",
+            labels(&[("source", source), ("le", "+Inf")]),
+            ages.count,
+            source_labels,
+            ages.sum,
+            source_labels,
+            ages.count
+        )
+        .unwrap();
+    }
+    metrics
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "shelly",
+        feature = "openmqttgateway",
+        feature = "klimalogger"
+    )
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut ages = Ages::default();
+        for age in [-5, 0, 1, 3, 7200] {
+            ages.observe(age, true);
+        }
+
+        assert_eq!(ages.buckets, [0, 0, 1, 1, 1, 0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!((ages.count, ages.sum), (5, 7199));
+        assert_eq!(
+            ages.take_window(),
+            Some(Window {
+                count: 5,
+                min: -5,
+                max: 7200,
+                mean: 7199.0 / 5.0
+            })
+        );
+        assert_eq!(ages.take_window(), None);
+    }
+
+    #[test]
+    fn test_metrics() {
+        with_source("age-test", || observe(3));
+        observe(3);
+
+        let metrics = metrics();
+        assert!(metrics.contains(
+            "mqtt_gateway_message_age_seconds_bucket{source=\"age-test\",le=\"2\"} 0\n\
+             mqtt_gateway_message_age_seconds_bucket{source=\"age-test\",le=\"5\"} 1\n"
+        ));
+        assert!(metrics.contains("mqtt_gateway_message_age_seconds_count{source=\"age-test\"} 1\n"));
+        assert_eq!(take_window("age-test"), None);
+    }
+}
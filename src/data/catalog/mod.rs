@@ -0,0 +1,163 @@
+use crate::config::{options, Config, SourceType};
+use crate::data::enrichment;
+use crate::data::enrichment::{Calendar, Enrichment};
+#[cfg(feature = "klimalogger")]
+use crate::data::klimalogger;
+#[cfg(feature = "opendtu")]
+use crate::data::opendtu;
+#[cfg(feature = "openmqttgateway")]
+use crate::data::openmqttgateway;
+#[cfg(feature = "senml")]
+use crate::data::senml;
+#[cfg(feature = "shelly")]
+use crate::data::shelly;
+#[cfg(feature = "zwave")]
+use crate::data::zwave;
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Measurement {
+    pub(crate) measurement: String,
+    pub(crate) fields: Vec<String>,
+    pub(crate) tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) unit: Option<String>,
+}
+
+impl Measurement {
+    pub(crate) fn new(
+        measurement: &str,
+        fields: &[&str],
+        tags: &[&str],
+        unit: Option<&str>,
+    ) -> Self {
+        Measurement {
+            measurement: measurement.to_string(),
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            unit: unit.map(|unit| unit.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SourceCatalog {
+    name: String,
+    #[serde(rename = "type")]
+    source_type: SourceType,
+    prefix: String,
+    measurements: Vec<Measurement>,
+}
+
+pub fn create_catalog(config: &Config) -> Vec<SourceCatalog> {
+    let instance_tags = enrichment::instance_tags(config);
+    let locations = config.locations.clone().unwrap_or_default();
+
+    config
+        .sources
+        .iter()
+        .map(|source| {
+            // options of another source type are rejected on startup
+            let source = &options::apply(source.clone()).unwrap_or_else(|_| source.clone());
+            let enrichment = Enrichment::new(
+                source
+                    .calendar
+                    .as_ref()
+                    .and_then(|calendar| Calendar::from_config(calendar).ok()),
+                &locations,
+            )
+            .with_static_tags(instance_tags.clone());
+            let (measurements, enrichment) = match source.source_type {
+                #[cfg(feature = "shelly")]
+                SourceType::Shelly => (
+                    shelly::catalog(
+                        source.energy_by_minute.unwrap_or(false),
+                        source.power_direction,
+                    ),
+                    enrichment,
+                ),
+                #[cfg(feature = "klimalogger")]
+                SourceType::Sensor => (klimalogger::catalog(), enrichment),
+                #[cfg(feature = "opendtu")]
+                SourceType::OpenDTU => (opendtu::catalog(), opendtu::enrichment(enrichment)),
+                #[cfg(feature = "openmqttgateway")]
+                SourceType::OpenMqttGateway => (openmqttgateway::catalog(), enrichment),
+                #[cfg(feature = "zwave")]
+                SourceType::ZWave => (zwave::catalog(), enrichment),
+                #[cfg(feature = "senml")]
+                SourceType::SenML => (senml::catalog(), enrichment),
+                // debug sources and sources not part of the build
+                _ => (Vec::new(), enrichment),
+            };
+            let tag_names = enrichment.tag_names();
+            let measurements = measurements
+                .into_iter()
+                .map(|measurement| with_tags(measurement, &tag_names))
+                .collect();
+
+            SourceCatalog {
+                name: source.name.clone(),
+                source_type: source.source_type.clone(),
+                prefix: source.prefix.clone(),
+                measurements,
+            }
+        })
+        .collect()
+}
+
+fn with_tags(mut measurement: Measurement, tags: &[String]) -> Measurement {
+    for tag in tags {
+        if !measurement.tags.contains(tag) {
+            measurement.tags.push(tag.clone());
+        }
+    }
+    measurement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_catalog() {
+        let config: Config = serde_yml::from_str(
+            r#"
+            mqttUrl: "mqtt://localhost:1883"
+            mqttClientId: "gateway"
+            sources:
+              - name: "PV data"
+                type: "opendtu"
+                prefix: "solar"
+              - name: "Shelly data"
+                type: "shelly"
+                prefix: "shellies"
+                calendar:
+                  tags: ["season"]
+            "#,
+        )
+        .unwrap();
+
+        let catalog = create_catalog(&config);
+
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog[0].prefix, "solar");
+        assert_eq!(
+            catalog[0].measurements[0].tags,
+            vec![
+                "device",
+                "component",
+                "string",
+                "month",
+                "year",
+                "year_month"
+            ]
+        );
+        let power = catalog[1]
+            .measurements
+            .iter()
+            .find(|measurement| measurement.measurement == "power")
+            .unwrap();
+        assert_eq!(power.unit.as_deref(), Some("W"));
+        assert!(power.tags.contains(&"season".to_string()));
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+
+/// Source of the current time of the loggers, replaced by a `ManualClock` in tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Seconds since the epoch.
+    fn timestamp(&self) -> i64 {
+        self.now().timestamp()
+    }
+}
+
+/// The system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock which only moves when told to, clones share the time.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn at(timestamp: i64) -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(DateTime::from_timestamp(timestamp, 0).unwrap())),
+        }
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        *self.now.lock().unwrap() += chrono::TimeDelta::seconds(seconds);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::at(1701292592);
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+
+        clock.advance(60);
+
+        assert_eq!(shared.timestamp(), 1701292652);
+    }
+}
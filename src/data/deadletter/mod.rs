@@ -0,0 +1,158 @@
+mod rotate;
+#[cfg(feature = "upload")]
+mod upload;
+
+use crate::config::DeadLetterConfig;
+use crate::data::encryption::Cipher;
+use crate::error::{GatewayError, Result};
+use log::{info, warn};
+use paho_mqtt::Message;
+pub use rotate::{RotatingFile, Rotation};
+use serde::Serialize;
+#[cfg(feature = "upload")]
+use std::path::PathBuf;
+#[cfg(feature = "upload")]
+use std::sync::mpsc::sync_channel;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+#[cfg(feature = "upload")]
+use upload::Uploader;
+
+const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_AGE: u64 = 24 * 60 * 60;
+const DEFAULT_RETAIN: usize = 10;
+
+static DEAD_LETTER: LazyLock<Mutex<Option<RotatingFile>>> = LazyLock::new(|| Mutex::new(None));
+
+/// A dropped message as written to the dead-letter files, one JSON object per line.
+#[derive(Debug, PartialEq, Serialize)]
+struct DeadLetter<'a> {
+    time: i64,
+    topic: &'a str,
+    reason: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a str>,
+    /// Hex dump of payloads which are not valid UTF-8.
+    #[serde(rename = "payloadHex", skip_serializing_if = "Option::is_none")]
+    payload_hex: Option<String>,
+}
+
+impl<'a> DeadLetter<'a> {
+    fn new(msg: &'a Message, reason: &'a str, time: i64) -> Self {
+        let payload = std::str::from_utf8(msg.payload()).ok();
+        DeadLetter {
+            time,
+            topic: msg.topic(),
+            reason,
+            payload,
+            payload_hex: match payload {
+                Some(_) => None,
+                None => Some(
+                    msg.payload()
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect(),
+                ),
+            },
+        }
+    }
+}
+
+/// Writes dropped messages to rotated NDJSON files in the configured directory, encrypted with the
+/// given cipher if set.
+pub fn enable(config: &DeadLetterConfig, cipher: Option<Cipher>) -> Result<()> {
+    #[cfg(not(feature = "zstd"))]
+    if config.compression == Some(crate::config::Compression::Zstd) {
+        return Err(GatewayError::config(
+            "zstd support not built, enable the zstd feature",
+        ));
+    }
+    let rotation = Rotation {
+        max_size: config.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+        max_age: Duration::from_secs(config.max_age.unwrap_or(DEFAULT_MAX_AGE)),
+        retain: config.retain.unwrap_or(DEFAULT_RETAIN),
+    };
+    let file = RotatingFile::new(
+        &config.directory,
+        "dead_letter",
+        config.compression.unwrap_or_default(),
+        rotation,
+    )
+    .map_err(|error| {
+        GatewayError::config(format!(
+            "failed to create dead-letter directory '{}': {}",
+            config.directory, error
+        ))
+    })?
+    .with_encryption(cipher);
+    let file = match &config.upload {
+        #[cfg(feature = "upload")]
+        Some(upload) => {
+            let uploader = Uploader::new(upload)?;
+            // Files left by previous runs are complete and uploaded first.
+            let pending: Vec<PathBuf> = file
+                .files()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|path| !rotate::is_uploaded(path))
+                .collect();
+            let (tx, rx) = sync_channel(pending.len() + 100);
+            for path in pending {
+                tx.send(path).unwrap();
+            }
+            upload::spawn_uploader(uploader, rx);
+            file.with_completed(tx)
+        }
+        #[cfg(not(feature = "upload"))]
+        Some(_) => {
+            return Err(GatewayError::config(
+                "Upload support not built, enable the upload feature",
+            ))
+        }
+        None => file,
+    };
+    info!("writing dead letters to {}", config.directory);
+    *DEAD_LETTER.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Records a message dropped for the given reason, does nothing unless enabled.
+pub fn record(msg: &Message, reason: &str) {
+    let mut dead_letter = DEAD_LETTER.lock().unwrap();
+    let Some(file) = dead_letter.as_mut() else {
+        return;
+    };
+    let line = DeadLetter::new(msg, reason, chrono::offset::Utc::now().timestamp());
+    if let Err(error) = file.write_line(&serde_json::to_string(&line).unwrap()) {
+        warn!("failed to write dead letter: {}", error);
+    }
+}
+
+/// Completes the current dead-letter file.
+pub fn close() {
+    if let Some(file) = DEAD_LETTER.lock().unwrap().as_mut() {
+        if let Err(error) = file.close() {
+            warn!("failed to close dead-letter file: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paho_mqtt::QOS_1;
+
+    #[test]
+    fn test_dead_letter() {
+        let msg = Message::new("shellies/loo-fan/status/switch:1", "{\"id\":0", QOS_1);
+        assert_eq!(
+            serde_json::to_string(&DeadLetter::new(&msg, "EOF", 100)).unwrap(),
+            "{\"time\":100,\"topic\":\"shellies/loo-fan/status/switch:1\",\"reason\":\"EOF\",\"payload\":\"{\\\"id\\\":0\"}"
+        );
+
+        let msg = Message::new("sensors/kitchen", vec![0x32u8, 0xb0], QOS_1);
+        let dead_letter = DeadLetter::new(&msg, "undecodable payload", 100);
+        assert_eq!(dead_letter.payload, None);
+        assert_eq!(dead_letter.payload_hex, Some("32b0".to_string()));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::config::Compression;
+use crate::data::encryption::{Cipher, EncryptingWriter};
+use flate2::write::GzEncoder;
+use log::warn;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
+
+/// Compressed data is flushed at most this often, which bounds the loss on a crash.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Appended to the names of uploaded files kept locally.
+pub(super) const UPLOADED_SUFFIX: &str = ".uploaded";
+
+/// Whether the file was uploaded and kept locally.
+pub(super) fn is_uploaded(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.ends_with(UPLOADED_SUFFIX))
+}
+
+/// When a file is closed and a new one started and how many files are kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation {
+    /// Uncompressed bytes written to a file.
+    pub(crate) max_size: u64,
+    pub(crate) max_age: Duration,
+    /// Number of files kept including the current one, older files are deleted.
+    pub(crate) retain: usize,
+}
+
+type FileWriter = Box<dyn Write + Send>;
+
+enum Encoder {
+    Plain(FileWriter),
+    Gzip(GzEncoder<FileWriter>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, FileWriter>),
+}
+
+impl Encoder {
+    fn new(file: File, compression: Compression, cipher: Option<&Cipher>) -> io::Result<Self> {
+        let file: FileWriter = match cipher {
+            Some(cipher) => Box::new(EncryptingWriter::new(cipher.clone(), BufWriter::new(file))?),
+            None => Box::new(BufWriter::new(file)),
+        };
+        Ok(match compression {
+            Compression::None => Encoder::Plain(file),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "zstd support not built, enable the zstd feature",
+                ))
+            }
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Plain(writer) => writer,
+            Encoder::Gzip(writer) => writer,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(writer) => writer,
+        }
+    }
+
+    /// Writes the trailer of the compression format, the file is incomplete without it.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(mut writer) => writer.flush(),
+            Encoder::Gzip(writer) => writer.finish()?.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+struct CurrentFile {
+    path: PathBuf,
+    encoder: Encoder,
+    written: u64,
+    opened: Instant,
+    flushed: Instant,
+}
+
+/// Line based files `<prefix>-<UTC time>.<extension>` in a directory, rotated by size and age.
+pub struct RotatingFile {
+    directory: PathBuf,
+    prefix: String,
+    compression: Compression,
+    rotation: Rotation,
+    cipher: Option<Cipher>,
+    current: Option<CurrentFile>,
+    completed: Option<SyncSender<PathBuf>>,
+}
+
+impl RotatingFile {
+    pub fn new(
+        directory: impl Into<PathBuf>,
+        prefix: impl Into<String>,
+        compression: Compression,
+        rotation: Rotation,
+    ) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(RotatingFile {
+            directory,
+            prefix: prefix.into(),
+            compression,
+            rotation,
+            cipher: None,
+            current: None,
+            completed: None,
+        })
+    }
+
+    /// Encrypts the files, which get the additional extension `.enc`.
+    pub fn with_encryption(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Sends the path of every completed file to the given channel, e.g. to upload it.
+    #[cfg(feature = "upload")]
+    pub fn with_completed(mut self, completed: SyncSender<PathBuf>) -> Self {
+        self.completed = Some(completed);
+        self
+    }
+
+    /// Existing files of this prefix, oldest first.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&format!("{}-", self.prefix)))
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.current.as_ref().is_some_and(|current| {
+            current.written >= self.rotation.max_size
+                || current.opened.elapsed() >= self.rotation.max_age
+        }) {
+            self.close()?;
+        }
+        let current = match &mut self.current {
+            Some(current) => current,
+            None => self.current.insert(self.open()?),
+        };
+
+        let writer = current.encoder.writer();
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+        current.written += line.len() as u64 + 1;
+        if matches!(self.compression, Compression::None)
+            || current.flushed.elapsed() >= FLUSH_INTERVAL
+        {
+            writer.flush()?;
+            current.flushed = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Completes the current file, the next line starts a new one.
+    pub fn close(&mut self) -> io::Result<()> {
+        let Some(current) = self.current.take() else {
+            return Ok(());
+        };
+        current.encoder.finish()?;
+        if let Some(completed) = &self.completed {
+            if completed.try_send(current.path).is_err() {
+                warn!("dropping completed file notification, it is handled after the next start");
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<CurrentFile> {
+        let name = format!(
+            "{}-{}.{}{}",
+            self.prefix,
+            chrono::offset::Utc::now().format("%Y%m%dT%H%M%S%3f"),
+            self.compression.extension(),
+            if self.cipher.is_some() { ".enc" } else { "" }
+        );
+        let path = self.directory.join(name);
+        let file = File::create(&path)?;
+        self.prune()?;
+        Ok(CurrentFile {
+            path,
+            encoder: Encoder::new(file, self.compression, self.cipher.as_ref())?,
+            written: 0,
+            opened: Instant::now(),
+            flushed: Instant::now(),
+        })
+    }
+
+    /// Deletes the oldest files beyond the number of files to retain. If completed files are
+    /// uploaded, files still waiting for their upload are kept.
+    fn prune(&self) -> io::Result<()> {
+        let files = self.files()?;
+        let obsolete = files.len().saturating_sub(self.rotation.retain.max(1));
+        for path in files[..obsolete]
+            .iter()
+            .filter(|path| self.completed.is_none() || is_uploaded(path))
+        {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn files(directory: &PathBuf) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+    }
+
+    const ROTATION: Rotation = Rotation {
+        max_size: 1024,
+        max_age: Duration::from_secs(3600),
+        retain: 2,
+    };
+
+    #[test]
+    fn test_rotate_by_size_and_retain() -> io::Result<()> {
+        let directory = directory("rotate-size");
+        let mut file = RotatingFile::new(
+            &directory,
+            "dead_letter",
+            Compression::None,
+            Rotation {
+                max_size: 10,
+                ..ROTATION
+            },
+        )?;
+
+        for line in ["first line", "second line", "third line"] {
+            file.write_line(line)?;
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        file.close()?;
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read_to_string(&files[0])?, "second line\n");
+        assert_eq!(fs::read_to_string(&files[1])?, "third line\n");
+        assert!(files[1].to_str().unwrap().ends_with(".ndjson"));
+
+        fs::remove_dir_all(&directory)
+    }
+
+    #[test]
+    fn test_is_uploaded() {
+        assert!(is_uploaded(Path::new(
+            "/data/dead_letter-20261016T120000000.ndjson.uploaded"
+        )));
+        assert!(!is_uploaded(Path::new(
+            "/data/dead_letter-20261016T120000000.ndjson"
+        )));
+    }
+
+    #[test]
+    #[cfg(feature = "upload")]
+    fn test_retain_files_until_uploaded() -> io::Result<()> {
+        let directory = directory("rotate-upload");
+        let (tx, rx) = std::sync::mpsc::sync_channel(10);
+        let mut file = RotatingFile::new(
+            &directory,
+            "dead_letter",
+            Compression::None,
+            Rotation {
+                max_size: 10,
+                retain: 1,
+                ..ROTATION
+            },
+        )?
+        .with_completed(tx);
+
+        file.write_line("first line")?;
+        std::thread::sleep(Duration::from_millis(2));
+        file.write_line("second line")?;
+        file.close()?;
+        assert_eq!(files(&directory).len(), 2);
+
+        let first = files(&directory).remove(0);
+        fs::rename(&first, format!("{}.uploaded", first.display()))?;
+        std::thread::sleep(Duration::from_millis(2));
+        file.write_line("third line")?;
+        file.close()?;
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read_to_string(&files[0])?, "second line\n");
+        assert_eq!(fs::read_to_string(&files[1])?, "third line\n");
+        assert_eq!(rx.try_iter().count(), 3);
+
+        fs::remove_dir_all(&directory)
+    }
+
+    #[test]
+    fn test_compressed_files() -> io::Result<()> {
+        let directory = directory("rotate-compressed");
+
+        let mut file = RotatingFile::new(&directory, "gzip", Compression::Gzip, ROTATION)?;
+        file.write_line("{\"topic\":\"foo\"}")?;
+        drop(file);
+        #[cfg(feature = "zstd")]
+        {
+            let mut file = RotatingFile::new(&directory, "zstd", Compression::Zstd, ROTATION)?;
+            file.write_line("{\"topic\":\"bar\"}")?;
+            file.close()?;
+        }
+
+        let files = files(&directory);
+        assert!(files[0].to_str().unwrap().ends_with(".ndjson.gz"));
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(File::open(&files[0])?).read_to_string(&mut content)?;
+        assert_eq!(content, "{\"topic\":\"foo\"}\n");
+
+        #[cfg(feature = "zstd")]
+        {
+            assert!(files[1].to_str().unwrap().ends_with(".ndjson.zst"));
+            let content = zstd::decode_all(File::open(&files[1])?)?;
+            assert_eq!(content, b"{\"topic\":\"bar\"}\n");
+        }
+
+        fs::remove_dir_all(&directory)
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_files() -> io::Result<()> {
+        use aes_gcm::aead::KeyInit;
+
+        let directory = directory("rotate-encrypted");
+        let cipher = Cipher::new(&[7; 32].into());
+
+        let mut file = RotatingFile::new(&directory, "gzip", Compression::Gzip, ROTATION)?
+            .with_encryption(Some(cipher.clone()));
+        file.write_line("{\"topic\":\"foo\"}")?;
+        file.close()?;
+
+        let files = files(&directory);
+        assert!(files[0].to_str().unwrap().ends_with(".ndjson.gz.enc"));
+        let compressed = crate::data::encryption::decrypt(&cipher, File::open(&files[0])?)?;
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_string(&mut content)?;
+        assert_eq!(content, "{\"topic\":\"foo\"}\n");
+
+        fs::remove_dir_all(&directory)
+    }
+}
@@ -0,0 +1,254 @@
+use super::rotate::UPLOADED_SUFFIX;
+use crate::config::UploadConfig;
+use crate::error::{GatewayError, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const DEFAULT_REGION: &str = "us-east-1";
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_ATTEMPTS: u32 = 5;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// AWS Signature Version 4 signing key of a day, region and service.
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+/// Percent-encodes everything except the unreserved characters and `/`.
+fn uri_encode(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Path style `PUT` of objects to an S3 compatible store like AWS S3 or MinIO.
+pub struct Uploader {
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    prefix: String,
+    keep_local: bool,
+}
+
+impl Uploader {
+    pub fn new(config: &UploadConfig) -> Result<Self> {
+        let endpoint = config.endpoint.trim_end_matches('/').to_string();
+        let host = endpoint
+            .split_once("://")
+            .map(|(_, host)| host)
+            .filter(|host| !host.is_empty() && !host.contains('/'))
+            .ok_or_else(|| {
+                GatewayError::config(format!(
+                    "invalid upload endpoint '{}', expected e.g. https://s3.amazonaws.com",
+                    config.endpoint
+                ))
+            })?
+            .to_string();
+        Ok(Uploader {
+            endpoint,
+            host,
+            bucket: config.bucket.clone(),
+            region: config
+                .region
+                .clone()
+                .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            access_key: config.access_key.clone(),
+            secret_key: config.secret_key.clone(),
+            prefix: config.prefix.clone().unwrap_or_default(),
+            keep_local: config.keep_local.unwrap_or(false),
+        })
+    }
+
+    /// Path and headers of a signed `PUT` request of the object `key` with the given content.
+    fn sign(
+        &self,
+        key: &str,
+        content: &[u8],
+        now: DateTime<Utc>,
+    ) -> (String, Vec<(String, String)>) {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let content_hash = hex(&Sha256::digest(content));
+        let path = uri_encode(&format!("/{}/{}", self.bucket, key));
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            path, self.host, content_hash, amz_date, signed_headers, content_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signature = hex(&hmac(
+            &signing_key(&self.secret_key, &date, &self.region, "s3"),
+            &string_to_sign,
+        ));
+
+        (
+            path,
+            vec![
+                (
+                    "Authorization".to_string(),
+                    format!(
+                        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                        self.access_key, scope, signed_headers, signature
+                    ),
+                ),
+                ("x-amz-content-sha256".to_string(), content_hash),
+                ("x-amz-date".to_string(), amz_date),
+            ],
+        )
+    }
+
+    fn upload(&self, path: &PathBuf) -> Result<()> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let content = fs::read(path)
+            .map_err(|error| GatewayError::target(format!("read {}", path.display()), error))?;
+        let (url_path, headers) =
+            self.sign(&format!("{}{}", self.prefix, name), &content, Utc::now());
+
+        let request = headers.iter().fold(
+            ureq::put(&format!("{}{}", self.endpoint, url_path)),
+            |request, (name, value)| request.set(name, value),
+        );
+        request
+            .send_bytes(&content)
+            .map_err(|error| GatewayError::target("s3 upload", error))?;
+        info!("uploaded {} to {}", name, self.bucket);
+
+        if self.keep_local {
+            let mut uploaded = path.clone().into_os_string();
+            uploaded.push(UPLOADED_SUFFIX);
+            fs::rename(path, &uploaded).map_err(|error| {
+                GatewayError::target(format!("rename {}", path.display()), error)
+            })?;
+        } else {
+            fs::remove_file(path).map_err(|error| {
+                GatewayError::target(format!("remove {}", path.display()), error)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Uploads the completed files received, failed uploads are retried a few times and otherwise
+/// left in place to be uploaded after the next start.
+pub fn spawn_uploader(uploader: Uploader, rx: Receiver<PathBuf>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for path in rx {
+            for attempt in 1..=MAX_ATTEMPTS {
+                match uploader.upload(&path) {
+                    Ok(()) => break,
+                    Err(error) => {
+                        warn!(
+                            "attempt {}/{} to upload {} failed: {}",
+                            attempt,
+                            MAX_ATTEMPTS,
+                            path.display(),
+                            error
+                        );
+                        if attempt < MAX_ATTEMPTS {
+                            thread::sleep(RETRY_INTERVAL);
+                        }
+                    }
+                }
+            }
+        }
+        info!("exiting dead-letter uploader");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn config() -> UploadConfig {
+        UploadConfig {
+            endpoint: "http://minio:9000/".to_string(),
+            bucket: "captures".to_string(),
+            region: None,
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            prefix: Some("gateway/".to_string()),
+            keep_local: None,
+        }
+    }
+
+    #[test]
+    fn test_signing_key() {
+        assert_eq!(
+            hex(&signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_sign() -> Result<()> {
+        let uploader = Uploader::new(&config())?;
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+
+        let (path, headers) = uploader.sign("gateway/dead letter.ndjson", b"", now);
+
+        assert_eq!(path, "/captures/gateway/dead%20letter.ndjson");
+        assert!(headers[0].1.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20261016/us-east-1/s3/aws4_request, \
+            SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="
+        ));
+        assert_eq!(
+            headers[1].1,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(headers[2].1, "20261016T120000Z");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_endpoint() {
+        let config = UploadConfig {
+            endpoint: "minio:9000".to_string(),
+            ..config()
+        };
+        assert!(Uploader::new(&config).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(300);
+
+static DEDUPLICATOR: LazyLock<Mutex<LogDeduplicator>> =
+    LazyLock::new(|| Mutex::new(LogDeduplicator::new(SUMMARY_INTERVAL)));
+
+#[derive(Debug, PartialEq)]
+enum Report {
+    First,
+    Summary(u32, Duration),
+    Suppressed,
+}
+
+struct Entry {
+    since: Instant,
+    count: u32,
+}
+
+/// Logs the first occurrence of a warning immediately and afterwards at most one summary per
+/// interval with the number of occurrences, so flapping devices don't drown the log.
+struct LogDeduplicator {
+    interval: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+impl LogDeduplicator {
+    fn new(interval: Duration) -> Self {
+        LogDeduplicator {
+            interval,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn record(&mut self, key: &str, now: Instant) -> Report {
+        match self.entries.get_mut(key) {
+            None => {
+                self.entries.insert(
+                    key.to_string(),
+                    Entry {
+                        since: now,
+                        count: 0,
+                    },
+                );
+                Report::First
+            }
+            Some(entry) => {
+                entry.count += 1;
+                let elapsed = now - entry.since;
+                if elapsed >= self.interval {
+                    let count = entry.count;
+                    entry.since = now;
+                    entry.count = 0;
+                    Report::Summary(count, elapsed)
+                } else {
+                    Report::Suppressed
+                }
+            }
+        }
+    }
+}
+
+/// Warns about `key` with the `detail` of the current occurrence, repeated occurrences of the
+/// same key are summarized periodically.
+pub fn warn_deduplicated(key: &str, detail: &str) {
+    let report = DEDUPLICATOR.lock().unwrap().record(key, Instant::now());
+    match report {
+        Report::First => warn!("{}: {}", key, detail),
+        Report::Summary(count, elapsed) => warn!(
+            "{} ×{} in last {}s, latest: {}",
+            key,
+            count,
+            elapsed.as_secs(),
+            detail
+        ),
+        Report::Suppressed => debug!("{}: {}", key, detail),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut deduplicator = LogDeduplicator::new(Duration::from_secs(300));
+        let start = Instant::now();
+
+        assert_eq!(deduplicator.record("foo", start), Report::First);
+        assert_eq!(
+            deduplicator.record("foo", start + Duration::from_secs(10)),
+            Report::Suppressed
+        );
+        assert_eq!(deduplicator.record("bar", start), Report::First);
+        assert_eq!(
+            deduplicator.record("foo", start + Duration::from_secs(300)),
+            Report::Summary(2, Duration::from_secs(300))
+        );
+        assert_eq!(
+            deduplicator.record("foo", start + Duration::from_secs(310)),
+            Report::Suppressed
+        );
+    }
+}
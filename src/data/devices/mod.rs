@@ -0,0 +1,234 @@
+use crate::data::age;
+use crate::error::{GatewayError, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+static REGISTRY: LazyLock<Mutex<DeviceRegistry>> =
+    LazyLock::new(|| Mutex::new(DeviceRegistry::default()));
+/// Seconds without events after which a device counts as offline, by source prefix.
+static ONLINE_TIMEOUTS: LazyLock<Mutex<HashMap<String, i64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Device {
+    source: String,
+    device: String,
+    #[serde(rename = "firstSeen")]
+    first_seen: i64,
+    #[serde(rename = "lastSeen")]
+    last_seen: i64,
+    messages: u64,
+    measurements: BTreeSet<String>,
+    /// Topic prefix of the source which last saw the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct DeviceListing<'a> {
+    #[serde(flatten)]
+    device: &'a Device,
+    /// Average number of events per minute since the device was first seen.
+    rate: f64,
+}
+
+/// Devices seen by any source, keyed by source type and device id (location, serial, ...).
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DeviceRegistry {
+    devices: BTreeMap<String, Device>,
+}
+
+impl DeviceRegistry {
+    fn record(
+        &mut self,
+        source: &str,
+        device: &str,
+        measurement: &str,
+        prefix: Option<String>,
+        now: i64,
+    ) {
+        let entry = self
+            .devices
+            .entry(format!("{}/{}", source, device))
+            .or_insert_with(|| Device {
+                source: source.to_string(),
+                device: device.to_string(),
+                first_seen: now,
+                last_seen: now,
+                messages: 0,
+                measurements: BTreeSet::new(),
+                prefix: None,
+            });
+        entry.last_seen = now;
+        if prefix.is_some() {
+            entry.prefix = prefix;
+        }
+        entry.messages += 1;
+        if !entry.measurements.contains(measurement) {
+            entry.measurements.insert(measurement.to_string());
+        }
+    }
+
+    fn listing(&self) -> Vec<DeviceListing<'_>> {
+        self.devices
+            .values()
+            .map(|device| {
+                let minutes = (device.last_seen - device.first_seen) as f64 / 60.0;
+                DeviceListing {
+                    device,
+                    rate: if minutes > 0.0 {
+                        device.messages as f64 / minutes
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Devices of the source with the given prefix and whether they were seen within the timeout.
+    fn online(&self, prefix: &str, timeout: i64, now: i64) -> Vec<(String, bool)> {
+        self.devices
+            .values()
+            .filter(|device| device.prefix.as_deref() == Some(prefix))
+            .map(|device| (device.device.clone(), now - device.last_seen <= timeout))
+            .collect()
+    }
+}
+
+/// Records an event of `measurement` forwarded for `device` of the given source type.
+pub fn record(source: &str, device: &str, measurement: &str) {
+    REGISTRY.lock().unwrap().record(
+        source,
+        device,
+        measurement,
+        age::current_source(),
+        chrono::offset::Utc::now().timestamp(),
+    );
+}
+
+/// Sends `device_online` events for the devices of the source with the given prefix with each
+/// heartbeat, devices count as offline after the timeout without events.
+pub fn enable_online(prefix: &str, timeout: Duration) {
+    ONLINE_TIMEOUTS
+        .lock()
+        .unwrap()
+        .insert(prefix.to_string(), timeout.as_secs() as i64);
+}
+
+/// Devices of the source with the given prefix and whether they are online at the given time, if
+/// enabled for the source.
+pub fn online(prefix: &str, now: i64) -> Vec<(String, bool)> {
+    let Some(timeout) = ONLINE_TIMEOUTS.lock().unwrap().get(prefix).copied() else {
+        return Vec::new();
+    };
+    REGISTRY.lock().unwrap().online(prefix, timeout, now)
+}
+
+/// Restores the registry saved by [`save`], a missing file starts with an empty registry.
+pub fn load(path: &str) -> Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(GatewayError::config(format!(
+                "failed to read device registry {}: {}",
+                path, error
+            )))
+        }
+    };
+    *REGISTRY.lock().unwrap() = serde_json::from_str(&content)?;
+    Ok(())
+}
+
+pub fn save(path: &str) {
+    let content = serde_json::to_string(&*REGISTRY.lock().unwrap()).unwrap();
+    if let Err(error) = fs::write(path, content) {
+        warn!("failed to write device registry {}: {}", path, error);
+    }
+}
+
+/// The registry contents with message rates as JSON.
+pub fn listing() -> String {
+    serde_json::to_string(&REGISTRY.lock().unwrap().listing()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut registry = DeviceRegistry::default();
+
+        registry.record("shelly", "loo-fan", "power", None, 1000);
+        registry.record("shelly", "loo-fan", "voltage", None, 1060);
+        registry.record("shelly", "loo-fan", "power", None, 1120);
+
+        let listing = registry.listing();
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].device.first_seen, 1000);
+        assert_eq!(listing[0].device.last_seen, 1120);
+        assert_eq!(listing[0].device.messages, 3);
+        assert_eq!(
+            listing[0].device.measurements,
+            BTreeSet::from(["power".to_string(), "voltage".to_string()])
+        );
+        assert_eq!(listing[0].rate, 1.5);
+    }
+
+    #[test]
+    fn test_serialization() -> Result<()> {
+        let mut registry = DeviceRegistry::default();
+        registry.record("sensor", "kitchen", "temperature", None, 1000);
+
+        let restored: DeviceRegistry = serde_json::from_str(&serde_json::to_string(&registry)?)?;
+
+        assert_eq!(restored.devices, registry.devices);
+        assert_eq!(
+            serde_json::to_string(&registry.listing())?,
+            "[{\"source\":\"sensor\",\"device\":\"kitchen\",\"firstSeen\":1000,\"lastSeen\":1000,\"messages\":1,\"measurements\":[\"temperature\"],\"rate\":0.0}]"
+        );
+
+        Ok(())
+    }
+    #[test]
+    fn test_online() {
+        let mut registry = DeviceRegistry::default();
+        registry.record(
+            "shelly",
+            "loo-fan",
+            "power",
+            Some("shellies".to_string()),
+            1000,
+        );
+        registry.record(
+            "shelly",
+            "garage",
+            "power",
+            Some("shellies".to_string()),
+            1500,
+        );
+        registry.record(
+            "sensor",
+            "kitchen",
+            "temperature",
+            Some("sensors".to_string()),
+            1500,
+        );
+        registry.record("shelly", "unattributed", "power", None, 1500);
+
+        assert_eq!(
+            registry.online("shellies", 300, 1600),
+            vec![("garage".to_string(), true), ("loo-fan".to_string(), false)]
+        );
+        assert_eq!(
+            registry.online("sensors", 60, 1560),
+            vec![("kitchen".to_string(), true)]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::error::{GatewayError, Result};
+#[cfg(feature = "encryption")]
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "encryption")]
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+#[cfg(feature = "encryption")]
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+
+#[cfg(feature = "encryption")]
+pub type Cipher = Aes256Gcm;
+
+/// Builds without the encryption feature can't read a key, so there is never a cipher.
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone)]
+pub struct Cipher(std::convert::Infallible);
+
+/// Start of every encrypted file.
+const MAGIC: &[u8; 8] = b"MQGWENC1";
+/// Buffered bytes are sealed into a record at the latest when reaching this size.
+const RECORD_SIZE: usize = 64 * 1024;
+#[cfg(feature = "encryption")]
+const NONCE_SIZE: usize = 12;
+
+/// Reads a 256 bit AES key stored as 64 hex digits, e.g. created with `openssl rand -hex 32`.
+#[cfg(feature = "encryption")]
+pub fn read_key(path: &str) -> Result<Cipher> {
+    let invalid = |reason: String| {
+        GatewayError::config(format!(
+            "invalid encryption key file '{}': {}",
+            path, reason
+        ))
+    };
+    let hex = fs::read_to_string(path).map_err(|error| invalid(error.to_string()))?;
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid("expected 64 hex digits".to_string()));
+    }
+    let key: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect();
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+#[cfg(not(feature = "encryption"))]
+pub fn read_key(_path: &str) -> Result<Cipher> {
+    Err(GatewayError::config(
+        "Encryption support not built, enable the encryption feature",
+    ))
+}
+
+/// Encrypts the written bytes with AES-256-GCM. Bytes are buffered and sealed into a record
+/// `<length u32 BE><nonce><ciphertext with tag>` on every flush, so each flush point can be
+/// decrypted even if the file is cut off later.
+pub struct EncryptingWriter<W: Write> {
+    cipher: Cipher,
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(cipher: Cipher, mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        Ok(EncryptingWriter {
+            cipher,
+            writer,
+            buffer: Vec::new(),
+        })
+    }
+
+    #[cfg(feature = "encryption")]
+    fn seal(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, self.buffer.as_slice())
+            .map_err(|error| io::Error::other(error.to_string()))?;
+        self.writer
+            .write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&nonce)?;
+        self.writer.write_all(&ciphertext)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn seal(&mut self) -> io::Result<()> {
+        match self.cipher.0 {}
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= RECORD_SIZE {
+            self.seal()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.seal()?;
+        self.writer.flush()
+    }
+}
+
+/// Decrypts a file written by one or more [`EncryptingWriter`]s appending to it, a truncated last
+/// record is ignored.
+#[cfg(feature = "encryption")]
+pub fn decrypt(cipher: &Cipher, mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an encrypted gateway file",
+        ));
+    }
+    let mut plaintext = Vec::new();
+    loop {
+        let mut length = [0; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        if length == MAGIC[..4] {
+            // header of an appended stream, records are far smaller than its value as length
+            reader.read_exact(&mut length)?;
+            if length != MAGIC[4..] {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid header"));
+            }
+            continue;
+        }
+        let mut record = vec![0; NONCE_SIZE + u32::from_be_bytes(length) as usize];
+        match reader.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        let (nonce, ciphertext) = record.split_at(NONCE_SIZE);
+        let decrypted = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "failed to decrypt record, wrong key or corrupted file",
+                )
+            })?;
+        plaintext.extend(decrypted);
+    }
+    Ok(plaintext)
+}
+
+#[cfg(not(feature = "encryption"))]
+pub fn decrypt(cipher: &Cipher, _reader: impl Read) -> io::Result<Vec<u8>> {
+    match cipher.0 {}
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[byte; 32]))
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let mut writer = EncryptingWriter::new(cipher(1), Vec::new())?;
+        writer.write_all(b"{\"topic\":\"foo\"}\n")?;
+        writer.flush()?;
+        writer.write_all(b"{\"topic\":\"bar\"}\n")?;
+        writer.flush()?;
+        let encrypted = writer.writer;
+
+        assert!(!encrypted.windows(5).any(|window| window == b"topic"));
+        assert_eq!(
+            decrypt(&cipher(1), encrypted.as_slice())?,
+            b"{\"topic\":\"foo\"}\n{\"topic\":\"bar\"}\n"
+        );
+        assert!(decrypt(&cipher(2), encrypted.as_slice()).is_err());
+        assert_eq!(
+            decrypt(&cipher(1), &encrypted[..encrypted.len() - 1])?,
+            b"{\"topic\":\"foo\"}\n"
+        );
+        assert!(decrypt(&cipher(1), &b"{\"topic\":\"foo\"}"[..]).is_err());
+
+        let mut appended = EncryptingWriter::new(cipher(1), encrypted)?;
+        appended.write_all(b"{\"topic\":\"baz\"}\n")?;
+        appended.flush()?;
+        assert_eq!(
+            decrypt(&cipher(1), appended.writer.as_slice())?,
+            b"{\"topic\":\"foo\"}\n{\"topic\":\"bar\"}\n{\"topic\":\"baz\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_key() {
+        let path = std::env::temp_dir().join(format!("gateway-key-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        fs::write(path, format!("{}\n", "0f".repeat(32))).unwrap();
+        assert!(read_key(path).is_ok());
+        fs::write(path, "0f".repeat(16)).unwrap();
+        assert!(read_key(path).is_err());
+        fs::write(path, "zz".repeat(32)).unwrap();
+        assert!(read_key(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,683 @@
+use crate::config::{CalendarConfig, CalendarTimezone, Config, LocationConfig};
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+use crate::data::query::WriteQuery;
+use crate::error::{GatewayError, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Timelike, Weekday};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::{env, fs};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalendarTag {
+    Year,
+    Month,
+    YearMonth,
+    Weekday,
+    Hour,
+    Season,
+    DayType,
+}
+
+impl FromStr for CalendarTag {
+    type Err = GatewayError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "year" => Ok(CalendarTag::Year),
+            "month" => Ok(CalendarTag::Month),
+            "year_month" => Ok(CalendarTag::YearMonth),
+            "weekday" => Ok(CalendarTag::Weekday),
+            "hour" => Ok(CalendarTag::Hour),
+            "season" => Ok(CalendarTag::Season),
+            "day_type" => Ok(CalendarTag::DayType),
+            _ => Err(GatewayError::config(format!(
+                "unknown calendar tag '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+impl CalendarTag {
+    fn name(&self) -> &str {
+        match self {
+            CalendarTag::Year => "year",
+            CalendarTag::Month => "month",
+            CalendarTag::YearMonth => "year_month",
+            CalendarTag::Weekday => "weekday",
+            CalendarTag::Hour => "hour",
+            CalendarTag::Season => "season",
+            CalendarTag::DayType => "day_type",
+        }
+    }
+}
+
+/// Tags describing how a message was received, e.g. to tell bridged from direct deliveries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrokerTag {
+    /// Host of the broker the gateway is connected to.
+    Broker,
+    Qos,
+    Retain,
+}
+
+impl FromStr for BrokerTag {
+    type Err = GatewayError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "broker" => Ok(BrokerTag::Broker),
+            "qos" => Ok(BrokerTag::Qos),
+            "retain" => Ok(BrokerTag::Retain),
+            _ => Err(GatewayError::config(format!(
+                "unknown broker tag '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+impl BrokerTag {
+    fn name(&self) -> &str {
+        match self {
+            BrokerTag::Broker => "broker",
+            BrokerTag::Qos => "qos",
+            BrokerTag::Retain => "retain",
+        }
+    }
+}
+
+/// Broker tags of the message being processed, clones share the values, so the logger receiving
+/// the message sets them for the enrichment of the parser.
+#[derive(Debug, Clone, Default)]
+pub struct BrokerTags {
+    tags: Vec<BrokerTag>,
+    current: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl PartialEq for BrokerTags {
+    fn eq(&self, other: &Self) -> bool {
+        self.tags == other.tags
+    }
+}
+
+impl BrokerTags {
+    pub fn new(tags: Vec<BrokerTag>) -> Self {
+        BrokerTags {
+            tags,
+            current: Arc::default(),
+        }
+    }
+
+    pub fn from_config(names: &[String]) -> Result<Self> {
+        Ok(BrokerTags::new(
+            names
+                .iter()
+                .map(|name| name.parse())
+                .collect::<Result<Vec<BrokerTag>>>()?,
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Records the tags of the message about to be processed.
+    pub fn set(&self, broker: Option<&str>, qos: i32, retained: bool) {
+        *self.current.lock().unwrap() = self
+            .tags
+            .iter()
+            .filter_map(|tag| {
+                let value = match tag {
+                    BrokerTag::Broker => broker?.to_string(),
+                    BrokerTag::Qos => qos.to_string(),
+                    BrokerTag::Retain => retained.to_string(),
+                };
+                Some((tag.name().to_string(), value))
+            })
+            .collect();
+    }
+
+    fn current(&self) -> Vec<(String, String)> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+/// Derives calendar tags from event timestamps. Days listed in the calendar file get their
+/// label as `day_type`, all other days are tagged as `workday` or `weekend`. The timestamps are
+/// taken as UTC unless the local time zone is configured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    tags: Vec<CalendarTag>,
+    labels: HashMap<NaiveDate, String>,
+    timezone: CalendarTimezone,
+}
+
+impl Calendar {
+    pub fn new(tags: Vec<CalendarTag>, labels: HashMap<NaiveDate, String>) -> Self {
+        Calendar {
+            tags,
+            labels,
+            timezone: CalendarTimezone::default(),
+        }
+    }
+
+    pub fn with_timezone(self, timezone: CalendarTimezone) -> Self {
+        Calendar { timezone, ..self }
+    }
+
+    pub fn from_config(config: &CalendarConfig) -> Result<Self> {
+        let tags = config
+            .tags
+            .iter()
+            .map(|tag| tag.parse())
+            .collect::<Result<Vec<CalendarTag>>>()?;
+        let labels = match &config.file {
+            Some(file) => {
+                let invalid_file = |error: &dyn std::error::Error| {
+                    GatewayError::config(format!("invalid calendar file '{}': {}", file, error))
+                };
+                let entries: HashMap<String, String> = fs::read_to_string(file)
+                    .map_err(|error| invalid_file(&error))
+                    .and_then(|content| {
+                        serde_yml::from_str(&content).map_err(|error| invalid_file(&error))
+                    })?;
+                entries
+                    .into_iter()
+                    .map(|(date, label)| {
+                        let date =
+                            NaiveDate::from_str(&date).map_err(|error| invalid_file(&error))?;
+                        Ok((date, label))
+                    })
+                    .collect::<Result<HashMap<NaiveDate, String>>>()?
+            }
+            None => HashMap::new(),
+        };
+        Ok(Calendar::new(tags, labels).with_timezone(config.timezone.unwrap_or_default()))
+    }
+
+    /// Tags of a timestamp in the time zone of the calendar.
+    pub fn tags_at(&self, timestamp: i64) -> Vec<(String, String)> {
+        match (self.timezone, DateTime::from_timestamp(timestamp, 0)) {
+            (CalendarTimezone::Utc, Some(time)) => self.tags(&time),
+            (CalendarTimezone::Local, Some(time)) => self.tags(&time.with_timezone(&Local)),
+            (_, None) => Vec::new(),
+        }
+    }
+
+    pub fn tags<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Vec<(String, String)> {
+        self.tags
+            .iter()
+            .map(|tag| {
+                let value = match tag {
+                    CalendarTag::Year => time.year().to_string(),
+                    CalendarTag::Month => time.month().to_string(),
+                    CalendarTag::YearMonth => format!("{:04}-{:02}", time.year(), time.month()),
+                    CalendarTag::Weekday => time.weekday().to_string(),
+                    CalendarTag::Hour => time.hour().to_string(),
+                    CalendarTag::Season => season(time.month()).to_string(),
+                    CalendarTag::DayType => self.day_type(time.date_naive()),
+                };
+                (tag.name().to_string(), value)
+            })
+            .collect()
+    }
+
+    fn day_type(&self, date: NaiveDate) -> String {
+        match self.labels.get(&date) {
+            Some(label) => label.clone(),
+            None => match date.weekday() {
+                Weekday::Sat | Weekday::Sun => "weekend".to_string(),
+                _ => "workday".to_string(),
+            },
+        }
+    }
+}
+
+fn season(month: u32) -> &'static str {
+    match month {
+        3..=5 => "spring",
+        6..=8 => "summer",
+        9..=11 => "autumn",
+        _ => "winter",
+    }
+}
+
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const GEOHASH_PRECISION: usize = 9;
+
+fn geohash(latitude: f64, longitude: f64) -> String {
+    let mut latitude_range = (-90.0, 90.0);
+    let mut longitude_range = (-180.0, 180.0);
+    let mut geohash = String::with_capacity(GEOHASH_PRECISION);
+    let mut even_bit = true;
+    let mut bits = 0;
+    let mut index = 0;
+
+    while geohash.len() < GEOHASH_PRECISION {
+        let (range, value) = if even_bit {
+            (&mut longitude_range, longitude)
+        } else {
+            (&mut latitude_range, latitude)
+        };
+        let middle = (range.0 + range.1) / 2.0;
+        index <<= 1;
+        if value >= middle {
+            index |= 1;
+            range.0 = middle;
+        } else {
+            range.1 = middle;
+        }
+        even_bit = !even_bit;
+
+        bits += 1;
+        if bits == 5 {
+            geohash.push(GEOHASH_ALPHABET[index] as char);
+            bits = 0;
+            index = 0;
+        }
+    }
+
+    geohash
+}
+
+fn location_tags(location: &LocationConfig) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    if let (Some(latitude), Some(longitude)) = (location.latitude, location.longitude) {
+        tags.push(("latitude".to_string(), latitude.to_string()));
+        tags.push(("longitude".to_string(), longitude.to_string()));
+        tags.push(("geohash".to_string(), geohash(latitude, longitude)));
+    }
+    if let Some(floor) = &location.floor {
+        tags.push(("floor".to_string(), floor.clone()));
+    }
+    if let Some(room) = &location.room {
+        tags.push(("room".to_string(), room.clone()));
+    }
+    tags
+}
+
+pub fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+/// Tags identifying this gateway instance, `gateway_host` when `hostTag` is enabled and the
+/// configured `instance` ID.
+pub fn instance_tags(config: &Config) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    if config.host_tag.unwrap_or(false) {
+        if let Some(hostname) = hostname() {
+            tags.push(("gateway_host".to_string(), hostname));
+        }
+    }
+    if let Some(instance) = &config.instance {
+        tags.push(("instance".to_string(), instance.clone()));
+    }
+    tags
+}
+
+static METRIC_LABELS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Sets the instance tags every metric is labelled with.
+pub fn set_metric_labels(tags: Vec<(String, String)>) {
+    *METRIC_LABELS.lock().unwrap() = tags;
+}
+
+/// Label set of a metric sample like `{instance="a",source="b"}`, the instance tags of the gateway
+/// followed by the given labels, empty without any.
+pub fn labels(labels: &[(&str, &str)]) -> String {
+    label_set(&METRIC_LABELS.lock().unwrap(), labels)
+}
+
+fn label_set(instance: &[(String, String)], labels: &[(&str, &str)]) -> String {
+    let labels: Vec<String> = instance
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .chain(labels.iter().copied())
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// Additional tags attached to all events of a source and the precision their values are
+/// rounded to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Enrichment {
+    calendar: Option<Calendar>,
+    locations: HashMap<String, Vec<(String, String)>>,
+    static_tags: Vec<(String, String)>,
+    broker_tags: BrokerTags,
+    precision: HashMap<String, u32>,
+}
+
+impl Enrichment {
+    pub fn new(calendar: Option<Calendar>, locations: &HashMap<String, LocationConfig>) -> Self {
+        Enrichment {
+            calendar,
+            locations: locations
+                .iter()
+                .map(|(name, location)| (name.clone(), location_tags(location)))
+                .collect(),
+            static_tags: Vec::new(),
+            broker_tags: BrokerTags::default(),
+            precision: HashMap::new(),
+        }
+    }
+
+    /// Rounds values of the given measurements to a number of decimal places.
+    pub fn with_precision(self, precision: HashMap<String, u32>) -> Self {
+        Enrichment { precision, ..self }
+    }
+
+    pub fn with_static_tags(self, static_tags: Vec<(String, String)>) -> Self {
+        Enrichment {
+            static_tags,
+            ..self
+        }
+    }
+
+    pub fn with_broker_tags(self, broker_tags: BrokerTags) -> Self {
+        Enrichment {
+            broker_tags,
+            ..self
+        }
+    }
+
+    #[cfg(feature = "opendtu")]
+    pub fn with_default_calendar(self, tags: &[CalendarTag]) -> Self {
+        Enrichment {
+            calendar: self
+                .calendar
+                .or_else(|| Some(Calendar::new(tags.to_vec(), HashMap::new()))),
+            ..self
+        }
+    }
+
+    pub fn tag_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .calendar
+            .iter()
+            .flat_map(|calendar| calendar.tags.iter().map(|tag| tag.name().to_string()))
+            .collect();
+        for (name, _) in self
+            .static_tags
+            .iter()
+            .chain(self.locations.values().flatten())
+        {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names.extend(
+            self.broker_tags
+                .tags
+                .iter()
+                .map(|tag| tag.name().to_string()),
+        );
+        names
+    }
+
+    pub fn tags(&self, timestamp: i64, location: &str) -> Vec<(String, String)> {
+        let mut tags = match &self.calendar {
+            Some(calendar) => calendar.tags_at(timestamp),
+            None => Vec::new(),
+        };
+        tags.extend(self.static_tags.iter().cloned());
+        if let Some(location_tags) = self.locations.get(location) {
+            tags.extend(location_tags.iter().cloned());
+        }
+        tags.extend(self.broker_tags.current());
+        tags
+    }
+
+    pub fn round(&self, measurement: &str, value: f64) -> f64 {
+        match self.precision.get(measurement) {
+            Some(&decimals) => {
+                let factor = 10f64.powi(decimals as i32);
+                (value * factor).round() / factor
+            }
+            None => value,
+        }
+    }
+
+    #[cfg(any(
+        feature = "shelly",
+        feature = "opendtu",
+        feature = "openmqttgateway",
+        feature = "zwave",
+        feature = "senml"
+    ))]
+    pub fn apply(&self, query: WriteQuery, timestamp: i64, location: &str) -> WriteQuery {
+        self.tags(timestamp, location)
+            .into_iter()
+            .fold(query, |query, (key, value)| query.add_tag(key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_label_set() {
+        let instance = vec![("instance".to_string(), "attic".to_string())];
+
+        assert_eq!(label_set(&[], &[]), "");
+        assert_eq!(label_set(&instance, &[]), "{instance=\"attic\"}");
+        assert_eq!(
+            label_set(&instance, &[("topic", "a\"b")]),
+            "{instance=\"attic\",topic=\"a\\\"b\"}"
+        );
+    }
+
+    #[test]
+    fn test_parse_tag() -> Result<()> {
+        assert_eq!("season".parse::<CalendarTag>()?, CalendarTag::Season);
+        assert!("foo".parse::<CalendarTag>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calendar_tags() {
+        let calendar = Calendar::new(
+            vec![
+                CalendarTag::Year,
+                CalendarTag::Month,
+                CalendarTag::YearMonth,
+                CalendarTag::Weekday,
+                CalendarTag::Hour,
+                CalendarTag::Season,
+                CalendarTag::DayType,
+            ],
+            HashMap::new(),
+        );
+
+        let tags = calendar.tags(&time("2023-07-08T14:30:00Z"));
+
+        assert_eq!(
+            tags,
+            vec![
+                ("year".to_string(), "2023".to_string()),
+                ("month".to_string(), "7".to_string()),
+                ("year_month".to_string(), "2023-07".to_string()),
+                ("weekday".to_string(), "Sat".to_string()),
+                ("hour".to_string(), "14".to_string()),
+                ("season".to_string(), "summer".to_string()),
+                ("day_type".to_string(), "weekend".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calendar_tags_at() {
+        let calendar = Calendar::new(
+            vec![CalendarTag::Hour, CalendarTag::YearMonth],
+            HashMap::new(),
+        );
+
+        assert_eq!(
+            calendar.tags_at(time("2023-07-31T23:30:00Z").timestamp()),
+            vec![
+                ("hour".to_string(), "23".to_string()),
+                ("year_month".to_string(), "2023-07".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calendar_day_type_labels() {
+        let labels = HashMap::from([(
+            NaiveDate::from_ymd_opt(2023, 12, 25).unwrap(),
+            "holiday".to_string(),
+        )]);
+        let calendar = Calendar::new(vec![CalendarTag::DayType], labels);
+
+        assert_eq!(
+            calendar.tags(&time("2023-12-25T10:00:00Z")),
+            vec![("day_type".to_string(), "holiday".to_string())]
+        );
+        assert_eq!(
+            calendar.tags(&time("2023-12-27T10:00:00Z")),
+            vec![("day_type".to_string(), "workday".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_geohash() {
+        assert_eq!(geohash(57.64911, 10.40744), "u4pruydqq");
+        assert_eq!(geohash(-25.382708, -49.265506), "6gkzwgjzn");
+    }
+
+    #[test]
+    fn test_broker_tags() -> Result<()> {
+        let broker_tags = BrokerTags::from_config(&["broker".to_string(), "retain".to_string()])?;
+        let enrichment = Enrichment::default().with_broker_tags(broker_tags.clone());
+
+        broker_tags.set(Some("bridge"), 1, true);
+
+        assert_eq!(
+            enrichment.tags(1701271852, "kitchen"),
+            vec![
+                ("broker".to_string(), "bridge".to_string()),
+                ("retain".to_string(), "true".to_string()),
+            ]
+        );
+        assert_eq!(enrichment.tag_names(), vec!["broker", "retain"]);
+        broker_tags.set(None, 0, false);
+        assert_eq!(
+            enrichment.tags(1701271852, "kitchen"),
+            vec![("retain".to_string(), "false".to_string())]
+        );
+        assert!(BrokerTags::from_config(&["topic".to_string()]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_enrichment_without_calendar() {
+        assert!(Enrichment::default().tags(1701271852, "kitchen").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "opendtu")]
+    fn test_enrichment_with_default_calendar() {
+        let enrichment = Enrichment::default().with_default_calendar(&[CalendarTag::Hour]);
+
+        assert_eq!(enrichment.tags(1701271852, "kitchen").len(), 1);
+    }
+
+    #[test]
+    fn test_enrichment_with_static_tags() {
+        let enrichment = Enrichment::default()
+            .with_static_tags(vec![("instance".to_string(), "gw-1".to_string())]);
+
+        assert_eq!(
+            enrichment.tags(1701271852, "kitchen"),
+            vec![("instance".to_string(), "gw-1".to_string())]
+        );
+        assert_eq!(enrichment.tag_names(), vec!["instance"]);
+    }
+
+    #[test]
+    fn test_instance_tags() {
+        let config: Config = serde_yml::from_str(
+            r#"
+            mqttUrl: "mqtt://localhost:1883"
+            mqttClientId: "gateway"
+            instance: "gw-1"
+            sources: []
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            instance_tags(&config),
+            vec![("instance".to_string(), "gw-1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_enrichment_with_location() {
+        let locations = HashMap::from([(
+            "kitchen".to_string(),
+            LocationConfig {
+                latitude: Some(57.64911),
+                longitude: Some(10.40744),
+                floor: Some("1".to_string()),
+                room: None,
+            },
+        )]);
+        let enrichment = Enrichment::new(None, &locations);
+
+        assert_eq!(
+            enrichment.tags(1701271852, "kitchen"),
+            vec![
+                ("latitude".to_string(), "57.64911".to_string()),
+                ("longitude".to_string(), "10.40744".to_string()),
+                ("geohash".to_string(), "u4pruydqq".to_string()),
+                ("floor".to_string(), "1".to_string()),
+            ]
+        );
+        assert!(enrichment.tags(1701271852, "garage").is_empty());
+    }
+
+    #[test]
+    fn test_round() {
+        let enrichment = Enrichment::default().with_precision(HashMap::from([
+            ("voltage".to_string(), 1),
+            ("total_energy".to_string(), 3),
+        ]));
+
+        assert_eq!(enrichment.round("voltage", 231.6499), 231.6);
+        assert_eq!(enrichment.round("total_energy", 1094.8654), 1094.865);
+        assert_eq!(enrichment.round("current", 3.12345), 3.12345);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::data::LogEvent;
+use crate::error::{GatewayError, Result};
+use serde_json::Value;
+
+/// Latest layout of `LogEvent`, log events are written as `{"version": 2, "event": {...}}`. Bump
+/// this together with a new entry in `MIGRATIONS` whenever the layout changes, so events written
+/// by tools and gateways of older releases stay readable.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Upgrades of the event layout on read, `MIGRATIONS[n]` turns version `n` into version `n + 1`.
+const MIGRATIONS: [fn(Value) -> Value; CURRENT_VERSION as usize] = [
+    // bare events (version 0) have the layout of version 1
+    std::convert::identity,
+    // version 2 allows booleans and strings as value, numbers stay valid
+    std::convert::identity,
+];
+
+/// Log event of a JSON line, either in an envelope or bare.
+pub fn decode(line: &str) -> Result<LogEvent> {
+    decode_value(serde_json::from_str(line)?)
+}
+
+/// Log event of a JSON value, upgrading the layout of older versions.
+pub fn decode_value(value: Value) -> Result<LogEvent> {
+    // events written before the envelope was introduced are bare `LogEvent` objects
+    let (version, event) = match value {
+        Value::Object(mut object) if object.contains_key("version") => {
+            let version = object
+                .remove("version")
+                .and_then(|version| version.as_u64())
+                .ok_or_else(|| GatewayError::parse("envelope", "invalid version"))?
+                as u32;
+            let event = object
+                .remove("event")
+                .ok_or_else(|| GatewayError::parse("envelope", "missing event"))?;
+            (version, event)
+        }
+        value => (0, value),
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(GatewayError::parse(
+            "envelope",
+            format!(
+                "unsupported version {} (supported up to {})",
+                version, CURRENT_VERSION
+            ),
+        ));
+    }
+
+    Ok(serde_json::from_value(migrate(version, event))?)
+}
+
+fn migrate(version: u32, event: Value) -> Value {
+    MIGRATIONS[version as usize..]
+        .iter()
+        .fold(event, |event, migration| migration(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::EventValue;
+
+    fn encode(event: &LogEvent) -> Result<String> {
+        Ok(format!(
+            "{{\"version\":{},\"event\":{}}}",
+            CURRENT_VERSION,
+            serde_json::to_string(event)?
+        ))
+    }
+
+    fn log_event() -> LogEvent {
+        LogEvent {
+            host: "host".to_string(),
+            location: "kitchen".to_string(),
+            measurement_type: "temperature".to_string(),
+            unit: "°C".to_string(),
+            sensor: "BME680".to_string(),
+            calculated: false,
+            time: "2024-01-01T00:00:00Z".to_string(),
+            value: EventValue::Float(21.5),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let event = log_event();
+
+        let line = encode(&event)?;
+
+        assert!(line.starts_with("{\"version\":2,\"event\":{"));
+        assert_eq!(decode(&line)?, event);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip_non_numeric_values() -> Result<()> {
+        for value in [
+            EventValue::Boolean(true),
+            EventValue::Text("open".to_string()),
+        ] {
+            let event = LogEvent {
+                value,
+                ..log_event()
+            };
+
+            assert_eq!(decode(&encode(&event)?)?, event);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_version_1() -> Result<()> {
+        let line = format!(
+            "{{\"version\":1,\"event\":{}}}",
+            serde_json::to_string(&log_event())?
+        );
+
+        assert!(line.contains("\"value\":21.5"));
+        assert_eq!(decode(&line)?, log_event());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_legacy_event() -> Result<()> {
+        let event = log_event();
+
+        let line = serde_json::to_string(&event)?;
+
+        assert_eq!(decode(&line)?, event);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_future_version() {
+        let line = "{\"version\":99,\"event\":{}}";
+
+        let error = decode(line).err().unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "failed to parse envelope: unsupported version 99 (supported up to 2)"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Number of distinct strings a pool keeps, it starts over once exceeded so that topics with
+/// ever changing segments don't grow it without bounds.
+const CAPACITY: usize = 10_000;
+
+/// Pool of the strings repeated in the events of a source like locations, sensors and
+/// measurements. Events are cloned for each target, pooled strings are shared by all clones and
+/// all events with the same value instead of being allocated for each.
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+    capacity: usize,
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new(CAPACITY)
+    }
+}
+
+impl Interner {
+    pub fn new(capacity: usize) -> Self {
+        Interner {
+            strings: HashSet::new(),
+            capacity,
+        }
+    }
+
+    /// The pooled string equal to `value`, added to the pool if missing.
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(string) = self.strings.get(value) {
+            return string.clone();
+        }
+        if self.strings.len() >= self.capacity {
+            self.strings.clear();
+        }
+        let string: Arc<str> = Arc::from(value);
+        self.strings.insert(string.clone());
+        string
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::default();
+
+        let first = interner.intern("kitchen");
+        let second = interner.intern(&String::from("kitchen"));
+
+        assert_eq!(&*first, "kitchen");
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &interner.intern("office")));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut interner = Interner::new(2);
+
+        let kitchen = interner.intern("kitchen");
+        interner.intern("office");
+        interner.intern("garage");
+
+        assert_eq!(interner.strings.len(), 1);
+        assert!(!Arc::ptr_eq(&kitchen, &interner.intern("kitchen")));
+    }
+}
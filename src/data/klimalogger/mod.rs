@@ -1,18 +1,30 @@
 use std::fmt;
 use std::sync::mpsc::SyncSender;
 
-use crate::config::Target;
-use crate::data::CheckMessage;
-use crate::target::influx;
-use crate::target::influx::InfluxConfig;
-use crate::target::postgres::PostgresConfig;
+use crate::config::TimestampConfig;
+use crate::config::{FieldsConfig, MissingTimestamp, StaticEventConfig, Target};
+use crate::data::catalog::Measurement;
+use crate::data::clock::{self, Clock};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::Enrichment;
+use crate::data::intern::Interner;
+use crate::data::query::{Timestamp, WriteQuery};
+use crate::data::timestamp::{TimestampPolicy, Timestamped};
+use crate::data::topic;
+use crate::data::topic::TopicSchema;
+use crate::data::{age, deadletter, devices, live, BuildInfo};
+use crate::data::{validate, CheckMessage, Logger, SourceStats};
+use crate::data::{HEARTBEAT_MEASUREMENT, ONLINE_MEASUREMENT, START_MEASUREMENT};
+use crate::error::{GatewayError, Result};
+use crate::target::ack::Ack;
+use crate::target::supervisor;
+use crate::target::Mappers;
 use crate::{target, SensorReading};
-use anyhow::Result;
 use chrono::{DateTime, Utc};
-use influxdb::{Timestamp, WriteQuery};
-use log::{debug, warn};
+use log::debug;
 use paho_mqtt::Message;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 
@@ -22,6 +34,9 @@ pub struct Data {
     pub(crate) timestamp: i32,
     pub(crate) value: f32,
     pub(crate) sensor: String,
+    /// Marks buffered historical readings, see `timestamp.allowBackfill`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) backfill: bool,
 }
 
 impl fmt::Debug for Data {
@@ -30,72 +45,431 @@ impl fmt::Debug for Data {
     }
 }
 
+impl Timestamped for Data {
+    fn timestamp(&self) -> Option<i64> {
+        Some(self.timestamp as i64)
+    }
+}
+
+const TIMESTAMP_POLICY: TimestampPolicy = TimestampPolicy::new(MissingTimestamp::Drop, Some(10));
+
+/// JSON pointers to the fields of a reading, for devices with a different payload layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPointers {
+    time: String,
+    value: String,
+    sensor: String,
+}
+
+impl Default for FieldPointers {
+    fn default() -> Self {
+        FieldPointers {
+            time: "/time".to_string(),
+            value: "/value".to_string(),
+            sensor: "/sensor".to_string(),
+        }
+    }
+}
+
+impl FieldPointers {
+    /// Overrides the defaults with the configured pointers, which must start with `/`.
+    pub fn from_config(config: Option<&FieldsConfig>) -> Result<Self> {
+        let defaults = FieldPointers::default();
+        let Some(config) = config else {
+            return Ok(defaults);
+        };
+        let pointer = |pointer: &Option<String>, default: String| match pointer {
+            Some(pointer) if !pointer.starts_with('/') => Err(GatewayError::config(format!(
+                "field pointer '{}' does not start with '/'",
+                pointer
+            ))),
+            Some(pointer) => Ok(pointer.clone()),
+            None => Ok(default),
+        };
+        Ok(FieldPointers {
+            time: pointer(&config.time, defaults.time)?,
+            value: pointer(&config.value, defaults.value)?,
+            sensor: pointer(&config.sensor, defaults.sensor)?,
+        })
+    }
+
+    fn extract(&self, reading: &Value) -> Result<Data> {
+        let field = |pointer: &str| {
+            reading
+                .pointer(pointer)
+                .cloned()
+                .ok_or_else(|| GatewayError::parse("json", format!("no field at {}", pointer)))
+        };
+        Ok(Data {
+            timestamp: serde_json::from_value(field(&self.time)?)?,
+            value: serde_json::from_value(field(&self.value)?)?,
+            sensor: serde_json::from_value(field(&self.sensor)?)?,
+            backfill: reading
+                .get("backfill")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Sensor of the events of the gateway itself like heartbeats.
+const GATEWAY_SENSOR: &str = "gateway";
+
+/// Further levels below the measurement are ignored like they always were.
+const TOPIC_SCHEMA: &str = "{prefix}/{location}/{measurement}/#";
+const TOPIC_VARIABLES: &[&str] = &["location", "measurement"];
+
 pub struct SensorLogger {
     txs: Vec<SyncSender<SensorReading>>,
+    enrichment: Enrichment,
+    timestamp_policy: TimestampPolicy,
+    clock: Arc<dyn Clock>,
+    topic_schema: TopicSchema,
+    fields: FieldPointers,
+    heartbeat_txs: Vec<SyncSender<SensorReading>>,
+    /// Acknowledgements are published to `<ack_prefix>/<location>` if set.
+    ack_prefix: Option<String>,
+    strings: Interner,
+    stats: SourceStats,
 }
 
 impl SensorLogger {
-    pub(crate) fn new(tx: Vec<SyncSender<SensorReading>>) -> Self {
-        SensorLogger { txs: tx }
+    pub(crate) fn new(tx: Vec<SyncSender<SensorReading>>, enrichment: Enrichment) -> Self {
+        SensorLogger {
+            txs: tx,
+            enrichment,
+            timestamp_policy: TIMESTAMP_POLICY,
+            clock: clock::system(),
+            topic_schema: TOPIC_SCHEMA.parse().unwrap(),
+            fields: FieldPointers::default(),
+            heartbeat_txs: Vec::new(),
+            ack_prefix: None,
+            strings: Interner::default(),
+            stats: SourceStats::default(),
+        }
+    }
+
+    /// Targets receiving heartbeat events, notification targets are usually left out.
+    pub(crate) fn with_heartbeat_txs(self, heartbeat_txs: Vec<SyncSender<SensorReading>>) -> Self {
+        SensorLogger {
+            heartbeat_txs,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_timestamp_policy(self, timestamp_policy: TimestampPolicy) -> Self {
+        SensorLogger {
+            timestamp_policy,
+            ..self
+        }
+    }
+
+    /// Time the readings are checked against and heartbeats are stamped with.
+    #[allow(dead_code)]
+    pub(crate) fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        SensorLogger { clock, ..self }
+    }
+
+    pub(crate) fn with_topic_schema(self, topic_schema: TopicSchema) -> Self {
+        SensorLogger {
+            topic_schema,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_fields(self, fields: FieldPointers) -> Self {
+        SensorLogger { fields, ..self }
+    }
+
+    /// Publishes an acknowledgement to `<ack_prefix>/<location>` once all targets wrote the
+    /// readings of a message.
+    pub(crate) fn with_ack(self, ack_prefix: impl Into<String>) -> Self {
+        SensorLogger {
+            ack_prefix: Some(ack_prefix.into()),
+            ..self
+        }
     }
 
     fn convert_timestamp(timestamp: i64) -> DateTime<Utc> {
         chrono::DateTime::from_timestamp(timestamp, 0).expect("failed to convert timestamp")
     }
+
+    fn send_reading(
+        &mut self,
+        location: &str,
+        measurement: &str,
+        result: &Data,
+        ack: Option<&Ack>,
+    ) {
+        debug!("Sensor {} \"{}\": {:?}", location, measurement, result);
+
+        let timestamp_policy = self.timestamp_policy.for_payload(result.backfill);
+        let date_time = match timestamp_policy.resolve(result.timestamp(), self.clock.as_ref()) {
+            Ok(timestamp) => Self::convert_timestamp(timestamp),
+            Err(error) => {
+                debug!("Sensor {} \"{}\": {}", location, measurement, error);
+                self.stats.dropped += 1;
+                return;
+            }
+        };
+
+        let value = self.enrichment.round(measurement, result.value as f64) as f32;
+        let sensor_reading = SensorReading {
+            measurement: self.strings.intern(measurement),
+            time: date_time,
+            location: self.strings.intern(location),
+            sensor: self.strings.intern(&result.sensor),
+            value,
+            tags: self.enrichment.tags(date_time.timestamp(), location),
+            ack: ack.cloned(),
+        };
+        if !validate::accept("Sensor", &sensor_reading) {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        target::send_all(&self.txs, &sensor_reading);
+        devices::record("sensor", location, measurement);
+        live::record(
+            measurement,
+            &[("location", location), ("sensor", &result.sensor)],
+            value as f64,
+            date_time.timestamp(),
+        );
+        self.stats.forwarded += 1;
+    }
 }
 
 impl CheckMessage for SensorLogger {
     fn check_message(&mut self, msg: &Message) {
-        let mut split = msg.topic().split("/");
-
-        let location = split.nth(1);
-        let measurement = split.next();
-        let result = parse(msg);
-        if let (Some(location), Some(measurement), Ok(result)) = (location, measurement, &result) {
-            let date_time = Self::convert_timestamp(result.timestamp as i64);
-
-            let now = chrono::offset::Utc::now();
-            let difference = now - date_time;
-
-            let has_high_time_offset = difference.num_seconds() > 10;
-            debug!(
-                "Sensor {} \"{}\": {:?} {:.2}s{}",
-                location,
-                measurement,
-                &result,
-                difference.num_milliseconds() as f32 / 1000.0,
-                if has_high_time_offset {
-                    " *** HIGH TIME OFFSET ***"
-                } else {
-                    ""
-                }
+        if self
+            .ack_prefix
+            .as_ref()
+            .is_some_and(|ack_prefix| msg.topic().starts_with(&format!("{}/", ack_prefix)))
+        {
+            // our own acknowledgements
+            return;
+        }
+        self.stats.received += 1;
+        let topic = self.topic_schema.matches(msg.topic());
+
+        let location = topic.as_ref().and_then(|topic| topic.get("location"));
+        let measurement = topic.as_ref().and_then(|topic| topic.get("measurement"));
+        let result = parse_readings(msg, &self.fields);
+        if let (Some(location), Some(measurement), Ok(readings)) = (location, measurement, &result)
+        {
+            self.stats.parsed += 1;
+            // readings dropped by the timestamp policy are acknowledged too, resending won't help
+            let ack = self.ack_prefix.as_ref().map(|ack_prefix| {
+                Ack::new(
+                    format!("{}/{}", ack_prefix, location),
+                    serde_json::json!({
+                        "topic": msg.topic(),
+                        "count": readings.len(),
+                        "time": readings.iter().map(|data| data.timestamp).max(),
+                    })
+                    .to_string(),
+                )
+            });
+            for result in readings {
+                self.send_reading(location, measurement, result, ack.as_ref());
+            }
+            if let Some(ack) = ack {
+                ack.confirm();
+            }
+        } else {
+            self.stats.dropped += 1;
+            warn_deduplicated(
+                &format!("Sensor parse error on '{}'", msg.topic()),
+                &format!("{:?}, {:?}, {:?}", location, measurement, &result),
             );
+            deadletter::record(msg, "invalid sensor reading");
+        }
+    }
 
-            if has_high_time_offset {
-                return;
-            }
+    fn stats(&self) -> SourceStats {
+        self.stats
+    }
 
+    fn shutdown(&mut self) {
+        self.txs.clear();
+        self.heartbeat_txs.clear();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        let time = self.clock.now();
+        let sensor_reading = SensorReading {
+            measurement: HEARTBEAT_MEASUREMENT.into(),
+            time,
+            location: self.strings.intern(source),
+            sensor: GATEWAY_SENSOR.into(),
+            value: messages as f32,
+            tags: self.enrichment.tags(time.timestamp(), source),
+            ack: None,
+        };
+        target::send_all(&self.heartbeat_txs, &sensor_reading);
+        // readings have a single value, so only the mean age is written
+        if let Some(window) = age::take_window(source) {
             let sensor_reading = SensorReading {
-                measurement: measurement.to_string(),
-                time: date_time,
-                location: location.to_string(),
-                sensor: result.sensor.to_string(),
-                value: result.value,
+                measurement: age::MEASUREMENT.into(),
+                value: window.mean as f32,
+                ..sensor_reading
             };
-
-            for tx in &self.txs {
-                tx.send(sensor_reading.clone()).expect("failed to send");
-            }
-        } else {
-            warn!("FAILED: {:?}, {:?}, {:?}", location, measurement, &result);
+            target::send_all(&self.heartbeat_txs, &sensor_reading);
+        }
+        // the location of a reading identifies its device
+        for (location, online) in devices::online(source, time.timestamp()) {
+            let sensor_reading = SensorReading {
+                measurement: ONLINE_MEASUREMENT.into(),
+                time,
+                tags: self.enrichment.tags(time.timestamp(), &location),
+                location: self.strings.intern(&location),
+                sensor: GATEWAY_SENSOR.into(),
+                value: online as u8 as f32,
+                ack: None,
+            };
+            target::send_all(&self.heartbeat_txs, &sensor_reading);
         }
     }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        let time = self.clock.now();
+        let mut tags = build.tags();
+        tags.append(&mut self.enrichment.tags(time.timestamp(), source));
+        let sensor_reading = SensorReading {
+            measurement: START_MEASUREMENT.into(),
+            time,
+            location: self.strings.intern(source),
+            sensor: GATEWAY_SENSOR.into(),
+            value: 1.0,
+            tags,
+            ack: None,
+        };
+        target::send_all(&self.heartbeat_txs, &sensor_reading);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        let mut tags: Vec<(String, String)> =
+            event.tags.clone().unwrap_or_default().into_iter().collect();
+        tags.sort();
+        tags.append(&mut self.enrichment.tags(time.timestamp(), source));
+        let sensor_reading = SensorReading {
+            measurement: self.strings.intern(&event.measurement),
+            time,
+            location: self.strings.intern(source),
+            sensor: GATEWAY_SENSOR.into(),
+            value: event.value as f32,
+            tags,
+            ack: None,
+        };
+        target::send_all(&self.heartbeat_txs, &sensor_reading);
+    }
 }
 
 pub fn parse(msg: &Message) -> Result<Data> {
     Ok(serde_json::from_slice::<Data>(msg.payload())?)
 }
 
+/// Parses a single reading or an array of readings buffered by the device, e.g. while sleeping.
+pub fn parse_readings(msg: &Message, fields: &FieldPointers) -> Result<Vec<Data>> {
+    if *fields != FieldPointers::default() {
+        return match serde_json::from_slice::<Value>(msg.payload())? {
+            Value::Array(readings) => readings
+                .iter()
+                .map(|reading| fields.extract(reading))
+                .collect(),
+            reading => fields.extract(&reading).map(|data| vec![data]),
+        };
+    }
+    if msg.payload().trim_ascii_start().starts_with(b"[") {
+        Ok(serde_json::from_slice::<Vec<Data>>(msg.payload())?)
+    } else {
+        parse(msg).map(|data| vec![data])
+    }
+}
+
+pub(crate) fn to_query(result: SensorReading) -> WriteQuery {
+    let timestamp = Timestamp::Seconds(result.time.timestamp() as u128);
+    let query = WriteQuery::new(timestamp, result.measurement.to_string())
+        .add_tag("location", result.location.to_string())
+        .add_tag("sensor", result.sensor.to_string())
+        .add_field("value", result.value);
+    result
+        .tags
+        .into_iter()
+        .fold(query, |query, (key, value)| query.add_tag(key, value))
+}
+
+fn to_items(result: SensorReading) -> Vec<(String, String)> {
+    let mut items = vec![
+        ("measurement".to_string(), result.measurement.to_string()),
+        ("time".to_string(), result.time.to_rfc3339()),
+        ("location".to_string(), result.location.to_string()),
+        ("sensor".to_string(), result.sensor.to_string()),
+        ("value".to_string(), result.value.to_string()),
+    ];
+    items.extend(result.tags);
+    items
+}
+
+pub fn catalog() -> Vec<Measurement> {
+    vec![Measurement::new(
+        "<measurement>",
+        &["value"],
+        &["location", "sensor"],
+        None,
+    )]
+}
+
+/// Writes the readings as queries and items, natively to PostgreSQL.
+fn mappers() -> Mappers<SensorReading> {
+    let mappers = Mappers::new("klimalogger", to_query).with_items(to_items);
+    #[cfg(feature = "postgres")]
+    let mappers = mappers.with_postgres(target::postgres::spawn_postgres_writer);
+    mappers
+}
+
+pub fn create_logger(
+    targets: Vec<Target>,
+    enrichment: Enrichment,
+    timestamp: Option<&TimestampConfig>,
+    topic_schema: Option<&str>,
+    fields: Option<&FieldsConfig>,
+    ack_prefix: Option<String>,
+) -> Result<Logger> {
+    let topic_schema = topic::parse(topic_schema.unwrap_or(TOPIC_SCHEMA), TOPIC_VARIABLES)?;
+    let fields = FieldPointers::from_config(fields)?;
+    let mut txs: Vec<SyncSender<SensorReading>> = Vec::new();
+    let mut heartbeat_txs: Vec<SyncSender<SensorReading>> = Vec::new();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for target in targets {
+        let notification = matches!(
+            target,
+            Target::Telegram { .. } | Target::Pushover { .. } | Target::Smtp { .. }
+        );
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            target::spawn_writer(target.clone(), &mappers())
+        })?;
+        if !notification {
+            heartbeat_txs.push(tx.clone());
+        }
+        txs.push(tx);
+        handles.push(handle);
+    }
+
+    let logger = SensorLogger::new(txs, enrichment)
+        .with_timestamp_policy(TIMESTAMP_POLICY.with_config(timestamp))
+        .with_heartbeat_txs(heartbeat_txs)
+        .with_topic_schema(topic_schema)
+        .with_fields(fields);
+    let logger = match ack_prefix {
+        Some(ack_prefix) => logger.with_ack(ack_prefix),
+        None => logger,
+    };
+    Ok((Arc::new(Mutex::new(logger)), handles))
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::mpsc::sync_channel;
@@ -104,6 +478,11 @@ mod tests {
     use paho_mqtt::QOS_1;
 
     use super::*;
+    use crate::data::clock::ManualClock;
+    use crate::data::validate::strategies::{location, measurement};
+    use crate::data::validate::{Validate, MIN_TIMESTAMP};
+    use anyhow::Result;
+    use proptest::prelude::*;
 
     #[test]
     fn test_parse() -> Result<()> {
@@ -129,7 +508,7 @@ mod tests {
 
         assert_eq!(
             error.to_string(),
-            "invalid type: string \"foo\", expected i32 at line 1 column 34"
+            "failed to parse json: invalid type: string \"foo\", expected i32 at line 1 column 34"
         );
 
         Ok(())
@@ -146,7 +525,7 @@ mod tests {
 
         let (tx, rx) = sync_channel(100);
 
-        let mut logger = SensorLogger::new(vec![tx]);
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default());
         let message = Message::new(topic, payload, QOS_1);
         thread::spawn(move || {
             logger.check_message(&message);
@@ -154,9 +533,235 @@ mod tests {
 
         let result = rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
 
-        assert_eq!(result.location, "location");
-        assert_eq!(result.measurement, "temperature");
-        assert_eq!(result.sensor, "BME680");
+        assert_eq!(&*result.location, "location");
+        assert_eq!(&*result.measurement, "temperature");
+        assert_eq!(&*result.sensor, "BME680");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_message_with_buffered_readings() -> Result<()> {
+        let now = chrono::offset::Utc::now().timestamp();
+        let payload = format!(
+            "[{{\"sensor\": \"BME680\", \"time\": {}, \"value\": 19.5}}, \
+            {{\"sensor\": \"BME680\", \"time\": {}, \"value\": 19.75}}, \
+            {{\"sensor\": \"BME680\", \"time\": 1701292592, \"value\": 20.0}}]",
+            now - 5,
+            now
+        );
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default());
+        logger.check_message(&Message::new(
+            "klimalogger/location/temperature",
+            payload,
+            QOS_1,
+        ));
+
+        let first = rx.try_recv()?;
+        assert_eq!(first.time.timestamp(), now - 5);
+        assert_eq!(first.value, 19.5);
+        assert_eq!(rx.try_recv()?.value, 19.75);
+        assert!(rx.try_recv().is_err());
+        let stats = logger.stats();
+        assert_eq!((stats.parsed, stats.forwarded, stats.dropped), (1, 2, 1));
+
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_readings_valid(
+            location in location(),
+            measurement in measurement(),
+            value in -1.0e6f32..1.0e6,
+            // the sensor format has 32 bit timestamps
+            now in MIN_TIMESTAMP..i32::MAX as i64 - 10,
+            offset in -10i64..=10,
+        ) {
+            let (tx, rx) = sync_channel(100);
+            let mut logger = SensorLogger::new(vec![tx], Enrichment::default())
+                .with_clock(Arc::new(ManualClock::at(now)));
+
+            logger.check_message(&Message::new(
+                format!("klimalogger/{}/{}", location, measurement),
+                format!(
+                    "{{\"sensor\": \"BME680\", \"time\": {}, \"value\": {}}}",
+                    now + offset,
+                    value
+                ),
+                QOS_1,
+            ));
+
+            let reading = rx.try_recv().unwrap();
+            prop_assert_eq!(reading.validate(), Ok(()));
+            prop_assert_eq!(&*reading.location, location);
+            prop_assert_eq!(&*reading.measurement, measurement);
+        }
+    }
+
+    #[test]
+    fn test_check_message_with_skewed_clock() -> Result<()> {
+        let clock = ManualClock::at(1701292600);
+        let (tx, rx) = sync_channel(100);
+        let mut logger =
+            SensorLogger::new(vec![tx], Enrichment::default()).with_clock(Arc::new(clock.clone()));
+        let message = Message::new(
+            "klimalogger/location/temperature",
+            "{\"sensor\": \"BME680\", \"time\": 1701292592, \"value\": 19.5}",
+            QOS_1,
+        );
+
+        logger.check_message(&message);
+        assert_eq!(rx.try_recv()?.time.timestamp(), 1701292592);
+
+        clock.advance(3);
+        logger.check_message(&message);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(logger.stats().dropped, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_readings_with_field_pointers() -> Result<()> {
+        let fields = FieldPointers::from_config(Some(&FieldsConfig {
+            time: Some("/meta/ts".to_string()),
+            value: Some("/reading/0".to_string()),
+            sensor: None,
+        }))?;
+        let message = Message::new(
+            "klimalogger/location/temperature",
+            "[{\"meta\": {\"ts\": 1701292592}, \"reading\": [19.5, 0.1], \"sensor\": \"BME680\"}]",
+            QOS_1,
+        );
+
+        let readings = parse_readings(&message, &fields)?;
+
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].timestamp, 1701292592);
+        assert_eq!(readings[0].value, 19.5);
+        assert_eq!(readings[0].sensor, "BME680");
+
+        let message = Message::new(
+            "klimalogger/location/temperature",
+            "{\"time\": 1701292592, \"value\": 19.5, \"sensor\": \"BME680\"}",
+            QOS_1,
+        );
+        assert_eq!(
+            parse_readings(&message, &fields).err().unwrap().to_string(),
+            "failed to parse json: no field at /meta/ts"
+        );
+        assert!(FieldPointers::from_config(Some(&FieldsConfig {
+            time: Some("meta.ts".to_string()),
+            ..FieldsConfig::default()
+        }))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_message_with_backfill() -> Result<()> {
+        let payload = "[{\"sensor\": \"BME680\", \"time\": 1701292592, \"value\": 19.5, \"backfill\": true}, \
+            {\"sensor\": \"BME680\", \"time\": 1701292652, \"value\": 19.75}]";
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default()).with_timestamp_policy(
+            TIMESTAMP_POLICY.with_config(Some(&TimestampConfig {
+                missing: None,
+                max_offset: None,
+                allow_backfill: Some(true),
+                align: None,
+            })),
+        );
+        logger.check_message(&Message::new(
+            "klimalogger/location/temperature",
+            payload,
+            QOS_1,
+        ));
+
+        assert_eq!(rx.try_recv()?.time.timestamp(), 1701292592);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(logger.stats().dropped, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_message_with_ack() -> Result<()> {
+        let payload = format!(
+            "{{\"sensor\": \"BME680\", \"time\": {}, \"value\": 19.45}}",
+            chrono::offset::Utc::now().timestamp()
+        );
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger =
+            SensorLogger::new(vec![tx], Enrichment::default()).with_ack("klimalogger/ack");
+        logger.check_message(&Message::new("klimalogger/ack/location", "{}", QOS_1));
+        logger.check_message(&Message::new(
+            "klimalogger/location/temperature",
+            payload,
+            QOS_1,
+        ));
+
+        assert!(rx.try_recv()?.ack.is_some());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(logger.stats().received, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_message_ignores_trailing_levels() -> Result<()> {
+        let payload = format!(
+            "{{\"sensor\": \"BME680\", \"time\": {}, \"value\": 19.45}}",
+            chrono::offset::Utc::now().timestamp()
+        );
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default());
+        logger.check_message(&Message::new(
+            "klimalogger/kitchen/humidity/BME680",
+            payload,
+            QOS_1,
+        ));
+
+        let result = rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(&*result.location, "kitchen");
+        assert_eq!(&*result.measurement, "humidity");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_message_with_topic_schema() -> Result<()> {
+        let payload = format!(
+            "{{\"sensor\": \"BME680\", \"time\": {}, \"value\": 19.45}}",
+            chrono::offset::Utc::now().timestamp()
+        );
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default()).with_topic_schema(
+            topic::parse("home/{location}/climate/{measurement}", TOPIC_VARIABLES)?,
+        );
+        logger.check_message(&Message::new(
+            "home/kitchen/climate/humidity",
+            payload.clone(),
+            QOS_1,
+        ));
+        logger.check_message(&Message::new(
+            "klimalogger/kitchen/humidity",
+            payload,
+            QOS_1,
+        ));
+
+        let result = rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(&*result.location, "kitchen");
+        assert_eq!(&*result.measurement, "humidity");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(logger.stats().dropped, 1);
 
         Ok(())
     }
@@ -168,58 +773,20 @@ mod tests {
 
         let (tx, rx) = sync_channel(100);
 
-        let mut logger = SensorLogger::new(vec![tx]);
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default());
         let message = Message::new(topic, payload, QOS_1);
-        thread::spawn(move || {
+        let handle = thread::spawn(move || {
             logger.check_message(&message);
+            logger.stats()
         });
 
         let result = rx.recv_timeout(std::time::Duration::from_secs(1));
 
         assert!(result.is_err());
+        let stats = handle.join().unwrap();
+        assert_eq!(stats.received, 1);
+        assert_eq!(stats.forwarded, 0);
 
         Ok(())
     }
 }
-
-pub fn create_logger(targets: Vec<Target>) -> (Arc<Mutex<dyn CheckMessage>>, Vec<JoinHandle<()>>) {
-    let mut txs: Vec<SyncSender<SensorReading>> = Vec::new();
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-
-    for target in targets {
-        let (tx, handle) = match target {
-            Target::InfluxDB {
-                url,
-                database,
-                user,
-                password,
-            } => {
-                fn mapper(result: SensorReading) -> WriteQuery {
-                    let timestamp = Timestamp::Seconds(result.time.timestamp() as u128);
-                    WriteQuery::new(timestamp, result.measurement.to_string())
-                        .add_tag("location", result.location.to_string())
-                        .add_tag("sensor", result.sensor.to_string())
-                        .add_field("value", result.value)
-                }
-
-                influx::spawn_influxdb_writer(
-                    InfluxConfig::new(url, database, user, password),
-                    mapper,
-                )
-            }
-            Target::Postgresql {
-                host,
-                port,
-                user,
-                password,
-                database,
-            } => target::postgres::spawn_postgres_writer(PostgresConfig::new(
-                host, port, user, password, database,
-            )),
-        };
-        txs.push(tx);
-        handles.push(handle);
-    }
-
-    (Arc::new(Mutex::new(SensorLogger::new(txs))), handles)
-}
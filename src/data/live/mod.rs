@@ -0,0 +1,180 @@
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+
+static LIVE_VALUES: LazyLock<Mutex<Option<LiveValues>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Last values per series, a series is identified by its measurement and tags in line protocol
+/// style, e.g. `power,channel=1,location=loo-fan`.
+struct LiveValues {
+    history: usize,
+    series: BTreeMap<String, VecDeque<(f64, i64)>>,
+}
+
+impl LiveValues {
+    fn new(history: usize) -> Self {
+        LiveValues {
+            history: history.max(1),
+            series: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, measurement: &str, tags: &[(&str, &str)], value: f64, timestamp: i64) {
+        let values = self
+            .series
+            .entry(series_key(measurement, tags))
+            .or_default();
+        if values.len() == self.history {
+            values.pop_front();
+        }
+        values.push_back((value, timestamp));
+    }
+
+    fn search(&self) -> Vec<String> {
+        self.series.keys().cloned().collect()
+    }
+
+    fn query(&self, target: &str, from: i64, to: i64) -> Vec<TimeSeries> {
+        self.series
+            .iter()
+            .filter(|(key, _)| matches(key, target))
+            .map(|(key, values)| TimeSeries {
+                target: key.clone(),
+                datapoints: values
+                    .iter()
+                    .filter(|(_, timestamp)| (from..=to).contains(timestamp))
+                    .map(|(value, timestamp)| (*value, timestamp * 1000))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+fn series_key(measurement: &str, tags: &[(&str, &str)]) -> String {
+    let mut tags = tags.to_vec();
+    tags.sort();
+    tags.iter()
+        .fold(measurement.to_string(), |key, (tag, value)| {
+            format!("{},{}={}", key, tag, value)
+        })
+}
+
+/// A target matches all series with the same measurement having at least the given tags.
+fn matches(key: &str, target: &str) -> bool {
+    let mut key = key.split(',');
+    let mut target = target.split(',');
+    if key.next() != target.next() {
+        return false;
+    }
+    let tags: Vec<&str> = key.collect();
+    target.all(|tag| tags.contains(&tag))
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TimeSeries {
+    target: String,
+    datapoints: Vec<(f64, i64)>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRange {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryTarget {
+    target: Option<String>,
+}
+
+/// Request body of the Grafana JSON datasource `/query` endpoint.
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    range: Option<QueryRange>,
+    targets: Vec<QueryTarget>,
+}
+
+/// Keeps the last `history` values of every series reported via [`record`].
+pub fn enable(history: usize) {
+    *LIVE_VALUES.lock().unwrap() = Some(LiveValues::new(history));
+}
+
+pub fn record(measurement: &str, tags: &[(&str, &str)], value: f64, timestamp: i64) {
+    if let Some(live_values) = LIVE_VALUES.lock().unwrap().as_mut() {
+        live_values.record(measurement, tags, value, timestamp);
+    }
+}
+
+pub fn search() -> Vec<String> {
+    LIVE_VALUES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(LiveValues::search)
+        .unwrap_or_default()
+}
+
+pub fn query(request: &QueryRequest) -> Vec<TimeSeries> {
+    let parse = |time: &str| DateTime::parse_from_rfc3339(time).map(|time| time.timestamp());
+    let (from, to) = match &request.range {
+        Some(range) => (
+            parse(&range.from).unwrap_or(i64::MIN),
+            parse(&range.to).unwrap_or(i64::MAX),
+        ),
+        None => (i64::MIN, i64::MAX),
+    };
+
+    let live_values = LIVE_VALUES.lock().unwrap();
+    let Some(live_values) = live_values.as_ref() else {
+        return Vec::new();
+    };
+    request
+        .targets
+        .iter()
+        .filter_map(|target| target.target.as_deref())
+        .flat_map(|target| live_values.query(target, from, to))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_keeps_history() {
+        let mut live_values = LiveValues::new(2);
+
+        for (value, timestamp) in [(1.0, 10), (2.0, 20), (3.0, 30)] {
+            live_values.record(
+                "power",
+                &[("location", "loo-fan"), ("channel", "1")],
+                value,
+                timestamp,
+            );
+        }
+
+        assert_eq!(
+            live_values.search(),
+            vec!["power,channel=1,location=loo-fan"]
+        );
+        assert_eq!(
+            live_values.query("power", 0, 100),
+            vec![TimeSeries {
+                target: "power,channel=1,location=loo-fan".to_string(),
+                datapoints: vec![(2.0, 20000), (3.0, 30000)],
+            }]
+        );
+        assert_eq!(live_values.query("power", 25, 100)[0].datapoints.len(), 1);
+    }
+
+    #[test]
+    fn test_matches() {
+        let key = "power,channel=1,location=loo-fan";
+
+        assert!(matches(key, "power"));
+        assert!(matches(key, "power,location=loo-fan"));
+        assert!(!matches(key, "power,location=kitchen"));
+        assert!(!matches(key, "voltage"));
+    }
+}
@@ -1,25 +1,558 @@
+use crate::config::StaticEventConfig;
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+use crate::data::clock::Clock;
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+use crate::data::enrichment::Enrichment;
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+use crate::data::query::{Timestamp, WriteQuery};
+use chrono::{DateTime, Utc};
+use log::info;
 use paho_mqtt::Message;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
+pub(crate) mod age;
+pub(crate) mod catalog;
+pub(crate) mod clock;
+pub(crate) mod deadletter;
 pub(crate) mod debug;
+pub(crate) mod dedup;
+pub(crate) mod devices;
+pub(crate) mod encryption;
+pub(crate) mod enrichment;
+pub(crate) mod envelope;
+#[cfg(feature = "klimalogger")]
+pub(crate) mod intern;
+#[cfg(feature = "klimalogger")]
 pub(crate) mod klimalogger;
+pub(crate) mod live;
+#[cfg(feature = "opendtu")]
 pub(crate) mod opendtu;
+#[cfg(feature = "openmqttgateway")]
 pub(crate) mod openmqttgateway;
+pub(crate) mod query;
+pub(crate) mod redact;
+#[cfg(feature = "senml")]
+pub(crate) mod senml;
+#[cfg(feature = "shelly")]
 pub(crate) mod shelly;
+#[cfg(any(
+    feature = "shelly",
+    feature = "openmqttgateway",
+    feature = "klimalogger"
+))]
+pub(crate) mod timestamp;
+#[cfg(any(feature = "shelly", feature = "klimalogger"))]
+pub(crate) mod topic;
+pub(crate) mod validate;
+#[cfg(all(
+    test,
+    any(
+        feature = "shelly",
+        feature = "opendtu",
+        feature = "openmqttgateway",
+        feature = "klimalogger"
+    )
+))]
+mod vectors;
+#[cfg(feature = "zwave")]
+pub(crate) mod zwave;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct LogEvent {
-    host: String,
-    location: String,
+    pub(crate) host: String,
+    pub(crate) location: String,
     #[serde(rename = "type")]
-    measurement_type: String,
-    unit: String,
-    sensor: String,
-    calculated: bool,
-    time: String,
-    value: f64,
+    pub(crate) measurement_type: String,
+    pub(crate) unit: String,
+    pub(crate) sensor: String,
+    pub(crate) calculated: bool,
+    /// RFC 3339 time like `2024-01-01T00:00:00Z`.
+    pub(crate) time: String,
+    pub(crate) value: EventValue,
 }
 
-pub trait CheckMessage {
+/// Value of a `LogEvent`, only numbers up to envelope version 1.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EventValue {
+    Float(f64),
+    Boolean(bool),
+    Text(String),
+}
+
+/// Message counters of a source: `received` messages, `parsed` events, `dropped` messages which
+/// could not be parsed or were discarded and events `forwarded` to the targets.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct SourceStats {
+    pub(crate) received: u64,
+    pub(crate) parsed: u64,
+    pub(crate) dropped: u64,
+    pub(crate) forwarded: u64,
+}
+
+impl fmt::Display for SourceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "received: {}, parsed: {}, dropped: {}, forwarded: {}",
+            self.received, self.parsed, self.dropped, self.forwarded
+        )
+    }
+}
+
+/// A source logger together with the join handles of its target writers.
+pub type Logger = (Arc<Mutex<dyn CheckMessage>>, Vec<JoinHandle<()>>);
+
+pub trait CheckMessage: Send {
     fn check_message(&mut self, msg: &Message);
+
+    fn stats(&self) -> SourceStats;
+
+    /// Closes the senders to all targets, which lets their writer threads exit.
+    fn shutdown(&mut self);
+
+    /// Sends a `gateway_heartbeat` event with the number of messages received by the source since
+    /// the last heartbeat to the targets. Does nothing by default.
+    fn heartbeat(&mut self, _source: &str, _messages: u64) {}
+
+    /// Per device statistics of the fields reported, logged together with the stats if present.
+    fn field_stats(&self) -> Option<String> {
+        None
+    }
+
+    /// Sends a `gateway_start` event describing the deployed gateway to the targets. Does nothing
+    /// by default.
+    fn started(&mut self, _source: &str, _build: &BuildInfo) {}
+
+    /// Sends an event of the configuration scheduled at the given time to the targets. Does
+    /// nothing by default.
+    fn inject(&mut self, _source: &str, _event: &StaticEventConfig, _time: DateTime<Utc>) {}
+}
+
+pub const HEARTBEAT_MEASUREMENT: &str = "gateway_heartbeat";
+pub const START_MEASUREMENT: &str = "gateway_start";
+pub const ONLINE_MEASUREMENT: &str = "device_online";
+
+/// Version, commit and configuration of the running gateway.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildInfo {
+    pub(crate) version: &'static str,
+    pub(crate) git_hash: &'static str,
+    pub(crate) config_hash: Option<String>,
+    /// Topic prefixes of the enabled sources, comma separated.
+    pub(crate) sources: String,
+}
+
+impl BuildInfo {
+    pub fn new(config_hash: Option<String>, sources: Vec<String>) -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("GIT_HASH").unwrap_or("unknown"),
+            config_hash,
+            sources: sources.join(","),
+        }
+    }
+
+    /// The build information as tags.
+    pub fn tags(&self) -> Vec<(String, String)> {
+        [
+            ("version", Some(self.version)),
+            ("git_hash", Some(self.git_hash)),
+            ("config_hash", self.config_hash.as_deref()),
+            ("sources", Some(self.sources.as_str())),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?.to_string())))
+        .collect()
+    }
+}
+
+/// Heartbeat event of a source tagged with the static tags of the enrichment.
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+pub fn heartbeat_query(
+    source: &str,
+    messages: u64,
+    enrichment: &Enrichment,
+    clock: &dyn Clock,
+) -> WriteQuery {
+    let now = clock.timestamp();
+    let query = WriteQuery::new(Timestamp::Seconds(now as u128), HEARTBEAT_MEASUREMENT)
+        .add_tag("source", source)
+        .add_field("messages", messages);
+    enrichment.apply(query, now, source)
+}
+
+/// Event with the ages of the messages of a source since the last one, if enabled for the source.
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+pub fn message_age_query(
+    source: &str,
+    enrichment: &Enrichment,
+    clock: &dyn Clock,
+) -> Option<WriteQuery> {
+    let window = age::take_window(source)?;
+    let now = clock.timestamp();
+    let query = WriteQuery::new(Timestamp::Seconds(now as u128), age::MEASUREMENT)
+        .add_tag("source", source)
+        .add_field("count", window.count)
+        .add_field("min", window.min)
+        .add_field("max", window.max)
+        .add_field("mean", window.mean);
+    Some(enrichment.apply(query, now, source))
+}
+
+/// Events with 1 for each device of a source seen within its timeout and 0 otherwise, if enabled
+/// for the source.
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+pub fn online_queries(source: &str, enrichment: &Enrichment, clock: &dyn Clock) -> Vec<WriteQuery> {
+    let now = clock.timestamp();
+    devices::online(source, now)
+        .into_iter()
+        .map(|(device, online)| {
+            let query = WriteQuery::new(Timestamp::Seconds(now as u128), ONLINE_MEASUREMENT)
+                .add_tag("source", source)
+                .add_tag("device", device)
+                .add_field("value", online as i32);
+            enrichment.apply(query, now, source)
+        })
+        .collect()
+}
+
+/// Start event of a source tagged with the build information and the static tags of the
+/// enrichment.
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+pub fn start_query(
+    source: &str,
+    build: &BuildInfo,
+    enrichment: &Enrichment,
+    clock: &dyn Clock,
+) -> WriteQuery {
+    let now = clock.timestamp();
+    let query = WriteQuery::new(Timestamp::Seconds(now as u128), START_MEASUREMENT)
+        .add_tag("source", source)
+        .add_field("value", 1);
+    let query = build
+        .tags()
+        .into_iter()
+        .fold(query, |query, (key, value)| query.add_tag(key, value));
+    enrichment.apply(query, now, source)
+}
+
+/// Scheduled event of the configuration tagged with its tags and the static tags of the
+/// enrichment.
+#[cfg(any(
+    feature = "shelly",
+    feature = "opendtu",
+    feature = "openmqttgateway",
+    feature = "zwave",
+    feature = "senml"
+))]
+pub fn static_query(
+    source: &str,
+    event: &StaticEventConfig,
+    time: DateTime<Utc>,
+    enrichment: &Enrichment,
+) -> WriteQuery {
+    let query = WriteQuery::new(
+        Timestamp::Seconds(time.timestamp() as u128),
+        event.measurement.as_str(),
+    )
+    .add_tag("source", source)
+    .add_field("value", event.value);
+    let mut tags: Vec<_> = event.tags.iter().flatten().collect();
+    tags.sort();
+    let query = tags.into_iter().fold(query, |query, (key, value)| {
+        query.add_tag(key, value.as_str())
+    });
+    enrichment.apply(query, time.timestamp(), source)
+}
+
+/// Source loggers by topic prefix together with the writer threads they send to.
+#[derive(Default)]
+pub struct Sources {
+    loggers: HashMap<String, Arc<Mutex<dyn CheckMessage>>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Sources {
+    pub fn insert(&mut self, prefix: String, logger: Logger) {
+        let (logger, mut handles) = logger;
+        self.loggers.insert(prefix, logger);
+        self.handles.append(&mut handles);
+    }
+
+    pub fn get(&self, prefix: &str) -> Option<&Arc<Mutex<dyn CheckMessage>>> {
+        self.loggers.get(prefix)
+    }
+
+    pub fn prefixes(&self) -> impl Iterator<Item = &String> {
+        self.loggers.keys()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loggers.is_empty()
+    }
+
+    /// The loggers by topic prefix, e.g. to report on them from another thread.
+    pub fn loggers(&self) -> Vec<(String, Arc<Mutex<dyn CheckMessage>>)> {
+        self.loggers
+            .iter()
+            .map(|(prefix, logger)| (prefix.clone(), logger.clone()))
+            .collect()
+    }
+
+    pub fn log_stats(&self) {
+        for (prefix, logger) in &self.loggers {
+            let logger = logger.lock().unwrap();
+            info!("source {}: {}", prefix, logger.stats());
+            if let Some(field_stats) = logger.field_stats() {
+                info!("source {} missing fields: {}", prefix, field_stats);
+            }
+        }
+    }
+
+    /// Sends the start event of every source.
+    pub fn started(&self, build: &BuildInfo) {
+        for (prefix, logger) in &self.loggers {
+            logger.lock().unwrap().started(prefix, build);
+        }
+    }
+
+    /// Triggers the heartbeat of every source in the given interval.
+    pub fn spawn_heartbeat(&self, interval: Duration) -> JoinHandle<()> {
+        let loggers = self.loggers();
+        thread::spawn(move || {
+            let mut last_received: HashMap<String, u64> = HashMap::new();
+            loop {
+                thread::sleep(interval);
+                for (prefix, logger) in &loggers {
+                    let mut logger = logger.lock().unwrap();
+                    let received = logger.stats().received;
+                    let last = last_received.insert(prefix.clone(), received).unwrap_or(0);
+                    logger.heartbeat(prefix, received.saturating_sub(last));
+                }
+            }
+        })
+    }
+
+    /// Shuts down all loggers and waits for their target writer threads to exit.
+    pub fn shutdown(self) {
+        for logger in self.loggers.values() {
+            logger.lock().unwrap().shutdown();
+        }
+        for handle in self.handles {
+            handle.join().expect("failed to join target writer thread");
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "shelly",
+        feature = "opendtu",
+        feature = "openmqttgateway",
+        feature = "zwave",
+        feature = "senml"
+    )
+))]
+mod tests {
+    use super::*;
+    use crate::data::clock::{ManualClock, SystemClock};
+    use crate::data::enrichment::Enrichment;
+    #[cfg(feature = "opendtu")]
+    use crate::data::opendtu::OpenDTULogger;
+    use crate::data::query::Query;
+    #[cfg(feature = "opendtu")]
+    use std::sync::mpsc::sync_channel;
+
+    #[cfg(all(
+        feature = "shelly",
+        feature = "opendtu",
+        feature = "openmqttgateway",
+        feature = "klimalogger"
+    ))]
+    fn writer<T: Send + 'static>(rx: std::sync::mpsc::Receiver<T>) -> JoinHandle<()> {
+        thread::spawn(move || while rx.recv().is_ok() {})
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "shelly",
+        feature = "opendtu",
+        feature = "openmqttgateway",
+        feature = "klimalogger"
+    ))]
+    fn test_shutdown_stops_all_writers() {
+        use crate::data::klimalogger::SensorLogger;
+        use crate::data::openmqttgateway::OpenMqttGatewayLogger;
+        use crate::data::shelly::ShellyLogger;
+
+        let mut sources = Sources::default();
+
+        let (tx, rx) = sync_channel(100);
+        let logger = SensorLogger::new(vec![tx], Enrichment::default());
+        sources.insert(
+            "sensors".to_string(),
+            (Arc::new(Mutex::new(logger)), vec![writer(rx)]),
+        );
+
+        let (tx, rx) = sync_channel(100);
+        let logger = ShellyLogger::new(vec![tx], Enrichment::default());
+        sources.insert(
+            "shellies".to_string(),
+            (Arc::new(Mutex::new(logger)), vec![writer(rx)]),
+        );
+
+        let (tx, rx) = sync_channel(100);
+        let logger = OpenDTULogger::new(vec![tx], Enrichment::default());
+        sources.insert(
+            "solar".to_string(),
+            (Arc::new(Mutex::new(logger)), vec![writer(rx)]),
+        );
+
+        let (tx, rx) = sync_channel(100);
+        let logger = OpenMqttGatewayLogger::new(vec![tx], Enrichment::default());
+        sources.insert(
+            "blegateway".to_string(),
+            (Arc::new(Mutex::new(logger)), vec![writer(rx)]),
+        );
+
+        sources.shutdown();
+    }
+
+    #[test]
+    #[cfg(feature = "opendtu")]
+    fn test_heartbeat() -> anyhow::Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut sources = Sources::default();
+        let logger = OpenDTULogger::new(vec![tx], Enrichment::default());
+        sources.insert("solar".to_string(), (Arc::new(Mutex::new(logger)), vec![]));
+
+        sources.spawn_heartbeat(Duration::from_millis(10));
+
+        let query: WriteQuery = rx.recv_timeout(Duration::from_secs(1))?;
+        assert!(query
+            .build()?
+            .get()
+            .starts_with("gateway_heartbeat,source=solar messages=0i "));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "opendtu")]
+    fn test_started() -> anyhow::Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut sources = Sources::default();
+        let logger = OpenDTULogger::new(vec![tx], Enrichment::default());
+        sources.insert("solar".to_string(), (Arc::new(Mutex::new(logger)), vec![]));
+
+        let build = BuildInfo::new(Some("0123456789ab".to_string()), vec!["solar".to_string()]);
+        sources.started(&build);
+
+        let query: WriteQuery = rx.recv_timeout(Duration::from_secs(1))?;
+        let line = query.build()?.get();
+        assert!(line.starts_with(&format!(
+            "gateway_start,source=solar,version={},git_hash=",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(line.contains(",config_hash=0123456789ab,sources=solar value=1i "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_heartbeat_query_stamped_by_clock() -> anyhow::Result<()> {
+        let clock = ManualClock::at(1701292592);
+
+        let query = heartbeat_query("solar", 3, &Enrichment::default(), &clock);
+
+        assert_eq!(
+            query.build()?.get(),
+            "gateway_heartbeat,source=solar messages=3i 1701292592"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_online_queries() -> anyhow::Result<()> {
+        let clock = SystemClock;
+        assert!(online_queries("online", &Enrichment::default(), &clock).is_empty());
+
+        devices::enable_online("online", Duration::from_secs(60));
+        age::with_source("online", || devices::record("shelly", "pantry", "power"));
+
+        let queries = online_queries("online", &Enrichment::default(), &clock);
+        assert_eq!(queries.len(), 1);
+        assert!(queries[0]
+            .build()?
+            .get()
+            .starts_with("device_online,source=online,device=pantry value=1i "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_query() -> anyhow::Result<()> {
+        let event = StaticEventConfig {
+            schedule: "0 0 * * *".to_string(),
+            measurement: "tariff_rate".to_string(),
+            value: 0.32,
+            tags: Some(HashMap::from([("tariff".to_string(), "day".to_string())])),
+        };
+        let time = DateTime::from_timestamp(1704067200, 0).unwrap();
+
+        let query = static_query("shellies", &event, time, &Enrichment::default());
+
+        assert_eq!(
+            query.build()?.get(),
+            "tariff_rate,source=shellies,tariff=day value=0.32 1704067200"
+        );
+        Ok(())
+    }
 }
@@ -1,15 +1,24 @@
 use std::sync::mpsc::SyncSender;
 
-use crate::config::Target;
-use crate::data::CheckMessage;
-use crate::target::influx;
-use crate::target::influx::InfluxConfig;
-use anyhow::Result;
-use chrono::Datelike;
-use influxdb::Timestamp::Seconds;
-use influxdb::WriteQuery;
+use crate::config::{StaticEventConfig, Target};
+use crate::data::catalog::Measurement;
+use crate::data::clock::{self, Clock};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::{CalendarTag, Enrichment};
+use crate::data::query::Timestamp::Seconds;
+use crate::data::query::WriteQuery;
+use crate::data::{deadletter, devices, heartbeat_query, live, start_query, BuildInfo};
+use crate::data::{message_age_query, online_queries, static_query};
+use crate::data::{validate, CheckMessage, Logger, SourceStats};
+use crate::error::{GatewayError, Result};
+use crate::target;
+use crate::target::supervisor;
+use crate::target::Mappers;
+use chrono::{DateTime, Utc};
 use log::{debug, trace};
 use paho_mqtt::Message;
+use std::collections::HashMap;
+use std::iter;
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 
@@ -22,45 +31,165 @@ struct Data {
     value: f64,
 }
 
+/// Inverter status topics recorded as boolean measurements.
+const STATUS_FIELDS: &[&str] = &["reachable", "producing"];
+
+/// Maximum time between matching AC and DC power samples of an inverter in seconds.
+const EFFICIENCY_WINDOW: i64 = 30;
+
+/// Value and timestamp of a power sample.
+type Sample = Option<(f64, i64)>;
+
+/// Derives the `efficiency` of each inverter from its last `powerac` and `powerdc` samples.
+#[derive(Default)]
+struct EfficiencyTracker {
+    samples: HashMap<String, (Sample, Sample)>,
+}
+
+impl EfficiencyTracker {
+    /// Records a power sample, returns the efficiency once both samples of a pair are present.
+    fn update(&mut self, device: &str, field: &str, value: f64, timestamp: i64) -> Option<f64> {
+        let (ac, dc) = self.samples.entry(device.to_string()).or_default();
+        match field {
+            "powerac" => *ac = Some((value, timestamp)),
+            "powerdc" => *dc = Some((value, timestamp)),
+            _ => return None,
+        }
+        let (Some((ac_value, ac_timestamp)), Some((dc_value, dc_timestamp))) = (*ac, *dc) else {
+            return None;
+        };
+        if (ac_timestamp - dc_timestamp).abs() > EFFICIENCY_WINDOW {
+            return None;
+        }
+        (*ac, *dc) = (None, None);
+        (dc_value > 0.0).then(|| ac_value / dc_value)
+    }
+}
+
 pub struct OpenDTULogger {
     txs: Vec<SyncSender<WriteQuery>>,
     parser: OpenDTUParser,
+    enrichment: Enrichment,
+    efficiency: EfficiencyTracker,
+    clock: Arc<dyn Clock>,
+    stats: SourceStats,
 }
 
 impl OpenDTULogger {
-    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>) -> Self {
+    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>, enrichment: Enrichment) -> Self {
         OpenDTULogger {
             txs,
             parser: OpenDTUParser::new(),
+            enrichment,
+            efficiency: EfficiencyTracker::default(),
+            clock: clock::system(),
+            stats: SourceStats::default(),
+        }
+    }
+
+    fn send_efficiency(&self, device: &str, efficiency: f64, timestamp: i64) {
+        let write_query = WriteQuery::new(Seconds(timestamp as u128), "efficiency")
+            .add_tag("device", device)
+            .add_tag("component", "inverter")
+            .add_field("value", self.enrichment.round("efficiency", efficiency));
+        let write_query = self.enrichment.apply(write_query, timestamp, device);
+        if !validate::accept("OpenDTU", &write_query) {
+            return;
         }
+        target::send_all(&self.txs, &write_query);
     }
 }
 
 impl CheckMessage for OpenDTULogger {
     fn check_message(&mut self, msg: &Message) {
-        let result1 = self.parser.parse(msg).unwrap();
+        self.stats.received += 1;
+        let result1 = match self.parser.parse(msg) {
+            Ok(result) => result,
+            Err(error) => {
+                warn_deduplicated("OpenDTU parse error", &error.to_string());
+                deadletter::record(msg, &error.to_string());
+                self.stats.dropped += 1;
+                return;
+            }
+        };
         if let Some(data) = result1 {
-            let timestamp = chrono::DateTime::from_timestamp(data.timestamp, 0)
-                .expect("failed to convert timestamp");
-            let month_string = format!("{:04}-{:02}", timestamp.year(), timestamp.month());
-            let mut write_query = WriteQuery::new(Seconds(data.timestamp as u128), data.field)
-                .add_tag("device", data.device)
-                .add_tag("component", data.component)
-                .add_field("value", data.value)
-                .add_tag("month", timestamp.month())
-                .add_tag("year", timestamp.year())
-                .add_tag("year_month", month_string);
+            self.stats.parsed += 1;
+            let efficiency = if data.component == "inverter" {
+                self.efficiency
+                    .update(&data.device, &data.field, data.value, data.timestamp)
+            } else {
+                None
+            };
+            let value = self.enrichment.round(&data.field, data.value);
+            devices::record("opendtu", &data.device, &data.field);
+            live::record(
+                &data.field,
+                &[("device", &data.device), ("component", &data.component)],
+                value,
+                data.timestamp,
+            );
+            let write_query = WriteQuery::new(Seconds(data.timestamp as u128), data.field)
+                .add_tag("device", data.device.clone());
+            let mut write_query = if data.component == "status" {
+                write_query.add_field("value", value != 0.0)
+            } else {
+                write_query.add_field("value", value)
+            }
+            .add_tag("component", data.component);
+            write_query = self
+                .enrichment
+                .apply(write_query, data.timestamp, &data.device);
 
             write_query = if let Some(string) = data.string {
                 write_query.add_tag("string", string)
             } else {
                 write_query
             };
-            for tx in &self.txs {
-                tx.send(write_query.clone()).expect("failed to send");
+            if !validate::accept("OpenDTU", &write_query) {
+                self.stats.dropped += 1;
+                return;
+            }
+            target::send_all(&self.txs, &write_query);
+            if let Some(efficiency) = efficiency {
+                self.send_efficiency(&data.device, efficiency, data.timestamp);
             }
+            self.stats.forwarded += 1;
         }
     }
+
+    fn stats(&self) -> SourceStats {
+        self.stats
+    }
+
+    fn shutdown(&mut self) {
+        self.txs.clear();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        let clock = self.clock.as_ref();
+        let queries = iter::once(heartbeat_query(source, messages, &self.enrichment, clock))
+            .chain(message_age_query(source, &self.enrichment, clock))
+            .chain(online_queries(source, &self.enrichment, clock));
+        for query in queries {
+            target::send_all(&self.txs, &query);
+        }
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        let query = start_query(source, build, &self.enrichment, self.clock.as_ref());
+        target::send_all(&self.txs, &query);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        let query = static_query(source, event, time, &self.enrichment);
+        target::send_all(&self.txs, &query);
+    }
+}
+
+fn parse_value(msg: &Message) -> Result<f64> {
+    msg.payload_str()
+        .parse()
+        .map_err(|error| GatewayError::parse(msg.topic().to_string(), error))
 }
 
 struct OpenDTUParser {
@@ -96,7 +225,7 @@ impl OpenDTUParser {
                                 device: String::from(section),
                                 component: String::from("inverter"),
                                 field: String::from(field),
-                                value: msg.payload_str().parse().unwrap(),
+                                value: parse_value(msg)?,
                                 string: None,
                             });
                         }
@@ -107,7 +236,26 @@ impl OpenDTUParser {
                     }
                     "status" => {
                         if field == "last_update" {
-                            self.timestamp = Some(msg.payload_str().parse::<i64>()?);
+                            self.timestamp =
+                                Some(msg.payload_str().parse::<i64>().map_err(|error| {
+                                    GatewayError::parse(msg.topic().to_string(), error)
+                                })?);
+                        } else if STATUS_FIELDS.contains(&field) {
+                            // availability changes are recorded when they are published
+                            debug!(
+                                "OpenDTU {} status: {:}: {:?}",
+                                section,
+                                field,
+                                msg.payload_str()
+                            );
+                            data = Some(Data {
+                                timestamp: chrono::offset::Utc::now().timestamp(),
+                                device: String::from(section),
+                                component: String::from("status"),
+                                field: String::from(field),
+                                value: parse_value(msg)?,
+                                string: None,
+                            });
                         } else {
                             // ignore other status data
                             trace!("  status: {:}: {:?}", field, msg.payload_str());
@@ -115,7 +263,7 @@ impl OpenDTUParser {
                     }
                     _ => {
                         let payload = msg.payload_str();
-                        if payload.len() > 0 {
+                        if !payload.is_empty() {
                             if let Some(timestamp) = self.timestamp {
                                 debug!(
                                     "OpenDTU {} string {:}: {:}: {:?}",
@@ -127,7 +275,7 @@ impl OpenDTUParser {
                                     component: String::from("string"),
                                     string: Some(String::from(element)),
                                     field: String::from(field),
-                                    value: payload.parse().unwrap(),
+                                    value: parse_value(msg)?,
                                 });
                             }
                         }
@@ -143,12 +291,78 @@ impl OpenDTUParser {
     }
 }
 
+pub fn enrichment(enrichment: Enrichment) -> Enrichment {
+    enrichment.with_default_calendar(&[
+        CalendarTag::Month,
+        CalendarTag::Year,
+        CalendarTag::YearMonth,
+    ])
+}
+
+pub fn catalog() -> Vec<Measurement> {
+    let mut measurements = vec![Measurement::new(
+        "<field>",
+        &["value"],
+        &["device", "component", "string"],
+        None,
+    )];
+    for field in STATUS_FIELDS {
+        measurements.push(Measurement::new(
+            field,
+            &["value"],
+            &["device", "component"],
+            Some("bool"),
+        ));
+    }
+    measurements.push(Measurement::new(
+        "efficiency",
+        &["value"],
+        &["device", "component"],
+        None,
+    ));
+    measurements
+}
+
+pub fn create_logger(targets: Vec<Target>, enrichment: Enrichment) -> Result<Logger> {
+    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for target in targets {
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            target::spawn_writer(target.clone(), &Mappers::queries("opendtu"))
+        })?;
+        txs.push(tx);
+        handles.push(handle);
+    }
+
+    let logger = OpenDTULogger::new(txs, self::enrichment(enrichment));
+
+    Ok((Arc::new(Mutex::new(logger)), handles))
+}
+
 #[cfg(test)]
 mod tests {
     use paho_mqtt::QOS_1;
 
     use super::*;
 
+    #[test]
+    fn test_parse_invalid_value() -> Result<()> {
+        let mut parser = OpenDTUParser::new();
+        let message = Message::new("solar/114190641177/status/last_update", "1701271852", QOS_1);
+        let _ = parser.parse(&message)?;
+
+        let message_2 = Message::new("solar/114190641177/0/powerdc", "foo", QOS_1);
+        let error = parser.parse(&message_2).err().unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "failed to parse solar/114190641177/0/powerdc: invalid float literal"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_timestamp_returns_none() -> Result<()> {
         let mut parser = OpenDTUParser::new();
@@ -196,32 +410,53 @@ mod tests {
 
         Ok(())
     }
-}
 
-pub fn create_logger(targets: Vec<Target>) -> (Arc<Mutex<dyn CheckMessage>>, Vec<JoinHandle<()>>) {
-    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+    #[test]
+    fn test_parse_status() -> Result<()> {
+        let mut parser = OpenDTUParser::new();
 
-    for target in targets {
-        let (tx, handle) = match target {
-            Target::InfluxDB {
-                url,
-                database,
-                user,
-                password,
-            } => influx::spawn_influxdb_writer(
-                InfluxConfig::new(url, database, user, password),
-                std::convert::identity,
-            ),
-            Target::Postgresql { .. } => {
-                panic!("Postgresql not supported for opendtu");
-            }
-        };
-        txs.push(tx);
-        handles.push(handle);
+        let message = Message::new("solar/114190641177/status/reachable", "1", QOS_1);
+        let result = parser.parse(&message)?.unwrap();
+
+        assert_eq!(result.field, "reachable");
+        assert_eq!(result.device, "114190641177");
+        assert_eq!(result.component, "status");
+        assert_eq!(result.value, 1.0);
+
+        let message = Message::new("solar/114190641177/status/limit_relative", "100", QOS_1);
+        assert!(parser.parse(&message)?.is_none());
+
+        Ok(())
     }
 
-    let logger = OpenDTULogger::new(txs);
+    #[test]
+    fn test_status_is_written_as_boolean() -> anyhow::Result<()> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(100);
+        let mut logger = OpenDTULogger::new(vec![tx], Enrichment::default());
+
+        logger.check_message(&Message::new(
+            "solar/114190641177/status/producing",
+            "0",
+            QOS_1,
+        ));
 
-    (Arc::new(Mutex::new(logger)), handles)
+        let line = crate::data::query::Query::build(&rx.try_recv()?)?.get();
+        assert!(line.starts_with("producing,device=114190641177,component=status value=false "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_efficiency() {
+        let mut tracker = EfficiencyTracker::default();
+
+        assert_eq!(tracker.update("inverter", "powerac", 95.0, 1000), None);
+        assert_eq!(tracker.update("inverter", "voltage", 230.0, 1000), None);
+        assert_eq!(
+            tracker.update("inverter", "powerdc", 100.0, 1000),
+            Some(0.95)
+        );
+        assert_eq!(tracker.update("inverter", "powerdc", 100.0, 1010), None);
+        assert_eq!(tracker.update("inverter", "powerac", 90.0, 1100), None);
+        assert_eq!(tracker.update("inverter", "powerdc", 0.0, 1100), None);
+    }
 }
@@ -1,14 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::iter;
 use std::sync::mpsc::SyncSender;
 
-use crate::config::Target;
-use crate::data::CheckMessage;
-use crate::target::influx;
-use crate::target::influx::InfluxConfig;
-use anyhow::Result;
-use influxdb::Timestamp::Seconds;
-use influxdb::WriteQuery;
-use log::warn;
+use crate::config::{MissingTimestamp, StaticEventConfig, Target, TimestampConfig};
+use crate::data::catalog::Measurement;
+use crate::data::clock::{self, Clock};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::Enrichment;
+use crate::data::query::Timestamp::Seconds;
+use crate::data::query::WriteQuery;
+use crate::data::timestamp::{TimestampPolicy, Timestamped};
+use crate::data::{deadletter, devices, heartbeat_query, live, start_query, BuildInfo};
+use crate::data::{message_age_query, online_queries, static_query};
+use crate::data::{validate, CheckMessage, Logger, SourceStats};
+use crate::error::{GatewayError, Result};
+use crate::target;
+use crate::target::supervisor;
+use crate::target::Mappers;
+use chrono::{DateTime, Utc};
 use paho_mqtt::Message;
 use serde_json::{Map, Number, Value};
 use std::sync::{Arc, Mutex};
@@ -19,44 +28,162 @@ struct Data {
     tags: HashMap<String, String>,
 }
 
+impl Timestamped for Data {
+    fn timestamp(&self) -> Option<i64> {
+        None
+    }
+}
+
+const TIMESTAMP_POLICY: TimestampPolicy = TimestampPolicy::new(MissingTimestamp::Now, None);
+
 pub struct OpenMqttGatewayLogger {
     txs: Vec<SyncSender<WriteQuery>>,
     parser: OpenMqttGatewayParser,
+    enrichment: Enrichment,
+    timestamp_policy: TimestampPolicy,
+    clock: Arc<dyn Clock>,
+    devices: Option<HashSet<String>>,
+    stats: SourceStats,
 }
 
 impl OpenMqttGatewayLogger {
-    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>) -> Self {
+    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>, enrichment: Enrichment) -> Self {
         OpenMqttGatewayLogger {
             txs,
             parser: OpenMqttGatewayParser::new(),
+            enrichment,
+            timestamp_policy: TIMESTAMP_POLICY,
+            clock: clock::system(),
+            devices: None,
+            stats: SourceStats::default(),
+        }
+    }
+
+    pub(crate) fn with_timestamp_policy(self, timestamp_policy: TimestampPolicy) -> Self {
+        OpenMqttGatewayLogger {
+            timestamp_policy,
+            ..self
+        }
+    }
+
+    /// Time events without timestamp are stamped with.
+    #[allow(dead_code)]
+    pub(crate) fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        OpenMqttGatewayLogger { clock, ..self }
+    }
+
+    /// IDs of the devices whose events are written, all devices if unset.
+    pub(crate) fn with_devices(self, devices: Option<Vec<String>>) -> Self {
+        OpenMqttGatewayLogger {
+            devices: devices.map(|devices| devices.into_iter().collect()),
+            ..self
+        }
+    }
+
+    fn is_listed(&self, device: Option<&String>) -> bool {
+        match &self.devices {
+            Some(devices) => device.is_some_and(|device| devices.contains(device)),
+            None => true,
         }
     }
 }
 
 impl CheckMessage for OpenMqttGatewayLogger {
     fn check_message(&mut self, msg: &Message) {
-        let data = self.parser.parse(msg).unwrap();
+        self.stats.received += 1;
+        let data = match self.parser.parse(msg) {
+            Ok(data) => data,
+            Err(error) => {
+                warn_deduplicated(
+                    &format!("OpenMqttGateway parse error on '{}'", msg.topic()),
+                    &error.to_string(),
+                );
+                deadletter::record(msg, &error.to_string());
+                self.stats.dropped += 1;
+                return;
+            }
+        };
         if let Some(data) = data {
-            let timestamp = chrono::offset::Utc::now();
+            self.stats.parsed += 1;
+            if !self.is_listed(data.tags.get("device")) {
+                return;
+            }
+            let timestamp = match self
+                .timestamp_policy
+                .resolve(data.timestamp(), self.clock.as_ref())
+            {
+                Ok(timestamp) => timestamp,
+                Err(error) => {
+                    warn_deduplicated(
+                        &format!("OpenMqttGateway {} on '{}'", error, msg.topic()),
+                        &msg.payload_str(),
+                    );
+                    deadletter::record(msg, &error.to_string());
+                    self.stats.dropped += 1;
+                    return;
+                }
+            };
 
-            let mut write_query = WriteQuery::new(Seconds(timestamp.timestamp() as u128), "btle");
+            let device = data.tags.get("device").cloned().unwrap_or_default();
+            let mut write_query = WriteQuery::new(Seconds(timestamp as u128), "btle");
             for (key, value) in data.fields {
-                write_query = write_query.add_field(key, value.as_f64());
+                // fields without a numeric value are left out
+                let Some(value) = value.as_f64() else {
+                    continue;
+                };
+                let value = self.enrichment.round(&key, value);
+                live::record(&key, &[("device", &device)], value, timestamp);
+                write_query = write_query.add_field(key, value);
             }
             for (key, value) in data.tags {
                 write_query = write_query.add_tag(key, value);
             }
-            for tx in &self.txs {
-                tx.send(write_query.clone()).expect("failed to send");
+            write_query = self.enrichment.apply(write_query, timestamp, &device);
+            if !validate::accept("OpenMqttGateway", &write_query) {
+                self.stats.dropped += 1;
+                return;
             }
+            target::send_all(&self.txs, &write_query);
+            devices::record("openmqttgateway", &device, "btle");
+            self.stats.forwarded += 1;
+        }
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats
+    }
+
+    fn shutdown(&mut self) {
+        self.txs.clear();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        let clock = self.clock.as_ref();
+        let queries = iter::once(heartbeat_query(source, messages, &self.enrichment, clock))
+            .chain(message_age_query(source, &self.enrichment, clock))
+            .chain(online_queries(source, &self.enrichment, clock));
+        for query in queries {
+            target::send_all(&self.txs, &query);
         }
     }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        let query = start_query(source, build, &self.enrichment, self.clock.as_ref());
+        target::send_all(&self.txs, &query);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        let query = static_query(source, event, time, &self.enrichment);
+        target::send_all(&self.txs, &query);
+    }
 }
 
 fn parse_json(payload: &str) -> Result<Map<String, Value>> {
     let parsed: Value = serde_json::from_str(payload)?;
-    let obj: Map<String, Value> = parsed.as_object().unwrap().clone();
-    Ok(obj)
+    match parsed {
+        Value::Object(obj) => Ok(obj),
+        _ => Err(GatewayError::parse("json", "payload is not an object")),
+    }
 }
 
 struct OpenMqttGatewayParser {}
@@ -98,7 +225,10 @@ impl OpenMqttGatewayParser {
                             tags.insert(key, value);
                         }
                         _ => {
-                            warn!("unhandled entry {}: {:?}", key, value);
+                            warn_deduplicated(
+                                &format!("OpenMqttGateway unhandled entry '{}'", key),
+                                &format!("{:?}", value),
+                            );
                         }
                     }
                 }
@@ -108,10 +238,13 @@ impl OpenMqttGatewayParser {
                 } else if !tags.contains_key("type") {
                     tags.insert(String::from("type"),String::from("UNKN"));
                 }
-                if fields.len() > 0 {
+                if !fields.is_empty() {
                     data = Some(Data { fields, tags });
                 } else {
-                    warn!("skip without fields {:?}", tags)
+                    warn_deduplicated(
+                        &format!("OpenMqttGateway skip without fields on '{}'", msg.topic()),
+                        &format!("{:?}", tags),
+                    );
                 }
             }
         }
@@ -119,11 +252,116 @@ impl OpenMqttGatewayParser {
     }
 }
 
+pub fn catalog() -> Vec<Measurement> {
+    vec![Measurement::new(
+        "btle",
+        &["<numeric payload values>"],
+        &["device", "gateway", "type", "<text payload values>"],
+        None,
+    )]
+}
+
+pub fn create_logger(
+    targets: Vec<Target>,
+    enrichment: Enrichment,
+    timestamp: Option<&TimestampConfig>,
+    devices: Option<Vec<String>>,
+) -> Result<Logger> {
+    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for target in targets {
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            target::spawn_writer(target.clone(), &Mappers::queries("openmqttgateway"))
+        })?;
+        txs.push(tx);
+        handles.push(handle);
+    }
+
+    let logger = OpenMqttGatewayLogger::new(txs, enrichment)
+        .with_timestamp_policy(TIMESTAMP_POLICY.with_config(timestamp))
+        .with_devices(devices);
+
+    Ok((Arc::new(Mutex::new(logger)), handles))
+}
+
 #[cfg(test)]
 mod tests {
     use paho_mqtt::QOS_1;
 
     use super::*;
+    use crate::data::clock::ManualClock;
+    use crate::data::query::Query;
+    use crate::data::validate::strategies::finite;
+    use crate::data::validate::Validate;
+    use proptest::prelude::*;
+    use std::sync::mpsc::sync_channel;
+
+    proptest! {
+        #[test]
+        fn test_btle_events_valid(
+            device in "[0-9A-F]{12}",
+            name in r"[^\\\p{Cc}]{1,20}",
+            fields in prop::collection::btree_map(
+                "[a-z_]{1,10}".prop_filter("id is the device", |key| key != "id"),
+                finite(),
+                1..5,
+            ),
+        ) {
+            let (tx, rx) = sync_channel(100);
+            let mut logger = OpenMqttGatewayLogger::new(vec![tx], Enrichment::default());
+            let mut payload = serde_json::Map::new();
+            payload.insert("id".to_string(), Value::String(device.clone()));
+            payload.insert("name".to_string(), Value::String(name));
+            for (key, value) in fields {
+                payload.insert(key, serde_json::json!(value));
+            }
+
+            logger.check_message(&Message::new(
+                format!("blegateway/D12331654712/BTtoMQTT/{}", device),
+                Value::Object(payload).to_string(),
+                QOS_1,
+            ));
+
+            prop_assert_eq!(rx.try_recv().unwrap().validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_check_message_stamped_by_clock() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut logger = OpenMqttGatewayLogger::new(vec![tx], Enrichment::default())
+            .with_clock(Arc::new(ManualClock::at(1701292592)));
+
+        logger.check_message(&Message::new(
+            "blegateway/D12331654712/BTtoMQTT/283146C17616",
+            "{\"id\":\"28:31:46:C1:76:16\",\"rssi\":-92}",
+            QOS_1,
+        ));
+
+        let query = rx.try_recv().unwrap().build().unwrap().get();
+        assert!(query.ends_with(" 1701292592"), "{}", query);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_message_of_listed_devices() {
+        let (tx, rx) = sync_channel(100);
+        let mut logger = OpenMqttGatewayLogger::new(vec![tx], Enrichment::default())
+            .with_devices(Some(vec!["283146C17616".to_string()]));
+
+        for device in ["283146C17616", "A4C138F0A1B2"] {
+            logger.check_message(&Message::new(
+                format!("blegateway/D12331654712/BTtoMQTT/{}", device),
+                "{\"rssi\":-92}",
+                QOS_1,
+            ));
+        }
+
+        let query = rx.try_recv().unwrap().build().unwrap().get();
+        assert!(query.contains("device=283146C17616"), "{}", query);
+        assert!(rx.try_recv().is_err());
+    }
 
     #[test]
     fn test_parse() -> Result<()> {
@@ -199,31 +437,3 @@ mod tests {
         Ok(())
     }
 }
-
-pub fn create_logger(targets: Vec<Target>) -> (Arc<Mutex<dyn CheckMessage>>, Vec<JoinHandle<()>>) {
-    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-
-    for target in targets {
-        let (tx, handle) = match target {
-            Target::InfluxDB {
-                url,
-                database,
-                user,
-                password,
-            } => influx::spawn_influxdb_writer(
-                InfluxConfig::new(url, database, user, password),
-                std::convert::identity,
-            ),
-            Target::Postgresql { .. } => {
-                panic!("Postgresql not supported for open");
-            }
-        };
-        txs.push(tx);
-        handles.push(handle);
-    }
-
-    let logger = OpenMqttGatewayLogger::new(txs);
-
-    (Arc::new(Mutex::new(logger)), handles)
-}
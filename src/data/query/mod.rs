@@ -0,0 +1,220 @@
+use crate::error::{GatewayError, Result};
+use std::fmt;
+
+/// Time of an event, the sources write seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    Seconds(u128),
+}
+
+#[cfg(feature = "influx")]
+impl Timestamp {
+    /// Precision parameter of the InfluxDB write API.
+    pub fn precision(&self) -> &'static str {
+        match self {
+            Timestamp::Seconds(_) => "s",
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timestamp::Seconds(time) => write!(f, "{}", time),
+        }
+    }
+}
+
+/// Value of a tag or a field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Boolean(bool),
+    Float(f64),
+    SignedInteger(i64),
+    UnsignedInteger(u64),
+    Text(String),
+}
+
+macro_rules! from_impl {
+    ( $variant:ident => $( $typ:ident ),+ ) => (
+        $(
+            impl From<$typ> for Type {
+                fn from(value: $typ) -> Self {
+                    Type::$variant(value.into())
+                }
+            }
+        )+
+    )
+}
+from_impl! {Boolean => bool}
+from_impl! {Float => f32, f64}
+from_impl! {SignedInteger => i8, i16, i32, i64}
+from_impl! {UnsignedInteger => u8, u16, u32, u64}
+from_impl! {Text => String}
+
+impl From<&str> for Type {
+    fn from(value: &str) -> Self {
+        Type::Text(value.into())
+    }
+}
+
+impl<T: Copy + Into<Type>> From<&T> for Type {
+    fn from(value: &T) -> Self {
+        (*value).into()
+    }
+}
+
+/// An event in the InfluxDB line protocol, the events of the sources are built with it whether
+/// or not the InfluxDB target is part of the build.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteQuery {
+    timestamp: Timestamp,
+    measurement: String,
+    tags: Vec<(String, Type)>,
+    fields: Vec<(String, Type)>,
+}
+
+impl WriteQuery {
+    pub fn new(timestamp: Timestamp, measurement: impl Into<String>) -> Self {
+        WriteQuery {
+            timestamp,
+            measurement: measurement.into(),
+            tags: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn add_tag(mut self, tag: impl Into<String>, value: impl Into<Type>) -> Self {
+        self.tags.push((tag.into(), value.into()));
+        self
+    }
+
+    pub fn add_field(mut self, field: impl Into<String>, value: impl Into<Type>) -> Self {
+        self.fields.push((field.into(), value.into()));
+        self
+    }
+
+    #[cfg(feature = "influx")]
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+}
+
+/// A line of the line protocol.
+#[derive(Debug, PartialEq)]
+pub struct ValidQuery(String);
+
+impl ValidQuery {
+    pub fn get(self) -> String {
+        self.0
+    }
+}
+
+pub trait Query {
+    /// The line of the event, which needs at least one field.
+    fn build(&self) -> Result<ValidQuery>;
+}
+
+impl Query for WriteQuery {
+    fn build(&self) -> Result<ValidQuery> {
+        if self.fields.is_empty() {
+            return Err(GatewayError::parse(
+                format!("event {}", self.measurement),
+                "fields cannot be empty",
+            ));
+        }
+        let tags: String = self
+            .tags
+            .iter()
+            .map(|(tag, value)| format!(",{}={}", escape(tag, ",= "), tag_value(value)))
+            .collect();
+        let fields = self
+            .fields
+            .iter()
+            .map(|(field, value)| format!("{}={}", escape(field, ",= "), field_value(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(ValidQuery(format!(
+            "{}{} {} {}",
+            escape(&self.measurement, ", "),
+            tags,
+            fields,
+            self.timestamp
+        )))
+    }
+}
+
+/// Prefixes the special characters with a backslash.
+fn escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if special.contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+fn tag_value(value: &Type) -> String {
+    match value {
+        Type::Text(text) => escape(text, "\\, =\""),
+        Type::Boolean(value) => value.to_string(),
+        Type::Float(value) => value.to_string(),
+        Type::SignedInteger(value) => value.to_string(),
+        Type::UnsignedInteger(value) => value.to_string(),
+    }
+}
+
+fn field_value(value: &Type) -> String {
+    match value {
+        Type::Text(text) => format!("\"{}\"", escape(text, "\"\\")),
+        Type::Boolean(value) => value.to_string(),
+        Type::Float(value) => value.to_string(),
+        // the InfluxDB 1.x line protocol has no unsigned integers
+        Type::SignedInteger(value) => format!("{}i", value),
+        Type::UnsignedInteger(value) => format!("{}i", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() -> Result<()> {
+        let query = WriteQuery::new(Timestamp::Seconds(1701271852), "power")
+            .add_tag("location", "pantry")
+            .add_tag("channel", 0)
+            .add_field("value", 21.5)
+            .add_field("count", 3u64)
+            .add_field("on", true)
+            .add_field("state", "ok");
+
+        assert_eq!(
+            query.build()?.get(),
+            r#"power,location=pantry,channel=0 value=21.5,count=3i,on=true,state="ok" 1701271852"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_escapes() -> Result<()> {
+        let query = WriteQuery::new(Timestamp::Seconds(1), "wea\", ther")
+            .add_tag("locat\\ ,=ion", "a tag w=i,th \"quotes\\")
+            .add_field("field key", "say \"hi\" \\o/");
+
+        assert_eq!(
+            query.build()?.get(),
+            r#"wea"\,\ ther,locat\\ \,\=ion=a\ tag\ w\=i\,th\ \"quotes\\ field\ key="say \"hi\" \\o/" 1"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_without_fields() {
+        let query = WriteQuery::new(Timestamp::Seconds(1), "power").add_tag("location", "pantry");
+
+        assert!(query.build().is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::config::RedactionConfig;
+use crate::data::query::{Query, Timestamp, WriteQuery};
+use crate::error::{GatewayError, Result};
+use crate::target::mqtt::{parse_line, FieldValue};
+use crate::SensorReading;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::{LazyLock, Mutex};
+
+static REDACTION: LazyLock<Mutex<Option<Redaction>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Bytes of the hash kept as pseudonym, 64 bits keep collisions between devices unlikely.
+const HASH_BYTES: usize = 8;
+
+/// Tags and fields replaced by a salted hash or dropped, e.g. the MAC addresses of BLE devices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+    salt: String,
+    hash: Vec<String>,
+    drop: Vec<String>,
+}
+
+impl Redaction {
+    pub fn from_config(config: &RedactionConfig) -> Result<Self> {
+        let salt = config.salt.clone().unwrap_or_default();
+        let hash = config.hash.clone().unwrap_or_default();
+        let drop = config.drop.clone().unwrap_or_default();
+        if !hash.is_empty() && salt.is_empty() {
+            return Err(GatewayError::config(
+                "hashing tags or fields requires a redaction salt".to_string(),
+            ));
+        }
+        if let Some(key) = hash.iter().find(|key| drop.contains(key)) {
+            return Err(GatewayError::config(format!(
+                "'{}' is both hashed and dropped",
+                key
+            )));
+        }
+        Ok(Redaction { salt, hash, drop })
+    }
+
+    /// Pseudonym of a value, the same within a deployment but not across deployments.
+    fn hash(&self, value: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.salt.as_bytes()).expect("HMAC accepts any key");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes()[..HASH_BYTES]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn affects(&self, key: &str) -> bool {
+        self.hash
+            .iter()
+            .chain(&self.drop)
+            .any(|redacted| redacted == key)
+    }
+}
+
+/// Events whose tags and fields are subject to the redaction.
+pub trait Redact {
+    fn redact(self, redaction: &Redaction) -> Self;
+}
+
+impl Redact for WriteQuery {
+    fn redact(self, redaction: &Redaction) -> Self {
+        // events were validated by their source, so they always parse
+        let Some(event) = self.build().ok().and_then(|line| parse_line(&line.get())) else {
+            return self;
+        };
+        if !event
+            .tags
+            .keys()
+            .chain(event.fields.keys())
+            .any(|key| redaction.affects(key))
+        {
+            return self;
+        }
+
+        let time = event
+            .time
+            .unwrap_or_else(|| chrono::offset::Utc::now().timestamp());
+        let mut query = WriteQuery::new(Timestamp::Seconds(time as u128), event.measurement);
+        for (key, value) in event.tags {
+            if redaction.drop.contains(&key) {
+                continue;
+            }
+            let value = if redaction.hash.contains(&key) {
+                redaction.hash(&value)
+            } else {
+                value
+            };
+            query = query.add_tag(key, value);
+        }
+        for (key, value) in event.fields {
+            if redaction.drop.contains(&key) {
+                continue;
+            }
+            query = match value {
+                _ if redaction.hash.contains(&key) => {
+                    let text = match value {
+                        FieldValue::Boolean(value) => value.to_string(),
+                        FieldValue::Integer(value) => value.to_string(),
+                        FieldValue::Float(value) => value.to_string(),
+                        FieldValue::Text(value) => value,
+                    };
+                    query.add_field(key, redaction.hash(&text))
+                }
+                FieldValue::Boolean(value) => query.add_field(key, value),
+                FieldValue::Integer(value) => query.add_field(key, value),
+                FieldValue::Float(value) => query.add_field(key, value),
+                FieldValue::Text(value) => query.add_field(key, value),
+            };
+        }
+        query
+    }
+}
+
+impl Redact for SensorReading {
+    /// The location and sensor of a reading can be hashed but not dropped.
+    fn redact(mut self, redaction: &Redaction) -> Self {
+        self.tags.retain(|(key, _)| !redaction.drop.contains(key));
+        for (key, value) in self.tags.iter_mut() {
+            if redaction.hash.contains(key) {
+                *value = redaction.hash(value);
+            }
+        }
+        for (key, value) in [
+            ("location", &mut self.location),
+            ("sensor", &mut self.sensor),
+        ] {
+            if redaction.hash.iter().any(|hashed| hashed == key) {
+                *value = redaction.hash(value).into();
+            }
+        }
+        self
+    }
+}
+
+/// Redacts all events sent to the targets afterwards.
+pub fn enable(config: &RedactionConfig) -> Result<()> {
+    *REDACTION.lock().unwrap() = Some(Redaction::from_config(config)?);
+    Ok(())
+}
+
+/// Applies the enabled redaction to an event.
+pub fn apply<T: Redact>(event: T) -> T {
+    match REDACTION.lock().unwrap().as_ref() {
+        Some(redaction) => event.redact(redaction),
+        None => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn redaction() -> Redaction {
+        Redaction::from_config(&RedactionConfig {
+            salt: Some("site-1".to_string()),
+            hash: Some(vec!["id".to_string(), "sensor".to_string()]),
+            drop: Some(vec!["name".to_string()]),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_redact_query() {
+        let redaction = redaction();
+        let query = WriteQuery::new(Timestamp::Seconds(1701271852), "btle")
+            .add_tag("id", "AA:BB:CC:DD:EE:FF")
+            .add_tag("name", "Alice's watch")
+            .add_field("rssi", -70)
+            .add_field("temperature", 21.5);
+
+        let line = query.redact(&redaction).build().unwrap().get();
+
+        assert_eq!(
+            line,
+            format!(
+                "btle,id={} rssi=-70i,temperature=21.5 1701271852",
+                redaction.hash("AA:BB:CC:DD:EE:FF")
+            )
+        );
+        assert_eq!(redaction.hash("AA:BB:CC:DD:EE:FF").len(), 2 * HASH_BYTES);
+        assert_ne!(
+            redaction.hash("AA:BB:CC:DD:EE:FF"),
+            redaction.hash("AA:BB:CC:DD:EE:00")
+        );
+    }
+
+    #[test]
+    fn test_unaffected_query() {
+        let query = WriteQuery::new(Timestamp::Seconds(1701271852), "power")
+            .add_tag("location", "kitchen")
+            .add_field("value", 12u64);
+
+        assert_eq!(
+            query.clone().redact(&redaction()).build().unwrap().get(),
+            query.build().unwrap().get()
+        );
+    }
+
+    #[test]
+    fn test_redact_reading() {
+        let redaction = redaction();
+        let reading = SensorReading {
+            measurement: "temperature".into(),
+            time: Utc.timestamp_opt(1701271852, 0).unwrap(),
+            location: "kitchen".into(),
+            sensor: "BME680".into(),
+            value: 21.5,
+            tags: vec![
+                ("name".to_string(), "Alice".to_string()),
+                ("id".to_string(), "42".to_string()),
+            ],
+            ack: None,
+        }
+        .redact(&redaction);
+
+        assert_eq!(&*reading.location, "kitchen");
+        assert_eq!(&*reading.sensor, redaction.hash("BME680"));
+        assert_eq!(reading.tags, vec![("id".to_string(), redaction.hash("42"))]);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = RedactionConfig {
+            salt: None,
+            hash: Some(vec!["id".to_string()]),
+            drop: None,
+        };
+        assert!(Redaction::from_config(&config).is_err());
+        assert!(Redaction::from_config(&RedactionConfig {
+            salt: Some("site-1".to_string()),
+            drop: Some(vec!["id".to_string()]),
+            ..config
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::sync::mpsc::SyncSender;
+
+use crate::config::{StaticEventConfig, Target};
+use crate::data::catalog::Measurement;
+use crate::data::clock::{self, Clock};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::Enrichment;
+use crate::data::query::Timestamp::Seconds;
+use crate::data::query::WriteQuery;
+use crate::data::{deadletter, devices, heartbeat_query, live, start_query, BuildInfo};
+use crate::data::{message_age_query, online_queries, static_query};
+use crate::data::{validate, CheckMessage, Logger, SourceStats};
+use crate::error::{GatewayError, Result};
+use crate::target;
+use crate::target::supervisor;
+use crate::target::Mappers;
+use chrono::{DateTime, Utc};
+use log::debug;
+use paho_mqtt::Message;
+use serde::Deserialize;
+use std::iter;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Times below 2^28 seconds are relative to the current time.
+const RELATIVE_TIME_LIMIT: f64 = 268_435_456.0;
+
+/// A record of a SenML pack, base fields apply to the following records until they change.
+#[derive(Debug, Deserialize)]
+struct Record {
+    bn: Option<String>,
+    bt: Option<f64>,
+    bu: Option<String>,
+    bv: Option<f64>,
+    bs: Option<f64>,
+    n: Option<String>,
+    u: Option<String>,
+    v: Option<f64>,
+    vs: Option<String>,
+    vb: Option<bool>,
+    s: Option<f64>,
+    t: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+struct Base {
+    name: String,
+    time: f64,
+    unit: Option<String>,
+    value: f64,
+    sum: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Float(f64),
+    Boolean(bool),
+    Text(String),
+}
+
+/// A record with its base fields resolved.
+#[derive(Debug, Clone, PartialEq)]
+struct Data {
+    timestamp: i64,
+    device: Option<String>,
+    measurement: String,
+    unit: Option<String>,
+    value: Option<Value>,
+    sum: Option<f64>,
+}
+
+/// Splits a resolved name like `urn:dev:ow:10e2073a01080063:temp` or `/3303/0/5700` at its last
+/// `:` or `/` into the device and the measurement, characters not allowed in measurement names
+/// are replaced by `_`.
+fn split_name(name: &str) -> Result<(Option<String>, String)> {
+    let (device, measurement) = match name.rfind([':', '/']) {
+        Some(index) => (Some(&name[..index]), &name[index + 1..]),
+        None => (None, name),
+    };
+    if measurement.is_empty() {
+        return Err(GatewayError::parse(
+            "senml",
+            format!("no measurement in name '{}'", name),
+        ));
+    }
+    let measurement = measurement
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Ok((
+        device.filter(|device| !device.is_empty()).map(String::from),
+        measurement,
+    ))
+}
+
+/// Resolves the records of a pack, records with only base fields are left out.
+fn resolve(records: Vec<Record>, now: f64) -> Result<Vec<Data>> {
+    let mut base = Base::default();
+    let mut resolved = Vec::new();
+    for record in records {
+        if let Some(name) = record.bn {
+            base.name = name;
+        }
+        if let Some(time) = record.bt {
+            base.time = time;
+        }
+        if let Some(unit) = record.bu {
+            base.unit = Some(unit);
+        }
+        if let Some(value) = record.bv {
+            base.value = value;
+        }
+        if let Some(sum) = record.bs {
+            base.sum = sum;
+        }
+        let value = match (record.v, record.vs, record.vb) {
+            (Some(value), _, _) => Some(Value::Float(base.value + value)),
+            (None, Some(text), _) => Some(Value::Text(text)),
+            (None, None, Some(flag)) => Some(Value::Boolean(flag)),
+            (None, None, None) => None,
+        };
+        let sum = record.s.map(|sum| base.sum + sum);
+        if value.is_none() && sum.is_none() {
+            continue;
+        }
+        let name = format!("{}{}", base.name, record.n.unwrap_or_default());
+        let (device, measurement) = split_name(&name)?;
+        let time = base.time + record.t.unwrap_or(0.0);
+        let time = if time < RELATIVE_TIME_LIMIT {
+            now + time
+        } else {
+            time
+        };
+        resolved.push(Data {
+            timestamp: time.round() as i64,
+            device,
+            measurement,
+            unit: record.u.or_else(|| base.unit.clone()),
+            value,
+            sum,
+        });
+    }
+    Ok(resolved)
+}
+
+fn parse(msg: &Message, now: f64) -> Result<Vec<Data>> {
+    let records: Vec<Record> = serde_json::from_slice(msg.payload())?;
+    resolve(records, now)
+}
+
+pub struct SenMLLogger {
+    txs: Vec<SyncSender<WriteQuery>>,
+    enrichment: Enrichment,
+    clock: Arc<dyn Clock>,
+    stats: SourceStats,
+}
+
+impl SenMLLogger {
+    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>, enrichment: Enrichment) -> Self {
+        SenMLLogger {
+            txs,
+            enrichment,
+            clock: clock::system(),
+            stats: SourceStats::default(),
+        }
+    }
+
+    fn send(&mut self, data: Data) {
+        let device = data.device.unwrap_or_default();
+        let mut write_query = WriteQuery::new(Seconds(data.timestamp as u128), &data.measurement);
+        write_query = match data.value {
+            Some(Value::Float(value)) => {
+                let value = self.enrichment.round(&data.measurement, value);
+                live::record(
+                    &data.measurement,
+                    &[("device", &device)],
+                    value,
+                    data.timestamp,
+                );
+                write_query.add_field("value", value)
+            }
+            Some(Value::Boolean(value)) => write_query.add_field("value", value),
+            Some(Value::Text(value)) => write_query.add_field("value", value),
+            None => write_query,
+        };
+        if let Some(sum) = data.sum {
+            write_query = write_query.add_field("sum", sum);
+        }
+        if !device.is_empty() {
+            write_query = write_query.add_tag("device", device.clone());
+        }
+        if let Some(unit) = data.unit {
+            write_query = write_query.add_tag("unit", unit);
+        }
+        write_query = self.enrichment.apply(write_query, data.timestamp, &device);
+        if !validate::accept("SenML", &write_query) {
+            self.stats.dropped += 1;
+            return;
+        }
+        target::send_all(&self.txs, &write_query);
+        devices::record("senml", &device, &data.measurement);
+        self.stats.forwarded += 1;
+    }
+}
+
+impl CheckMessage for SenMLLogger {
+    fn check_message(&mut self, msg: &Message) {
+        self.stats.received += 1;
+        let now = self.clock.now().timestamp_millis() as f64 / 1000.0;
+        let readings = match parse(msg, now) {
+            Ok(readings) => readings,
+            Err(error) => {
+                warn_deduplicated(
+                    &format!("SenML parse error on '{}'", msg.topic()),
+                    &error.to_string(),
+                );
+                deadletter::record(msg, &error.to_string());
+                self.stats.dropped += 1;
+                return;
+            }
+        };
+        debug!("SenML {}: {:?}", msg.topic(), readings);
+        self.stats.parsed += 1;
+        for data in readings {
+            self.send(data);
+        }
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats
+    }
+
+    fn shutdown(&mut self) {
+        self.txs.clear();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        let clock = self.clock.as_ref();
+        let queries = iter::once(heartbeat_query(source, messages, &self.enrichment, clock))
+            .chain(message_age_query(source, &self.enrichment, clock))
+            .chain(online_queries(source, &self.enrichment, clock));
+        for query in queries {
+            target::send_all(&self.txs, &query);
+        }
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        let query = start_query(source, build, &self.enrichment, self.clock.as_ref());
+        target::send_all(&self.txs, &query);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        let query = static_query(source, event, time, &self.enrichment);
+        target::send_all(&self.txs, &query);
+    }
+}
+
+pub fn catalog() -> Vec<Measurement> {
+    vec![Measurement::new(
+        "<name>",
+        &["value", "sum"],
+        &["device", "unit"],
+        None,
+    )]
+}
+
+pub fn create_logger(targets: Vec<Target>, enrichment: Enrichment) -> Result<Logger> {
+    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for target in targets {
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            target::spawn_writer(target.clone(), &Mappers::queries("senml"))
+        })?;
+        txs.push(tx);
+        handles.push(handle);
+    }
+
+    let logger = SenMLLogger::new(txs, enrichment);
+
+    Ok((Arc::new(Mutex::new(logger)), handles))
+}
+
+#[cfg(test)]
+mod tests {
+    use paho_mqtt::QOS_1;
+
+    use super::*;
+
+    const NOW: f64 = 1704067200.0;
+
+    fn parse_payload(payload: &str) -> Result<Vec<Data>> {
+        parse(&Message::new("senml/device", payload, QOS_1), NOW)
+    }
+
+    #[test]
+    fn test_resolve_base_fields() -> Result<()> {
+        let readings = parse_payload(
+            r#"[
+                {"bn":"urn:dev:ow:10e2073a01080063:","bt":1.320067464e+09,"bu":"A","n":"voltage","u":"V","v":120.1},
+                {"n":"current","t":-5,"v":1.2},
+                {"n":"current","t":-4,"v":1.3}
+            ]"#,
+        )?;
+
+        assert_eq!(readings.len(), 3);
+        assert_eq!(
+            readings[0],
+            Data {
+                timestamp: 1320067464,
+                device: Some("urn:dev:ow:10e2073a01080063".to_string()),
+                measurement: "voltage".to_string(),
+                unit: Some("V".to_string()),
+                value: Some(Value::Float(120.1)),
+                sum: None,
+            }
+        );
+        assert_eq!(readings[1].measurement, "current");
+        assert_eq!(readings[1].unit.as_deref(), Some("A"));
+        assert_eq!(readings[1].timestamp, 1320067459);
+        assert_eq!(readings[2].timestamp, 1320067460);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_relative_time_and_values() -> Result<()> {
+        let readings = parse_payload(
+            r#"[
+                {"bn":"/3303/0/","bv":20,"bs":100},
+                {"n":"5700","v":1.5,"t":-60},
+                {"n":"5850","vb":true},
+                {"n":"5750","vs":"kitchen"},
+                {"n":"5805","s":2.5}
+            ]"#,
+        )?;
+
+        assert_eq!(readings.len(), 4);
+        assert_eq!(readings[0].device.as_deref(), Some("/3303/0"));
+        assert_eq!(readings[0].measurement, "5700");
+        assert_eq!(readings[0].value, Some(Value::Float(21.5)));
+        assert_eq!(readings[0].timestamp, NOW as i64 - 60);
+        assert_eq!(readings[1].value, Some(Value::Boolean(true)));
+        assert_eq!(readings[2].value, Some(Value::Text("kitchen".to_string())));
+        assert_eq!(readings[3].value, None);
+        assert_eq!(readings[3].sum, Some(102.5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_name() -> Result<()> {
+        assert_eq!(
+            split_name("temperature")?,
+            (None, "temperature".to_string())
+        );
+        assert_eq!(
+            split_name("dev:air quality")?,
+            (Some("dev".to_string()), "air_quality".to_string())
+        );
+        assert!(split_name("urn:dev:ow:").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(parse_payload(r#"{"n":"temperature","v":1}"#).is_err());
+        assert!(parse_payload(r#"[{"v":1}]"#).is_err());
+    }
+
+    #[test]
+    fn test_write_query() -> anyhow::Result<()> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(100);
+        let mut logger = SenMLLogger::new(vec![tx], Enrichment::default());
+
+        logger.check_message(&Message::new(
+            "senml/device",
+            r#"[{"bn":"urn:dev:mac:0024befffe804ff1:","bt":1701271852,"n":"temp","u":"Cel","v":23.5}]"#,
+            QOS_1,
+        ));
+
+        let line = crate::data::query::Query::build(&rx.try_recv()?)?.get();
+        assert!(line.starts_with("temp,device=urn:dev:mac:0024befffe804ff1,unit=Cel value=23.5 "));
+        Ok(())
+    }
+}
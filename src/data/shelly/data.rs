@@ -1,25 +1,91 @@
-use crate::data::shelly::{Timestamped, Typenamed};
+use crate::data::shelly::Typenamed;
+use crate::data::timestamp::Timestamped;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+/// Fields some firmwares omit, which strict parsing requires.
+pub trait Required {
+    /// Name of the first missing field.
+    fn missing(&self) -> Option<&'static str>;
+}
+
+fn missing(
+    energy: &Option<EnergyData>,
+    temperature: &Option<TemperatureData>,
+) -> Option<&'static str> {
+    match (energy, temperature) {
+        (None, _) => Some("aenergy"),
+        (_, None) => Some("temperature"),
+        _ => None,
+    }
+}
+
+/// Devices reporting `aenergy`.
+pub trait Metered {
+    fn energy(&self) -> Option<&EnergyData>;
+}
+
+/// Origin of the state change reported by a status message.
+pub trait Triggered {
+    fn trigger(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Components whose status carries no timestamp, stamped with the receive time instead.
+pub trait Untimed {
+    fn untimed(&self) -> bool {
+        false
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SwitchData {
     pub(crate) output: bool,
+    /// What triggered the last change of the output like `timer`, `button` or `http`.
+    pub(crate) source: Option<String>,
     #[serde(rename = "apower")]
-    pub(crate) power: Option<f32>,
-    pub(crate) voltage: Option<f32>,
-    pub(crate) current: Option<f32>,
+    pub(crate) power: Option<f64>,
+    pub(crate) voltage: Option<f64>,
+    pub(crate) current: Option<f64>,
+    #[serde(rename = "pf")]
+    pub(crate) power_factor: Option<f64>,
+    #[serde(rename = "freq")]
+    pub(crate) frequency: Option<f64>,
     #[serde(rename = "aenergy")]
-    pub(crate) energy: EnergyData,
-    pub(crate) temperature: TemperatureData,
+    pub(crate) energy: Option<EnergyData>,
+    pub(crate) temperature: Option<TemperatureData>,
+    /// Active protection errors like `overpower` or `overtemp`.
+    #[serde(default)]
+    pub(crate) errors: Vec<String>,
 }
 
 impl Timestamped for SwitchData {
     fn timestamp(&self) -> Option<i64> {
-        self.energy.minute_ts
+        self.energy.as_ref().and_then(|energy| energy.minute_ts)
     }
 }
 
+impl Required for SwitchData {
+    fn missing(&self) -> Option<&'static str> {
+        missing(&self.energy, &self.temperature)
+    }
+}
+
+impl Metered for SwitchData {
+    fn energy(&self) -> Option<&EnergyData> {
+        self.energy.as_ref()
+    }
+}
+
+impl Triggered for SwitchData {
+    fn trigger(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+}
+
+impl Untimed for SwitchData {}
+
 impl Typenamed for SwitchData {
     fn type_name(&self) -> &str {
         "switch"
@@ -31,32 +97,208 @@ pub struct CoverData {
     #[serde(rename = "current_pos")]
     pub(crate) position: Option<i32>,
     #[serde(rename = "apower")]
-    pub(crate) power: Option<f32>,
-    pub(crate) voltage: Option<f32>,
-    pub(crate) current: Option<f32>,
+    pub(crate) power: Option<f64>,
+    pub(crate) voltage: Option<f64>,
+    pub(crate) current: Option<f64>,
+    #[serde(rename = "pf")]
+    pub(crate) power_factor: Option<f64>,
+    #[serde(rename = "freq")]
+    pub(crate) frequency: Option<f64>,
     #[serde(rename = "aenergy")]
-    pub(crate) energy: EnergyData,
-    pub(crate) temperature: TemperatureData,
+    pub(crate) energy: Option<EnergyData>,
+    pub(crate) temperature: Option<TemperatureData>,
+    #[serde(default)]
+    pub(crate) errors: Vec<String>,
 }
 
 impl Timestamped for CoverData {
     fn timestamp(&self) -> Option<i64> {
-        self.energy.minute_ts
+        self.energy.as_ref().and_then(|energy| energy.minute_ts)
+    }
+}
+
+impl Required for CoverData {
+    fn missing(&self) -> Option<&'static str> {
+        missing(&self.energy, &self.temperature)
     }
 }
 
+impl Triggered for CoverData {}
+
+impl Metered for CoverData {
+    fn energy(&self) -> Option<&EnergyData> {
+        self.energy.as_ref()
+    }
+}
+
+impl Untimed for CoverData {}
+
 impl Typenamed for CoverData {
     fn type_name(&self) -> &str {
         "cover"
     }
 }
 
+/// Power meter of plugs and the PM mini like the Shelly Plus Plug S or PM1 Mini, measuring energy
+/// in both directions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PM1Data {
+    #[serde(rename = "apower")]
+    pub(crate) power: Option<f64>,
+    pub(crate) voltage: Option<f64>,
+    pub(crate) current: Option<f64>,
+    #[serde(rename = "pf")]
+    pub(crate) power_factor: Option<f64>,
+    #[serde(rename = "freq")]
+    pub(crate) frequency: Option<f64>,
+    #[serde(rename = "aenergy")]
+    pub(crate) energy: Option<EnergyData>,
+    /// Energy fed back into the grid.
+    #[serde(rename = "ret_aenergy")]
+    pub(crate) returned_energy: Option<EnergyData>,
+}
+
+impl Timestamped for PM1Data {
+    fn timestamp(&self) -> Option<i64> {
+        self.energy.as_ref().and_then(|energy| energy.minute_ts)
+    }
+}
+
+impl Required for PM1Data {
+    /// A power meter has no temperature sensor.
+    fn missing(&self) -> Option<&'static str> {
+        self.energy.is_none().then_some("aenergy")
+    }
+}
+
+impl Triggered for PM1Data {}
+
+impl Metered for PM1Data {
+    fn energy(&self) -> Option<&EnergyData> {
+        self.energy.as_ref()
+    }
+}
+
+impl Untimed for PM1Data {}
+
+impl Typenamed for PM1Data {
+    fn type_name(&self) -> &str {
+        "pm1"
+    }
+}
+
+/// Power of a single phase energy meter like the Shelly Pro EM, negative while feeding in.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EM1Data {
+    #[serde(rename = "act_power")]
+    pub(crate) power: Option<f64>,
+    #[serde(rename = "aprt_power")]
+    pub(crate) apparent_power: Option<f64>,
+    pub(crate) voltage: Option<f64>,
+    pub(crate) current: Option<f64>,
+    #[serde(rename = "pf")]
+    pub(crate) power_factor: Option<f64>,
+    #[serde(rename = "freq")]
+    pub(crate) frequency: Option<f64>,
+}
+
+impl Timestamped for EM1Data {
+    fn timestamp(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl Required for EM1Data {
+    fn missing(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl Triggered for EM1Data {}
+
+impl Metered for EM1Data {
+    fn energy(&self) -> Option<&EnergyData> {
+        None
+    }
+}
+
+impl Untimed for EM1Data {
+    fn untimed(&self) -> bool {
+        true
+    }
+}
+
+impl Typenamed for EM1Data {
+    fn type_name(&self) -> &str {
+        "em1"
+    }
+}
+
+/// Energy counters of a single phase energy meter in Wh.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EM1EnergyData {
+    #[serde(rename = "total_act_energy")]
+    pub(crate) energy: Option<f64>,
+    /// Energy fed into the grid.
+    #[serde(rename = "total_act_ret_energy")]
+    pub(crate) returned_energy: Option<f64>,
+}
+
+impl Timestamped for EM1EnergyData {
+    fn timestamp(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl Required for EM1EnergyData {
+    fn missing(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl Triggered for EM1EnergyData {}
+
+impl Metered for EM1EnergyData {
+    fn energy(&self) -> Option<&EnergyData> {
+        None
+    }
+}
+
+impl Untimed for EM1EnergyData {
+    fn untimed(&self) -> bool {
+        true
+    }
+}
+
+impl Typenamed for EM1EnergyData {
+    fn type_name(&self) -> &str {
+        "em1data"
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct EnergyData {
-    pub(crate) total: f32,
+    pub(crate) total: f64,
+    /// Energy in mWh of the minute starting at `minute_ts` and the two minutes before.
+    #[serde(default)]
+    pub(crate) by_minute: Vec<f64>,
     pub(crate) minute_ts: Option<i64>,
 }
 
+impl EnergyData {
+    /// Start and energy in Wh of the minutes in `by_minute`, newest first.
+    pub fn by_minute(&self) -> Vec<(i64, f64)> {
+        let Some(minute_ts) = self.minute_ts else {
+            return Vec::new();
+        };
+        self.by_minute
+            .iter()
+            .enumerate()
+            .map(|(index, energy)| (minute_ts - 60 * index as i64, energy / 1000.0))
+            .collect()
+    }
+}
+
 impl fmt::Debug for EnergyData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} Wh", self.total)
@@ -66,7 +308,7 @@ impl fmt::Debug for EnergyData {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct TemperatureData {
     #[serde(rename = "tC")]
-    pub(crate) t_celsius: f32,
+    pub(crate) t_celsius: f64,
 }
 
 impl fmt::Debug for TemperatureData {
@@ -83,26 +325,32 @@ mod tests {
     fn test_switch_data_debug() {
         let energy = EnergyData {
             total: 10.0,
+            by_minute: Vec::new(),
             minute_ts: Some(1627848123),
         };
         let temperature = TemperatureData { t_celsius: 25.0 };
         let switch_data = SwitchData {
             output: true,
+            source: None,
             power: Some(100.0),
             voltage: Some(220.0),
             current: Some(0.45),
-            energy,
-            temperature,
+            power_factor: None,
+            frequency: None,
+            energy: Some(energy),
+            temperature: Some(temperature),
+            errors: Vec::new(),
         };
 
-        assert_eq!(format!("{:?}", switch_data.energy), "10 Wh");
-        assert_eq!(format!("{:?}", switch_data.temperature), "25 °C");
+        assert_eq!(format!("{:?}", switch_data.energy.unwrap()), "10 Wh");
+        assert_eq!(format!("{:?}", switch_data.temperature.unwrap()), "25 °C");
     }
 
     #[test]
     fn test_cover_data_debug() {
         let energy = EnergyData {
             total: 20.0,
+            by_minute: Vec::new(),
             minute_ts: Some(1627848124),
         };
         let temperature = TemperatureData { t_celsius: 26.0 };
@@ -111,27 +359,35 @@ mod tests {
             power: Some(110.0),
             voltage: Some(230.0),
             current: Some(0.50),
-            energy,
-            temperature,
+            power_factor: None,
+            frequency: None,
+            energy: Some(energy),
+            temperature: Some(temperature),
+            errors: Vec::new(),
         };
 
-        assert_eq!(format!("{:?}", cover_data.energy), "20 Wh");
-        assert_eq!(format!("{:?}", cover_data.temperature), "26 °C");
+        assert_eq!(format!("{:?}", cover_data.energy.unwrap()), "20 Wh");
+        assert_eq!(format!("{:?}", cover_data.temperature.unwrap()), "26 °C");
     }
 
     #[test]
     fn test_switch_data_timestamp() {
         let energy = EnergyData {
             total: 10.0,
+            by_minute: Vec::new(),
             minute_ts: Some(1627848123),
         };
         let switch_data = SwitchData {
             output: true,
+            source: None,
             power: Some(100.0),
             voltage: Some(220.0),
             current: Some(0.45),
-            energy,
-            temperature: TemperatureData { t_celsius: 25.0 },
+            power_factor: None,
+            frequency: None,
+            energy: Some(energy),
+            temperature: Some(TemperatureData { t_celsius: 25.0 }),
+            errors: Vec::new(),
         };
 
         assert_eq!(switch_data.timestamp(), Some(1627848123));
@@ -141,6 +397,7 @@ mod tests {
     fn test_cover_data_timestamp() {
         let energy = EnergyData {
             total: 20.0,
+            by_minute: Vec::new(),
             minute_ts: Some(1627848124),
         };
         let cover_data = CoverData {
@@ -148,8 +405,11 @@ mod tests {
             power: Some(110.0),
             voltage: Some(230.0),
             current: Some(0.50),
-            energy,
-            temperature: TemperatureData { t_celsius: 26.0 },
+            power_factor: None,
+            frequency: None,
+            energy: Some(energy),
+            temperature: Some(TemperatureData { t_celsius: 26.0 }),
+            errors: Vec::new(),
         };
 
         assert_eq!(cover_data.timestamp(), Some(1627848124));
@@ -159,19 +419,39 @@ mod tests {
     fn test_switch_data_typename() {
         let switch_data = SwitchData {
             output: true,
+            source: None,
             power: Some(100.0),
             voltage: Some(220.0),
             current: Some(0.45),
-            energy: EnergyData {
+            power_factor: None,
+            frequency: None,
+            energy: Some(EnergyData {
                 total: 10.0,
+                by_minute: Vec::new(),
                 minute_ts: Some(1627848123),
-            },
-            temperature: TemperatureData { t_celsius: 25.0 },
+            }),
+            temperature: Some(TemperatureData { t_celsius: 25.0 }),
+            errors: Vec::new(),
         };
 
         assert_eq!(switch_data.type_name(), "switch");
     }
 
+    #[test]
+    fn test_pm1_data() {
+        let pm1_data: PM1Data = serde_json::from_str(
+            "{\"id\":0,\"voltage\":231.2,\"current\":0.12,\"apower\":-20.5,\"freq\":50.0,\
+            \"aenergy\":{\"total\":12.5,\"minute_ts\":1703415907},\
+            \"ret_aenergy\":{\"total\":3.25,\"minute_ts\":1703415907}}",
+        )
+        .unwrap();
+
+        assert_eq!(pm1_data.timestamp(), Some(1703415907));
+        assert_eq!(pm1_data.missing(), None);
+        assert_eq!(pm1_data.type_name(), "pm1");
+        assert_eq!(pm1_data.returned_energy.unwrap().total, 3.25);
+    }
+
     #[test]
     fn test_cover_data_typename() {
         let cover_data = CoverData {
@@ -179,11 +459,15 @@ mod tests {
             power: Some(110.0),
             voltage: Some(230.0),
             current: Some(0.50),
-            energy: EnergyData {
+            power_factor: None,
+            frequency: None,
+            energy: Some(EnergyData {
                 total: 20.0,
+                by_minute: Vec::new(),
                 minute_ts: Some(1627848124),
-            },
-            temperature: TemperatureData { t_celsius: 26.0 },
+            }),
+            temperature: Some(TemperatureData { t_celsius: 26.0 }),
+            errors: Vec::new(),
         };
 
         assert_eq!(cover_data.type_name(), "cover");
@@ -0,0 +1,143 @@
+use crate::error::{GatewayError, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceTag {
+    Model,
+    Firmware,
+    Mac,
+}
+
+impl FromStr for DeviceTag {
+    type Err = GatewayError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "model" => Ok(DeviceTag::Model),
+            "fw" => Ok(DeviceTag::Firmware),
+            "mac" => Ok(DeviceTag::Mac),
+            _ => Err(GatewayError::config(format!(
+                "unknown device tag '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+impl DeviceTag {
+    fn name(&self) -> &str {
+        match self {
+            DeviceTag::Model => "model",
+            DeviceTag::Firmware => "fw",
+            DeviceTag::Mac => "mac",
+        }
+    }
+}
+
+/// Payload of `<prefix>/announce` (gen 1) and `<prefix>/<device>/announce` (gen 2) messages.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AnnounceData {
+    pub(crate) id: Option<String>,
+    pub(crate) model: Option<String>,
+    pub(crate) mac: Option<String>,
+    #[serde(alias = "ver")]
+    pub(crate) fw_ver: Option<String>,
+}
+
+/// Payload of `<prefix>/<device>/status/sys` messages.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SysData {
+    pub(crate) mac: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DeviceInfo {
+    model: Option<String>,
+    firmware: Option<String>,
+    mac: Option<String>,
+}
+
+/// Device information collected from announce and sys status messages, keyed by device id.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceRegistry {
+    tags: Vec<DeviceTag>,
+    devices: HashMap<String, DeviceInfo>,
+}
+
+impl DeviceRegistry {
+    pub fn new(tags: Vec<DeviceTag>) -> Self {
+        DeviceRegistry {
+            tags,
+            devices: HashMap::new(),
+        }
+    }
+
+    pub fn announce(&mut self, device: &str, data: AnnounceData) {
+        let device = data.id.as_deref().unwrap_or(device);
+        let info = self.devices.entry(device.to_string()).or_default();
+        info.model = data.model.or(info.model.take());
+        info.firmware = data.fw_ver.or(info.firmware.take());
+        info.mac = data.mac.or(info.mac.take());
+    }
+
+    pub fn sys(&mut self, device: &str, data: SysData) {
+        let info = self.devices.entry(device.to_string()).or_default();
+        info.mac = data.mac.or(info.mac.take());
+    }
+
+    pub fn tags(&self, device: &str) -> Vec<(String, String)> {
+        let Some(info) = self.devices.get(device) else {
+            return Vec::new();
+        };
+        self.tags
+            .iter()
+            .filter_map(|tag| {
+                let value = match tag {
+                    DeviceTag::Model => info.model.as_ref(),
+                    DeviceTag::Firmware => info.firmware.as_ref(),
+                    DeviceTag::Mac => info.mac.as_ref(),
+                };
+                value.map(|value| (tag.name().to_string(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_tags() -> Result<()> {
+        let mut registry = DeviceRegistry::new(vec!["model".parse()?, "fw".parse()?]);
+
+        registry.announce(
+            "ignored",
+            serde_json::from_str(
+                r#"{"id":"loo-fan","model":"SNSW-102P16EU","mac":"AABBCC","ver":"1.4.4"}"#,
+            )?,
+        );
+        registry.sys("loo-fan", serde_json::from_str(r#"{"mac":"DDEEFF"}"#)?);
+
+        assert_eq!(
+            registry.tags("loo-fan"),
+            vec![
+                ("model".to_string(), "SNSW-102P16EU".to_string()),
+                ("fw".to_string(), "1.4.4".to_string())
+            ]
+        );
+        assert!(registry.tags("unknown").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_device_tag() {
+        assert_eq!(
+            "serial".parse::<DeviceTag>().err().map(|e| e.to_string()),
+            Some("configuration error: unknown device tag 'serial'".to_string())
+        );
+    }
+}
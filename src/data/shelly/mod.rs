@@ -1,47 +1,419 @@
 mod data;
+mod device;
+mod presence;
 
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::iter;
 use std::sync::mpsc::SyncSender;
 use std::sync::{Arc, LazyLock, Mutex};
 
-use crate::config::Target;
-use crate::data::{shelly, CheckMessage};
-use crate::target::influx;
-use crate::target::influx::InfluxConfig;
+use crate::config::TimestampConfig;
+use crate::config::{MissingTimestamp, ParseMode, PowerDirection, StaticEventConfig, Target};
+use crate::data::catalog::Measurement;
+use crate::data::clock::{self, Clock};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::Enrichment;
+use crate::data::query::{Timestamp, WriteQuery};
+use crate::data::timestamp::{TimestampPolicy, Timestamped};
+use crate::data::topic;
+use crate::data::topic::TopicSchema;
+use crate::data::{deadletter, devices, heartbeat_query, live, start_query, BuildInfo};
+use crate::data::{message_age_query, online_queries, static_query};
+use crate::data::{shelly, validate, CheckMessage, Logger, SourceStats};
+use crate::error::Result;
+use crate::target;
+use crate::target::supervisor;
+use crate::target::Mappers;
 use crate::WriteType;
-use anyhow::Result;
-use data::{CoverData, SwitchData};
-use influxdb::{Timestamp, WriteQuery};
-use log::{debug, warn};
+use chrono::{DateTime, Utc};
+use data::{
+    CoverData, EM1Data, EM1EnergyData, Metered, PM1Data, Required, SwitchData, Triggered, Untimed,
+};
+pub use device::DeviceTag;
+use device::{AnnounceData, DeviceRegistry, SysData};
+use log::debug;
 use paho_mqtt::Message;
+use presence::FieldPresence;
+pub use presence::DEFAULT_MISSING_FIELD_THRESHOLD;
 use regex::Regex;
 use serde::Deserialize;
 use std::thread::JoinHandle;
 
-pub trait Timestamped {
-    fn timestamp(&self) -> Option<i64>;
-}
-
 pub trait Typenamed {
     fn type_name(&self) -> &str;
 }
 
+const TIMESTAMP_POLICY: TimestampPolicy = TimestampPolicy::new(MissingTimestamp::Drop, None);
+
+/// Measurement tagged with what triggered the last change.
+const TRIGGER_MEASUREMENT: &str = "output";
+/// Measurement of the energy per minute.
+const ENERGY_BY_MINUTE: &str = "energy_by_minute";
+/// Measurement of the signed power and the ones it is split into.
+const POWER: &str = "power";
+const POWER_IMPORT: &str = "power_import";
+const POWER_EXPORT: &str = "power_export";
+/// Prefix of the measurements of the protection error flags.
+const ERROR_PREFIX: &str = "error_";
+
+const TOPIC_SCHEMA: &str = "{prefix}/{location}/status/{component}:{channel}";
+const TOPIC_VARIABLES: &[&str] = &["location", "component", "channel"];
+
+static DEVICE_TOPIC_SCHEMA: LazyLock<TopicSchema> =
+    LazyLock::new(|| "{prefix}/{location}/#".parse().unwrap());
+
 pub struct ShellyLogger {
     txs: Vec<SyncSender<WriteQuery>>,
+    enrichment: Enrichment,
+    timestamp_policy: TimestampPolicy,
+    clock: Arc<dyn Clock>,
+    topic_schema: TopicSchema,
+    devices: DeviceRegistry,
+    presence: FieldPresence,
+    parse_mode: ParseMode,
+    energy_by_minute: bool,
+    power_direction: Option<PowerDirection>,
+    /// Newest minute of `aenergy.by_minute` written per `location:channel`.
+    last_minute: HashMap<String, i64>,
+    /// Last error flag written per `location:channel:measurement`.
+    error_flags: HashMap<String, bool>,
+    stats: SourceStats,
 }
 
 impl ShellyLogger {
-    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>) -> Self {
-        ShellyLogger { txs }
+    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>, enrichment: Enrichment) -> Self {
+        ShellyLogger {
+            txs,
+            enrichment,
+            timestamp_policy: TIMESTAMP_POLICY,
+            clock: clock::system(),
+            topic_schema: TOPIC_SCHEMA.parse().unwrap(),
+            devices: DeviceRegistry::default(),
+            presence: FieldPresence::new(DEFAULT_MISSING_FIELD_THRESHOLD),
+            parse_mode: ParseMode::default(),
+            energy_by_minute: false,
+            power_direction: None,
+            last_minute: HashMap::new(),
+            error_flags: HashMap::new(),
+            stats: SourceStats::default(),
+        }
+    }
+
+    pub(crate) fn with_device_tags(self, device_tags: Vec<DeviceTag>) -> Self {
+        ShellyLogger {
+            devices: DeviceRegistry::new(device_tags),
+            ..self
+        }
+    }
+
+    /// Layout of the status topics, which defines the `location` and `channel` of the events.
+    pub(crate) fn with_topic_schema(self, topic_schema: TopicSchema) -> Self {
+        ShellyLogger {
+            topic_schema,
+            ..self
+        }
+    }
+
+    /// Warns about devices which did not report an optional field in `threshold` messages.
+    pub(crate) fn with_missing_field_threshold(self, threshold: u64) -> Self {
+        ShellyLogger {
+            presence: FieldPresence::new(threshold),
+            ..self
+        }
+    }
+
+    /// Strict parsing drops messages lacking `aenergy` or `temperature`, tolerant parsing writes
+    /// the fields present.
+    pub(crate) fn with_parse_mode(self, parse_mode: ParseMode) -> Self {
+        ShellyLogger { parse_mode, ..self }
+    }
+
+    /// Writes the energy of each minute from `aenergy.by_minute` with the time of the minute.
+    pub(crate) fn with_energy_by_minute(self, energy_by_minute: bool) -> Self {
+        ShellyLogger {
+            energy_by_minute,
+            ..self
+        }
+    }
+
+    /// Splits the signed power into import and export or tags its direction.
+    pub(crate) fn with_power_direction(self, power_direction: Option<PowerDirection>) -> Self {
+        ShellyLogger {
+            power_direction,
+            ..self
+        }
+    }
+
+    /// Time untimed components are stamped with and timestamps are checked against.
+    #[allow(dead_code)]
+    pub(crate) fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        ShellyLogger { clock, ..self }
+    }
+
+    fn handle_device_message<'a, T: Deserialize<'a>>(
+        &mut self,
+        msg: &'a Message,
+        update: fn(&mut DeviceRegistry, &str, T),
+    ) {
+        let device = DEVICE_TOPIC_SCHEMA
+            .matches(msg.topic())
+            .and_then(|topic| topic.get("location"))
+            .unwrap_or_default();
+        match shelly::parse::<Option<T>>(msg) {
+            Ok(Some(data)) => {
+                self.stats.parsed += 1;
+                update(&mut self.devices, device, data);
+            }
+            Ok(None) => {}
+            Err(error) => {
+                warn_deduplicated(
+                    &format!("Shelly parse error on '{}'", msg.topic()),
+                    &format!("{} on '{}'", error, msg.payload_str()),
+                );
+                deadletter::record(msg, &error.to_string());
+                self.stats.dropped += 1;
+            }
+        }
+    }
+
+    fn handle_message<
+        'a,
+        T: Deserialize<'a>
+            + Clone
+            + Debug
+            + Timestamped
+            + Typenamed
+            + Required
+            + Triggered
+            + Metered
+            + Untimed,
+    >(
+        &mut self,
+        msg: &'a Message,
+        fields: &[(&'static str, WriteTypeMapper<T>, &str)],
+    ) {
+        let ShellyLogger {
+            txs,
+            enrichment,
+            timestamp_policy,
+            clock,
+            topic_schema,
+            devices,
+            presence,
+            parse_mode,
+            energy_by_minute,
+            power_direction,
+            last_minute,
+            error_flags,
+            stats,
+        } = self;
+        let topic = topic_schema.matches(msg.topic());
+        let (Some(location), Some(channel)) = (
+            topic.as_ref().and_then(|topic| topic.get("location")),
+            topic.as_ref().and_then(|topic| topic.get("channel")),
+        ) else {
+            warn_deduplicated("Shelly topic does not match the topic schema", msg.topic());
+            stats.dropped += 1;
+            return;
+        };
+        let parse_result = shelly::parse(msg);
+        if let Err(error) = &parse_result {
+            warn_deduplicated(
+                &format!("Shelly parse error on '{}'", msg.topic()),
+                &format!("{:?} on '{}'", error, msg.payload_str()),
+            );
+            deadletter::record(msg, &error.to_string());
+            stats.dropped += 1;
+            return;
+        }
+        let result: Option<T> = parse_result.unwrap();
+        if let Some(field) = result.as_ref().and_then(|data| data.missing()) {
+            if *parse_mode == ParseMode::Strict {
+                let error = format!("missing field `{}`", field);
+                warn_deduplicated(
+                    &format!("Shelly parse error on '{}'", msg.topic()),
+                    &format!("{} on '{}'", error, msg.payload_str()),
+                );
+                deadletter::record(msg, &error);
+                stats.dropped += 1;
+                return;
+            }
+        }
+        if let Some(data) = result {
+            debug!("Shelly {}:{}: {:?}", location, channel, data);
+            stats.parsed += 1;
+            presence.record(
+                &format!("{}:{}", location, channel),
+                fields
+                    .iter()
+                    .map(|(measurement, value, _)| (*measurement, value(&data).is_some())),
+            );
+
+            let timestamp_policy = if data.untimed() {
+                timestamp_policy.with_receive_time()
+            } else {
+                *timestamp_policy
+            };
+            match timestamp_policy.resolve(data.timestamp(), clock.as_ref()) {
+                Ok(minute_ts) => {
+                    let mut send = |measurement: &str,
+                                    unit: &str,
+                                    direction: Option<&str>,
+                                    query: WriteQuery,
+                                    time: i64| {
+                        let query = query
+                            .add_tag("location", location)
+                            .add_tag("channel", channel)
+                            .add_tag("sensor", "shelly")
+                            .add_tag("type", data.type_name())
+                            .add_tag("unit", unit);
+                        let query = match data.trigger() {
+                            Some(trigger) if measurement == TRIGGER_MEASUREMENT => {
+                                query.add_tag("trigger", trigger)
+                            }
+                            _ => query,
+                        };
+                        let query = match direction {
+                            Some(direction) => query.add_tag("direction", direction),
+                            None => query,
+                        };
+                        let query = devices
+                            .tags(location)
+                            .into_iter()
+                            .fold(query, |query, (key, value)| query.add_tag(key, value));
+                        let query = enrichment.apply(query, time, location);
+                        if !validate::accept("Shelly", &query) {
+                            stats.dropped += 1;
+                            return;
+                        }
+
+                        target::send_all(txs, &query);
+                        devices::record("shelly", location, measurement);
+                        stats.forwarded += 1;
+                    };
+
+                    let timestamp = Timestamp::Seconds(minute_ts as u128);
+                    for (measurement, value, unit) in fields {
+                        let Some(result) = value(&data) else {
+                            continue;
+                        };
+                        if measurement.starts_with(ERROR_PREFIX) {
+                            // Error flags are written when first seen, while set and once cleared.
+                            let active = matches!(result, WriteType::Int(1));
+                            let series = format!("{}:{}:{}", location, channel, measurement);
+                            if error_flags.insert(series, active) == Some(false) && !active {
+                                continue;
+                            }
+                        }
+                        for (measurement, result, direction) in
+                            directed(measurement, result, *power_direction)
+                        {
+                            let query = WriteQuery::new(timestamp, measurement);
+                            let (query, live_value) = match result {
+                                WriteType::Int(i) => (query.add_field("value", i), i as f64),
+                                WriteType::Float(f) => {
+                                    let f = enrichment.round(measurement, f);
+                                    (query.add_field("value", f), f)
+                                }
+                            };
+                            live::record(
+                                measurement,
+                                &[
+                                    ("location", location),
+                                    ("channel", channel),
+                                    ("type", data.type_name()),
+                                ],
+                                live_value,
+                                minute_ts,
+                            );
+                            send(measurement, unit, direction, query, minute_ts);
+                        }
+                    }
+
+                    if let Some(energy) = data.energy().filter(|_| *energy_by_minute) {
+                        let series = format!("{}:{}", location, channel);
+                        let last = last_minute.get(&series).copied().unwrap_or(i64::MIN);
+                        let minutes = energy.by_minute();
+                        for (minute, value) in
+                            minutes.iter().rev().filter(|(minute, _)| *minute > last)
+                        {
+                            let query = WriteQuery::new(
+                                Timestamp::Seconds(*minute as u128),
+                                ENERGY_BY_MINUTE,
+                            )
+                            .add_field("value", enrichment.round(ENERGY_BY_MINUTE, *value));
+                            send(ENERGY_BY_MINUTE, "Wh", None, query, *minute);
+                        }
+                        if let Some((newest, _)) = minutes.first() {
+                            last_minute.insert(series, last.max(*newest));
+                        }
+                    }
+                }
+                Err(error) => {
+                    warn_deduplicated(
+                        &format!("Shelly {} on '{}'", error, msg.topic()),
+                        &msg.payload_str(),
+                    );
+                    deadletter::record(msg, &error.to_string());
+                    stats.dropped += 1;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn with_timestamp_policy(self, timestamp_policy: TimestampPolicy) -> Self {
+        ShellyLogger {
+            timestamp_policy,
+            ..self
+        }
     }
 }
 
-pub fn parse<'a, T: Deserialize<'a> + Clone>(msg: &'a Message) -> Result<T> {
+pub fn parse<'a, T: Deserialize<'a>>(msg: &'a Message) -> Result<T> {
     Ok(serde_json::from_slice::<T>(msg.payload())?)
 }
 
 type WriteTypeMapper<T> = fn(&T) -> Option<WriteType>;
 
+/// Measurements, values and `direction` tags a reading is written as, only the power is split or
+/// tagged by the direction it flows.
+fn directed(
+    measurement: &'static str,
+    value: WriteType,
+    power_direction: Option<PowerDirection>,
+) -> Vec<(&'static str, WriteType, Option<&'static str>)> {
+    let Some(power_direction) = power_direction else {
+        return vec![(measurement, value, None)];
+    };
+    let power = match value {
+        WriteType::Float(power) if measurement == POWER => power,
+        value => return vec![(measurement, value, None)],
+    };
+    match power_direction {
+        PowerDirection::Split => {
+            let (import, export) = if power < 0.0 {
+                (0.0, -power)
+            } else {
+                (power, 0.0)
+            };
+            vec![
+                (POWER_IMPORT, WriteType::Float(import), None),
+                (POWER_EXPORT, WriteType::Float(export), None),
+            ]
+        }
+        PowerDirection::Tag => {
+            let direction = if power < 0.0 { "export" } else { "import" };
+            vec![(measurement, WriteType::Float(power), Some(direction))]
+        }
+    }
+}
+
+/// Whether the given protection error is active, written like `output` as 0 or 1.
+fn error_flag(errors: &[String], error: &str) -> WriteType {
+    WriteType::Int(errors.iter().any(|active| active == error) as i32)
+}
+
 const SWITCH_FIELDS: &[(&str, WriteTypeMapper<SwitchData>, &str)] = &[
     (
         "output",
@@ -63,16 +435,54 @@ const SWITCH_FIELDS: &[(&str, WriteTypeMapper<SwitchData>, &str)] = &[
         |data: &SwitchData| data.voltage.map(WriteType::Float),
         "V",
     ),
+    (
+        "powerfactor",
+        |data: &SwitchData| data.power_factor.map(WriteType::Float),
+        "1",
+    ),
+    (
+        "frequency",
+        |data: &SwitchData| data.frequency.map(WriteType::Float),
+        "Hz",
+    ),
     (
         "total_energy",
-        |data: &SwitchData| Some(WriteType::Float(data.energy.total)),
+        |data: &SwitchData| {
+            data.energy
+                .as_ref()
+                .map(|energy| WriteType::Float(energy.total))
+        },
         "Wh",
     ),
     (
         "temperature",
-        |data: &SwitchData| Some(WriteType::Float(data.temperature.t_celsius)),
+        |data: &SwitchData| {
+            data.temperature
+                .as_ref()
+                .map(|temperature| WriteType::Float(temperature.t_celsius))
+        },
         "°C",
     ),
+    (
+        "error_overpower",
+        |data: &SwitchData| Some(error_flag(&data.errors, "overpower")),
+        "bool",
+    ),
+    (
+        "error_overtemp",
+        |data: &SwitchData| Some(error_flag(&data.errors, "overtemp")),
+        "bool",
+    ),
+    (
+        "error_overvoltage",
+        |data: &SwitchData| Some(error_flag(&data.errors, "overvoltage")),
+        "bool",
+    ),
+    (
+        "error_undervoltage",
+        |data: &SwitchData| Some(error_flag(&data.errors, "undervoltage")),
+        "bool",
+    ),
 ];
 
 const COVER_FIELDS: &[(&str, WriteTypeMapper<CoverData>, &str)] = &[
@@ -96,83 +506,329 @@ const COVER_FIELDS: &[(&str, WriteTypeMapper<CoverData>, &str)] = &[
         |data: &CoverData| data.voltage.map(WriteType::Float),
         "V",
     ),
+    (
+        "powerfactor",
+        |data: &CoverData| data.power_factor.map(WriteType::Float),
+        "1",
+    ),
+    (
+        "frequency",
+        |data: &CoverData| data.frequency.map(WriteType::Float),
+        "Hz",
+    ),
     (
         "total_energy",
-        |data: &CoverData| Some(WriteType::Float(data.energy.total)),
+        |data: &CoverData| {
+            data.energy
+                .as_ref()
+                .map(|energy| WriteType::Float(energy.total))
+        },
         "Wh",
     ),
     (
         "temperature",
-        |data: &CoverData| Some(WriteType::Float(data.temperature.t_celsius)),
+        |data: &CoverData| {
+            data.temperature
+                .as_ref()
+                .map(|temperature| WriteType::Float(temperature.t_celsius))
+        },
         "°C",
     ),
+    (
+        "error_overpower",
+        |data: &CoverData| Some(error_flag(&data.errors, "overpower")),
+        "bool",
+    ),
+    (
+        "error_overtemp",
+        |data: &CoverData| Some(error_flag(&data.errors, "overtemp")),
+        "bool",
+    ),
+    (
+        "error_overvoltage",
+        |data: &CoverData| Some(error_flag(&data.errors, "overvoltage")),
+        "bool",
+    ),
+    (
+        "error_undervoltage",
+        |data: &CoverData| Some(error_flag(&data.errors, "undervoltage")),
+        "bool",
+    ),
+];
+
+const PM1_FIELDS: &[(&str, WriteTypeMapper<PM1Data>, &str)] = &[
+    (
+        "power",
+        |data: &PM1Data| data.power.map(WriteType::Float),
+        "W",
+    ),
+    (
+        "current",
+        |data: &PM1Data| data.current.map(WriteType::Float),
+        "A",
+    ),
+    (
+        "voltage",
+        |data: &PM1Data| data.voltage.map(WriteType::Float),
+        "V",
+    ),
+    (
+        "powerfactor",
+        |data: &PM1Data| data.power_factor.map(WriteType::Float),
+        "1",
+    ),
+    (
+        "frequency",
+        |data: &PM1Data| data.frequency.map(WriteType::Float),
+        "Hz",
+    ),
+    (
+        "total_energy",
+        |data: &PM1Data| {
+            data.energy
+                .as_ref()
+                .map(|energy| WriteType::Float(energy.total))
+        },
+        "Wh",
+    ),
+    (
+        "total_returned_energy",
+        |data: &PM1Data| {
+            data.returned_energy
+                .as_ref()
+                .map(|energy| WriteType::Float(energy.total))
+        },
+        "Wh",
+    ),
+];
+
+const EM1_FIELDS: &[(&str, WriteTypeMapper<EM1Data>, &str)] = &[
+    (
+        "power",
+        |data: &EM1Data| data.power.map(WriteType::Float),
+        "W",
+    ),
+    (
+        "apparent_power",
+        |data: &EM1Data| data.apparent_power.map(WriteType::Float),
+        "VA",
+    ),
+    (
+        "current",
+        |data: &EM1Data| data.current.map(WriteType::Float),
+        "A",
+    ),
+    (
+        "voltage",
+        |data: &EM1Data| data.voltage.map(WriteType::Float),
+        "V",
+    ),
+    (
+        "powerfactor",
+        |data: &EM1Data| data.power_factor.map(WriteType::Float),
+        "1",
+    ),
+    (
+        "frequency",
+        |data: &EM1Data| data.frequency.map(WriteType::Float),
+        "Hz",
+    ),
 ];
 
-static SWITCH_REGEX: LazyLock<Regex, fn() -> Regex> =
-    LazyLock::new(|| Regex::new("/status/switch:.").unwrap());
-static COVER_REGEX: LazyLock<Regex, fn() -> Regex> =
-    LazyLock::new(|| Regex::new("/status/cover:.").unwrap());
+const EM1_ENERGY_FIELDS: &[(&str, WriteTypeMapper<EM1EnergyData>, &str)] = &[
+    (
+        "total_energy",
+        |data: &EM1EnergyData| data.energy.map(WriteType::Float),
+        "Wh",
+    ),
+    (
+        "total_returned_energy",
+        |data: &EM1EnergyData| data.returned_energy.map(WriteType::Float),
+        "Wh",
+    ),
+];
+
+static ANNOUNCE_REGEX: LazyLock<Regex, fn() -> Regex> =
+    LazyLock::new(|| Regex::new("/announce$").unwrap());
+static SYS_REGEX: LazyLock<Regex, fn() -> Regex> =
+    LazyLock::new(|| Regex::new("/status/sys$").unwrap());
 
 impl CheckMessage for ShellyLogger {
     fn check_message(&mut self, msg: &Message) {
+        self.stats.received += 1;
         let topic = msg.topic();
-        if SWITCH_REGEX.is_match(topic) {
-            handle_message(msg, &self.txs, SWITCH_FIELDS);
-        } else if COVER_REGEX.is_match(topic) {
-            handle_message(msg, &self.txs, COVER_FIELDS);
+        let component = self
+            .topic_schema
+            .matches(topic)
+            .and_then(|topic| topic.get("component"));
+        match component {
+            Some("switch") => self.handle_message(msg, SWITCH_FIELDS),
+            Some("cover") => self.handle_message(msg, COVER_FIELDS),
+            Some("pm1") => self.handle_message(msg, PM1_FIELDS),
+            Some("em1") => self.handle_message(msg, EM1_FIELDS),
+            Some("em1data") => self.handle_message(msg, EM1_ENERGY_FIELDS),
+            _ if ANNOUNCE_REGEX.is_match(topic) => {
+                self.handle_device_message::<AnnounceData>(msg, DeviceRegistry::announce)
+            }
+            _ if SYS_REGEX.is_match(topic) => {
+                self.handle_device_message::<SysData>(msg, DeviceRegistry::sys)
+            }
+            _ => {}
         }
     }
-}
 
-fn handle_message<'a, T: Deserialize<'a> + Clone + Debug + Timestamped + Typenamed>(
-    msg: &'a Message,
-    txs: &Vec<SyncSender<WriteQuery>>,
-    fields: &[(&str, WriteTypeMapper<T>, &str)],
-) {
-    let location = msg.topic().split("/").nth(1).unwrap();
-    let channel = msg.topic().split(":").last().unwrap();
-    let parse_result = shelly::parse(msg);
-    if parse_result.is_err() {
-        warn!("Shelly parse error: {:?} on '{}'", parse_result.err(), msg.payload_str());
-        return;
-    }
-    let result: Option<T> = parse_result.unwrap();
-    if let Some(data) = result {
-        debug!("Shelly {}:{}: {:?}", location, channel, data);
-
-        if let Some(minute_ts) = data.timestamp() {
-            let timestamp = Timestamp::Seconds(minute_ts as u128);
-            for (measurement, value, unit) in fields {
-                let query = WriteQuery::new(timestamp, *measurement);
-                if let Some(result) = value(&data) {
-                    let query = match result {
-                        WriteType::Int(i) => query.add_field("value", i),
-                        WriteType::Float(f) => query.add_field("value", f),
-                    };
+    fn stats(&self) -> SourceStats {
+        self.stats
+    }
+
+    fn shutdown(&mut self) {
+        self.txs.clear();
+    }
 
-                    let query = query
-                        .add_tag("location", location)
-                        .add_tag("channel", channel)
-                        .add_tag("sensor", "shelly")
-                        .add_tag("type", data.type_name())
-                        .add_tag("unit", unit);
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        let clock = self.clock.as_ref();
+        let queries = iter::once(heartbeat_query(source, messages, &self.enrichment, clock))
+            .chain(message_age_query(source, &self.enrichment, clock))
+            .chain(online_queries(source, &self.enrichment, clock));
+        for query in queries {
+            target::send_all(&self.txs, &query);
+        }
+    }
 
-                    for tx in txs {
-                        tx.send(query.clone()).expect("failed to send");
-                    }
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        let query = start_query(source, build, &self.enrichment, self.clock.as_ref());
+        target::send_all(&self.txs, &query);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        let query = static_query(source, event, time, &self.enrichment);
+        target::send_all(&self.txs, &query);
+    }
+
+    fn field_stats(&self) -> Option<String> {
+        Some(self.presence.to_string()).filter(|field_stats| !field_stats.is_empty())
+    }
+}
+
+fn fields_catalog<T>(fields: &[(&str, WriteTypeMapper<T>, &str)]) -> Vec<Measurement> {
+    fields
+        .iter()
+        .map(|(measurement, _, unit)| Measurement {
+            measurement: measurement.to_string(),
+            fields: vec!["value".to_string()],
+            tags: ["location", "channel", "sensor", "type", "unit"]
+                .into_iter()
+                .chain((*measurement == TRIGGER_MEASUREMENT).then_some("trigger"))
+                .map(|tag| tag.to_string())
+                .collect(),
+            unit: Some(unit.to_string()),
+        })
+        .collect()
+}
+
+pub fn catalog(
+    energy_by_minute: bool,
+    power_direction: Option<PowerDirection>,
+) -> Vec<Measurement> {
+    let mut measurements = fields_catalog(SWITCH_FIELDS);
+    for measurement in fields_catalog(COVER_FIELDS)
+        .into_iter()
+        .chain(fields_catalog(PM1_FIELDS))
+        .chain(fields_catalog(EM1_FIELDS))
+        .chain(fields_catalog(EM1_ENERGY_FIELDS))
+    {
+        if !measurements.contains(&measurement) {
+            measurements.push(measurement);
+        }
+    }
+    match power_direction {
+        Some(PowerDirection::Split) => {
+            let tags = ["location", "channel", "sensor", "type", "unit"];
+            measurements.retain(|measurement| measurement.measurement != POWER);
+            measurements.push(Measurement::new(POWER_IMPORT, &["value"], &tags, Some("W")));
+            measurements.push(Measurement::new(POWER_EXPORT, &["value"], &tags, Some("W")));
+        }
+        Some(PowerDirection::Tag) => {
+            for measurement in &mut measurements {
+                if measurement.measurement == POWER {
+                    measurement.tags.push("direction".to_string());
                 }
             }
-        } else {
-            warn!("{} no timestamp {:?}", msg.topic(), msg.payload_str());
         }
+        None => {}
+    }
+    if energy_by_minute {
+        measurements.push(Measurement::new(
+            ENERGY_BY_MINUTE,
+            &["value"],
+            &["location", "channel", "sensor", "type", "unit"],
+            Some("Wh"),
+        ));
     }
+    measurements
+}
+
+/// Shelly specific settings of a source.
+#[derive(Debug, Clone, Default)]
+pub struct ShellyOptions {
+    pub(crate) device_tags: Vec<DeviceTag>,
+    pub(crate) missing_field_threshold: Option<u64>,
+    pub(crate) topic_schema: Option<String>,
+    pub(crate) parse_mode: Option<ParseMode>,
+    pub(crate) energy_by_minute: bool,
+    pub(crate) power_direction: Option<PowerDirection>,
+}
+
+pub fn create_logger(
+    targets: Vec<Target>,
+    enrichment: Enrichment,
+    timestamp: Option<&TimestampConfig>,
+    options: ShellyOptions,
+) -> Result<Logger> {
+    let topic_schema = topic::parse(
+        options.topic_schema.as_deref().unwrap_or(TOPIC_SCHEMA),
+        TOPIC_VARIABLES,
+    )?;
+    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for target in targets {
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            target::spawn_writer(target.clone(), &Mappers::queries("shelly"))
+        })?;
+        txs.push(tx);
+        handles.push(handle);
+    }
+
+    Ok((
+        Arc::new(Mutex::new(
+            ShellyLogger::new(txs, enrichment)
+                .with_timestamp_policy(TIMESTAMP_POLICY.with_config(timestamp))
+                .with_device_tags(options.device_tags)
+                .with_power_direction(options.power_direction)
+                .with_missing_field_threshold(
+                    options
+                        .missing_field_threshold
+                        .unwrap_or(DEFAULT_MISSING_FIELD_THRESHOLD),
+                )
+                .with_topic_schema(topic_schema)
+                .with_parse_mode(options.parse_mode.unwrap_or_default())
+                .with_energy_by_minute(options.energy_by_minute),
+        )),
+        handles,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use influxdb::Query;
+    use crate::data::clock::ManualClock;
+    use crate::data::query::Query;
+    use crate::data::validate::strategies::{finite, location, timestamp};
+    use crate::data::validate::Validate;
+    use anyhow::Result;
     use paho_mqtt::QOS_1;
+    use proptest::prelude::*;
     use std::sync::mpsc::{sync_channel, Receiver};
     use std::time::Duration;
 
@@ -180,50 +836,374 @@ mod tests {
         Ok(rx.recv_timeout(Duration::from_micros(100))?.build()?.get())
     }
 
+    proptest! {
+        #[test]
+        fn test_switch_events_valid(
+            location in location(),
+            power in finite(),
+            voltage in finite(),
+            minute_ts in timestamp(),
+        ) {
+            let (tx, rx) = sync_channel(100);
+            let mut logger = ShellyLogger::new(vec![tx], Enrichment::default());
+
+            logger.check_message(&Message::new(
+                format!("shellies/{}/status/switch:0", location),
+                format!(
+                    "{{\"id\":0, \"output\":true, \"apower\":{}, \"voltage\":{}, \
+                    \"aenergy\":{{\"total\":1.5,\"minute_ts\":{}}}}}",
+                    power, voltage, minute_ts
+                ),
+                QOS_1,
+            ));
+
+            let events: Vec<WriteQuery> = rx.try_iter().collect();
+            prop_assert!(events.len() > 3);
+            for event in events {
+                prop_assert_eq!(event.validate(), Ok(()));
+            }
+            prop_assert_eq!(logger.stats().dropped, 0);
+        }
+    }
+
+    #[test]
+    fn test_dispatch_on_component_of_topic_schema() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger = ShellyLogger::new(vec![tx], Enrichment::default()).with_topic_schema(
+            topic::parse("home/{location}/{component}/{channel}", TOPIC_VARIABLES)?,
+        );
+        let payload = "{\"id\":0, \"source\":\"timer\", \"output\":false, \
+            \"aenergy\":{\"total\":1094.865,\"minute_ts\":1703415907}}";
+        logger.check_message(&Message::new("home/loo-fan/switch/1", payload, QOS_1));
+
+        assert!(next(&rx)?.starts_with("output,location=loo-fan,channel=1,"));
+        assert!(next(&rx)?.starts_with("total_energy,location=loo-fan,channel=1,"));
+        while next(&rx).is_ok() {}
+
+        logger.check_message(&Message::new("home/loo-fan/input/1", payload, QOS_1));
+        assert!(next(&rx).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_handle_switch_message() -> Result<()> {
         let (tx, rx) = sync_channel(100);
         let txs = vec![tx];
 
-        let mut logger = ShellyLogger::new(txs);
+        let mut logger = ShellyLogger::new(txs, Enrichment::default());
 
         let message = Message::new(
             "shellies/loo-fan/status/switch:1",
             "{\"id\":0, \"source\":\"timer\", \"output\":false, \
             \"apower\":0.0, \"voltage\":226.5, \"current\":3.1, \
             \"aenergy\":{\"total\":1094.865,\"by_minute\":[0.000,0.000,0.000],\
-            \"minute_ts\":1703415907},\"temperature\":{\"tC\":36.4, \"tF\":97.5}}",
+            \"minute_ts\":1703415907},\"temperature\":{\"tC\":36.4, \"tF\":97.5},\
+            \"errors\":[\"overpower\"]}",
             QOS_1,
         );
         logger.check_message(&message);
 
         assert!(next(&rx)?.starts_with(
-            "output,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=bool value=0i "
+            "output,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=bool,trigger=timer value=0i "
         ));
         assert!(next(&rx)?.starts_with(
             "power,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=W value=0 "
         ));
         assert!(next(&rx)?.starts_with(
-            "current,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=A value=3.0999"
+            "current,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=A value=3.1 "
         ));
         assert!(next(&rx)?.starts_with(
             "voltage,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=V value=226.5 "
         ));
-        assert!(next(&rx)?.starts_with("total_energy,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=Wh value=1094.86"));
+        assert!(next(&rx)?.starts_with("total_energy,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=Wh value=1094.865 "));
+        assert!(next(&rx)?.starts_with(
+            "temperature,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=°C value=36.4 "
+        ));
+        assert!(next(&rx)?.starts_with(
+            "error_overpower,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=bool value=1i "
+        ));
+        assert!(next(&rx)?.starts_with(
+            "error_overtemp,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=bool value=0i "
+        ));
+        assert!(next(&rx)?.starts_with("error_overvoltage,"));
+        assert!(next(&rx)?.starts_with("error_undervoltage,"));
+
+        assert!(next(&rx).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_flags_written_when_set_or_cleared() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut logger = ShellyLogger::new(vec![tx], Enrichment::default());
+        let message = |errors: &str| {
+            Message::new(
+                "shellies/loo-fan/status/switch:1",
+                format!(
+                    "{{\"id\":0, \"output\":true, \"aenergy\":{{\"total\":1094.865,\
+                    \"minute_ts\":1703415907}}, \"errors\":[{}]}}",
+                    errors
+                ),
+                QOS_1,
+            )
+        };
+        // measurement and field of the error flags written
+        let errors = |rx: &Receiver<WriteQuery>| -> Vec<String> {
+            std::iter::from_fn(|| next(rx).ok())
+                .filter(|line| line.starts_with(ERROR_PREFIX))
+                .map(|line| {
+                    let mut parts = line.split(' ');
+                    let series = parts.next().unwrap_or_default();
+                    let measurement = series.split(',').next().unwrap_or_default();
+                    format!("{} {}", measurement, parts.next().unwrap_or_default())
+                })
+                .collect()
+        };
+
+        logger.check_message(&message(""));
+        assert_eq!(errors(&rx).len(), 4);
+
+        logger.check_message(&message(""));
+        assert!(errors(&rx).is_empty());
+
+        logger.check_message(&message("\"overtemp\""));
+        assert_eq!(errors(&rx), vec!["error_overtemp value=1i"]);
+        logger.check_message(&message("\"overtemp\""));
+        assert_eq!(errors(&rx), vec!["error_overtemp value=1i"]);
+
+        logger.check_message(&message(""));
+        assert_eq!(errors(&rx), vec!["error_overtemp value=0i"]);
+        logger.check_message(&message(""));
+        assert!(errors(&rx).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_pm1_message() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut logger =
+            ShellyLogger::new(vec![tx], Enrichment::default()).with_parse_mode(ParseMode::Strict);
+
+        let message = Message::new(
+            "shellies/balcony/status/pm1:0",
+            "{\"id\":0, \"voltage\":231.2, \"current\":2.5, \"apower\":-560.0, \
+            \"freq\":50.0, \"aenergy\":{\"total\":125.5,\"by_minute\":[0.0,0.0,0.0],\
+            \"minute_ts\":1703415907},\"ret_aenergy\":{\"total\":2048.25,\
+            \"by_minute\":[9333.3,9333.3,9333.3],\"minute_ts\":1703415907}}",
+            QOS_1,
+        );
+        logger.check_message(&message);
+
+        assert!(next(&rx)?.starts_with(
+            "power,location=balcony,channel=0,sensor=shelly,type=pm1,unit=W value=-560 "
+        ));
+        assert!(next(&rx)?.starts_with("current,"));
+        assert!(next(&rx)?.starts_with("voltage,"));
+        assert!(next(&rx)?.starts_with("frequency,"));
+        assert!(next(&rx)?.starts_with(
+            "total_energy,location=balcony,channel=0,sensor=shelly,type=pm1,unit=Wh value=125.5 "
+        ));
+        assert!(next(&rx)?.starts_with("total_returned_energy,location=balcony,channel=0,sensor=shelly,type=pm1,unit=Wh value=2048.25 "));
+
+        assert!(next(&rx).is_err());
+        assert_eq!(logger.stats().dropped, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_em1_messages() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut logger = ShellyLogger {
+            clock: Arc::new(ManualClock::at(1703415907)),
+            ..ShellyLogger::new(vec![tx], Enrichment::default())
+        };
+
+        logger.check_message(&Message::new(
+            "shellies/grid/status/em1:1",
+            "{\"id\":1, \"current\":4.2, \"voltage\":230.1, \"act_power\":-950.5, \
+            \"aprt_power\":966.4, \"pf\":0.98, \"freq\":50.0, \"calibration\":\"factory\"}",
+            QOS_1,
+        ));
+        logger.check_message(&Message::new(
+            "shellies/grid/status/em1data:1",
+            "{\"id\":1, \"total_act_energy\":1520.25, \"total_act_ret_energy\":8731.5}",
+            QOS_1,
+        ));
+
+        assert_eq!(
+            next(&rx)?,
+            "power,location=grid,channel=1,sensor=shelly,type=em1,unit=W value=-950.5 1703415907"
+        );
+        assert!(next(&rx)?.starts_with(
+            "apparent_power,location=grid,channel=1,sensor=shelly,type=em1,unit=VA value=966.4 "
+        ));
+        assert!(next(&rx)?.starts_with("current,"));
+        assert!(next(&rx)?.starts_with("voltage,"));
+        assert!(next(&rx)?.starts_with("powerfactor,"));
+        assert!(next(&rx)?.starts_with("frequency,"));
+        assert!(next(&rx)?.starts_with("total_energy,location=grid,channel=1,sensor=shelly,type=em1data,unit=Wh value=1520.25 "));
+        assert!(next(&rx)?.starts_with("total_returned_energy,location=grid,channel=1,sensor=shelly,type=em1data,unit=Wh value=8731.5 "));
+
+        assert!(next(&rx).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_power_direction() -> Result<()> {
+        let message = Message::new(
+            "shellies/balcony/status/pm1:0",
+            "{\"id\":0, \"apower\":-560.0, \"aenergy\":{\"total\":125.5,\
+            \"minute_ts\":1703415907}}",
+            QOS_1,
+        );
+        let (tx, rx) = sync_channel(100);
+        let mut split = ShellyLogger::new(vec![tx], Enrichment::default())
+            .with_power_direction(Some(PowerDirection::Split));
+        split.check_message(&message);
+
         assert!(next(&rx)?.starts_with(
-            "temperature,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=°C value=36.40"
+            "power_import,location=balcony,channel=0,sensor=shelly,type=pm1,unit=W value=0 "
         ));
+        assert!(next(&rx)?.starts_with(
+            "power_export,location=balcony,channel=0,sensor=shelly,type=pm1,unit=W value=560 "
+        ));
+        assert!(next(&rx)?.starts_with("total_energy,"));
+
+        let (tx, rx) = sync_channel(100);
+        let mut tag = ShellyLogger::new(vec![tx], Enrichment::default())
+            .with_power_direction(Some(PowerDirection::Tag));
+        tag.check_message(&message);
+
+        assert!(next(&rx)?.starts_with(
+            "power,location=balcony,channel=0,sensor=shelly,type=pm1,unit=W,direction=export value=-560 "
+        ));
+        assert!(next(&rx)?.starts_with("total_energy,"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_energy_by_minute() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+        let mut logger =
+            ShellyLogger::new(vec![tx], Enrichment::default()).with_energy_by_minute(true);
+        let message = |minute_ts: i64, by_minute: &str| {
+            Message::new(
+                "shellies/loo-fan/status/switch:1",
+                format!(
+                    "{{\"id\":0, \"output\":true, \"aenergy\":{{\"total\":1.0,\
+                    \"by_minute\":{},\"minute_ts\":{}}}}}",
+                    by_minute, minute_ts
+                ),
+                QOS_1,
+            )
+        };
+
+        logger.check_message(&message(1703415900, "[1000.0,2000.0,3000.0]"));
+        let energy: Vec<String> = rx
+            .try_iter()
+            .map(|query| query.build().unwrap().get())
+            .filter(|line| line.starts_with("energy_by_minute,"))
+            .collect();
+        assert_eq!(energy.len(), 3);
+        assert!(energy[0].starts_with(
+            "energy_by_minute,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=Wh value=3 "
+        ));
+        assert!(energy[2].contains(" value=1 "));
+
+        logger.check_message(&message(1703415960, "[4000.0,1000.0,2000.0]"));
+        let energy: Vec<String> = rx
+            .try_iter()
+            .map(|query| query.build().unwrap().get())
+            .filter(|line| line.starts_with("energy_by_minute,"))
+            .collect();
+        assert_eq!(energy.len(), 1);
+        assert!(energy[0].contains(" value=4 "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_message_without_temperature() -> Result<()> {
+        let message = Message::new(
+            "shellies/loo-fan/status/switch:1",
+            "{\"id\":0, \"output\":true, \"aenergy\":{\"total\":1.0,\"minute_ts\":1703415907}}",
+            QOS_1,
+        );
+
+        let (tx, rx) = sync_channel(100);
+        let mut logger = ShellyLogger::new(vec![tx], Enrichment::default());
+        logger.check_message(&message);
+
+        assert!(next(&rx)?.starts_with("output,"));
+        assert!(next(&rx)?.starts_with("total_energy,"));
+        assert!(next(&rx)?.starts_with("error_overpower,"));
+
+        let (tx, rx) = sync_channel(100);
+        let mut logger =
+            ShellyLogger::new(vec![tx], Enrichment::default()).with_parse_mode(ParseMode::Strict);
+        logger.check_message(&message);
 
         assert!(next(&rx).is_err());
+        assert_eq!(logger.stats().dropped, 1);
         Ok(())
     }
 
+    #[test]
+    fn test_handle_message_with_device_tags() -> Result<()> {
+        let (tx, rx) = sync_channel(100);
+
+        let mut logger = ShellyLogger::new(vec![tx], Enrichment::default())
+            .with_device_tags(vec![DeviceTag::Model, DeviceTag::Firmware]);
+
+        logger.check_message(&Message::new(
+            "shellies/loo-fan/announce",
+            "{\"id\":\"loo-fan\", \"model\":\"SNSW-102P16EU\", \"ver\":\"1.4.4\"}",
+            QOS_1,
+        ));
+        logger.check_message(&Message::new(
+            "shellies/loo-fan/status/switch:1",
+            "{\"id\":0, \"output\":true, \"aenergy\":{\"total\":1.0,\"minute_ts\":1703415907},\
+            \"temperature\":{\"tC\":36.4}}",
+            QOS_1,
+        ));
+
+        assert!(next(&rx)?.starts_with(
+            "output,location=loo-fan,channel=1,sensor=shelly,type=switch,unit=bool,model=SNSW-102P16EU,fw=1.4.4 value=1i "
+        ));
+        assert_eq!(logger.stats().parsed, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_field_stats_of_device_without_metering() {
+        let (tx, _rx) = sync_channel(100);
+
+        let mut logger =
+            ShellyLogger::new(vec![tx], Enrichment::default()).with_missing_field_threshold(1);
+        assert_eq!(logger.field_stats(), None);
+
+        logger.check_message(&Message::new(
+            "shellies/loo-fan/status/switch:1",
+            "{\"id\":0, \"output\":true, \"aenergy\":{\"total\":1.0,\"minute_ts\":1703415907},\
+            \"temperature\":{\"tC\":36.4}}",
+            QOS_1,
+        ));
+
+        assert_eq!(
+            logger.field_stats(),
+            Some(
+                "loo-fan:1 (current 0/1, frequency 0/1, power 0/1, powerfactor 0/1, voltage 0/1)"
+                    .to_string()
+            )
+        );
+    }
+
     #[test]
     fn test_handle_curtain_message() -> Result<()> {
         let (tx, rx) = sync_channel(100);
         let txs = vec![tx];
 
-        let mut logger = ShellyLogger::new(txs);
+        let mut logger = ShellyLogger::new(txs, Enrichment::default());
 
         let message = Message::new(
             "shellies/bedroom-curtain/status/cover:0",
@@ -243,9 +1223,17 @@ mod tests {
         assert!(next(&rx)?.starts_with(
             "current,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=A value=0.5 "
         ));
-        assert!(next(&rx)?.starts_with("voltage,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=V value=231.6999"));
-        assert!(next(&rx)?.starts_with("total_energy,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=Wh value=3.14"));
-        assert!(next(&rx)?.starts_with("temperature,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=°C value=30.7"));
+        assert!(next(&rx)?.starts_with("voltage,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=V value=231.7 "));
+        assert!(next(&rx)?.starts_with("powerfactor,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=1 value=0 "));
+        assert!(next(&rx)?.starts_with("frequency,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=Hz value=50 "));
+        assert!(next(&rx)?.starts_with("total_energy,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=Wh value=3.143 "));
+        assert!(next(&rx)?.starts_with("temperature,location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=°C value=30.7 "));
+        for error in ["overpower", "overtemp", "overvoltage", "undervoltage"] {
+            assert!(next(&rx)?.starts_with(&format!(
+                "error_{},location=bedroom-curtain,channel=0,sensor=shelly,type=cover,unit=bool value=0i ",
+                error
+            )));
+        }
         assert!(next(&rx).is_err());
 
         Ok(())
@@ -256,7 +1244,8 @@ mod tests {
         let (tx, rx) = sync_channel(100);
         let txs = vec![tx];
 
-        let mut logger = ShellyLogger::new(txs);
+        let mut logger =
+            ShellyLogger::new(txs, Enrichment::default()).with_parse_mode(ParseMode::Strict);
 
         let message = Message::new(
             "shellies/bedroom-curtain/status/cover:0",
@@ -267,6 +1256,15 @@ mod tests {
         logger.check_message(&message);
 
         assert!(next(&rx).is_err());
+        assert_eq!(
+            logger.stats(),
+            SourceStats {
+                received: 1,
+                parsed: 0,
+                dropped: 1,
+                forwarded: 0
+            }
+        );
 
         Ok(())
     }
@@ -275,12 +1273,12 @@ mod tests {
         let message = Message::new("shellies/loo-fan/status/switch:0", "{\"id\":0, \"source\":\"timer\", \"output\":false, \"apower\":0.0, \"voltage\":226.5, \"current\":3.1, \"aenergy\":{\"total\":1094.865,\"by_minute\":[0.000,0.000,0.000],\"minute_ts\":1703415907},\"temperature\":{\"tC\":36.4, \"tF\":97.5}}", QOS_1);
         let result: SwitchData = parse(&message)?;
 
-        assert_eq!(result.output, false);
+        assert!(!result.output);
         assert_eq!(result.power, Some(0.0));
         assert_eq!(result.voltage, Some(226.5));
         assert_eq!(result.current, Some(3.1));
-        assert_eq!(result.energy.total, 1094.865);
-        assert_eq!(result.temperature.t_celsius, 36.4);
+        assert_eq!(result.energy.unwrap().total, 1094.865);
+        assert_eq!(result.temperature.unwrap().t_celsius, 36.4);
 
         Ok(())
     }
@@ -294,9 +1292,12 @@ mod tests {
         assert_eq!(result.power, Some(0.0));
         assert_eq!(result.voltage, Some(231.7));
         assert_eq!(result.current, Some(0.5));
-        assert_eq!(result.energy.total, 3.143);
-        assert_eq!(result.temperature.t_celsius, 30.7);
-        assert_eq!(result.energy.minute_ts.unwrap(), 1703414519);
+        assert_eq!(result.power_factor, Some(0.0));
+        assert_eq!(result.frequency, Some(50.0));
+        let energy = result.energy.unwrap();
+        assert_eq!(energy.total, 3.143);
+        assert_eq!(energy.minute_ts.unwrap(), 1703414519);
+        assert_eq!(result.temperature.unwrap().t_celsius, 30.7);
 
         Ok(())
     }
@@ -306,7 +1307,7 @@ mod tests {
         let message = Message::new("shellies/bedroom-curtain/status/cover:0", "{\"id\":0, \"source\":\"limit_switch\", \"state\":\"open\",\"apower\":0.0,\"voltage\":231.7,\"current\":0.500,\"pf\":0.00,\"freq\":50.0,\"aenergy\":{\"total\":3.143,\"by_minute\":[0.000,0.000,97.712]},\"temperature\":{\"tC\":30.7, \"tF\":87.3},\"pos_control\":true,\"last_direction\":\"open\",\"current_pos\":100}", QOS_1);
         let result: CoverData = parse(&message)?;
 
-        assert!(result.energy.minute_ts.is_none());
+        assert!(result.energy.unwrap().minute_ts.is_none());
 
         Ok(())
     }
@@ -321,29 +1322,3 @@ mod tests {
         Ok(())
     }
 }
-
-pub fn create_logger(targets: Vec<Target>) -> (Arc<Mutex<dyn CheckMessage>>, Vec<JoinHandle<()>>) {
-    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-
-    for target in targets {
-        let (tx, handle) = match target {
-            Target::InfluxDB {
-                url,
-                database,
-                user,
-                password,
-            } => influx::spawn_influxdb_writer(
-                InfluxConfig::new(url, database, user, password),
-                std::convert::identity,
-            ),
-            Target::Postgresql { .. } => {
-                panic!("Postgresql not supported for shelly");
-            }
-        };
-        txs.push(tx);
-        handles.push(handle);
-    }
-
-    (Arc::new(Mutex::new(ShellyLogger::new(txs))), handles)
-}
@@ -0,0 +1,116 @@
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Number of messages after which a device is reported for optional fields it never sent.
+pub const DEFAULT_MISSING_FIELD_THRESHOLD: u64 = 100;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct DevicePresence {
+    messages: u64,
+    fields: BTreeMap<&'static str, u64>,
+    warned: bool,
+}
+
+/// Counts per device (`<location>:<channel>`) how often each field was part of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPresence {
+    threshold: u64,
+    devices: BTreeMap<String, DevicePresence>,
+}
+
+impl FieldPresence {
+    pub fn new(threshold: u64) -> Self {
+        FieldPresence {
+            threshold: threshold.max(1),
+            devices: BTreeMap::new(),
+        }
+    }
+
+    /// Records a message with the given fields and whether they were present, returns the fields
+    /// the device never reported once it reaches the threshold.
+    pub fn record(
+        &mut self,
+        device: &str,
+        fields: impl IntoIterator<Item = (&'static str, bool)>,
+    ) -> Vec<&'static str> {
+        let presence = self.devices.entry(device.to_string()).or_default();
+        presence.messages += 1;
+        for (field, present) in fields {
+            *presence.fields.entry(field).or_default() += present as u64;
+        }
+        if presence.warned || presence.messages < self.threshold {
+            return Vec::new();
+        }
+        presence.warned = true;
+        let missing: Vec<&'static str> = presence
+            .fields
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(field, _)| *field)
+            .collect();
+        if !missing.is_empty() {
+            warn!(
+                "Shelly {} did not report {} in {} messages",
+                device,
+                missing.join(", "),
+                presence.messages
+            );
+        }
+        missing
+    }
+}
+
+/// Lists the devices with fields missing in some of their messages, e.g.
+/// `loo-fan:1 (power 0/120, voltage 0/120)`.
+impl fmt::Display for FieldPresence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let devices: Vec<String> = self
+            .devices
+            .iter()
+            .filter_map(|(device, presence)| {
+                let fields: Vec<String> = presence
+                    .fields
+                    .iter()
+                    .filter(|(_, count)| **count < presence.messages)
+                    .map(|(field, count)| format!("{} {}/{}", field, count, presence.messages))
+                    .collect();
+                (!fields.is_empty()).then(|| format!("{} ({})", device, fields.join(", ")))
+            })
+            .collect();
+        write!(f, "{}", devices.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_reports_missing_fields_once() {
+        let mut presence = FieldPresence::new(2);
+
+        assert!(presence
+            .record("loo-fan:1", [("power", false), ("voltage", true)])
+            .is_empty());
+        assert_eq!(
+            presence.record("loo-fan:1", [("power", false), ("voltage", true)]),
+            vec!["power"]
+        );
+        assert!(presence
+            .record("loo-fan:1", [("power", false), ("voltage", true)])
+            .is_empty());
+        assert_eq!(presence.to_string(), "loo-fan:1 (power 0/3)");
+    }
+
+    #[test]
+    fn test_display_partially_present_fields() {
+        let mut presence = FieldPresence::new(10);
+
+        presence.record("bedroom-curtain:0", [("position", true)]);
+        presence.record("bedroom-curtain:0", [("position", false)]);
+        presence.record("kitchen:0", [("position", true)]);
+
+        assert_eq!(presence.to_string(), "bedroom-curtain:0 (position 1/2)");
+    }
+}
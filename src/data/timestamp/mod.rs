@@ -0,0 +1,210 @@
+use crate::config::{MissingTimestamp, TimestampConfig};
+use crate::data::age;
+use crate::data::clock::Clock;
+use std::fmt;
+
+pub trait Timestamped {
+    fn timestamp(&self) -> Option<i64>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampError {
+    Missing,
+    Offset(i64),
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimestampError::Missing => write!(f, "missing timestamp"),
+            TimestampError::Offset(offset) => write!(f, "timestamp offset of {}s", offset),
+        }
+    }
+}
+
+/// Decides which timestamp an event gets: payloads without one are either dropped or stamped with
+/// the receive time, timestamps more than `max_offset` seconds older than the current time are
+/// dropped.
+/// If backfill is allowed, payloads marked as catch-up data skip the offset check. With `align`
+/// the resulting timestamps are truncated to multiples of that many seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimestampPolicy {
+    missing: MissingTimestamp,
+    max_offset: Option<i64>,
+    allow_backfill: bool,
+    align: Option<i64>,
+}
+
+impl TimestampPolicy {
+    pub const fn new(missing: MissingTimestamp, max_offset: Option<i64>) -> Self {
+        TimestampPolicy {
+            missing,
+            max_offset,
+            allow_backfill: false,
+            align: None,
+        }
+    }
+
+    /// Overrides the source specific defaults with the configured values.
+    pub fn with_config(self, config: Option<&TimestampConfig>) -> Self {
+        match config {
+            Some(config) => TimestampPolicy {
+                missing: config.missing.unwrap_or(self.missing),
+                max_offset: config.max_offset.or(self.max_offset),
+                allow_backfill: config.allow_backfill.unwrap_or(self.allow_backfill),
+                align: config.align.map(|align| align as i64).or(self.align),
+            },
+            None => self,
+        }
+    }
+
+    /// Policy stamping payloads without timestamp with the receive time, e.g. for devices which
+    /// never send one.
+    #[cfg(feature = "shelly")]
+    pub fn with_receive_time(self) -> Self {
+        TimestampPolicy {
+            missing: MissingTimestamp::Now,
+            ..self
+        }
+    }
+
+    /// Policy for a payload, without offset check if it is marked as backfill and that is allowed.
+    #[cfg(feature = "klimalogger")]
+    pub fn for_payload(self, backfill: bool) -> Self {
+        if backfill && self.allow_backfill {
+            TimestampPolicy {
+                max_offset: None,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn resolve(
+        &self,
+        timestamp: Option<i64>,
+        clock: &dyn Clock,
+    ) -> Result<i64, TimestampError> {
+        let now = clock.timestamp();
+        if let Some(timestamp) = timestamp {
+            age::observe(now - timestamp);
+        }
+        self.resolve_at(timestamp, now)
+    }
+
+    fn resolve_at(&self, timestamp: Option<i64>, now: i64) -> Result<i64, TimestampError> {
+        let timestamp = match (timestamp, self.missing) {
+            (Some(timestamp), _) => timestamp,
+            (None, MissingTimestamp::Now) => return Ok(self.aligned(now)),
+            (None, MissingTimestamp::Drop) => return Err(TimestampError::Missing),
+        };
+
+        match self.max_offset {
+            Some(max_offset) if now - timestamp > max_offset => {
+                Err(TimestampError::Offset(now - timestamp))
+            }
+            _ => Ok(self.aligned(timestamp)),
+        }
+    }
+
+    fn aligned(&self, timestamp: i64) -> i64 {
+        match self.align {
+            Some(align) if align > 1 => timestamp - timestamp.rem_euclid(align),
+            _ => timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1701292592;
+
+    #[test]
+    fn test_missing_timestamp() {
+        let drop = TimestampPolicy::new(MissingTimestamp::Drop, None);
+        let now = TimestampPolicy::new(MissingTimestamp::Now, None);
+
+        assert_eq!(drop.resolve_at(None, NOW), Err(TimestampError::Missing));
+        assert_eq!(now.resolve_at(None, NOW), Ok(NOW));
+        assert_eq!(drop.resolve_at(Some(NOW - 3600), NOW), Ok(NOW - 3600));
+    }
+
+    #[test]
+    fn test_max_offset() {
+        let policy = TimestampPolicy::new(MissingTimestamp::Drop, Some(10));
+
+        assert_eq!(policy.resolve_at(Some(NOW - 10), NOW), Ok(NOW - 10));
+        assert_eq!(
+            policy.resolve_at(Some(NOW - 11), NOW),
+            Err(TimestampError::Offset(11))
+        );
+        // only readings which are too old are dropped
+        assert_eq!(policy.resolve_at(Some(NOW + 11), NOW), Ok(NOW + 11));
+    }
+
+    #[test]
+    fn test_with_config() {
+        let policy = TimestampPolicy::new(MissingTimestamp::Drop, Some(10)).with_config(Some(
+            &TimestampConfig {
+                missing: Some(MissingTimestamp::Now),
+                max_offset: None,
+                allow_backfill: None,
+                align: None,
+            },
+        ));
+
+        assert_eq!(
+            policy,
+            TimestampPolicy::new(MissingTimestamp::Now, Some(10))
+        );
+    }
+
+    #[test]
+    fn test_align() {
+        let policy = TimestampPolicy::new(MissingTimestamp::Now, Some(10)).with_config(Some(
+            &TimestampConfig {
+                missing: None,
+                max_offset: None,
+                allow_backfill: None,
+                align: Some(10),
+            },
+        ));
+
+        assert_eq!(policy.resolve_at(Some(NOW - 9), NOW), Ok(NOW - 12));
+        assert_eq!(policy.resolve_at(None, NOW), Ok(NOW - 2));
+        assert_eq!(
+            policy.resolve_at(Some(NOW - 11), NOW),
+            Err(TimestampError::Offset(11))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "klimalogger")]
+    fn test_backfill() {
+        let policy = TimestampPolicy::new(MissingTimestamp::Drop, Some(10));
+        let backfill = policy.with_config(Some(&TimestampConfig {
+            missing: None,
+            max_offset: None,
+            allow_backfill: Some(true),
+            align: None,
+        }));
+
+        assert_eq!(
+            policy.for_payload(true).resolve_at(Some(NOW - 3600), NOW),
+            Err(TimestampError::Offset(3600))
+        );
+        assert_eq!(
+            backfill
+                .for_payload(false)
+                .resolve_at(Some(NOW - 3600), NOW),
+            Err(TimestampError::Offset(3600))
+        );
+        assert_eq!(
+            backfill.for_payload(true).resolve_at(Some(NOW - 3600), NOW),
+            Ok(NOW - 3600)
+        );
+    }
+}
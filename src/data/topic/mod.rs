@@ -0,0 +1,239 @@
+use crate::error::{GatewayError, Result};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// Literal text and `{name}` variables, e.g. `status` or `{component}:{channel}`.
+    Pattern(Vec<Part>),
+    /// `+`, matches any single level.
+    Any,
+    /// `#` at the end, matches all remaining levels including none.
+    Rest,
+}
+
+/// Layout of the topics of a source like `{prefix}/{location}/{measurement}`, which extracts
+/// the values of the named variables from a topic.
+///
+/// Levels are separated by `/`. A level consists of literal text and `{name}` variables, which
+/// match at least one character up to the next literal text of the level. `+` matches any level
+/// and a final `#` any number of remaining levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicSchema {
+    segments: Vec<Segment>,
+}
+
+/// Values of the variables of a [`TopicSchema`] matched by a topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicMatch<'s, 't> {
+    values: Vec<(&'s str, &'t str)>,
+}
+
+impl<'t> TopicMatch<'_, 't> {
+    pub fn get(&self, name: &str) -> Option<&'t str> {
+        self.values
+            .iter()
+            .find(|(variable, _)| *variable == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+fn parse_segment(segment: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = segment;
+    while !rest.is_empty() {
+        match rest.find('{') {
+            Some(0) => {
+                let end = rest.find('}').ok_or_else(|| {
+                    GatewayError::config(format!("unclosed variable in topic level '{}'", segment))
+                })?;
+                let name = &rest[1..end];
+                if name.is_empty() || name.contains('{') {
+                    return Err(GatewayError::config(format!(
+                        "invalid variable in topic level '{}'",
+                        segment
+                    )));
+                }
+                if matches!(parts.last(), Some(Part::Variable(_))) {
+                    return Err(GatewayError::config(format!(
+                        "variables in topic level '{}' must be separated by text",
+                        segment
+                    )));
+                }
+                parts.push(Part::Variable(name.to_string()));
+                rest = &rest[end + 1..];
+            }
+            Some(start) => {
+                parts.push(Part::Literal(rest[..start].to_string()));
+                rest = &rest[start..];
+            }
+            None => {
+                parts.push(Part::Literal(rest.to_string()));
+                rest = "";
+            }
+        }
+    }
+    Ok(parts)
+}
+
+/// Matches a single level, variables extend up to the first occurrence of the following text.
+fn match_segment<'s, 't>(
+    parts: &'s [Part],
+    mut level: &'t str,
+    values: &mut Vec<(&'s str, &'t str)>,
+) -> bool {
+    for (index, part) in parts.iter().enumerate() {
+        match part {
+            Part::Literal(text) => match level.strip_prefix(text.as_str()) {
+                Some(rest) => level = rest,
+                None => return false,
+            },
+            Part::Variable(name) => {
+                let end = match parts.get(index + 1) {
+                    Some(Part::Literal(text)) => match level.find(text.as_str()) {
+                        Some(end) => end,
+                        None => return false,
+                    },
+                    _ => level.len(),
+                };
+                if end == 0 {
+                    return false;
+                }
+                values.push((name, &level[..end]));
+                level = &level[end..];
+            }
+        }
+    }
+    level.is_empty()
+}
+
+impl TopicSchema {
+    pub fn matches<'t>(&self, topic: &'t str) -> Option<TopicMatch<'_, 't>> {
+        let mut values = Vec::new();
+        let mut levels = topic.split('/');
+        for segment in &self.segments {
+            match segment {
+                Segment::Rest => return Some(TopicMatch { values }),
+                Segment::Any => {
+                    levels.next()?;
+                }
+                Segment::Pattern(parts) => {
+                    if !match_segment(parts, levels.next()?, &mut values) {
+                        return None;
+                    }
+                }
+            }
+        }
+        levels.next().is_none().then_some(TopicMatch { values })
+    }
+
+    /// Whether the schema defines the given variable.
+    pub fn has(&self, name: &str) -> bool {
+        self.segments.iter().any(|segment| match segment {
+            Segment::Pattern(parts) => parts
+                .iter()
+                .any(|part| matches!(part, Part::Variable(variable) if variable == name)),
+            _ => false,
+        })
+    }
+}
+
+impl FromStr for TopicSchema {
+    type Err = GatewayError;
+
+    fn from_str(schema: &str) -> Result<Self> {
+        let levels: Vec<&str> = schema.split('/').collect();
+        let segments = levels
+            .iter()
+            .enumerate()
+            .map(|(index, level)| match *level {
+                "+" => Ok(Segment::Any),
+                "#" if index == levels.len() - 1 => Ok(Segment::Rest),
+                "#" => Err(GatewayError::config(format!(
+                    "'#' is only allowed at the end of topic schema '{}'",
+                    schema
+                ))),
+                "" => Err(GatewayError::config(format!(
+                    "empty level in topic schema '{}'",
+                    schema
+                ))),
+                level => parse_segment(level).map(Segment::Pattern),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(TopicSchema { segments })
+    }
+}
+
+/// Parses a schema and checks that it defines all variables the source needs.
+pub fn parse(schema: &str, required: &[&str]) -> Result<TopicSchema> {
+    let topic_schema: TopicSchema = schema.parse()?;
+    match required.iter().find(|name| !topic_schema.has(name)) {
+        Some(name) => Err(GatewayError::config(format!(
+            "topic schema '{}' lacks the variable {{{}}}",
+            schema, name
+        ))),
+        None => Ok(topic_schema),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        let schema: TopicSchema = "{prefix}/{location}/{measurement}".parse()?;
+
+        let values = schema.matches("sensors/kitchen/temperature").unwrap();
+        assert_eq!(values.get("prefix"), Some("sensors"));
+        assert_eq!(values.get("location"), Some("kitchen"));
+        assert_eq!(values.get("measurement"), Some("temperature"));
+        assert_eq!(values.get("sensor"), None);
+
+        assert!(schema.matches("sensors/kitchen").is_none());
+        assert!(schema.matches("sensors/kitchen/temperature/raw").is_none());
+        assert!(schema.matches("sensors//temperature").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_within_level() -> Result<()> {
+        let schema: TopicSchema = "+/{location}/status/{component}:{channel}".parse()?;
+
+        let values = schema.matches("shellies/loo-fan/status/switch:1").unwrap();
+        assert_eq!(values.get("location"), Some("loo-fan"));
+        assert_eq!(values.get("component"), Some("switch"));
+        assert_eq!(values.get("channel"), Some("1"));
+
+        assert!(schema.matches("shellies/loo-fan/status/sys").is_none());
+        assert!(schema.matches("shellies/loo-fan/events/switch:1").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_rest() -> Result<()> {
+        let schema: TopicSchema = "home/{room}/#".parse()?;
+
+        assert_eq!(
+            schema.matches("home/kitchen").unwrap().get("room"),
+            Some("kitchen")
+        );
+        assert!(schema.matches("home/kitchen/a/b").is_some());
+        assert!(schema.matches("garden/shed").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert!("{prefix}/#/{location}".parse::<TopicSchema>().is_err());
+        assert!("{prefix}//{location}".parse::<TopicSchema>().is_err());
+        assert!("{prefix}/{location".parse::<TopicSchema>().is_err());
+        assert!("{prefix}/{a}{b}".parse::<TopicSchema>().is_err());
+        assert!(parse("{prefix}/{room}", &["location"]).is_err());
+    }
+}
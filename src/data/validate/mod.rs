@@ -0,0 +1,198 @@
+use crate::data::dedup::warn_deduplicated;
+use crate::data::query::{Query, WriteQuery};
+use crate::target::mqtt::{parse_line, FieldValue};
+use crate::SensorReading;
+use std::fmt;
+
+#[cfg(test)]
+pub(crate) mod strategies;
+
+/// 2000-01-01T00:00:00Z, earlier timestamps are considered a device with an unset clock.
+pub const MIN_TIMESTAMP: i64 = 946_684_800;
+/// 2100-01-01T00:00:00Z
+pub const MAX_TIMESTAMP: i64 = 4_102_444_800;
+
+/// An invariant an event violates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    Unparsable(String),
+    Measurement(String),
+    EmptyKey,
+    NotFinite(String),
+    Timestamp(i64),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unparsable(error) => write!(f, "unparsable event: {}", error),
+            Violation::Measurement(name) => write!(f, "invalid measurement name '{}'", name),
+            Violation::EmptyKey => write!(f, "empty tag or field key"),
+            Violation::NotFinite(field) => write!(f, "field {} is not a finite number", field),
+            Violation::Timestamp(timestamp) => write!(f, "timestamp {} out of range", timestamp),
+        }
+    }
+}
+
+/// Measurement names consist of ASCII letters, digits, `_`, `-` and `.`.
+pub fn is_valid_measurement(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn check_measurement(name: &str) -> Result<(), Violation> {
+    if is_valid_measurement(name) {
+        Ok(())
+    } else {
+        Err(Violation::Measurement(name.to_string()))
+    }
+}
+
+fn check_timestamp(timestamp: i64) -> Result<(), Violation> {
+    if (MIN_TIMESTAMP..MAX_TIMESTAMP).contains(&timestamp) {
+        Ok(())
+    } else {
+        Err(Violation::Timestamp(timestamp))
+    }
+}
+
+/// Events checked for the invariants every target relies on before they are sent.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Violation>;
+}
+
+impl Validate for WriteQuery {
+    fn validate(&self) -> Result<(), Violation> {
+        let line = self
+            .build()
+            .map_err(|error| Violation::Unparsable(error.to_string()))?
+            .get();
+        let event = parse_line(&line).ok_or_else(|| Violation::Unparsable(line.clone()))?;
+        check_measurement(&event.measurement)?;
+        if event
+            .tags
+            .keys()
+            .chain(event.fields.keys())
+            .any(String::is_empty)
+        {
+            return Err(Violation::EmptyKey);
+        }
+        if let Some((field, _)) = event
+            .fields
+            .iter()
+            .find(|(_, value)| matches!(value, FieldValue::Float(value) if !value.is_finite()))
+        {
+            return Err(Violation::NotFinite(field.clone()));
+        }
+        event.time.map_or(Ok(()), check_timestamp)
+    }
+}
+
+impl Validate for SensorReading {
+    fn validate(&self) -> Result<(), Violation> {
+        check_measurement(&self.measurement)?;
+        if self.tags.iter().any(|(key, _)| key.is_empty()) {
+            return Err(Violation::EmptyKey);
+        }
+        if !self.value.is_finite() {
+            return Err(Violation::NotFinite("value".to_string()));
+        }
+        check_timestamp(self.time.timestamp())
+    }
+}
+
+/// Whether an event of the given source may be sent, violations are logged.
+pub fn accept(source: &str, event: &impl Validate) -> bool {
+    match event.validate() {
+        Ok(()) => true,
+        Err(violation) => {
+            warn_deduplicated(
+                &format!("{} dropped invalid event", source),
+                &violation.to_string(),
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strategies::*;
+    use super::*;
+    use crate::data::query::Timestamp;
+    use proptest::prelude::*;
+
+    fn query(measurement: &str, timestamp: i64) -> WriteQuery {
+        WriteQuery::new(Timestamp::Seconds(timestamp as u128), measurement)
+    }
+
+    #[test]
+    fn test_violations() {
+        let now = chrono::offset::Utc::now().timestamp();
+
+        assert_eq!(
+            query("power", now).add_field("value", 1.5).validate(),
+            Ok(())
+        );
+        assert_eq!(
+            query("power", now).add_field("value", f64::NAN).validate(),
+            Err(Violation::NotFinite("value".to_string()))
+        );
+        assert_eq!(
+            query("power", now)
+                .add_tag("", "kitchen")
+                .add_field("value", 1)
+                .validate(),
+            Err(Violation::EmptyKey)
+        );
+        assert_eq!(
+            query("power usage", now).add_field("value", 1).validate(),
+            Err(Violation::Measurement("power usage".to_string()))
+        );
+        assert_eq!(
+            query("power", 0).add_field("value", 1).validate(),
+            Err(Violation::Timestamp(0))
+        );
+        assert!(matches!(
+            query("power", now).validate(),
+            Err(Violation::Unparsable(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn test_valid_query_accepted(
+            measurement in measurement(),
+            tags in tags(),
+            value in finite(),
+            timestamp in timestamp(),
+        ) {
+            let query = tags
+                .into_iter()
+                .fold(query(&measurement, timestamp), |query, (key, value)| {
+                    query.add_tag(key, value)
+                })
+                .add_field("value", value);
+
+            prop_assert_eq!(query.validate(), Ok(()));
+        }
+
+        #[test]
+        fn test_non_finite_rejected(
+            measurement in measurement(),
+            value in prop_oneof![Just(f64::NAN), Just(f64::INFINITY), Just(f64::NEG_INFINITY)],
+            timestamp in timestamp(),
+        ) {
+            let query = query(&measurement, timestamp).add_field("value", value);
+
+            prop_assert_eq!(query.validate(), Err(Violation::NotFinite("value".to_string())));
+        }
+
+        #[test]
+        fn test_unsafe_measurement_rejected(name in "[a-z]{0,4}[ ,/\"=]{1,2}[a-z]{0,4}") {
+            prop_assert!(!is_valid_measurement(&name));
+        }
+    }
+}
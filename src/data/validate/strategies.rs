@@ -0,0 +1,27 @@
+//! Generators shared by the property tests of the parsers.
+
+use super::{MAX_TIMESTAMP, MIN_TIMESTAMP};
+use proptest::prelude::*;
+
+/// Measurement names from the safe charset.
+pub fn measurement() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_]{0,15}"
+}
+
+/// Topic levels as used for locations and devices.
+pub fn location() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9][a-zA-Z0-9_-]{0,15}"
+}
+
+/// Tags with arbitrary printable values, including separators of the line protocol.
+pub fn tags() -> impl Strategy<Value = Vec<(String, String)>> {
+    prop::collection::vec(("[a-z][a-z_]{0,9}", "[^\\\\\\p{Cc}]{1,20}"), 0..5)
+}
+
+pub fn finite() -> impl Strategy<Value = f64> {
+    prop::num::f64::NORMAL | prop::num::f64::ZERO
+}
+
+pub fn timestamp() -> impl Strategy<Value = i64> {
+    MIN_TIMESTAMP..MAX_TIMESTAMP
+}
@@ -0,0 +1,180 @@
+//! Test vectors of the parsers. Each YAML file in `testdata/<source type>` holds a message as
+//! `topic` and `payload` with the `events` expected from it in line protocol, events without a
+//! timestamp match any time. The loggers get a clock at `now`, default 2024-01-01T00:00:00Z.
+//!
+//! New device samples need no Rust code: add a file with topic and payload and run
+//! `UPDATE_VECTORS=1 cargo test vectors` to fill in the events written for it, then review them.
+
+use crate::data::clock::{Clock, ManualClock};
+use crate::data::enrichment::Enrichment;
+#[cfg(feature = "klimalogger")]
+use crate::data::klimalogger::{self, SensorLogger};
+#[cfg(feature = "opendtu")]
+use crate::data::opendtu::OpenDTULogger;
+#[cfg(feature = "openmqttgateway")]
+use crate::data::openmqttgateway::OpenMqttGatewayLogger;
+use crate::data::query::{Query, WriteQuery};
+#[cfg(feature = "shelly")]
+use crate::data::shelly::ShellyLogger;
+use crate::data::CheckMessage;
+use crate::target::mqtt::parse_line;
+use paho_mqtt::{Message, QOS_1};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::Arc;
+
+const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+const DEFAULT_NOW: i64 = 1704067200;
+
+/// Creates a logger writing to the sender with the clock of a vector.
+type LoggerFactory<T> = fn(SyncSender<T>, Arc<dyn Clock>) -> Box<dyn CheckMessage>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Vector {
+    /// Device and firmware the sample was taken from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    topic: String,
+    /// Text sent as is, other values as JSON.
+    payload: serde_yml::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    now: Option<i64>,
+    #[serde(default)]
+    events: Vec<String>,
+}
+
+impl Vector {
+    fn payload(&self) -> String {
+        match &self.payload {
+            serde_yml::Value::String(text) => text.clone(),
+            value => serde_json::to_string(value).unwrap(),
+        }
+    }
+}
+
+/// Whether a written event is the expected one, compared as parsed events so that the order of
+/// the tags and the notation of numbers don't matter.
+fn matches(expected: &str, actual: &str) -> bool {
+    match (parse_line(expected), parse_line(actual)) {
+        (Some(expected), Some(mut actual)) => {
+            if expected.time.is_none() {
+                actual.time = None;
+            }
+            expected == actual
+        }
+        _ => false,
+    }
+}
+
+fn vectors(source_type: &str) -> Vec<PathBuf> {
+    let directory = Path::new(TESTDATA).join(source_type);
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+        .unwrap_or_else(|error| panic!("{}: {}", directory.display(), error))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "yml"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no vectors in {}", directory.display());
+    paths
+}
+
+/// Passes the message of each vector of the source type to a fresh logger and compares the
+/// events it writes with the expected ones, reporting all mismatching vectors at once.
+fn run<T>(source_type: &str, logger: LoggerFactory<T>, line: fn(T) -> String) {
+    let update = env::var_os("UPDATE_VECTORS").is_some();
+    let mut failures = Vec::new();
+    for path in vectors(source_type) {
+        let text = fs::read_to_string(&path).unwrap();
+        let mut vector: Vector = serde_yml::from_str(&text)
+            .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        let (tx, rx) = sync_channel(1000);
+        let clock = Arc::new(ManualClock::at(vector.now.unwrap_or(DEFAULT_NOW)));
+        logger(tx, clock).check_message(&Message::new(&vector.topic, vector.payload(), QOS_1));
+        let actual: Vec<String> = rx.try_iter().map(line).collect();
+
+        if update {
+            vector.events = actual;
+            fs::write(&path, serde_yml::to_string(&vector).unwrap()).unwrap();
+        } else if vector.events.len() != actual.len()
+            || !vector
+                .events
+                .iter()
+                .zip(&actual)
+                .all(|(expected, actual)| matches(expected, actual))
+        {
+            failures.push(format!(
+                "{}\n  expected: {:#?}\n  actual: {:#?}",
+                path.display(),
+                vector.events,
+                actual
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn line(query: WriteQuery) -> String {
+    query.build().unwrap().get()
+}
+
+#[test]
+fn test_matches() {
+    let line = "power,location=kitchen,channel=0 value=12.5 1704067200";
+
+    assert!(matches(line, line));
+    assert!(matches(
+        "power,channel=0,location=kitchen value=12.50",
+        line
+    ));
+    assert!(!matches(
+        "power,location=kitchen,channel=0 value=12.5 1704067140",
+        line
+    ));
+    assert!(!matches("power,location=kitchen value=12.5", line));
+}
+
+#[test]
+#[cfg(feature = "shelly")]
+fn test_shelly_vectors() {
+    run(
+        "shelly",
+        |tx, clock| Box::new(ShellyLogger::new(vec![tx], Enrichment::default()).with_clock(clock)),
+        line,
+    );
+}
+
+#[test]
+#[cfg(feature = "klimalogger")]
+fn test_sensor_vectors() {
+    run(
+        "sensor",
+        |tx, clock| Box::new(SensorLogger::new(vec![tx], Enrichment::default()).with_clock(clock)),
+        |reading| line(klimalogger::to_query(reading)),
+    );
+}
+
+#[test]
+#[cfg(feature = "opendtu")]
+fn test_opendtu_vectors() {
+    // OpenDTU messages carry no timestamp and are stamped with the system time
+    run(
+        "opendtu",
+        |tx, _| Box::new(OpenDTULogger::new(vec![tx], Enrichment::default())),
+        line,
+    );
+}
+
+#[test]
+#[cfg(feature = "openmqttgateway")]
+fn test_openmqttgateway_vectors() {
+    run(
+        "openmqttgateway",
+        |tx, clock| {
+            Box::new(OpenMqttGatewayLogger::new(vec![tx], Enrichment::default()).with_clock(clock))
+        },
+        line,
+    );
+}
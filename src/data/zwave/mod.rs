@@ -0,0 +1,328 @@
+use std::sync::mpsc::SyncSender;
+
+use crate::config::{StaticEventConfig, Target};
+use crate::data::catalog::Measurement;
+use crate::data::clock::{self, Clock};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::Enrichment;
+use crate::data::query::Timestamp::Seconds;
+use crate::data::query::WriteQuery;
+use crate::data::{deadletter, devices, heartbeat_query, live, start_query, BuildInfo};
+use crate::data::{message_age_query, online_queries, static_query};
+use crate::data::{validate, CheckMessage, Logger, SourceStats};
+use crate::error::{GatewayError, Result};
+use crate::target;
+use crate::target::supervisor;
+use crate::target::Mappers;
+use chrono::{DateTime, Utc};
+use log::{debug, trace};
+use paho_mqtt::Message;
+use serde_json::Value;
+use std::iter;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+struct Data {
+    timestamp: i64,
+    location: Option<String>,
+    node: String,
+    command_class: &'static str,
+    endpoint: String,
+    field: String,
+    value: f64,
+}
+
+const SENSOR_MULTILEVEL: &str = "sensor_multilevel";
+const METER: &str = "meter";
+
+/// Measurements of the `value/<propertyKey>` topics of electric meters, keyed by the property
+/// key Z-Wave JS derives from the meter type, rate type and scale.
+const METER_FIELDS: &[(&str, &str)] = &[
+    ("65537", "energy"),
+    ("66049", "power"),
+    ("66561", "voltage"),
+    ("66817", "current"),
+];
+
+/// Name of a supported command class, given by name or by id in the topic.
+fn command_class(segment: &str) -> Option<&'static str> {
+    match segment {
+        "sensor_multilevel" | "49" => Some(SENSOR_MULTILEVEL),
+        "meter" | "50" => Some(METER),
+        _ => None,
+    }
+}
+
+pub struct ZWaveLogger {
+    txs: Vec<SyncSender<WriteQuery>>,
+    enrichment: Enrichment,
+    clock: Arc<dyn Clock>,
+    stats: SourceStats,
+}
+
+impl ZWaveLogger {
+    pub(crate) fn new(txs: Vec<SyncSender<WriteQuery>>, enrichment: Enrichment) -> Self {
+        ZWaveLogger {
+            txs,
+            enrichment,
+            clock: clock::system(),
+            stats: SourceStats::default(),
+        }
+    }
+}
+
+impl CheckMessage for ZWaveLogger {
+    fn check_message(&mut self, msg: &Message) {
+        self.stats.received += 1;
+        let data = match parse(msg) {
+            Ok(Some(data)) => data,
+            Ok(None) => return,
+            Err(error) => {
+                warn_deduplicated("Z-Wave parse error", &error.to_string());
+                deadletter::record(msg, &error.to_string());
+                self.stats.dropped += 1;
+                return;
+            }
+        };
+        self.stats.parsed += 1;
+        let value = self.enrichment.round(&data.field, data.value);
+        devices::record("zwave", &data.node, &data.field);
+        live::record(
+            &data.field,
+            &[("node", &data.node), ("command_class", data.command_class)],
+            value,
+            data.timestamp,
+        );
+        let mut write_query = WriteQuery::new(Seconds(data.timestamp as u128), data.field)
+            .add_field("value", value)
+            .add_tag("node", data.node.clone())
+            .add_tag("command_class", data.command_class)
+            .add_tag("endpoint", data.endpoint);
+        if let Some(location) = data.location {
+            write_query = write_query.add_tag("location", location);
+        }
+        write_query = self
+            .enrichment
+            .apply(write_query, data.timestamp, &data.node);
+        if !validate::accept("Z-Wave", &write_query) {
+            self.stats.dropped += 1;
+            return;
+        }
+        target::send_all(&self.txs, &write_query);
+        self.stats.forwarded += 1;
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats
+    }
+
+    fn shutdown(&mut self) {
+        self.txs.clear();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        let clock = self.clock.as_ref();
+        let queries = iter::once(heartbeat_query(source, messages, &self.enrichment, clock))
+            .chain(message_age_query(source, &self.enrichment, clock))
+            .chain(online_queries(source, &self.enrichment, clock));
+        for query in queries {
+            target::send_all(&self.txs, &query);
+        }
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        let query = start_query(source, build, &self.enrichment, self.clock.as_ref());
+        target::send_all(&self.txs, &query);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        let query = static_query(source, event, time, &self.enrichment);
+        target::send_all(&self.txs, &query);
+    }
+}
+
+/// Value and time of a payload, either `{"time": <ms>, "value": <number>}` or the plain value
+/// when Z-Wave JS UI is configured to send just values.
+fn parse_payload(msg: &Message) -> Result<(f64, Option<i64>)> {
+    let invalid = |reason: &str| GatewayError::parse(msg.topic().to_string(), reason.to_string());
+    match serde_json::from_str(&msg.payload_str())? {
+        Value::Number(value) => Ok((
+            value.as_f64().ok_or_else(|| invalid("invalid value"))?,
+            None,
+        )),
+        Value::Object(object) => {
+            let value = object
+                .get("value")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| invalid("value is not a number"))?;
+            let time = object
+                .get("time")
+                .and_then(Value::as_i64)
+                .map(|time| time / 1000);
+            Ok((value, time))
+        }
+        _ => Err(invalid("payload is neither a number nor an object")),
+    }
+}
+
+/// Parses `<prefix>/[<location>/]<node>/<commandclass>/<endpoint>/<property>[/<propertyKey>]`,
+/// values of other command classes are ignored.
+fn parse(msg: &Message) -> Result<Option<Data>> {
+    let segments: Vec<&str> = msg.topic().split('/').skip(1).collect();
+    let (location, node, index) = match segments.as_slice() {
+        [location, node, class, ..] if command_class(class).is_some() => {
+            (Some(location.to_string()), node.to_string(), 2)
+        }
+        [node, class, ..] if command_class(class).is_some() => (None, node.to_string(), 1),
+        _ => {
+            trace!("Z-Wave ignored {}: {:?}", msg.topic(), msg.payload_str());
+            return Ok(None);
+        }
+    };
+    let command_class = command_class(segments[index]).unwrap_or_default();
+    let (endpoint, property, key) = match &segments[index + 1..] {
+        [endpoint, property] => (endpoint, *property, None),
+        [endpoint, property, key] => (endpoint, *property, Some(*key)),
+        _ => return Ok(None),
+    };
+    let field = match (command_class, key) {
+        (SENSOR_MULTILEVEL, None) => property.to_lowercase().replace(' ', "_"),
+        (METER, Some(key)) if property == "value" => {
+            match METER_FIELDS.iter().find(|(meter_key, _)| *meter_key == key) {
+                Some((_, field)) => field.to_string(),
+                None => {
+                    trace!("Z-Wave ignored meter value {}", key);
+                    return Ok(None);
+                }
+            }
+        }
+        _ => return Ok(None),
+    };
+    let (value, time) = parse_payload(msg)?;
+    debug!(
+        "Z-Wave node {} {} {}: {}",
+        node, command_class, field, value
+    );
+    Ok(Some(Data {
+        timestamp: time.unwrap_or_else(|| Utc::now().timestamp()),
+        location,
+        node,
+        command_class,
+        endpoint: endpoint.to_string(),
+        field,
+        value,
+    }))
+}
+
+pub fn catalog() -> Vec<Measurement> {
+    let tags = &["location", "node", "command_class", "endpoint"];
+    iter::once(Measurement::new("<property>", &["value"], tags, None))
+        .chain(
+            METER_FIELDS
+                .iter()
+                .map(|(_, field)| Measurement::new(field, &["value"], tags, None)),
+        )
+        .collect()
+}
+
+pub fn create_logger(targets: Vec<Target>, enrichment: Enrichment) -> Result<Logger> {
+    let mut txs: Vec<SyncSender<WriteQuery>> = Vec::new();
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for target in targets {
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            target::spawn_writer(target.clone(), &Mappers::queries("zwave"))
+        })?;
+        txs.push(tx);
+        handles.push(handle);
+    }
+
+    let logger = ZWaveLogger::new(txs, enrichment);
+
+    Ok((Arc::new(Mutex::new(logger)), handles))
+}
+
+#[cfg(test)]
+mod tests {
+    use paho_mqtt::QOS_1;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_sensor_multilevel() -> Result<()> {
+        let message = Message::new(
+            "zwave/kitchen/nodeID_5/sensor_multilevel/0/Air_temperature",
+            r#"{"time":1701271852123,"value":21.5}"#,
+            QOS_1,
+        );
+        let data = parse(&message)?.unwrap();
+
+        assert_eq!(data.timestamp, 1701271852);
+        assert_eq!(data.location.as_deref(), Some("kitchen"));
+        assert_eq!(data.node, "nodeID_5");
+        assert_eq!(data.command_class, "sensor_multilevel");
+        assert_eq!(data.endpoint, "0");
+        assert_eq!(data.field, "air_temperature");
+        assert_eq!(data.value, 21.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_meter_without_location() -> Result<()> {
+        let message = Message::new("zwave/plug/50/1/value/66049", "12.3", QOS_1);
+        let data = parse(&message)?.unwrap();
+
+        assert!(data.location.is_none());
+        assert_eq!(data.node, "plug");
+        assert_eq!(data.command_class, "meter");
+        assert_eq!(data.endpoint, "1");
+        assert_eq!(data.field, "power");
+        assert_eq!(data.value, 12.3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ignores_other_values() -> Result<()> {
+        for topic in [
+            "zwave/kitchen/plug/switch_binary/0/currentValue",
+            "zwave/kitchen/plug/meter/0/value/65540",
+            "zwave/kitchen/plug/meter/0/reset",
+            "zwave/_CLIENTS/ZWAVE_GATEWAY-zwave-js-ui/status",
+        ] {
+            assert!(parse(&Message::new(topic, "1", QOS_1))?.is_none());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_value() {
+        let message = Message::new(
+            "zwave/kitchen/sensor/sensor_multilevel/0/Humidity",
+            r#"{"time":1701271852123,"value":"n/a"}"#,
+            QOS_1,
+        );
+
+        assert!(parse(&message).is_err());
+    }
+
+    #[test]
+    fn test_write_query() -> anyhow::Result<()> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(100);
+        let mut logger = ZWaveLogger::new(vec![tx], Enrichment::default());
+
+        logger.check_message(&Message::new(
+            "zwave/kitchen/plug/meter/0/value/65537",
+            r#"{"time":1701271852000,"value":1.25}"#,
+            QOS_1,
+        ));
+
+        let line = crate::data::query::Query::build(&rx.try_recv()?)?.get();
+        assert!(line.starts_with(
+            "energy,node=plug,command_class=meter,endpoint=0,location=kitchen value=1.25 "
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::error::Error;
+use std::fmt;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// Exit codes of the process per failure class, so that service managers can apply a restart
+// policy per class. They follow sysexits.h.
+/// Invalid configuration, restarting won't help.
+pub const EXIT_CONFIG: i32 = 78;
+/// The broker rejected the credentials.
+pub const EXIT_AUTH: i32 = 77;
+/// The broker or a target could not be reached.
+pub const EXIT_UNAVAILABLE: i32 = 69;
+/// A target failed fatally.
+pub const EXIT_TARGET: i32 = 74;
+/// Input which could not be parsed, e.g. of `decrypt`.
+pub const EXIT_DATA: i32 = 65;
+/// A panic of the main thread.
+pub const EXIT_INTERNAL: i32 = 70;
+/// Added to the number of the signal the gateway shut down on, like a shell does.
+pub const EXIT_SIGNAL: i32 = 128;
+
+#[derive(Debug)]
+pub enum GatewayError {
+    /// Invalid or unreadable configuration.
+    Config(String),
+    /// Connection to the broker or a target could not be established.
+    Connect { context: String, source: BoxError },
+    /// The broker rejected the credentials.
+    Auth { context: String, source: BoxError },
+    /// Message payload could not be parsed.
+    Parse { context: String, source: BoxError },
+    /// Writing to a target failed.
+    Target { context: String, source: BoxError },
+}
+
+pub type Result<T> = std::result::Result<T, GatewayError>;
+
+impl GatewayError {
+    pub fn config(message: impl Into<String>) -> Self {
+        GatewayError::Config(message.into())
+    }
+
+    pub fn connect(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        GatewayError::Connect {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn auth(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        GatewayError::Auth {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn parse(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        GatewayError::Parse {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn target(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        GatewayError::Target {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Exit code of the process failing with the error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GatewayError::Config(_) => EXIT_CONFIG,
+            GatewayError::Connect { .. } => EXIT_UNAVAILABLE,
+            GatewayError::Auth { .. } => EXIT_AUTH,
+            GatewayError::Parse { .. } => EXIT_DATA,
+            GatewayError::Target { .. } => EXIT_TARGET,
+        }
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GatewayError::Config(message) => write!(f, "configuration error: {}", message),
+            GatewayError::Connect { context, source } => {
+                write!(f, "failed to connect to {}: {}", context, source)
+            }
+            GatewayError::Auth { context, source } => {
+                write!(f, "not authorized by {}: {}", context, source)
+            }
+            GatewayError::Parse { context, source } => {
+                write!(f, "failed to parse {}: {}", context, source)
+            }
+            GatewayError::Target { context, source } => {
+                write!(f, "failed to write to {}: {}", context, source)
+            }
+        }
+    }
+}
+
+impl Error for GatewayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GatewayError::Config(_) => None,
+            GatewayError::Connect { source, .. }
+            | GatewayError::Auth { source, .. }
+            | GatewayError::Parse { source, .. }
+            | GatewayError::Target { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for GatewayError {
+    fn from(error: serde_json::Error) -> Self {
+        GatewayError::parse("json", error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = GatewayError::connect("redis://localhost", "connection refused");
+
+        assert_eq!(
+            error.to_string(),
+            "failed to connect to redis://localhost: connection refused"
+        );
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn test_from_json_error() {
+        let error: GatewayError = serde_json::from_str::<i32>("foo").unwrap_err().into();
+
+        assert!(matches!(error, GatewayError::Parse { .. }));
+        assert_eq!(
+            error.to_string(),
+            "failed to parse json: expected ident at line 1 column 2"
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(GatewayError::config("no sources").exit_code(), EXIT_CONFIG);
+        assert_eq!(
+            GatewayError::auth("mqtt broker", "not authorized").exit_code(),
+            EXIT_AUTH
+        );
+        assert_eq!(
+            GatewayError::connect("mqtt broker", "connection refused").exit_code(),
+            EXIT_UNAVAILABLE
+        );
+    }
+}
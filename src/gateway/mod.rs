@@ -0,0 +1,1312 @@
+pub(crate) mod diagnostics;
+pub(crate) mod signal;
+mod status;
+
+use crate::config::{options, pipeline};
+use crate::config::{
+    AuditConfig, AuthConfig, CaptureConfig, Command, Config, DeadLetterConfig, EncryptionConfig,
+    ModbusConfig, Profile, RedactionConfig, SnmpConfig, SourceType, StaticEventConfig, Target,
+    TimestampConfig, VirtualMetersConfig,
+};
+use crate::data::age;
+use crate::data::enrichment;
+use crate::data::enrichment::{BrokerTags, Calendar, Enrichment};
+#[cfg(feature = "klimalogger")]
+use crate::data::klimalogger;
+#[cfg(feature = "opendtu")]
+use crate::data::opendtu;
+#[cfg(feature = "openmqttgateway")]
+use crate::data::openmqttgateway;
+#[cfg(feature = "senml")]
+use crate::data::senml;
+#[cfg(feature = "shelly")]
+use crate::data::shelly;
+#[cfg(feature = "shelly")]
+use crate::data::shelly::{DeviceTag, ShellyOptions};
+#[cfg(feature = "zwave")]
+use crate::data::zwave;
+use crate::data::{
+    deadletter, debug, devices, encryption, live, redact, BuildInfo, CheckMessage, Sources,
+};
+use crate::error::{GatewayError, Result};
+#[cfg(feature = "http")]
+use crate::http;
+use crate::memory;
+use crate::source;
+use crate::source::broker::BrokerTaggingLogger;
+use crate::source::charset::{DecodingLogger, PayloadDecoder};
+use crate::source::connection;
+use crate::source::control;
+use crate::source::control::ControlMessage;
+use crate::source::control::{audit, auth, capture};
+use crate::source::loopback;
+use crate::source::mqtt::{Brokers, SessionMonitor};
+use crate::source::sample::{Sampler, SamplingLogger};
+use crate::source::schedule::{ActiveHours, ScheduledLogger};
+use crate::source::warmup::WarmupLogger;
+use crate::target;
+use crate::target::ack;
+use crate::target::meter;
+use crate::target::supervisor;
+use futures::{executor::block_on, stream::StreamExt};
+use log::{info, warn};
+use paho_mqtt as mqtt;
+use paho_mqtt::QOS_1;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const STATS_INTERVAL: Duration = Duration::from_secs(300);
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+/// Pause after the first failed broker connect, doubled after each further one.
+const CONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_CONNECT_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_INFLIGHT_LIMIT: usize = 100;
+const DEFAULT_LIVE_HISTORY: usize = 60;
+const DEFAULT_STREAM_BUFFER: usize = 200;
+
+/// Pauses reading before a single queue fills up, a parser then rarely waits for room.
+const CONSTRAINED_INFLIGHT_LIMIT: usize = 16;
+/// Holds all events of one message, e.g. the queries of a Shelly status fanning out to a dozen.
+const CONSTRAINED_QUEUE_SIZE: usize = 32;
+const CONSTRAINED_STREAM_BUFFER: usize = 20;
+const CONSTRAINED_MEMORY_LIMIT: usize = 32;
+const CONSTRAINED_QUEUE_MEMORY_LIMIT: usize = 8;
+/// Longest pause of the consumer while the heap exceeds the memory limit. Long-lived state like
+/// the device registry or the history counts towards the heap as well, draining the queues
+/// alone may not get below the limit.
+const MEMORY_PAUSE: Duration = Duration::from_secs(30);
+/// Wait for messages still in flight while paused before checking whether to resume.
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+
+/// Builds a [`Gateway`] from sources and an MQTT connection without a configuration file.
+///
+/// Sources are [`CheckMessage`] implementations added with the writer threads they send to,
+/// e.g. `SensorLogger::new(vec![tx], Enrichment::default())` writing to any `SyncSender`.
+pub struct GatewayBuilder {
+    mqtt_urls: Vec<String>,
+    mqtt_client_id: String,
+    /// ID republished messages are marked with to detect loops.
+    gateway_id: String,
+    persistent_session: bool,
+    session_expiry: Option<u32>,
+    inflight_limit: usize,
+    stream_buffer: usize,
+    memory_limit: Option<usize>,
+    queue_memory_limit: Option<usize>,
+    heartbeat: Option<Duration>,
+    control_topic: Option<String>,
+    devices_file: Option<String>,
+    http_listen: Option<String>,
+    mdns_name: Option<String>,
+    live_history: usize,
+    dead_letter: Option<DeadLetterConfig>,
+    virtual_meters: Option<VirtualMetersConfig>,
+    auth: Option<AuthConfig>,
+    audit: Option<AuditConfig>,
+    capture: Option<CaptureConfig>,
+    encryption: Option<EncryptionConfig>,
+    redaction: Option<RedactionConfig>,
+    status: Option<(String, Duration)>,
+    config_hash: Option<String>,
+    /// Redacted configuration included in the diagnostics.
+    config: Option<serde_json::Value>,
+    sources: Sources,
+    qos: HashMap<String, i32>,
+    message_age: Vec<String>,
+    online_timeouts: Vec<(String, Duration)>,
+    /// Sources reading log events from a socket with its path.
+    sockets: Vec<(String, String)>,
+    /// Sources accepting log events via the HTTP API.
+    ingest: Vec<String>,
+    /// Devices polled via SNMP for the sources.
+    snmp: Vec<(String, SnmpConfig)>,
+    /// Devices polled via Modbus TCP for the sources.
+    modbus: Vec<(String, ModbusConfig)>,
+    /// Events written on a schedule for the sources.
+    events: Vec<(String, Vec<StaticEventConfig>)>,
+}
+
+impl GatewayBuilder {
+    pub fn new(mqtt_url: impl Into<String>, mqtt_client_id: impl Into<String>) -> Self {
+        let mqtt_client_id = mqtt_client_id.into();
+        GatewayBuilder {
+            mqtt_urls: vec![mqtt_url.into()],
+            gateway_id: mqtt_client_id.clone(),
+            mqtt_client_id,
+            persistent_session: true,
+            session_expiry: None,
+            inflight_limit: DEFAULT_INFLIGHT_LIMIT,
+            stream_buffer: DEFAULT_STREAM_BUFFER,
+            memory_limit: None,
+            queue_memory_limit: None,
+            heartbeat: None,
+            control_topic: None,
+            devices_file: None,
+            http_listen: None,
+            mdns_name: None,
+            live_history: DEFAULT_LIVE_HISTORY,
+            dead_letter: None,
+            virtual_meters: None,
+            auth: None,
+            audit: None,
+            capture: None,
+            encryption: None,
+            redaction: None,
+            status: None,
+            config_hash: None,
+            config: None,
+            sources: Sources::default(),
+            qos: HashMap::new(),
+            message_age: Vec::new(),
+            online_timeouts: Vec::new(),
+            sockets: Vec::new(),
+            ingest: Vec::new(),
+            snmp: Vec::new(),
+            modbus: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: Config) -> Result<Self> {
+        let instance_tags = enrichment::instance_tags(&config);
+        enrichment::set_metric_labels(instance_tags.clone());
+        let redacted_config = diagnostics::redacted_config(&config)?;
+        let locations = config.locations.unwrap_or_default();
+        let pipelines = config.pipelines.unwrap_or_default();
+        let mdns_name = config
+            .instance
+            .clone()
+            .unwrap_or_else(|| config.mqtt_client_id.clone());
+
+        let mut builder = Gateway::builder(String::new(), config.mqtt_client_id)
+            .gateway_id(mdns_name.clone())
+            .brokers(config.mqtt_url.urls())
+            .persistent_session(config.persistent_session.unwrap_or(true))
+            .profile(config.profile.unwrap_or_default());
+        builder.config = Some(redacted_config);
+        if let Some(memory_limit) = config.memory_limit {
+            builder = builder.memory_limit(memory_limit);
+        }
+        if let Some(queue_memory_limit) = config.queue_memory_limit {
+            builder = builder.queue_memory_limit(queue_memory_limit);
+        }
+        if let Some(session_expiry) = config.session_expiry {
+            builder = builder.session_expiry(session_expiry);
+        }
+        if let Some(inflight_limit) = config.inflight_limit {
+            builder = builder.inflight_limit(inflight_limit);
+        }
+        if let Some(heartbeat) = config.heartbeat {
+            builder = builder.heartbeat(Duration::from_secs(heartbeat));
+        }
+        if let Some(control_topic) = config.control_topic {
+            builder = builder.control_topic(control_topic);
+        }
+        if let Some(devices_file) = config.devices.and_then(|devices| devices.file) {
+            builder = builder.devices_file(devices_file);
+        }
+        if let Some(http) = config.http {
+            builder = builder.http_listen(http.listen);
+            if let Some(live_history) = http.live_history {
+                builder.live_history = live_history;
+            }
+            if http.mdns.unwrap_or(false) {
+                builder = builder.mdns(mdns_name);
+            }
+        }
+        if let Some(dead_letter) = config.dead_letter {
+            builder = builder.dead_letter(dead_letter);
+        }
+        if let Some(virtual_meters) = config.virtual_meters {
+            builder = builder.virtual_meters(virtual_meters);
+        }
+        if let Some(auth) = config.auth {
+            builder = builder.auth(auth);
+        }
+        if let Some(audit) = config.audit {
+            builder = builder.audit(audit);
+        }
+        if let Some(capture) = config.capture {
+            builder = builder.capture(capture);
+        }
+        if let Some(encryption) = config.encryption {
+            builder = builder.encryption(encryption);
+        }
+        if let Some(redaction) = config.redaction {
+            builder = builder.redaction(redaction);
+        }
+        if let Some(status) = config.status {
+            builder = builder.status(
+                status
+                    .topic
+                    .unwrap_or_else(|| status::DEFAULT_STATUS_TOPIC.to_string()),
+                status
+                    .interval
+                    .map_or(status::DEFAULT_STATUS_INTERVAL, Duration::from_secs),
+            );
+        }
+
+        for source in config.sources {
+            let source = options::apply(pipeline::apply(source, &pipelines)?)?;
+            let calendar = match &source.calendar {
+                Some(calendar) => Some(Calendar::from_config(calendar)?),
+                None => None,
+            };
+            let broker_tags = BrokerTags::from_config(&source.broker_tags.unwrap_or_default())?;
+            let enrichment = Enrichment::new(calendar, &locations)
+                .with_static_tags(instance_tags.clone())
+                .with_broker_tags(broker_tags.clone())
+                .with_precision(source.precision.unwrap_or_default());
+            let polled = source.snmp.is_some() || source.modbus.is_some();
+            if (source.socket.is_some() || source.ingest.is_some() || polled)
+                && (source.source_type != SourceType::Sensor
+                    || source.topic_schema.is_some()
+                    || source.fields.is_some())
+            {
+                return Err(GatewayError::config(format!(
+                    "socket, ingest, snmp and modbus are only supported by sensor sources \
+                     without topicSchema and fields, not by source {}",
+                    source.name
+                )));
+            }
+            if source.ingest.unwrap_or(false)
+                && (builder.auth.is_none() || builder.http_listen.is_none())
+            {
+                return Err(GatewayError::config(format!(
+                    "ingest requires http and auth tokens, see source {}",
+                    source.name
+                )));
+            }
+            let (logger, handles) = match source.source_type {
+                #[cfg(feature = "shelly")]
+                SourceType::Shelly => shelly::create_logger(
+                    source.targets.unwrap_or_default(),
+                    enrichment,
+                    source.timestamp.as_ref(),
+                    ShellyOptions {
+                        device_tags: source
+                            .device_tags
+                            .unwrap_or_default()
+                            .iter()
+                            .map(|tag| tag.parse())
+                            .collect::<Result<Vec<DeviceTag>>>()?,
+                        missing_field_threshold: source.missing_field_threshold,
+                        topic_schema: source.topic_schema,
+                        parse_mode: source.parsing,
+                        energy_by_minute: source.energy_by_minute.unwrap_or(false),
+                        power_direction: source.power_direction,
+                    },
+                ),
+                #[cfg(feature = "klimalogger")]
+                SourceType::Sensor => klimalogger::create_logger(
+                    source.targets.unwrap_or_default(),
+                    enrichment,
+                    source.timestamp.as_ref(),
+                    source.topic_schema.as_deref(),
+                    source.fields.as_ref(),
+                    source
+                        .ack
+                        .unwrap_or(false)
+                        .then(|| format!("{}/ack", source.prefix)),
+                ),
+                _ if source.topic_schema.is_some() => {
+                    return Err(GatewayError::config(format!(
+                        "topicSchema is not supported by source {}",
+                        source.name
+                    )));
+                }
+                _ if source.ack.is_some() => {
+                    return Err(GatewayError::config(format!(
+                        "ack is not supported by source {}",
+                        source.name
+                    )));
+                }
+                _ if source.parsing.is_some() => {
+                    return Err(GatewayError::config(format!(
+                        "parsing is not supported by source {}",
+                        source.name
+                    )));
+                }
+                _ if source.energy_by_minute.is_some() => {
+                    return Err(GatewayError::config(format!(
+                        "energyByMinute is not supported by source {}",
+                        source.name
+                    )));
+                }
+                _ if source.fields.is_some() => {
+                    return Err(GatewayError::config(format!(
+                        "fields is not supported by source {}",
+                        source.name
+                    )));
+                }
+                #[cfg(feature = "openmqttgateway")]
+                SourceType::OpenMqttGateway => openmqttgateway::create_logger(
+                    source.targets.unwrap_or_default(),
+                    enrichment,
+                    source.timestamp.as_ref(),
+                    options::devices(source.options.as_ref()),
+                ),
+                source_type => create_logger(
+                    source_type,
+                    source.targets.unwrap_or_default(),
+                    enrichment,
+                    source.timestamp.as_ref(),
+                ),
+            }?;
+            let logger: Arc<Mutex<dyn CheckMessage>> = if broker_tags.is_empty() {
+                logger
+            } else {
+                Arc::new(Mutex::new(BrokerTaggingLogger::new(broker_tags, logger)))
+            };
+            let logger: Arc<Mutex<dyn CheckMessage>> = if source.skip_retained.unwrap_or(false) {
+                Arc::new(Mutex::new(WarmupLogger::new(logger)))
+            } else {
+                logger
+            };
+            let logger: Arc<Mutex<dyn CheckMessage>> = match source.active_hours {
+                Some(active_hours) => Arc::new(Mutex::new(ScheduledLogger::new(
+                    ActiveHours::parse(&active_hours)?,
+                    logger,
+                ))),
+                None => logger,
+            };
+            let logger: Arc<Mutex<dyn CheckMessage>> = match (source.charset, source.lossy) {
+                (None, None) => logger,
+                (charset, lossy) => Arc::new(Mutex::new(DecodingLogger::new(
+                    PayloadDecoder::new(charset.unwrap_or_default(), lossy.unwrap_or(false)),
+                    logger,
+                ))),
+            };
+            let logger: Arc<Mutex<dyn CheckMessage>> = match &source.sample_rate {
+                Some(sample_rate) => Arc::new(Mutex::new(SamplingLogger::new(
+                    Sampler::new(sample_rate)?,
+                    logger,
+                ))),
+                None => logger,
+            };
+            if let Some(qos) = source.qos {
+                builder = builder.qos(source.prefix.clone(), qos);
+            }
+            if source.message_age.unwrap_or(false) {
+                builder = builder.message_age(source.prefix.clone());
+            }
+            if let Some(online_timeout) = source.online_timeout {
+                if builder.heartbeat.is_none() {
+                    return Err(GatewayError::config(format!(
+                        "onlineTimeout requires heartbeat, see source {}",
+                        source.name
+                    )));
+                }
+                builder = builder
+                    .online_timeout(source.prefix.clone(), Duration::from_secs(online_timeout));
+            }
+            if let Some(socket) = source.socket {
+                builder = builder.socket(source.prefix.clone(), socket);
+            }
+            if source.ingest.unwrap_or(false) {
+                builder = builder.ingest(source.prefix.clone());
+            }
+            for device in source.snmp.unwrap_or_default() {
+                builder = builder.snmp(source.prefix.clone(), device);
+            }
+            for device in source.modbus.unwrap_or_default() {
+                builder = builder.modbus(source.prefix.clone(), device);
+            }
+            if let Some(events) = source.events {
+                builder = builder.events(source.prefix.clone(), events);
+            }
+            builder = builder.logger(source.prefix, logger, handles);
+        }
+
+        Ok(builder)
+    }
+
+    /// Marks republished messages with the given ID instead of the client id, messages with the
+    /// mark reaching a source again are dropped.
+    pub fn gateway_id(mut self, gateway_id: impl Into<String>) -> Self {
+        self.gateway_id = gateway_id.into();
+        self
+    }
+
+    /// Replaces the broker URI with a list of brokers to fail over between, in order of preference.
+    pub fn brokers(mut self, mqtt_urls: Vec<String>) -> Self {
+        self.mqtt_urls = mqtt_urls;
+        self
+    }
+
+    pub fn persistent_session(mut self, persistent_session: bool) -> Self {
+        self.persistent_session = persistent_session;
+        self
+    }
+
+    pub fn session_expiry(mut self, session_expiry: u32) -> Self {
+        self.session_expiry = Some(session_expiry);
+        self
+    }
+
+    /// Sets the number of items waiting for the targets above which reading from the broker is
+    /// paused, default is 100.
+    pub fn inflight_limit(mut self, inflight_limit: usize) -> Self {
+        self.inflight_limit = inflight_limit;
+        self
+    }
+
+    /// Sends a `gateway_heartbeat` event per source to its targets in the given interval.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    /// Enables and disables sources on messages like `{"source": "shellies", "enabled": false}`
+    /// published to the given topic.
+    pub fn control_topic(mut self, topic: impl Into<String>) -> Self {
+        self.control_topic = Some(topic.into());
+        self
+    }
+
+    /// Persists the device registry to the given file and restores it on start.
+    pub fn devices_file(mut self, path: impl Into<String>) -> Self {
+        self.devices_file = Some(path.into());
+        self
+    }
+
+    /// Applies the defaults of a resource profile, set explicit limits afterwards. The queue size
+    /// applies to the writers of sources added afterwards.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        if profile == Profile::Constrained {
+            target::set_queue_size(CONSTRAINED_QUEUE_SIZE);
+            self.inflight_limit = CONSTRAINED_INFLIGHT_LIMIT;
+            self.stream_buffer = CONSTRAINED_STREAM_BUFFER;
+            self.memory_limit = Some(CONSTRAINED_MEMORY_LIMIT);
+            self.queue_memory_limit = Some(CONSTRAINED_QUEUE_MEMORY_LIMIT);
+            // no live values cache for the Grafana datasource
+            self.live_history = 0;
+        }
+        self
+    }
+
+    /// Pauses reading from the broker while more than this many MiB are allocated.
+    pub fn memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    /// Sheds events instead of queueing them for a target while the events queued for all targets
+    /// take more than this many MiB.
+    pub fn queue_memory_limit(mut self, queue_memory_limit: usize) -> Self {
+        self.queue_memory_limit = Some(queue_memory_limit);
+        self
+    }
+
+    /// Requires one of the tokens for HTTP requests and control messages.
+    pub fn auth(mut self, config: AuthConfig) -> Self {
+        self.auth = Some(config);
+        self
+    }
+
+    /// Records runtime control actions to a file and/or topic.
+    pub fn audit(mut self, config: AuditConfig) -> Self {
+        self.audit = Some(config);
+        self
+    }
+
+    /// Where messages captured via `capture start <topic-filter> <seconds>` on the control topic
+    /// are written to.
+    pub fn capture(mut self, config: CaptureConfig) -> Self {
+        self.capture = Some(config);
+        self
+    }
+
+    /// Encrypts the dead-letter and capture files with the key of the configuration.
+    pub fn encryption(mut self, config: EncryptionConfig) -> Self {
+        self.encryption = Some(config);
+        self
+    }
+
+    /// Hashes or drops tags and fields of all events before they are sent to the targets.
+    pub fn redaction(mut self, config: RedactionConfig) -> Self {
+        self.redaction = Some(config);
+        self
+    }
+
+    /// Publishes a retained JSON document with version, uptime, source counters, writer states
+    /// and queue depths to the given topic in the given interval.
+    pub fn status(mut self, topic: impl Into<String>, interval: Duration) -> Self {
+        self.status = Some((topic.into(), interval));
+        self
+    }
+
+    /// Tags the `gateway_start` event sent to all targets on startup with the hash of the
+    /// configuration.
+    pub fn config_hash(mut self, hash: impl Into<String>) -> Self {
+        self.config_hash = Some(hash.into());
+        self
+    }
+
+    /// Writes messages dropped by the sources to rotated, optionally compressed NDJSON files.
+    pub fn dead_letter(mut self, config: DeadLetterConfig) -> Self {
+        self.dead_letter = Some(config);
+        self
+    }
+
+    /// Combines the events of the devices of all sources to virtual meters written to their own
+    /// targets.
+    pub fn virtual_meters(mut self, config: VirtualMetersConfig) -> Self {
+        self.virtual_meters = Some(config);
+        self
+    }
+
+    /// Serves the device registry and the Grafana JSON datasource on the given address.
+    pub fn http_listen(mut self, address: impl Into<String>) -> Self {
+        self.http_listen = Some(address.into());
+        self
+    }
+
+    /// Advertises the HTTP API via mDNS under the given instance name.
+    pub fn mdns(mut self, name: impl Into<String>) -> Self {
+        self.mdns_name = Some(name.into());
+        self
+    }
+
+    /// Sets the subscription QoS (0, 1 or 2) for the source with the given prefix, default is 1.
+    pub fn qos(mut self, prefix: impl Into<String>, qos: i32) -> Self {
+        self.qos.insert(prefix.into(), qos);
+        self
+    }
+
+    /// Sends a `gateway_message_age` event with the ages of the messages of the source with the
+    /// given prefix since the last heartbeat.
+    pub fn message_age(mut self, prefix: impl Into<String>) -> Self {
+        self.message_age.push(prefix.into());
+        self
+    }
+
+    /// Passes NDJSON log events read from the Unix socket at `path`, or stdin for `-`, to the
+    /// source with the given prefix as if they were published to `<prefix>/<location>/<type>`.
+    pub fn socket(mut self, prefix: impl Into<String>, path: impl Into<String>) -> Self {
+        self.sockets.push((prefix.into(), path.into()));
+        self
+    }
+
+    /// Accepts batches of log events for the source with the given prefix via
+    /// `POST /ingest/<prefix>` of the HTTP API.
+    pub fn ingest(mut self, prefix: impl Into<String>) -> Self {
+        self.ingest.push(prefix.into());
+        self
+    }
+
+    /// Polls the device via SNMP and passes its values as log events to the source with the given
+    /// prefix.
+    pub fn snmp(mut self, prefix: impl Into<String>, device: SnmpConfig) -> Self {
+        self.snmp.push((prefix.into(), device));
+        self
+    }
+
+    /// Polls the registers of the device via Modbus TCP and passes their values as log events to
+    /// the source with the given prefix.
+    pub fn modbus(mut self, prefix: impl Into<String>, device: ModbusConfig) -> Self {
+        self.modbus.push((prefix.into(), device));
+        self
+    }
+
+    /// Writes the events to the targets of the source with the given prefix whenever their
+    /// schedule matches.
+    pub fn events(mut self, prefix: impl Into<String>, events: Vec<StaticEventConfig>) -> Self {
+        self.events.push((prefix.into(), events));
+        self
+    }
+
+    /// Sends a `device_online` event per device of the source with the given prefix with each
+    /// heartbeat, 0 once the device sent no events for the timeout and 1 otherwise.
+    pub fn online_timeout(mut self, prefix: impl Into<String>, timeout: Duration) -> Self {
+        self.online_timeouts.push((prefix.into(), timeout));
+        self
+    }
+
+    /// Adds a source logger together with the writer threads it sends to.
+    pub fn logger(
+        mut self,
+        prefix: impl Into<String>,
+        logger: Arc<Mutex<dyn CheckMessage>>,
+        handles: Vec<JoinHandle<()>>,
+    ) -> Self {
+        self.sources.insert(prefix.into(), (logger, handles));
+        self
+    }
+
+    pub fn build(self) -> Result<Gateway> {
+        if self.sources.is_empty() {
+            return Err(GatewayError::config("no sources configured"));
+        }
+        if let Some((prefix, qos)) = self.qos.iter().find(|(_, qos)| !(0..=2).contains(*qos)) {
+            return Err(GatewayError::config(format!(
+                "invalid QoS {} for source {}",
+                qos, prefix
+            )));
+        }
+        if self.mqtt_urls.is_empty() {
+            return Err(GatewayError::config("no MQTT broker configured"));
+        }
+        source::mqtt::check_client_id(&self.mqtt_client_id, self.persistent_session)?;
+        #[cfg(not(feature = "snmp"))]
+        if !self.snmp.is_empty() {
+            return Err(GatewayError::config(
+                "SNMP support not built, enable the snmp feature",
+            ));
+        }
+        loopback::set_gateway_id(&self.gateway_id);
+
+        if let Some(path) = &self.devices_file {
+            devices::load(path)?;
+        }
+        if let Some(auth) = &self.auth {
+            auth::configure(auth);
+        }
+        if let Some(audit) = &self.audit {
+            audit::enable(audit)?;
+        }
+        if let Some(redaction) = &self.redaction {
+            redact::enable(redaction)?;
+        }
+        let cipher = match &self.encryption {
+            Some(encryption) => Some(encryption::read_key(&encryption.key_file)?),
+            None => None,
+        };
+        if let Some(capture) = &self.capture {
+            capture::enable(capture, cipher.clone())?;
+        }
+        if let Some(address) = &self.http_listen {
+            if self.live_history > 0 {
+                live::enable(self.live_history);
+            }
+            serve_http(address, self.mdns_name.as_deref())?;
+        }
+        for prefix in &self.message_age {
+            age::enable_measurement(prefix);
+        }
+        for (prefix, timeout) in &self.online_timeouts {
+            devices::enable_online(prefix, *timeout);
+        }
+        if let Some(memory_limit) = self.memory_limit {
+            memory::set_limit(memory_limit * 1024 * 1024);
+        }
+        if let Some(queue_memory_limit) = self.queue_memory_limit {
+            target::set_memory_limit(queue_memory_limit * 1024 * 1024);
+        }
+        if let Some(dead_letter) = &self.dead_letter {
+            deadletter::enable(dead_letter, cipher)?;
+        }
+        if let Some(virtual_meters) = &self.virtual_meters {
+            meter::enable(virtual_meters)?;
+        }
+
+        let mqtt_client =
+            source::mqtt::create_mqtt_client(self.mqtt_urls[0].clone(), self.mqtt_client_id)?;
+
+        Ok(Gateway {
+            mqtt_client,
+            brokers: Brokers::new(self.mqtt_urls),
+            persistent_session: self.persistent_session,
+            session_expiry: self.session_expiry,
+            backpressure: Backpressure::new(self.inflight_limit),
+            stream_buffer: self.stream_buffer,
+            heartbeat: self.heartbeat,
+            status: self.status,
+            config_hash: self.config_hash,
+            config: self.config,
+            control_topic: self.control_topic,
+            devices_file: self.devices_file,
+            sources: self.sources,
+            qos: self.qos,
+            sockets: self.sockets,
+            ingest: self.ingest,
+            #[cfg(feature = "snmp")]
+            snmp: self.snmp,
+            modbus: self.modbus,
+            events: self.events,
+        })
+    }
+}
+
+/// Serves the HTTP API on the given address, advertised via mDNS under the given name.
+#[cfg(feature = "http")]
+fn serve_http(address: &str, mdns_name: Option<&str>) -> Result<()> {
+    http::serve(address)?;
+    match mdns_name {
+        #[cfg(feature = "mdns")]
+        Some(name) => {
+            // discovery is a convenience, e.g. containers often lack multicast
+            if let Err(error) = http::mdns::advertise(name, address) {
+                warn!("failed to advertise HTTP API via mDNS: {}", error);
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "mdns"))]
+        Some(_) => Err(GatewayError::config(
+            "mDNS support not built, enable the mdns feature",
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn serve_http(_address: &str, _mdns_name: Option<&str>) -> Result<()> {
+    Err(GatewayError::config(
+        "HTTP support not built, enable the http feature",
+    ))
+}
+
+/// Origin of a control message for the audit, fails if the token may not issue the command.
+fn control_origin(token: Option<&str>, command: Command) -> Result<String> {
+    let token = auth::authorize(token, command)
+        .map_err(|denied| GatewayError::config(denied.to_string()))?;
+    Ok(match token {
+        Some(name) => format!("mqtt ({})", name),
+        None => "mqtt".to_string(),
+    })
+}
+
+/// Applies a control command received via MQTT and (un)subscribes the topics of the source, or
+/// starts or stops a capture.
+async fn handle_control_message(mqtt_client: &mqtt::AsyncClient, msg: &mqtt::Message) {
+    let change = ControlMessage::parse(msg.payload()).and_then(|message| match message {
+        ControlMessage::Source(command) => {
+            let origin = control_origin(command.token.as_deref(), command.command())?;
+            Ok((control::apply(&command, &origin)?, command.enabled))
+        }
+        ControlMessage::Capture(command) => {
+            let origin = control_origin(command.token.as_deref(), Command::Capture)?;
+            capture::apply(&command, &origin)?;
+            Ok((None, false))
+        }
+    });
+    let result = match change {
+        Ok((Some((topic, qos)), true)) => {
+            let topics = [topic];
+            mqtt_client
+                .subscribe_many(&topics, &[qos])
+                .await
+                .map(|response| source::mqtt::check_subscriptions(&topics, &[qos], &response))
+        }
+        Ok((Some((topic, _)), false)) => mqtt_client.unsubscribe(topic).await.map(|_| ()),
+        Ok((None, _)) => Ok(()),
+        Err(error) => {
+            warn!("invalid control message '{}': {}", msg.payload_str(), error);
+            Ok(())
+        }
+    };
+    if let Err(error) = result {
+        warn!("failed to change subscription: {}", error);
+    }
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "shelly",
+        feature = "openmqttgateway",
+        feature = "klimalogger"
+    )),
+    allow(unused_variables)
+)]
+fn create_logger(
+    source_type: SourceType,
+    targets: Vec<Target>,
+    enrichment: Enrichment,
+    timestamp: Option<&TimestampConfig>,
+) -> Result<crate::data::Logger> {
+    match source_type {
+        #[cfg(feature = "shelly")]
+        SourceType::Shelly => {
+            shelly::create_logger(targets, enrichment, timestamp, ShellyOptions::default())
+        }
+        #[cfg(feature = "klimalogger")]
+        SourceType::Sensor => {
+            klimalogger::create_logger(targets, enrichment, timestamp, None, None, None)
+        }
+        #[cfg(feature = "opendtu")]
+        SourceType::OpenDTU => opendtu::create_logger(targets, enrichment),
+        #[cfg(feature = "openmqttgateway")]
+        SourceType::OpenMqttGateway => {
+            openmqttgateway::create_logger(targets, enrichment, timestamp, None)
+        }
+        #[cfg(feature = "zwave")]
+        SourceType::ZWave => zwave::create_logger(targets, enrichment),
+        #[cfg(feature = "senml")]
+        SourceType::SenML => senml::create_logger(targets, enrichment),
+        SourceType::Debug => debug::create_logger(targets),
+        #[allow(unreachable_patterns)]
+        source_type => Err(GatewayError::config(format!(
+            "{:?} sources are not part of this build, enable their feature",
+            source_type
+        ))),
+    }
+}
+
+fn connect_options(
+    brokers: &Brokers,
+    persistent_session: bool,
+    session_expiry: Option<u32>,
+) -> std::result::Result<mqtt::ConnectOptions, mqtt::Error> {
+    let server_uris = brokers.ordered();
+    connection::log_connect_options(&server_uris, !persistent_session, session_expiry);
+    let mut conn_opts_builder = mqtt::ConnectOptionsBuilder::new_v5();
+    conn_opts_builder
+        .server_uris(&server_uris)
+        .keep_alive_interval(Duration::from_secs(30))
+        .clean_session(!persistent_session)
+        .automatic_reconnect(Duration::from_secs(1), Duration::from_secs(300));
+    if let Some(session_expiry) = session_expiry {
+        let mut properties = mqtt::Properties::new();
+        properties.push_int(
+            mqtt::PropertyCode::SessionExpiryInterval,
+            session_expiry as i32,
+        )?;
+        conn_opts_builder.properties(properties);
+    }
+    Ok(conn_opts_builder.finalize())
+}
+
+/// Connects to the brokers, cycling through them with a growing pause after each failed attempt,
+/// until connected or the gateway shuts down. Returns the connect response, none on shutdown.
+async fn connect_brokers(
+    mqtt_client: &mqtt::AsyncClient,
+    brokers: &mut Brokers,
+    persistent_session: bool,
+    session_expiry: Option<u32>,
+    context: &str,
+) -> std::result::Result<Option<mqtt::ServerResponse>, mqtt::Error> {
+    let mut delay = CONNECT_DELAY;
+    while !signal::received() {
+        let conn_opts = connect_options(brokers, persistent_session, session_expiry)?;
+        match mqtt_client.connect(conn_opts).await {
+            Ok(response) => {
+                connection::log_connack("broker", &response);
+                brokers.connected(&response);
+                return Ok(Some(response));
+            }
+            Err(err) => {
+                warn!("Error connecting: {}", err);
+                connection::log_failure(context, &err);
+                brokers.failed();
+                // For tokio use: tokio::time::delay_for()
+                async_std::task::sleep(delay).await;
+                delay = (delay * 2).min(MAX_CONNECT_DELAY);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Pauses consuming once `limit` items are queued for the targets and resumes when the queues
+/// drained to half of the limit.
+struct Backpressure {
+    limit: usize,
+    paused: bool,
+}
+
+impl Backpressure {
+    fn new(limit: usize) -> Self {
+        Backpressure {
+            limit,
+            paused: false,
+        }
+    }
+
+    /// Returns whether consuming is paused for the given number of queued items.
+    fn update(&mut self, queued: usize) -> bool {
+        self.paused = if self.paused {
+            queued > self.limit / 2
+        } else {
+            queued >= self.limit
+        };
+        self.paused
+    }
+}
+
+pub struct Gateway {
+    mqtt_client: mqtt::AsyncClient,
+    brokers: Brokers,
+    persistent_session: bool,
+    session_expiry: Option<u32>,
+    backpressure: Backpressure,
+    stream_buffer: usize,
+    heartbeat: Option<Duration>,
+    status: Option<(String, Duration)>,
+    config_hash: Option<String>,
+    config: Option<serde_json::Value>,
+    devices_file: Option<String>,
+    control_topic: Option<String>,
+    sources: Sources,
+    qos: HashMap<String, i32>,
+    sockets: Vec<(String, String)>,
+    ingest: Vec<String>,
+    #[cfg(feature = "snmp")]
+    snmp: Vec<(String, SnmpConfig)>,
+    modbus: Vec<(String, ModbusConfig)>,
+    events: Vec<(String, Vec<StaticEventConfig>)>,
+}
+
+impl Gateway {
+    pub fn builder(
+        mqtt_url: impl Into<String>,
+        mqtt_client_id: impl Into<String>,
+    ) -> GatewayBuilder {
+        GatewayBuilder::new(mqtt_url, mqtt_client_id)
+    }
+
+    /// Topic prefixes of the sources with their subscription QoS.
+    fn source_qos(&self) -> Vec<(String, i32)> {
+        let mut source_qos: Vec<(String, i32)> = self
+            .sources
+            .prefixes()
+            .map(|prefix| {
+                (
+                    prefix.clone(),
+                    self.qos.get(prefix).copied().unwrap_or(QOS_1),
+                )
+            })
+            .collect();
+        source_qos.sort();
+        source_qos
+    }
+
+    fn subscriptions(&self) -> Vec<(String, i32)> {
+        let mut subscriptions: Vec<(String, i32)> = self
+            .source_qos()
+            .into_iter()
+            .map(|(prefix, qos)| (format!("{}/#", prefix), qos))
+            .collect();
+        if let Some(control_topic) = &self.control_topic {
+            subscriptions.push((control_topic.clone(), QOS_1));
+        }
+        subscriptions
+    }
+
+    /// Connects to the broker and dispatches messages to the sources until the stream ends.
+    pub fn run(self) -> Result<()> {
+        let (topics, qoss): (Vec<String>, Vec<i32>) = self.subscriptions().into_iter().unzip();
+        let (source_topics, source_qoss): (Vec<String>, Vec<i32>) = topics
+            .iter()
+            .cloned()
+            .zip(qoss.iter().copied())
+            .filter(|(topic, _)| self.control_topic.as_ref() != Some(topic))
+            .unzip();
+        control::register(&self.mqtt_client, &self.source_qos());
+        ack::register(&self.mqtt_client);
+        let Gateway {
+            mut mqtt_client,
+            mut brokers,
+            persistent_session,
+            session_expiry,
+            mut backpressure,
+            stream_buffer,
+            heartbeat,
+            status,
+            config_hash,
+            config,
+            devices_file,
+            control_topic,
+            sources,
+            sockets,
+            ingest,
+            #[cfg(feature = "snmp")]
+            snmp,
+            modbus,
+            events,
+            ..
+        } = self;
+        let mut session_monitor = SessionMonitor::new(persistent_session);
+        let mut enabled: Vec<String> = sources
+            .prefixes()
+            .filter(|prefix| control::is_enabled(prefix))
+            .cloned()
+            .collect();
+        enabled.sort();
+        sources.started(&BuildInfo::new(config_hash.clone(), enabled));
+        diagnostics::register(config, config_hash.clone(), sources.loggers());
+        for (prefix, path) in &sockets {
+            if let Some(logger) = sources.get(prefix) {
+                source::socket::spawn_socket(path, prefix.clone(), logger.clone())?;
+            }
+        }
+        for prefix in ingest {
+            if let Some(logger) = sources.get(&prefix) {
+                source::ingest::register(prefix.clone(), logger.clone());
+            }
+        }
+        #[cfg(feature = "snmp")]
+        for (prefix, device) in snmp {
+            if let Some(logger) = sources.get(&prefix) {
+                source::snmp::spawn_snmp(device, prefix.clone(), logger.clone())?;
+            }
+        }
+        for (prefix, device) in modbus {
+            if let Some(logger) = sources.get(&prefix) {
+                source::modbus::spawn_modbus(device, prefix.clone(), logger.clone())?;
+            }
+        }
+        for (prefix, events) in events {
+            if let Some(logger) = sources.get(&prefix) {
+                source::inject::spawn_injection(events, prefix.clone(), logger.clone())?;
+            }
+        }
+        if let Some(interval) = heartbeat {
+            sources.spawn_heartbeat(interval);
+        }
+        supervisor::spawn_watchdog(WATCHDOG_INTERVAL);
+        signal::spawn_handler(mqtt_client.clone())?;
+        if let Some((topic, interval)) = status {
+            status::spawn_status(
+                mqtt_client.clone(),
+                topic,
+                interval,
+                config_hash,
+                sources.loggers(),
+            );
+        }
+
+        let result = block_on(async {
+            // Get message stream before connecting.
+            let mut strm = mqtt_client.get_stream(stream_buffer);
+
+            let Some(response) = connect_brokers(
+                &mqtt_client,
+                &mut brokers,
+                persistent_session,
+                session_expiry,
+                "broker connect",
+            )
+            .await?
+            else {
+                return Ok(());
+            };
+            session_monitor.connected(Some(response));
+
+            info!("Subscribing to topics: {:?}", &topics);
+            let response = mqtt_client.subscribe_many(&topics, &qoss).await?;
+            connection::log_suback(&topics, &qoss, &response);
+            source::mqtt::check_subscriptions(&topics, &qoss, &response);
+
+            info!("Waiting for messages...");
+
+            let mut last_stats = Instant::now();
+            let mut paused: Option<Instant> = None;
+
+            loop {
+                if let Some(since) = paused {
+                    let draining = backpressure.update(target::queued())
+                        || memory::exceeded()
+                            && target::queued() > 0
+                            && since.elapsed() < MEMORY_PAUSE;
+                    if !draining {
+                        // The retained messages were processed before, skip them this time.
+                        let opts = vec![
+                            mqtt::SubscribeOptions::with_retain_handling(
+                                mqtt::RetainHandling::DontSendRetained
+                            );
+                            source_topics.len()
+                        ];
+                        let response = mqtt_client
+                            .subscribe_many_with_options(&source_topics, &source_qoss, &opts, None)
+                            .await?;
+                        connection::log_suback(&source_topics, &source_qoss, &response);
+                        info!("resuming consumer");
+                        paused = None;
+                    }
+                }
+                let next = match paused {
+                    // Keep draining the messages which were in flight when unsubscribing.
+                    Some(_) => match async_std::future::timeout(PAUSE_POLL, strm.next()).await {
+                        Ok(next) => next,
+                        Err(_) => continue,
+                    },
+                    None => strm.next().await,
+                };
+                let Some(msg_opt) = next else {
+                    break;
+                };
+                if last_stats.elapsed() >= STATS_INTERVAL {
+                    sources.log_stats();
+                    if let Some(limit) = memory::limit() {
+                        info!(
+                            "memory: {} of {} bytes allocated",
+                            memory::allocated(),
+                            limit
+                        );
+                    }
+                    if let Some(path) = &devices_file {
+                        devices::save(path);
+                    }
+                    last_stats = Instant::now();
+                }
+
+                if let Some(msg) = msg_opt {
+                    if control_topic.as_deref() == Some(msg.topic()) {
+                        handle_control_message(&mqtt_client, &msg).await;
+                        continue;
+                    }
+                    if loopback::is_loop(&msg) {
+                        continue;
+                    }
+                    capture::tee(&msg);
+                    let prefix = msg.topic().split("/").next().unwrap();
+                    if !control::is_enabled(prefix) {
+                        log::debug!("ignoring message of disabled source {}", msg.topic());
+                        continue;
+                    }
+
+                    // The stream drops messages once its buffer is full, so rather than stop
+                    // reading it, unsubscribe and let the broker hold further messages back.
+                    if paused.is_none() {
+                        let pause = if backpressure.update(target::queued()) {
+                            warn!(
+                                "pausing consumer, {} items queued for targets",
+                                target::queued()
+                            );
+                            true
+                        } else if memory::exceeded() && target::queued() > 0 {
+                            // Events held in queues and buffers are released once the targets
+                            // drained.
+                            warn!(
+                                "pausing consumer, {} bytes allocated exceed the memory limit",
+                                memory::allocated()
+                            );
+                            true
+                        } else {
+                            false
+                        };
+                        if pause {
+                            mqtt_client.unsubscribe_many(&source_topics).await?;
+                            paused = Some(Instant::now());
+                        }
+                    }
+
+                    let handler = sources.get(prefix);
+                    if let Some(handler) = handler {
+                        age::with_source(prefix, || handler.lock().unwrap().check_message(&msg));
+                    } else {
+                        warn!("unhandled prefix {} from topic {}", prefix, msg.topic());
+                    }
+                } else {
+                    // A "None" means we were disconnected. Try to reconnect...
+                    warn!(
+                        "Lost connection. Attempting reconnect. {:?}",
+                        mqtt_client.is_connected()
+                    );
+                    session_monitor.disconnected();
+                    if let Some(response) = connect_brokers(
+                        &mqtt_client,
+                        &mut brokers,
+                        persistent_session,
+                        session_expiry,
+                        "broker reconnect",
+                    )
+                    .await?
+                    {
+                        session_monitor.connected(Some(response));
+                    }
+                }
+            }
+
+            // Explicit return type for the async block
+            Ok::<(), mqtt::Error>(())
+        });
+
+        sources.shutdown();
+        meter::close();
+        deadletter::close();
+        if let Some(path) = &devices_file {
+            devices::save(path);
+        }
+
+        result.map_err(|error| {
+            if source::mqtt::is_auth_failure(&error) {
+                GatewayError::auth("mqtt broker", error)
+            } else {
+                GatewayError::connect("mqtt broker", error)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::SourceStats;
+    use paho_mqtt::Message;
+
+    #[derive(Default)]
+    struct CountingLogger {
+        stats: SourceStats,
+    }
+
+    impl CheckMessage for CountingLogger {
+        fn check_message(&mut self, _msg: &Message) {
+            self.stats.received += 1;
+        }
+
+        fn stats(&self) -> SourceStats {
+            self.stats
+        }
+
+        fn shutdown(&mut self) {}
+    }
+
+    fn counting_logger() -> Arc<Mutex<dyn CheckMessage>> {
+        Arc::new(Mutex::new(CountingLogger::default()))
+    }
+
+    #[test]
+    fn test_backpressure_pauses_until_drained() {
+        let mut backpressure = Backpressure::new(10);
+
+        assert!(!backpressure.update(9));
+        assert!(backpressure.update(10));
+        assert!(backpressure.update(6));
+        assert!(!backpressure.update(5));
+        assert!(!backpressure.update(9));
+    }
+
+    #[test]
+    fn test_build_without_sources() {
+        let result = GatewayBuilder::new("tcp://localhost:1883", "gateway").build();
+
+        assert_eq!(
+            result.err().map(|error| error.to_string()),
+            Some("configuration error: no sources configured".to_string())
+        );
+    }
+
+    #[test]
+    fn test_build_with_sources() -> Result<()> {
+        let (debug, handles) =
+            create_logger(SourceType::Debug, Vec::new(), Enrichment::default(), None)?;
+        let gateway = Gateway::builder("tcp://localhost:1883", "gateway")
+            .logger("custom", counting_logger(), Vec::new())
+            .logger("debug", debug, handles)
+            .qos("debug", 2)
+            .build()?;
+
+        assert_eq!(
+            gateway.subscriptions(),
+            vec![("custom/#".to_string(), 1), ("debug/#".to_string(), 2)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_with_invalid_qos() {
+        let result = Gateway::builder("tcp://localhost:1883", "gateway")
+            .logger("custom", counting_logger(), Vec::new())
+            .qos("custom", 3)
+            .build();
+
+        assert_eq!(
+            result.err().map(|error| error.to_string()),
+            Some("configuration error: invalid QoS 3 for source custom".to_string())
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::error::{GatewayError, Result, EXIT_SIGNAL};
+use log::{info, warn};
+use paho_mqtt as mqtt;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::process;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Number of the first signal received, 0 before.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Stops the message stream of the client on SIGTERM or SIGINT, which lets the gateway shut down
+/// the sources and drain the queues of the targets. A second signal exits right away.
+pub fn spawn_handler(mqtt_client: mqtt::AsyncClient) -> Result<JoinHandle<()>> {
+    let mut signals = Signals::new([SIGTERM, SIGINT]).map_err(|error| {
+        GatewayError::config(format!("failed to register signal handlers: {}", error))
+    })?;
+    Ok(thread::spawn(move || {
+        for signal in signals.forever() {
+            if RECEIVED.swap(signal, Ordering::Relaxed) != 0 {
+                warn!("received signal {} again, exiting without draining", signal);
+                process::exit(EXIT_SIGNAL + signal);
+            }
+            info!("received signal {}, shutting down", signal);
+            mqtt_client.stop_stream();
+        }
+    }))
+}
+
+/// Whether the gateway is shutting down on a signal.
+pub fn received() -> bool {
+    RECEIVED.load(Ordering::Relaxed) != 0
+}
+
+/// Exit code after shutting down on a signal.
+pub fn exit_code() -> Option<i32> {
+    match RECEIVED.load(Ordering::Relaxed) {
+        0 => None,
+        signal => Some(EXIT_SIGNAL + signal),
+    }
+}
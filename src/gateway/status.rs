@@ -0,0 +1,138 @@
+use crate::data::{CheckMessage, SourceStats};
+use crate::memory;
+use crate::source::control;
+use crate::target;
+use crate::target::supervisor;
+use futures::executor::block_on;
+use log::warn;
+use paho_mqtt as mqtt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_STATUS_TOPIC: &str = "mqtt-gateway/status/full";
+pub const DEFAULT_STATUS_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize)]
+struct SourceStatus {
+    enabled: bool,
+    #[serde(flatten)]
+    stats: SourceStats,
+}
+
+/// State of the whole gateway as published to the status topic.
+#[derive(Debug, Serialize)]
+pub(super) struct Status {
+    version: &'static str,
+    #[serde(rename = "uptimeSeconds")]
+    uptime_seconds: u64,
+    time: i64,
+    #[serde(rename = "configHash", skip_serializing_if = "Option::is_none")]
+    config_hash: Option<String>,
+    sources: BTreeMap<String, SourceStatus>,
+    writers: serde_json::Value,
+    /// Events waiting in the queues of all writers.
+    queued: usize,
+    /// Approximate bytes of the queued events.
+    #[serde(rename = "queuedBytes")]
+    queued_bytes: usize,
+    /// Events dropped because of the queue memory limit.
+    shed: u64,
+    #[serde(rename = "allocatedBytes")]
+    allocated_bytes: usize,
+}
+
+/// State of the gateway started at the given time.
+pub(super) fn status(
+    started: Instant,
+    config_hash: Option<String>,
+    loggers: &[(String, Arc<Mutex<dyn CheckMessage>>)],
+) -> Status {
+    Status {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_seconds: started.elapsed().as_secs(),
+        time: chrono::offset::Utc::now().timestamp(),
+        config_hash,
+        sources: loggers
+            .iter()
+            .map(|(prefix, logger)| {
+                let status = SourceStatus {
+                    enabled: control::is_enabled(prefix),
+                    stats: logger.lock().unwrap().stats(),
+                };
+                (prefix.clone(), status)
+            })
+            .collect(),
+        writers: supervisor::report(),
+        queued: target::queued(),
+        queued_bytes: target::queued_bytes(),
+        shed: target::shed(),
+        allocated_bytes: memory::allocated(),
+    }
+}
+
+fn document(
+    started: Instant,
+    config_hash: Option<String>,
+    loggers: &[(String, Arc<Mutex<dyn CheckMessage>>)],
+) -> String {
+    serde_json::to_string(&status(started, config_hash, loggers)).unwrap()
+}
+
+/// Publishes the gateway state as retained message to `topic` in the given interval.
+pub fn spawn_status(
+    client: mqtt::AsyncClient,
+    topic: String,
+    interval: Duration,
+    config_hash: Option<String>,
+    loggers: Vec<(String, Arc<Mutex<dyn CheckMessage>>)>,
+) -> JoinHandle<()> {
+    let started = Instant::now();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let message = mqtt::Message::new_retained(
+            &topic,
+            document(started, config_hash.clone(), &loggers),
+            mqtt::QOS_1,
+        );
+        if let Err(error) = block_on(client.publish(message)) {
+            warn!("failed to publish status to {}: {}", topic, error);
+        }
+    })
+}
+
+#[cfg(all(test, feature = "klimalogger"))]
+mod tests {
+    use super::*;
+    use crate::data::enrichment::Enrichment;
+    use crate::data::klimalogger::SensorLogger;
+    use paho_mqtt::Message;
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn test_document() {
+        let (tx, _rx) = sync_channel(100);
+        let mut logger = SensorLogger::new(vec![tx], Enrichment::default());
+        logger.check_message(&Message::new("sensors/kitchen/temperature", "{", 1));
+        let loggers: Vec<(String, Arc<Mutex<dyn CheckMessage>>)> =
+            vec![("sensors".to_string(), Arc::new(Mutex::new(logger)))];
+
+        let status: serde_json::Value = serde_json::from_str(&document(
+            Instant::now(),
+            Some("0123456789ab".to_string()),
+            &loggers,
+        ))
+        .unwrap();
+
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["uptimeSeconds"], 0);
+        assert_eq!(status["configHash"], "0123456789ab");
+        assert_eq!(status["sources"]["sensors"]["enabled"], true);
+        assert_eq!(status["sources"]["sensors"]["received"], 1);
+        assert_eq!(status["sources"]["sensors"]["dropped"], 1);
+        assert!(status["writers"].is_array());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::data::enrichment;
+use crate::error::{GatewayError, Result};
+use log::info;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+
+/// DNS-SD service type of the HTTP API.
+pub const SERVICE_TYPE: &str = "_mqtt-gateway._tcp.local.";
+
+/// The daemon answers queries in its own thread as long as it is running.
+static DAEMON: LazyLock<Mutex<Option<ServiceDaemon>>> = LazyLock::new(|| Mutex::new(None));
+
+/// TXT records of the service, the endpoints let tooling tell gateway versions apart.
+fn properties() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("path", "/"),
+        (
+            "endpoints",
+            "/devices,/sources,/writers,/metrics,/history,/grafana",
+        ),
+    ]
+}
+
+/// Advertises the HTTP API listening on the given address under the instance name via mDNS.
+/// Unspecified addresses like `0.0.0.0` advertise the addresses of all interfaces.
+pub fn advertise(name: &str, address: &str) -> Result<()> {
+    let address: SocketAddr = address.parse().map_err(|error| {
+        GatewayError::config(format!("invalid HTTP address '{}': {}", address, error))
+    })?;
+    let host = format!(
+        "{}.local.",
+        enrichment::hostname().unwrap_or_else(|| name.to_string())
+    );
+    let addresses: Vec<IpAddr> = if address.ip().is_unspecified() {
+        Vec::new()
+    } else {
+        vec![address.ip()]
+    };
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        name,
+        &host,
+        addresses.as_slice(),
+        address.port(),
+        properties().as_slice(),
+    )
+    .map_err(|error| GatewayError::config(format!("invalid mDNS service: {}", error)))?;
+    let service = if addresses.is_empty() {
+        service.enable_addr_auto()
+    } else {
+        service
+    };
+
+    let daemon = ServiceDaemon::new().map_err(|error| GatewayError::connect("mdns", error))?;
+    daemon
+        .register(service)
+        .map_err(|error| GatewayError::connect("mdns", error))?;
+    info!(
+        "advertising HTTP API as '{}' ({}) on port {}",
+        name,
+        SERVICE_TYPE,
+        address.port()
+    );
+    *DAEMON.lock().unwrap() = Some(daemon);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_address() {
+        assert!(advertise("gateway-1", "localhost").is_err());
+    }
+}
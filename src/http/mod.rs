@@ -0,0 +1,311 @@
+#[cfg(feature = "mdns")]
+pub(crate) mod mdns;
+
+use crate::config::Command;
+use crate::data::{age, devices, live};
+use crate::error::{GatewayError, Result};
+use crate::gateway::diagnostics;
+use crate::source::control;
+use crate::source::control::auth;
+use crate::source::ingest;
+use crate::source::loopback;
+use crate::source::mqtt;
+use crate::target;
+use crate::target::history;
+use crate::target::supervisor;
+use log::{info, warn};
+use std::io::Read;
+use std::thread;
+use std::thread::JoinHandle;
+use tiny_http::Method;
+
+const NOT_FOUND: &str = "{\"error\":\"not found\"}";
+/// Largest request body read, enough for ingestion batches of a few thousand events.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Value of the query parameter `name` of the given URL.
+fn parameter<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Enables or disables the source of a `/sources/<prefix>/enable` or `/sources/<prefix>/disable`
+/// request.
+fn control_source(path: &str, origin: &str) -> Option<(u16, String)> {
+    let (prefix, action) = path.strip_prefix("/sources/")?.split_once('/')?;
+    let enabled = match action {
+        "enable" => true,
+        "disable" => false,
+        _ => return None,
+    };
+    Some(match control::set_enabled(prefix, enabled, origin) {
+        Ok(()) => (200, control::status()),
+        Err(GatewayError::Config(message)) => {
+            (404, serde_json::json!({ "error": message }).to_string())
+        }
+        Err(error) => (
+            502,
+            serde_json::json!({ "error": error.to_string() }).to_string(),
+        ),
+    })
+}
+
+/// Command of a request for the authorization.
+fn command(method: &Method, url: &str) -> Command {
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    match method {
+        Method::Post if path.starts_with("/sources/") && path.ends_with("/enable") => {
+            Command::Enable
+        }
+        Method::Post if path.starts_with("/sources/") && path.ends_with("/disable") => {
+            Command::Disable
+        }
+        Method::Post if path.starts_with("/ingest/") => Command::Ingest,
+        _ => Command::Read,
+    }
+}
+
+/// Content type of the response to an authorized request, the metrics are served in the
+/// Prometheus text format.
+fn content_type(method: &Method, url: &str) -> &'static str {
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    match (method, path) {
+        (Method::Get, "/metrics") => "text/plain; version=0.0.4",
+        _ => "application/json",
+    }
+}
+
+/// Body of an authorized request. Bodies larger than [`MAX_BODY`] are refused, without reading
+/// them if their length is announced.
+fn read_body(request: &mut tiny_http::Request) -> std::result::Result<String, (u16, String)> {
+    let too_large = || {
+        let error = format!("body exceeds {} bytes", MAX_BODY);
+        (413, serde_json::json!({ "error": error }).to_string())
+    };
+    if request
+        .body_length()
+        .is_some_and(|length| length > MAX_BODY)
+    {
+        return Err(too_large());
+    }
+    let mut body = String::new();
+    let mut reader = request.as_reader();
+    if let Err(error) = Read::take(&mut reader, MAX_BODY as u64 + 1).read_to_string(&mut body) {
+        warn!("failed to read HTTP request body: {}", error);
+    }
+    if body.len() > MAX_BODY {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// Token of an `Authorization: Bearer <token>` header.
+fn bearer_token(request: &tiny_http::Request) -> Option<&str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+}
+
+/// Routes a request to the device registry, the queue and message age metrics (`/metrics`), the event history (`/history?measurement=..&minutes=..`),
+/// the diagnostics for bug reports (`/diagnostics`), the source control (`/sources`), the event ingestion (`/ingest/<prefix>`) or the Grafana JSON
+/// datasource endpoints below `/grafana`, which serve the live values. Control actions are audited with the given origin.
+fn respond(origin: &str, method: &Method, url: &str, body: &str) -> (u16, String) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    match (method, path) {
+        (Method::Get, "/devices") => (200, devices::listing()),
+        (Method::Get, "/sources") => (200, control::status()),
+        (Method::Get, "/writers") => (200, supervisor::status()),
+        (Method::Get, "/metrics") => (
+            200,
+            target::metrics() + &age::metrics() + &loopback::metrics() + &mqtt::metrics(),
+        ),
+        (Method::Get, "/diagnostics") => (200, diagnostics::report()),
+        (Method::Post, path) if path.starts_with("/sources/") => {
+            control_source(path, origin).unwrap_or_else(|| (404, NOT_FOUND.to_string()))
+        }
+        (Method::Post, path) if path.starts_with("/ingest/") => {
+            ingest::respond(&path["/ingest/".len()..], body)
+        }
+        (Method::Get, "/history") => {
+            let minutes = match parameter(query, "minutes").map(str::parse).transpose() {
+                Ok(minutes) => minutes,
+                Err(error) => {
+                    return (
+                        400,
+                        serde_json::json!({"error": format!("invalid minutes: {}", error)})
+                            .to_string(),
+                    )
+                }
+            };
+            (200, history::dump(parameter(query, "measurement"), minutes))
+        }
+        (Method::Get, "/grafana") | (Method::Get, "/grafana/") => (200, "{}".to_string()),
+        (Method::Post, "/grafana/search") => (200, serde_json::to_string(&live::search()).unwrap()),
+        (Method::Post, "/grafana/metrics") => {
+            let metrics: Vec<_> = live::search()
+                .into_iter()
+                .map(|series| serde_json::json!({"label": series, "value": series}))
+                .collect();
+            (200, serde_json::to_string(&metrics).unwrap())
+        }
+        (Method::Post, "/grafana/query") => match serde_json::from_str(body) {
+            Ok(request) => (200, serde_json::to_string(&live::query(&request)).unwrap()),
+            Err(error) => (
+                400,
+                serde_json::json!({"error": error.to_string()}).to_string(),
+            ),
+        },
+        _ => (404, NOT_FOUND.to_string()),
+    }
+}
+
+/// Serves the HTTP API on the given address.
+pub fn serve(address: &str) -> Result<JoinHandle<()>> {
+    let server = tiny_http::Server::http(address)
+        .map_err(|error| GatewayError::connect(format!("http {}", address), error))?;
+    info!("serving HTTP API on http://{}", address);
+
+    Ok(thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let authorized = auth::authorize(
+                bearer_token(&request),
+                command(request.method(), request.url()),
+            );
+            let (status, body, content_type) = match authorized {
+                Ok(token) => {
+                    let address = request
+                        .remote_addr()
+                        .map_or_else(|| "unknown".to_string(), |address| address.to_string());
+                    let origin = match token {
+                        Some(name) => format!("http {} ({})", address, name),
+                        None => format!("http {}", address),
+                    };
+                    match read_body(&mut request) {
+                        Ok(body) => {
+                            let (status, body) =
+                                respond(&origin, request.method(), request.url(), &body);
+                            (status, body, content_type(request.method(), request.url()))
+                        }
+                        Err((status, body)) => (status, body, "application/json"),
+                    }
+                }
+                Err(denied) => {
+                    warn!("denied HTTP request {}: {}", request.url(), denied);
+                    (
+                        denied.status(),
+                        serde_json::json!({ "error": denied.to_string() }).to_string(),
+                        "application/json",
+                    )
+                }
+            };
+            let response = tiny_http::Response::from_string(body)
+                .with_status_code(status)
+                .with_header(
+                    format!("Content-Type: {}", content_type)
+                        .parse::<tiny_http::Header>()
+                        .unwrap(),
+                );
+            if let Err(error) = request.respond(response) {
+                warn!("failed to send HTTP response: {}", error);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            content_type(&Method::Get, "/metrics"),
+            "text/plain; version=0.0.4"
+        );
+        assert_eq!(
+            content_type(&Method::Get, "/history?minutes=5"),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn test_respond_not_found() {
+        assert_eq!(respond("test", &Method::Get, "/", "").0, 404);
+        assert_eq!(respond("test", &Method::Post, "/devices", "").0, 404);
+    }
+
+    #[test]
+    fn test_respond_grafana() {
+        assert_eq!(
+            respond("test", &Method::Get, "/grafana", ""),
+            (200, "{}".to_string())
+        );
+        assert_eq!(
+            respond("test", &Method::Post, "/grafana/query", "{\"targets\":[]}"),
+            (200, "[]".to_string())
+        );
+        assert_eq!(
+            respond("test", &Method::Post, "/grafana/query", "foo").0,
+            400
+        );
+    }
+
+    #[test]
+    fn test_respond_sources() {
+        assert_eq!(respond("test", &Method::Get, "/sources", "").0, 200);
+        assert_eq!(
+            respond("test", &Method::Post, "/sources/unknown/disable", "").0,
+            404
+        );
+        assert_eq!(
+            respond("test", &Method::Post, "/sources/unknown/pause", "").0,
+            404
+        );
+    }
+
+    #[test]
+    fn test_respond_diagnostics() {
+        let (status, body) = respond("test", &Method::Get, "/diagnostics", "");
+
+        assert_eq!(status, 200);
+        let diagnostics: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(diagnostics["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(command(&Method::Get, "/sources"), Command::Read);
+        assert_eq!(
+            command(&Method::Post, "/sources/shellies/enable"),
+            Command::Enable
+        );
+        assert_eq!(
+            command(&Method::Post, "/sources/shellies/disable?x=1"),
+            Command::Disable
+        );
+        assert_eq!(command(&Method::Post, "/grafana/query"), Command::Read);
+        assert_eq!(command(&Method::Post, "/ingest/sensors"), Command::Ingest);
+    }
+
+    #[test]
+    fn test_respond_history() {
+        assert_eq!(
+            parameter("measurement=power&minutes=10", "minutes"),
+            Some("10")
+        );
+        assert_eq!(parameter("measurement=power", "minutes"), None);
+        assert_eq!(
+            respond("test", &Method::Get, "/history?measurement=unknown", ""),
+            (200, "{}".to_string())
+        );
+        assert_eq!(
+            respond("test", &Method::Get, "/history?minutes=ten", "").0,
+            400
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::config::{Config, SourceType};
+use crate::error::{GatewayError, Result};
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+const DEFAULT_BROKER: &str = "mqtt://localhost:1883";
+const DEFAULT_CLIENT_ID: &str = "mqtt-gateway";
+const DEFAULT_OUTPUT: &str = "config.yml";
+
+/// Storage targets a starter configuration can be generated for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TargetType {
+    InfluxDB,
+    Postgresql,
+    Redis,
+    Mqtt,
+    History,
+}
+
+impl TargetType {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "influxdb" => Ok(TargetType::InfluxDB),
+            "postgresql" => Ok(TargetType::Postgresql),
+            "redis" => Ok(TargetType::Redis),
+            "mqtt" => Ok(TargetType::Mqtt),
+            "history" => Ok(TargetType::History),
+            _ => Err(GatewayError::config(format!(
+                "unknown target type '{}', expected influxdb, postgresql, redis, mqtt or history",
+                name
+            ))),
+        }
+    }
+
+    /// Readings of sensor sources can be written to every target, other sources to all but
+    /// PostgreSQL targets.
+    fn supports(&self, source_type: &SourceType) -> bool {
+        matches!(source_type, SourceType::Sensor) || !matches!(self, TargetType::Postgresql)
+    }
+}
+
+fn parse_source_type(name: &str) -> Result<SourceType> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).map_err(|_| {
+        GatewayError::config(format!(
+            "unknown source type '{}', expected sensor, shelly, opendtu, openmqttgateway, zwave, senml or debug",
+            name
+        ))
+    })
+}
+
+/// Choices of `mqtt-gateway init`, given as flags or answered interactively.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitOptions {
+    broker: String,
+    client_id: String,
+    sources: Vec<SourceType>,
+    target: TargetType,
+    /// File to write, `-` for standard output.
+    output: String,
+    force: bool,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        InitOptions {
+            broker: DEFAULT_BROKER.to_string(),
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            sources: vec![SourceType::Sensor],
+            target: TargetType::InfluxDB,
+            output: DEFAULT_OUTPUT.to_string(),
+            force: false,
+        }
+    }
+}
+
+impl InitOptions {
+    /// Parses `--broker`, `--client-id`, `--source` (repeatable or comma separated), `--target`,
+    /// `--output` and `--force`.
+    fn from_args(args: &[String]) -> Result<Self> {
+        let mut options = InitOptions::default();
+        let mut sources = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--force" {
+                options.force = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| GatewayError::config(format!("missing value of {}", arg)))?;
+            match arg.as_str() {
+                "--broker" => options.broker = value.clone(),
+                "--client-id" => options.client_id = value.clone(),
+                "--source" => {
+                    for name in value.split(',') {
+                        sources.push(parse_source_type(name.trim())?);
+                    }
+                }
+                "--target" => options.target = TargetType::parse(value)?,
+                "--output" => options.output = value.clone(),
+                _ => return Err(GatewayError::config(format!("unknown option {}", arg))),
+            }
+        }
+        if !sources.is_empty() {
+            options.sources = sources;
+        }
+        Ok(options)
+    }
+
+    /// Asks for every choice, an empty answer keeps the default.
+    fn ask(input: &mut impl BufRead, output: &mut impl Write) -> Result<Self> {
+        let mut options = InitOptions::default();
+        options.broker = ask(input, output, "MQTT broker URL", &options.broker)?;
+        options.client_id = ask(input, output, "MQTT client ID", &options.client_id)?;
+        options.sources = ask(
+            input,
+            output,
+            "Source types (sensor, shelly, opendtu, openmqttgateway, zwave, senml, debug)",
+            "sensor",
+        )?
+        .split(',')
+        .map(|name| parse_source_type(name.trim()))
+        .collect::<Result<_>>()?;
+        options.target = TargetType::parse(&ask(
+            input,
+            output,
+            "Target type (influxdb, postgresql, redis, mqtt, history)",
+            "influxdb",
+        )?)?;
+        options.output = ask(input, output, "Write to", &options.output)?;
+        Ok(options)
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self
+            .sources
+            .iter()
+            .find(|source_type| !self.target.supports(source_type))
+        {
+            Some(source_type) => Err(GatewayError::config(format!(
+                "target {:?} is not supported by source type {:?}",
+                self.target, source_type
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> Result<String> {
+    let mut answer = String::new();
+    write!(output, "{} [{}]: ", question, default)
+        .and_then(|_| output.flush())
+        .and_then(|_| input.read_line(&mut answer))
+        .map_err(|error| GatewayError::config(format!("failed to read answer: {}", error)))?;
+    Ok(match answer.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    })
+}
+
+fn source_names(source_type: &SourceType) -> (&'static str, &'static str) {
+    match source_type {
+        SourceType::Sensor => ("sensor", "sensors"),
+        SourceType::Shelly => ("shelly", "shellies"),
+        SourceType::OpenDTU => ("opendtu", "solar"),
+        SourceType::OpenMqttGateway => ("openmqttgateway", "home"),
+        SourceType::ZWave => ("zwave", "zwave"),
+        SourceType::SenML => ("senml", "senml"),
+        SourceType::Debug => ("debug", "debug"),
+    }
+}
+
+fn render_target(target: TargetType, database: &str) -> String {
+    match target {
+        TargetType::InfluxDB => format!(
+            "      - type: \"influxdb\"
+        url: \"http://localhost:8086\"
+        database: \"{database}\"
+        # user: \"<user>\"
+        # password: \"<password>\"
+"
+        ),
+        TargetType::Postgresql => format!(
+            "      - type: \"postgresql\"
+        host: \"localhost\"
+        port: 5432
+        user: \"<user>\"
+        password: \"<password>\"
+        database: \"{database}\"
+"
+        ),
+        TargetType::Redis => format!(
+            "      - type: \"redis\"
+        url: \"redis://localhost:6379\"
+        stream: \"{database}\"
+        # maxLength: 10000
+"
+        ),
+        TargetType::Mqtt => format!(
+            "      - type: \"mqtt\"
+        url: \"mqtt://localhost:1883\"
+        topic: \"gateway/{database}/{{location}}/{{measurement}}\"
+        # format: \"json\"
+"
+        ),
+        TargetType::History => "      - type: \"history\"
+        # size: 100
+"
+        .to_string(),
+    }
+}
+
+fn render_source(source_type: &SourceType, target: TargetType) -> String {
+    let (name, prefix) = source_names(source_type);
+    let mut source = format!(
+        "  - name: \"{name}\"
+    type: \"{name}\"
+    prefix: \"{prefix}\"
+    # only process messages at these times
+    # activeHours: \"05:00-22:00\"
+    # drop events without timestamp or with a timestamp more than maxOffset seconds off
+    # timestamp:
+    #   missing: \"drop\"
+    #   maxOffset: 10
+"
+    );
+    match source_type {
+        SourceType::Sensor => source.push_str(
+            "    # publish an acknowledgement to {prefix}/ack/{location} once all targets wrote a message
+    # ack: true
+",
+        ),
+        SourceType::Shelly => source.push_str(
+            "    # tag measurements with device info from announce and status/sys messages
+    # deviceTags: [\"model\", \"fw\"]
+    # drop messages lacking aenergy or temperature instead of writing the fields present
+    # parsing: \"strict\"
+",
+        ),
+        _ => {}
+    }
+    source.push_str("    targets:\n");
+    source.push_str(&render_target(target, prefix));
+    source
+}
+
+/// A commented starter configuration for the chosen sources and target.
+fn render(options: &InitOptions) -> String {
+    let mut config = format!(
+        "# generated by mqtt-gateway init, see the README for all options
+mqttUrl: \"{}\"
+mqttClientId: \"{}\"
+# keep the broker session across restarts, requires a stable mqttClientId
+# persistentSession: true
+# send a gateway_heartbeat event every 60 seconds
+# heartbeat: 60
+# enable or disable sources at runtime
+# controlTopic: \"mqtt-gateway/control\"
+# HTTP API with devices, sources, writers and a Grafana datasource
+# http:
+#   listen: \"0.0.0.0:8080\"
+# write dropped messages to files
+# deadLetter:
+#   directory: \"/data/dead_letter\"
+# \"constrained\" lowers queue sizes and memory use for small devices
+# profile: \"constrained\"
+sources:
+",
+        options.broker, options.client_id
+    );
+    for source_type in &options.sources {
+        config.push_str(&render_source(source_type, options.target));
+    }
+    config
+}
+
+/// Generates a starter configuration from the given flags, or interactively without flags on a
+/// terminal, and writes it to `config.yml` unless it exists.
+pub fn init(args: &[String]) -> Result<()> {
+    let stdin = std::io::stdin();
+    let options = if args.is_empty() && stdin.is_terminal() {
+        InitOptions::ask(&mut stdin.lock(), &mut std::io::stdout())?
+    } else {
+        InitOptions::from_args(args)?
+    };
+    options.validate()?;
+
+    let config = render(&options);
+    serde_yml::from_str::<Config>(&config).map_err(|error| {
+        GatewayError::config(format!("generated an invalid configuration: {}", error))
+    })?;
+
+    if options.output == "-" {
+        print!("{}", config);
+        return Ok(());
+    }
+    if Path::new(&options.output).exists() && !options.force {
+        return Err(GatewayError::config(format!(
+            "{} exists, use --force to overwrite it",
+            options.output
+        )));
+    }
+    fs::write(&options.output, config).map_err(|error| {
+        GatewayError::config(format!("failed to write {}: {}", options.output, error))
+    })?;
+    println!("wrote {}", options.output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_valid_config() -> Result<()> {
+        for target in ["influxdb", "postgresql", "redis", "mqtt", "history"] {
+            let options = InitOptions::from_args(&args(&["--target", target]))?;
+            let config: Config = serde_yml::from_str(&render(&options)).unwrap();
+            assert_eq!(config.sources.len(), 1);
+            assert_eq!(config.sources[0].prefix, "sensors");
+        }
+
+        let options = InitOptions::from_args(&args(&[
+            "--broker",
+            "mqtt://broker:1883",
+            "--source",
+            "shelly,opendtu",
+            "--source",
+            "openmqttgateway",
+        ]))?;
+        let config: Config = serde_yml::from_str(&render(&options)).unwrap();
+        assert_eq!(config.mqtt_url.urls(), vec!["mqtt://broker:1883"]);
+        assert_eq!(config.sources.len(), 3);
+        assert_eq!(config.sources[1].source_type, SourceType::OpenDTU);
+        assert!(matches!(
+            config.sources[0].targets.as_deref(),
+            Some([Target::InfluxDB { .. }])
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(InitOptions::from_args(&args(&["--source", "zigbee"])).is_err());
+        assert!(InitOptions::from_args(&args(&["--target"])).is_err());
+        assert!(InitOptions::from_args(&args(&["--verbose", "1"])).is_err());
+
+        let options =
+            InitOptions::from_args(&args(&["--source", "shelly", "--target", "postgresql"]))
+                .unwrap();
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_ask() -> Result<()> {
+        let mut input = "\nsensors-1\nsensor, shelly\nmqtt\n-\n".as_bytes();
+        let mut output = Vec::new();
+
+        let options = InitOptions::ask(&mut input, &mut output)?;
+
+        assert_eq!(options.broker, DEFAULT_BROKER);
+        assert_eq!(options.client_id, "sensors-1");
+        assert_eq!(
+            options.sources,
+            vec![SourceType::Sensor, SourceType::Shelly]
+        );
+        assert_eq!(options.target, TargetType::Mqtt);
+        assert_eq!(options.output, "-");
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("MQTT broker URL [mqtt://localhost:1883]: "));
+        Ok(())
+    }
+}
@@ -1,36 +1,76 @@
-use crate::config::SourceType;
-use crate::data::{debug, openmqttgateway, CheckMessage};
+// Builds without any source parser only serve the debug source, which writes to no target, so
+// the writers and the helpers of the parsers stay unused. The status, metrics and query helpers
+// are only read by the HTTP API.
+#![cfg_attr(
+    any(
+        not(any(
+            feature = "shelly",
+            feature = "opendtu",
+            feature = "openmqttgateway",
+            feature = "klimalogger",
+            feature = "zwave",
+            feature = "senml"
+        )),
+        not(feature = "http")
+    ),
+    allow(dead_code)
+)]
+#![cfg_attr(
+    not(any(
+        feature = "shelly",
+        feature = "opendtu",
+        feature = "openmqttgateway",
+        feature = "klimalogger",
+        feature = "zwave",
+        feature = "senml"
+    )),
+    allow(unused_imports, unused_variables)
+)]
+
+use crate::config::drift;
+use crate::error::{GatewayError, Result, EXIT_INTERNAL};
+use crate::gateway::signal;
+use crate::gateway::GatewayBuilder;
+use crate::target::ack::Ack;
 use chrono::{DateTime, Utc};
-use data::{klimalogger, opendtu, shelly};
-use futures::{executor::block_on, stream::StreamExt};
-use log::{debug, error, info, warn};
-use paho_mqtt as mqtt;
-use paho_mqtt::QOS_1;
-use std::collections::HashMap;
+use log::{debug, error, info};
+use std::env;
 use std::fmt::Debug;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::panic;
 use std::path::Path;
 use std::process::exit;
-use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
-use std::{env, fs, time::Duration};
+use std::sync::Arc;
+use std::time::Duration;
 
 mod config;
 mod data;
+mod error;
+mod gateway;
+#[cfg(feature = "http")]
+mod http;
+mod init;
+mod memory;
 mod source;
 mod target;
 
 #[derive(Debug, Clone)]
 pub struct SensorReading {
-    pub measurement: String,
+    pub measurement: Arc<str>,
     pub time: DateTime<Utc>,
-    pub location: String,
-    pub sensor: String,
+    pub location: Arc<str>,
+    pub sensor: Arc<str>,
     pub value: f32,
+    pub tags: Vec<(String, String)>,
+    /// Confirmed by every target after writing the reading.
+    pub ack: Option<Ack>,
 }
 
 pub enum WriteType {
     Int(i32),
-    Float(f32),
+    Float(f64),
 }
 
 fn main() {
@@ -38,92 +78,81 @@ fn main() {
         env::set_var("RUST_LOG", "info")
     }
     // Initialize the logger from the environment
-    env_logger::init();
+    gateway::diagnostics::init_logger();
 
-    let config_file_path = determine_config_file_path();
+    let code = match panic::catch_unwind(run) {
+        Ok(Ok(())) => signal::exit_code().unwrap_or(0),
+        Ok(Err(err)) => {
+            error!("{}", err);
+            err.exit_code()
+        }
+        Err(_) => EXIT_INTERNAL,
+    };
+    exit(code);
+}
+
+fn run() -> Result<()> {
+    if env::args().nth(1).as_deref() == Some("init") {
+        return init::init(&env::args().skip(2).collect::<Vec<_>>());
+    }
+    if env::args().nth(1).as_deref() == Some("decrypt") {
+        return decrypt(&env::args().skip(2).collect::<Vec<_>>());
+    }
+
+    if env::args().skip(1).any(|arg| arg == "--debug-connection") {
+        source::connection::enable();
+    }
+
+    let config_file_path = determine_config_file_path()?;
 
-    let config_string = fs::read_to_string(config_file_path).expect("failed to read config file");
-    let config: config::Config =
-        serde_yml::from_str(&config_string).expect("failed to parse config file");
+    let config = drift::read(&config_file_path)?;
 
     debug!("config: {:?}", config);
 
-    let mut handler_map: HashMap<String, Arc<Mutex<dyn CheckMessage>>> = HashMap::new();
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-    let mut topics: Vec<String> = Vec::new();
-    let mut qoss: Vec<i32> = Vec::new();
-
-    for source in config.sources {
-        let targets = source.targets.unwrap_or_default();
-        let (logger, mut source_handles) = match source.source_type {
-            SourceType::Shelly => shelly::create_logger(targets),
-            SourceType::Sensor => klimalogger::create_logger(targets),
-            SourceType::OpenDTU => opendtu::create_logger(targets),
-            SourceType::OpenMqttGateway => openmqttgateway::create_logger(targets),
-            SourceType::Debug => debug::create_logger(targets),
-        };
-        handler_map.insert(source.prefix.clone(), logger);
-        handles.append(&mut source_handles);
-
-        topics.push(format!("{}/#", source.prefix));
-        qoss.push(QOS_1);
+    if env::args().nth(1).as_deref() == Some("catalog") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&data::catalog::create_catalog(&config))?
+        );
+        return Ok(());
     }
 
-    let mut mqtt_client = source::mqtt::create_mqtt_client(config.mqtt_url, config.mqtt_client_id);
-
-    if let Err(err) = block_on(async {
-        // Get message stream before connecting.
-        let mut strm = mqtt_client.get_stream(200);
-
-        let conn_opts = mqtt::ConnectOptionsBuilder::new_v5()
-            .keep_alive_interval(Duration::from_secs(30))
-            .clean_session(false)
-            .automatic_reconnect(Duration::from_secs(1), Duration::from_secs(300))
-            .finalize();
-
-        mqtt_client.connect(conn_opts).await?;
-
-        info!("Subscribing to topics: {:?}", &topics);
-        mqtt_client.subscribe_many(&topics, &qoss).await?;
-
-        info!("Waiting for messages...");
-
-        while let Some(msg_opt) = strm.next().await {
-            if let Some(msg) = msg_opt {
-                let prefix = msg.topic().split("/").next().unwrap();
-
-                let handler = handler_map.get(prefix);
-                if let Some(handler) = handler {
-                    handler.lock().unwrap().check_message(&msg);
-                } else {
-                    warn!("unhandled prefix {} from topic {}", prefix, msg.topic());
-                }
-            } else {
-                // A "None" means we were disconnected. Try to reconnect...
-                warn!(
-                    "Lost connection. Attempting reconnect. {:?}",
-                    mqtt_client.is_connected()
-                );
-                while let Err(err) = mqtt_client.reconnect().await {
-                    warn!("Error reconnecting: {}", err);
-                    // For tokio use: tokio::time::delay_for()
-                    async_std::task::sleep(Duration::from_millis(1000)).await;
-                }
-            }
-        }
+    let config_hash = drift::hash(&config)?;
+    info!(
+        "running configuration {} with hash {}",
+        config_file_path, config_hash
+    );
+    let check_interval = config
+        .config_check
+        .map_or(drift::DEFAULT_CHECK_INTERVAL, Duration::from_secs);
+    if !check_interval.is_zero() {
+        drift::spawn_drift_check(config_file_path, config_hash.clone(), check_interval);
+    }
 
-        for handle in handles {
-            handle.join().expect("failed to join influx writer thread");
-        }
+    GatewayBuilder::from_config(config)?
+        .config_hash(config_hash)
+        .build()?
+        .run()
+}
 
-        // Explicit return type for the async block
-        Ok::<(), mqtt::Error>(())
-    }) {
-        error!("{}", err);
-    }
+/// Writes the content of an encrypted dead-letter or capture file to stdout, still compressed for
+/// `.gz.enc` and `.zst.enc` files.
+fn decrypt(args: &[String]) -> Result<()> {
+    let [key_file, path] = args else {
+        return Err(GatewayError::config(
+            "usage: mqtt-gateway decrypt <key file> <encrypted file>",
+        ));
+    };
+    let cipher = data::encryption::read_key(key_file)?;
+    let plaintext = File::open(path)
+        .and_then(|file| data::encryption::decrypt(&cipher, file))
+        .map_err(|error| GatewayError::parse(format!("encrypted file {}", path), error))?;
+    io::stdout()
+        .write_all(&plaintext)
+        .map_err(|error| GatewayError::config(format!("failed to write output: {}", error)))
 }
 
-fn determine_config_file_path() -> String {
+fn determine_config_file_path() -> Result<String> {
     let config_file_name = "config.yml";
     let config_locations = ["./", "./config"];
 
@@ -138,10 +167,5 @@ fn determine_config_file_path() -> String {
         }
     }
 
-    if config_file_path.is_none() {
-        error!("ERROR: no configuration file found");
-        exit(10);
-    }
-
-    config_file_path.unwrap()
+    config_file_path.ok_or_else(|| GatewayError::config("no configuration file found"))
 }
@@ -0,0 +1,92 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator::new();
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The system allocator counting the bytes currently allocated on the heap, which is compared
+/// against the memory limit.
+pub struct CountingAllocator {
+    allocated: AtomicUsize,
+}
+
+impl CountingAllocator {
+    pub const fn new() -> Self {
+        CountingAllocator {
+            allocated: AtomicUsize::new(0),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            self.allocated.fetch_add(new_size, Ordering::Relaxed);
+            self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Bytes currently allocated on the heap.
+pub fn allocated() -> usize {
+    ALLOCATOR.allocated.load(Ordering::Relaxed)
+}
+
+/// Sets the heap size above which the gateway pauses reading from the broker while the targets
+/// drain their queues.
+pub fn set_limit(bytes: usize) {
+    LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+pub fn limit() -> Option<usize> {
+    Some(LIMIT.load(Ordering::Relaxed)).filter(|limit| *limit != usize::MAX)
+}
+
+pub fn exceeded() -> bool {
+    allocated() > LIMIT.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocated() {
+        // a separate allocator, the global one also counts the allocations of parallel tests
+        let allocator = CountingAllocator::new();
+        let layout = Layout::from_size_align(1024, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert_eq!(allocator.allocated.load(Ordering::Relaxed), 1024);
+            let ptr = allocator.realloc(ptr, layout, 4096);
+            assert_eq!(allocator.allocated.load(Ordering::Relaxed), 4096);
+            allocator.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap());
+        }
+        assert_eq!(allocator.allocated.load(Ordering::Relaxed), 0);
+    }
+}
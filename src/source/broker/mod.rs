@@ -0,0 +1,113 @@
+use crate::config::StaticEventConfig;
+use crate::data::enrichment::BrokerTags;
+use crate::data::{BuildInfo, CheckMessage, SourceStats};
+use crate::source::mqtt;
+use chrono::{DateTime, Utc};
+use paho_mqtt::Message;
+use std::sync::{Arc, Mutex};
+
+/// Records the broker, QoS and retain flag of each message for the enrichment of the wrapped
+/// logger, which tags the events parsed from the message with them.
+pub struct BrokerTaggingLogger {
+    broker_tags: BrokerTags,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+}
+
+impl BrokerTaggingLogger {
+    pub(crate) fn new(broker_tags: BrokerTags, logger: Arc<Mutex<dyn CheckMessage>>) -> Self {
+        BrokerTaggingLogger {
+            broker_tags,
+            logger,
+        }
+    }
+}
+
+impl CheckMessage for BrokerTaggingLogger {
+    fn check_message(&mut self, msg: &Message) {
+        self.broker_tags.set(
+            mqtt::connected_broker().as_deref(),
+            msg.qos(),
+            msg.retained(),
+        );
+        self.logger.lock().unwrap().check_message(msg);
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.logger.lock().unwrap().stats()
+    }
+
+    fn shutdown(&mut self) {
+        self.logger.lock().unwrap().shutdown();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        // heartbeats are not caused by a message
+        self.broker_tags.set(None, 0, false);
+        self.logger.lock().unwrap().heartbeat(source, messages);
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        self.logger.lock().unwrap().started(source, build);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        // scheduled events are not caused by a message either
+        self.broker_tags.set(None, 0, false);
+        self.logger.lock().unwrap().inject(source, event, time);
+    }
+
+    fn field_stats(&self) -> Option<String> {
+        self.logger.lock().unwrap().field_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::enrichment::{BrokerTag, Enrichment};
+    use paho_mqtt::MessageBuilder;
+
+    struct TagRecorder {
+        enrichment: Enrichment,
+        tags: Vec<(String, String)>,
+    }
+
+    impl CheckMessage for TagRecorder {
+        fn check_message(&mut self, _msg: &Message) {
+            self.tags = self.enrichment.tags(1701271852, "kitchen");
+        }
+
+        fn stats(&self) -> SourceStats {
+            SourceStats::default()
+        }
+
+        fn shutdown(&mut self) {}
+    }
+
+    #[test]
+    fn test_tags_of_message() {
+        let broker_tags = BrokerTags::new(vec![BrokerTag::Qos, BrokerTag::Retain]);
+        let recorder = Arc::new(Mutex::new(TagRecorder {
+            enrichment: Enrichment::default().with_broker_tags(broker_tags.clone()),
+            tags: Vec::new(),
+        }));
+        let mut logger = BrokerTaggingLogger::new(broker_tags, recorder.clone());
+
+        logger.check_message(
+            &MessageBuilder::new()
+                .topic("sensors/kitchen")
+                .payload("{}")
+                .qos(1)
+                .retained(true)
+                .finalize(),
+        );
+
+        assert_eq!(
+            recorder.lock().unwrap().tags,
+            vec![
+                ("qos".to_string(), "1".to_string()),
+                ("retain".to_string(), "true".to_string()),
+            ]
+        );
+    }
+}
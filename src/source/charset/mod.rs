@@ -0,0 +1,163 @@
+use crate::config::{Charset, StaticEventConfig};
+use crate::data::{deadletter, BuildInfo, CheckMessage, SourceStats};
+use chrono::{DateTime, Utc};
+use log::warn;
+use paho_mqtt::{Message, MessageBuilder};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+/// Decodes payloads of the given charset to UTF-8 before they reach the parsers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadDecoder {
+    charset: Charset,
+    lossy: bool,
+}
+
+impl PayloadDecoder {
+    pub fn new(charset: Charset, lossy: bool) -> Self {
+        PayloadDecoder { charset, lossy }
+    }
+
+    /// Returns the UTF-8 payload or `None` if it can not be decoded.
+    pub fn decode<'a>(&self, payload: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        match self.charset {
+            Charset::Utf8 => match std::str::from_utf8(payload) {
+                Ok(_) => Some(Cow::Borrowed(payload)),
+                Err(_) if self.lossy => Some(Cow::Owned(
+                    String::from_utf8_lossy(payload).into_owned().into_bytes(),
+                )),
+                Err(_) => None,
+            },
+            Charset::Latin1 if payload.is_ascii() => Some(Cow::Borrowed(payload)),
+            Charset::Latin1 => Some(Cow::Owned(
+                payload
+                    .iter()
+                    .map(|&byte| byte as char)
+                    .collect::<String>()
+                    .into_bytes(),
+            )),
+        }
+    }
+}
+
+fn hex_dump(payload: &[u8]) -> String {
+    payload
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct DecodingLogger {
+    decoder: PayloadDecoder,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+    undecodable: u64,
+}
+
+impl DecodingLogger {
+    pub(crate) fn new(decoder: PayloadDecoder, logger: Arc<Mutex<dyn CheckMessage>>) -> Self {
+        DecodingLogger {
+            decoder,
+            logger,
+            undecodable: 0,
+        }
+    }
+}
+
+impl CheckMessage for DecodingLogger {
+    fn check_message(&mut self, msg: &Message) {
+        match self.decoder.decode(msg.payload()) {
+            Some(Cow::Borrowed(_)) => self.logger.lock().unwrap().check_message(msg),
+            Some(Cow::Owned(payload)) => {
+                let decoded = MessageBuilder::new()
+                    .topic(msg.topic())
+                    .payload(payload)
+                    .qos(msg.qos())
+                    .retained(msg.retained())
+                    .properties(msg.properties().clone())
+                    .finalize();
+                self.logger.lock().unwrap().check_message(&decoded);
+            }
+            None => {
+                warn!(
+                    target: "dead_letter",
+                    "undecodable payload on '{}': {}",
+                    msg.topic(),
+                    hex_dump(msg.payload())
+                );
+                deadletter::record(msg, "undecodable payload");
+                self.undecodable += 1;
+            }
+        }
+    }
+
+    fn stats(&self) -> SourceStats {
+        let mut stats = self.logger.lock().unwrap().stats();
+        stats.received += self.undecodable;
+        stats.dropped += self.undecodable;
+        stats
+    }
+
+    fn shutdown(&mut self) {
+        self.logger.lock().unwrap().shutdown();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        self.logger.lock().unwrap().heartbeat(source, messages);
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        self.logger.lock().unwrap().started(source, build);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        self.logger.lock().unwrap().inject(source, event, time);
+    }
+
+    fn field_stats(&self) -> Option<String> {
+        self.logger.lock().unwrap().field_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATIN1: &[u8] = b"{\"location\": \"K\xfcche\"}";
+
+    #[test]
+    fn test_decode_utf8() {
+        let decoder = PayloadDecoder::new(Charset::Utf8, false);
+
+        assert_eq!(
+            decoder.decode("Küche".as_bytes()),
+            Some(Cow::Borrowed("Küche".as_bytes()))
+        );
+        assert_eq!(decoder.decode(LATIN1), None);
+    }
+
+    #[test]
+    fn test_decode_utf8_lossy() {
+        let decoder = PayloadDecoder::new(Charset::Utf8, true);
+
+        assert_eq!(
+            decoder.decode(LATIN1).as_deref(),
+            Some("{\"location\": \"K\u{fffd}che\"}".as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_decode_latin1() {
+        let decoder = PayloadDecoder::new(Charset::Latin1, false);
+
+        assert_eq!(
+            decoder.decode(LATIN1).as_deref(),
+            Some("{\"location\": \"Küche\"}".as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(hex_dump(b"K\xfc"), "4b fc");
+    }
+}
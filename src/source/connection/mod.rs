@@ -0,0 +1,103 @@
+//! Verbose diagnostics of the connections to the broker and the targets, enabled with
+//! `--debug-connection`. Broker ACLs which deny a subscription or a publish often show up only
+//! as missing data, the details of the acknowledgements tell them apart.
+
+use log::info;
+use paho_mqtt as mqtt;
+use paho_mqtt::PropertyCode;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Logs the options of a connect attempt to the given brokers.
+pub fn log_connect_options(
+    server_uris: &[String],
+    clean_session: bool,
+    session_expiry: Option<u32>,
+) {
+    if enabled() {
+        info!(
+            "connecting to {:?}, clean session: {}, session expiry: {:?}",
+            server_uris, clean_session, session_expiry
+        );
+    }
+}
+
+/// Logs the acknowledgement of a connect with the limits the broker announced.
+pub fn log_connack(context: &str, response: &mqtt::ServerResponse) {
+    if !enabled() {
+        return;
+    }
+    let properties = response.properties();
+    let limits: Vec<String> = [
+        ("maximum QoS", PropertyCode::MaximumQos),
+        ("retain available", PropertyCode::RetainAvailable),
+        ("receive maximum", PropertyCode::ReceiveMaximum),
+        ("server keep alive", PropertyCode::ServerKeepAlive),
+        ("session expiry", PropertyCode::SessionExpiryInterval),
+    ]
+    .into_iter()
+    .filter_map(|(name, code)| Some(format!("{}: {}", name, properties.get_int(code)?)))
+    .collect();
+    match response.connect_response() {
+        Some(connect) => info!(
+            "{} CONNACK from {}: reason {:?}, MQTT version {}, session present: {}, {}",
+            context,
+            connect.server_uri,
+            response.reason_code(),
+            connect.mqtt_version,
+            connect.session_present,
+            limits.join(", ")
+        ),
+        None => info!(
+            "{} CONNACK: reason {:?}, {}",
+            context,
+            response.reason_code(),
+            limits.join(", ")
+        ),
+    }
+    if let Some(reason) = properties.get_string(PropertyCode::ReasonString) {
+        info!("{} CONNACK reason string: {}", context, reason);
+    }
+}
+
+/// Logs the QoS the broker granted per requested topic, or the reason it refused a topic.
+pub fn log_suback(topics: &[String], qoss: &[i32], response: &mqtt::ServerResponse) {
+    if !enabled() {
+        return;
+    }
+    match response.subscribe_many_response() {
+        Some(granted) => {
+            for ((topic, qos), reason) in topics.iter().zip(qoss).zip(granted) {
+                info!(
+                    "SUBACK for '{}' (requested QoS {}): {:?}",
+                    topic, qos, reason
+                );
+            }
+        }
+        None => info!("SUBACK without per topic reason codes for {:?}", topics),
+    }
+}
+
+/// Logs all details of a failed connect or handshake, e.g. the reason code of the broker.
+pub fn log_failure(context: &str, error: &impl Debug) {
+    if enabled() {
+        info!("{} failed: {:?}", context, error);
+    }
+}
+
+/// Logs a completed handshake with a target.
+pub fn log_handshake(target: &str, details: &str) {
+    if enabled() {
+        info!("{} handshake: {}", target, details);
+    }
+}
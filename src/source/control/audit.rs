@@ -0,0 +1,100 @@
+use crate::config::{AuditConfig, Command};
+use crate::error::{GatewayError, Result};
+use futures::executor::block_on;
+use log::{info, warn};
+use paho_mqtt as mqtt;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+
+static AUDIT: LazyLock<Mutex<Audit>> = LazyLock::new(|| Mutex::new(Audit::default()));
+
+#[derive(Default)]
+struct Audit {
+    file: Option<File>,
+    topic: Option<String>,
+}
+
+/// A runtime control action as written to the audit file and topic.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct AuditEntry<'a> {
+    time: String,
+    action: Command,
+    source: &'a str,
+    /// Whether the action changed the state, repeated actions are recorded too.
+    changed: bool,
+    /// Where the action came from, e.g. `http 192.168.1.10:54321 (admin)` or `mqtt`.
+    origin: &'a str,
+}
+
+/// Appends control actions as JSON lines to a file and/or publishes them to a topic.
+pub fn enable(config: &AuditConfig) -> Result<()> {
+    let mut audit = AUDIT.lock().unwrap();
+    if let Some(path) = &config.file {
+        audit.file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|error| {
+                    GatewayError::config(format!("failed to open audit file '{}': {}", path, error))
+                })?,
+        );
+        info!("writing audit log to {}", path);
+    }
+    audit.topic = config.topic.clone();
+    Ok(())
+}
+
+/// Records a control action, it is published with the client registered with the control.
+pub fn record(action: Command, source: &str, changed: bool, origin: &str) {
+    let entry = AuditEntry {
+        time: chrono::offset::Utc::now().to_rfc3339(),
+        action,
+        source,
+        changed,
+        origin,
+    };
+    let line = serde_json::to_string(&entry).unwrap();
+    info!("audit: {}", line);
+
+    let mut audit = AUDIT.lock().unwrap();
+    if let Some(file) = audit.file.as_mut() {
+        if let Err(error) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            warn!("failed to write audit log: {}", error);
+        }
+    }
+    let client = super::CONTROL.lock().unwrap().client.clone();
+    if let (Some(topic), Some(client)) = (&audit.topic, client) {
+        let message = mqtt::Message::new(topic.as_str(), line, mqtt::QOS_1);
+        // control messages are handled within the async message loop, which must not block
+        thread::spawn(move || {
+            if let Err(error) = block_on(client.publish(message)) {
+                warn!("failed to publish audit entry: {}", error);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_entry() {
+        let entry = AuditEntry {
+            time: "2026-10-16T12:00:00+00:00".to_string(),
+            action: Command::Disable,
+            source: "shellies",
+            changed: true,
+            origin: "mqtt",
+        };
+
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            "{\"time\":\"2026-10-16T12:00:00+00:00\",\"action\":\"disable\",\"source\":\"shellies\",\"changed\":true,\"origin\":\"mqtt\"}"
+        );
+    }
+}
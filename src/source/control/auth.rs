@@ -0,0 +1,122 @@
+use crate::config::{AuthConfig, Command, TokenConfig};
+use log::{info, warn};
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+
+static TOKENS: LazyLock<Mutex<Option<Vec<TokenConfig>>>> = LazyLock::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Denied {
+    /// No or an unknown token.
+    Unauthenticated,
+    /// The token is not allowed to issue the command.
+    Forbidden,
+}
+
+impl Denied {
+    pub fn status(&self) -> u16 {
+        match self {
+            Denied::Unauthenticated => 401,
+            Denied::Forbidden => 403,
+        }
+    }
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Denied::Unauthenticated => write!(f, "missing or invalid token"),
+            Denied::Forbidden => write!(f, "command not allowed"),
+        }
+    }
+}
+
+/// Requires one of the configured tokens for all commands, everything is allowed otherwise.
+pub fn configure(config: &AuthConfig) {
+    info!(
+        "requiring one of {} tokens for commands",
+        config.tokens.len()
+    );
+    *TOKENS.lock().unwrap() = Some(config.tokens.clone());
+}
+
+/// Compares in constant time for tokens of the same length.
+fn token_eq(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Name of the token allowed to issue the command.
+fn check(tokens: &[TokenConfig], token: Option<&str>, command: Command) -> Result<String, Denied> {
+    let Some(config) =
+        token.and_then(|token| tokens.iter().find(|config| token_eq(&config.token, token)))
+    else {
+        return Err(Denied::Unauthenticated);
+    };
+    match &config.allow {
+        Some(allow) if !allow.contains(&command) => {
+            warn!("{} is not allowed to {:?}", config.name, command);
+            Err(Denied::Forbidden)
+        }
+        _ => Ok(config.name.clone()),
+    }
+}
+
+/// Checks whether the given token may issue the command, returns the name of the token if tokens
+/// are configured.
+pub fn authorize(token: Option<&str>, command: Command) -> Result<Option<String>, Denied> {
+    match TOKENS.lock().unwrap().as_deref() {
+        Some(tokens) => check(tokens, token, command).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> Vec<TokenConfig> {
+        vec![
+            TokenConfig {
+                name: "dashboards".to_string(),
+                token: "read-only".to_string(),
+                allow: Some(vec![Command::Read]),
+            },
+            TokenConfig {
+                name: "admin".to_string(),
+                token: "secret".to_string(),
+                allow: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_check() {
+        let tokens = tokens();
+
+        assert_eq!(
+            check(&tokens, Some("read-only"), Command::Read),
+            Ok("dashboards".to_string())
+        );
+        assert_eq!(
+            check(&tokens, Some("read-only"), Command::Disable),
+            Err(Denied::Forbidden)
+        );
+        assert_eq!(
+            check(&tokens, Some("secret"), Command::Disable),
+            Ok("admin".to_string())
+        );
+        assert_eq!(
+            check(&tokens, Some("secre"), Command::Read),
+            Err(Denied::Unauthenticated)
+        );
+        assert_eq!(
+            check(&tokens, None, Command::Read),
+            Err(Denied::Unauthenticated)
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use crate::config::{CaptureConfig, Command};
+use crate::data::encryption::{Cipher, EncryptingWriter};
+use crate::error::{GatewayError, Result};
+use log::{info, warn};
+use paho_mqtt as mqtt;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+static CAPTURE: LazyLock<Mutex<Capture>> = LazyLock::new(|| Mutex::new(Capture::default()));
+
+/// Upper limit of the duration of a capture, so a forgotten capture does not fill the disk.
+pub const MAX_DURATION: Duration = Duration::from_secs(3600);
+
+/// Payload of capture control messages, e.g. `{"capture": "start shellies/+/power 300"}` or the
+/// plain text `capture stop`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CaptureCommand {
+    pub(crate) capture: String,
+    /// Required if tokens are configured.
+    pub(crate) token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureAction {
+    Start { filter: String, duration: Duration },
+    Stop,
+}
+
+impl FromStr for CaptureAction {
+    type Err = GatewayError;
+
+    /// Parses `start <topic-filter> <duration in seconds>` or `stop`.
+    fn from_str(command: &str) -> Result<Self> {
+        let invalid = || {
+            GatewayError::config(format!(
+                "invalid capture command '{}', expected 'start <topic-filter> <seconds>' or 'stop'",
+                command
+            ))
+        };
+        match command.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["start", filter, duration] => {
+                check_filter(filter)?;
+                let duration = duration.parse().map_err(|_| invalid())?;
+                Ok(CaptureAction::Start {
+                    filter: filter.to_string(),
+                    duration: Duration::from_secs(duration).min(MAX_DURATION),
+                })
+            }
+            ["stop"] => Ok(CaptureAction::Stop),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Wildcards must fill a whole level and `#` must be the last level.
+fn check_filter(filter: &str) -> Result<()> {
+    let levels: Vec<&str> = filter.split('/').collect();
+    let valid = levels
+        .iter()
+        .enumerate()
+        .all(|(index, level)| match *level {
+            "+" => true,
+            "#" => index == levels.len() - 1,
+            level => !level.contains(['+', '#']),
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(GatewayError::config(format!(
+            "invalid topic filter '{}'",
+            filter
+        )))
+    }
+}
+
+/// Whether a topic matches an MQTT topic filter with `+` and `#` wildcards.
+fn filter_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// A captured message as written to the capture file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CapturedMessage<'a> {
+    time: String,
+    topic: &'a str,
+    payload: &'a str,
+    retained: bool,
+}
+
+struct Active {
+    filter: String,
+    until: Instant,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Capture {
+    file: Option<Box<dyn Write + Send>>,
+    topic: Option<String>,
+    active: Option<Active>,
+}
+
+impl Capture {
+    fn start(&mut self, filter: String, duration: Duration) -> Result<()> {
+        if self.file.is_none() && self.topic.is_none() {
+            return Err(GatewayError::config(
+                "no capture file or topic configured".to_string(),
+            ));
+        }
+        self.stop();
+        info!("capturing messages of {} for {:?}", filter, duration);
+        self.active = Some(Active {
+            filter,
+            until: Instant::now() + duration,
+            count: 0,
+        });
+        Ok(())
+    }
+
+    /// Ends a running capture, returns whether there was one.
+    fn stop(&mut self) -> bool {
+        match self.active.take() {
+            Some(active) => {
+                info!(
+                    "capture of {} ended after {} messages",
+                    active.filter, active.count
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the message is to be captured, ends an expired capture.
+    fn matches(&mut self, topic: &str) -> bool {
+        let Some(active) = self.active.as_mut() else {
+            return false;
+        };
+        if Instant::now() >= active.until {
+            self.stop();
+            return false;
+        }
+        // do not capture the captured messages again
+        if self
+            .topic
+            .as_ref()
+            .is_some_and(|prefix| topic.starts_with(&format!("{}/", prefix)))
+        {
+            return false;
+        }
+        if !filter_matches(&active.filter, topic) {
+            return false;
+        }
+        active.count += 1;
+        true
+    }
+}
+
+/// Writes captured messages as JSON lines to a file, encrypted with the given cipher if set,
+/// and/or republishes them below a topic with the client registered with the control.
+pub fn enable(config: &CaptureConfig, cipher: Option<Cipher>) -> Result<()> {
+    let mut capture = CAPTURE.lock().unwrap();
+    if let Some(path) = &config.file {
+        let open_error = |error: std::io::Error| {
+            GatewayError::config(format!("failed to open capture file '{}': {}", path, error))
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(open_error)?;
+        capture.file = Some(match cipher {
+            // every start of the gateway appends a new encrypted stream
+            Some(cipher) => Box::new(EncryptingWriter::new(cipher, file).map_err(open_error)?),
+            None => Box::new(file),
+        });
+    }
+    capture.topic = config.topic.clone();
+    Ok(())
+}
+
+/// Starts or stops a capture, the action is audited with the given origin.
+pub fn apply(command: &CaptureCommand, origin: &str) -> Result<()> {
+    let action: CaptureAction = command.capture.parse()?;
+    let mut capture = CAPTURE.lock().unwrap();
+    let (filter, changed) = match action {
+        CaptureAction::Start { filter, duration } => {
+            capture.start(filter.clone(), duration)?;
+            (filter, true)
+        }
+        CaptureAction::Stop => {
+            let filter = capture
+                .active
+                .as_ref()
+                .map(|active| active.filter.clone())
+                .unwrap_or_default();
+            (filter, capture.stop())
+        }
+    };
+    drop(capture);
+    super::audit::record(Command::Capture, &filter, changed, origin);
+    Ok(())
+}
+
+/// Tees a received message into the capture file and topic if it matches a running capture.
+pub fn tee(msg: &mqtt::Message) {
+    let mut capture = CAPTURE.lock().unwrap();
+    if !capture.matches(msg.topic()) {
+        return;
+    }
+    if let Some(file) = capture.file.as_mut() {
+        let entry = CapturedMessage {
+            time: chrono::offset::Utc::now().to_rfc3339(),
+            topic: msg.topic(),
+            payload: &msg.payload_str(),
+            retained: msg.retained(),
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        if let Err(error) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            warn!("failed to write captured message: {}", error);
+        }
+    }
+    let client = super::CONTROL.lock().unwrap().client.clone();
+    if let (Some(topic), Some(client)) = (&capture.topic, client) {
+        let message = mqtt::Message::new(
+            format!("{}/{}", topic, msg.topic()),
+            msg.payload(),
+            mqtt::QOS_0,
+        );
+        // the message is queued by the client, the delivery is not awaited as the message loop
+        // must not block
+        drop(client.publish(message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_action() -> Result<()> {
+        assert_eq!(
+            "start shellies/+/power 300".parse::<CaptureAction>()?,
+            CaptureAction::Start {
+                filter: "shellies/+/power".to_string(),
+                duration: Duration::from_secs(300)
+            }
+        );
+        assert_eq!(
+            "start # 86400".parse::<CaptureAction>()?,
+            CaptureAction::Start {
+                filter: "#".to_string(),
+                duration: MAX_DURATION
+            }
+        );
+        assert_eq!(" stop ".parse::<CaptureAction>()?, CaptureAction::Stop);
+        assert!("start shellies/#/power 300"
+            .parse::<CaptureAction>()
+            .is_err());
+        assert!("start shellies/+/power".parse::<CaptureAction>().is_err());
+        assert!("start shellies/+/power soon"
+            .parse::<CaptureAction>()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        assert!(filter_matches("shellies/+/power", "shellies/kitchen/power"));
+        assert!(!filter_matches(
+            "shellies/+/power",
+            "shellies/kitchen/energy"
+        ));
+        assert!(!filter_matches("shellies/+", "shellies/kitchen/power"));
+        assert!(filter_matches("shellies/#", "shellies/kitchen/power"));
+        assert!(filter_matches("shellies/#", "shellies"));
+        assert!(!filter_matches("shellies/kitchen", "shellies"));
+    }
+
+    #[test]
+    fn test_capture() -> Result<()> {
+        let mut capture = Capture {
+            topic: Some("debug".to_string()),
+            ..Capture::default()
+        };
+
+        assert!(!capture.matches("shellies/kitchen/power"));
+        capture.start("#".to_string(), Duration::from_secs(60))?;
+        assert!(capture.matches("shellies/kitchen/power"));
+        assert!(!capture.matches("debug/shellies/kitchen/power"));
+        assert_eq!(capture.active.as_ref().map(|active| active.count), Some(1));
+        assert!(capture.stop());
+        assert!(!capture.stop());
+
+        capture.start("#".to_string(), Duration::ZERO)?;
+        assert!(!capture.matches("shellies/kitchen/power"));
+        assert!(capture.active.is_none());
+
+        assert!(Capture::default()
+            .start("#".to_string(), Duration::from_secs(60))
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+pub(crate) mod audit;
+pub(crate) mod auth;
+pub(crate) mod capture;
+
+use crate::config::Command;
+use crate::error::{GatewayError, Result};
+use crate::source::control::capture::CaptureCommand;
+use futures::executor::block_on;
+use log::info;
+use paho_mqtt as mqtt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+
+static CONTROL: LazyLock<Mutex<SourceControl>> =
+    LazyLock::new(|| Mutex::new(SourceControl::default()));
+
+/// Payload of control messages, e.g. `{"source": "shellies", "enabled": false}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ControlCommand {
+    pub(crate) source: String,
+    pub(crate) enabled: bool,
+    /// Required if tokens are configured.
+    pub(crate) token: Option<String>,
+}
+
+impl ControlCommand {
+    pub fn command(&self) -> Command {
+        if self.enabled {
+            Command::Enable
+        } else {
+            Command::Disable
+        }
+    }
+}
+
+/// Messages accepted on the control topic.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ControlMessage {
+    Source(ControlCommand),
+    Capture(CaptureCommand),
+}
+
+impl ControlMessage {
+    /// Parses a JSON control message or a plain text `capture ...` command.
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        match std::str::from_utf8(payload).map(str::trim) {
+            Ok(text) if text.starts_with("capture ") => {
+                Ok(ControlMessage::Capture(CaptureCommand {
+                    capture: text["capture ".len()..].to_string(),
+                    token: None,
+                }))
+            }
+            _ => Ok(serde_json::from_slice(payload)?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct SourceState {
+    source: String,
+    enabled: bool,
+}
+
+/// Subscription state of the sources by topic prefix.
+#[derive(Default)]
+struct SourceControl {
+    sources: BTreeMap<String, (i32, bool)>,
+    client: Option<mqtt::AsyncClient>,
+}
+
+impl SourceControl {
+    /// Updates the state of a source, returns its topic and QoS if the subscription has to change.
+    fn update(&mut self, prefix: &str, enabled: bool) -> Result<Option<(String, i32)>> {
+        let Some((qos, state)) = self.sources.get_mut(prefix) else {
+            return Err(GatewayError::config(format!("unknown source '{}'", prefix)));
+        };
+        if *state == enabled {
+            return Ok(None);
+        }
+        *state = enabled;
+        info!(
+            "{} source {}",
+            if enabled { "enabling" } else { "disabling" },
+            prefix
+        );
+        Ok(Some((format!("{}/#", prefix), *qos)))
+    }
+
+    fn status(&self) -> Vec<SourceState> {
+        self.sources
+            .iter()
+            .map(|(source, (_, enabled))| SourceState {
+                source: source.clone(),
+                enabled: *enabled,
+            })
+            .collect()
+    }
+}
+
+/// Registers the client and the topic prefixes with their QoS the sources are subscribed with.
+pub fn register(client: &mqtt::AsyncClient, prefixes: &[(String, i32)]) {
+    let mut control = CONTROL.lock().unwrap();
+    control.client = Some(client.clone());
+    control.sources = prefixes
+        .iter()
+        .map(|(prefix, qos)| (prefix.clone(), (*qos, true)))
+        .collect();
+}
+
+pub fn is_enabled(prefix: &str) -> bool {
+    CONTROL
+        .lock()
+        .unwrap()
+        .sources
+        .get(prefix)
+        .is_none_or(|(_, enabled)| *enabled)
+}
+
+/// Records the new state of a source, the caller has to change the subscription returned.
+pub fn apply(command: &ControlCommand, origin: &str) -> Result<Option<(String, i32)>> {
+    let change = CONTROL
+        .lock()
+        .unwrap()
+        .update(&command.source, command.enabled)?;
+    audit::record(command.command(), &command.source, change.is_some(), origin);
+    Ok(change)
+}
+
+/// Enables or disables a source and (un)subscribes its topics, blocks until the broker replied.
+pub fn set_enabled(prefix: &str, enabled: bool, origin: &str) -> Result<()> {
+    let (change, client) = {
+        let mut control = CONTROL.lock().unwrap();
+        (control.update(prefix, enabled)?, control.client.clone())
+    };
+    let action = if enabled {
+        Command::Enable
+    } else {
+        Command::Disable
+    };
+    audit::record(action, prefix, change.is_some(), origin);
+    if let (Some((topic, qos)), Some(client)) = (change, client) {
+        let result = if enabled {
+            block_on(client.subscribe(topic, qos)).map(|_| ())
+        } else {
+            block_on(client.unsubscribe(topic)).map(|_| ())
+        };
+        result.map_err(|error| GatewayError::connect("mqtt broker", error))?;
+    }
+    Ok(())
+}
+
+/// State of all sources as JSON.
+pub fn status() -> String {
+    serde_json::to_string(&CONTROL.lock().unwrap().status()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update() -> Result<()> {
+        let mut control = SourceControl::default();
+        control
+            .sources
+            .insert("shellies".to_string(), (mqtt::QOS_2, true));
+
+        assert_eq!(
+            control.update("shellies", false)?,
+            Some(("shellies/#".to_string(), mqtt::QOS_2))
+        );
+        assert_eq!(control.update("shellies", false)?, None);
+        assert_eq!(
+            control.status(),
+            vec![SourceState {
+                source: "shellies".to_string(),
+                enabled: false
+            }]
+        );
+        assert!(control.update("unknown", false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_command() -> Result<()> {
+        let command: ControlCommand =
+            serde_json::from_str(r#"{"source": "shellies", "enabled": true}"#)?;
+
+        assert_eq!(
+            command,
+            ControlCommand {
+                source: "shellies".to_string(),
+                enabled: true,
+                token: None
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_control_message() -> Result<()> {
+        assert!(matches!(
+            ControlMessage::parse(br#"{"source": "shellies", "enabled": false}"#)?,
+            ControlMessage::Source(_)
+        ));
+        assert_eq!(
+            ControlMessage::parse(br#"{"capture": "stop", "token": "secret"}"#)?,
+            ControlMessage::Capture(CaptureCommand {
+                capture: "stop".to_string(),
+                token: Some("secret".to_string())
+            })
+        );
+        assert_eq!(
+            ControlMessage::parse(b"capture start shellies/# 60\n")?,
+            ControlMessage::Capture(CaptureCommand {
+                capture: "start shellies/# 60".to_string(),
+                token: None
+            })
+        );
+        assert!(ControlMessage::parse(b"enable shellies").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use crate::data::envelope;
+use crate::data::CheckMessage;
+use crate::error::{GatewayError, Result};
+use crate::source::control;
+use crate::source::socket;
+use log::info;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+type Loggers = HashMap<String, Arc<Mutex<dyn CheckMessage>>>;
+
+static LOGGERS: LazyLock<Mutex<Loggers>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Accepts log events posted to `/ingest/<prefix>` for the source with the given prefix.
+pub fn register(prefix: String, logger: Arc<Mutex<dyn CheckMessage>>) {
+    info!(
+        "accepting log events for {} via POST /ingest/{}",
+        prefix, prefix
+    );
+    LOGGERS.lock().unwrap().insert(prefix, logger);
+}
+
+/// Passes a JSON array of log events to the source with the given prefix as if they were
+/// published via MQTT, returns the number of events. Batches with an invalid event are rejected
+/// as a whole, so clients can safely send them again.
+fn ingest(prefix: &str, body: &str) -> Result<usize> {
+    let Some(logger) = LOGGERS.lock().unwrap().get(prefix).cloned() else {
+        return Err(GatewayError::config(format!(
+            "no source {} to ingest",
+            prefix
+        )));
+    };
+    let events = match serde_json::from_str(body)? {
+        Value::Array(events) => events,
+        event => vec![event],
+    };
+    let messages = events
+        .into_iter()
+        .enumerate()
+        .map(|(index, event)| {
+            envelope::decode_value(event)
+                .and_then(|event| socket::to_message(prefix, event))
+                .map_err(|error| GatewayError::parse(format!("event {}", index), error))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut logger = logger.lock().unwrap();
+    for msg in &messages {
+        logger.check_message(msg);
+    }
+    Ok(messages.len())
+}
+
+/// Status and body of the response to a `POST /ingest/<prefix>` request.
+pub fn respond(prefix: &str, body: &str) -> (u16, String) {
+    if !control::is_enabled(prefix) {
+        let message = format!("source {} is disabled", prefix);
+        return (409, serde_json::json!({ "error": message }).to_string());
+    }
+    match ingest(prefix, body) {
+        Ok(count) => (200, serde_json::json!({ "ingested": count }).to_string()),
+        Err(GatewayError::Config(message)) => {
+            (404, serde_json::json!({ "error": message }).to_string())
+        }
+        Err(error) => (
+            400,
+            serde_json::json!({ "error": error.to_string() }).to_string(),
+        ),
+    }
+}
+
+#[cfg(all(test, feature = "klimalogger"))]
+mod tests {
+    use super::*;
+    use crate::config::MissingTimestamp;
+    use crate::data::enrichment::Enrichment;
+    use crate::data::klimalogger::SensorLogger;
+    use crate::data::timestamp::TimestampPolicy;
+    use std::sync::mpsc::sync_channel;
+
+    const EVENT: &str = r#"{"host":"pump","location":"garden","type":"moisture","unit":"%","sensor":"soil","calculated":false,"time":"2024-01-01T00:00:00Z","value":41.5}"#;
+
+    #[test]
+    fn test_respond() {
+        let (tx, rx) = sync_channel(10);
+        let logger = SensorLogger::new(vec![tx], Enrichment::default())
+            .with_timestamp_policy(TimestampPolicy::new(MissingTimestamp::Drop, None));
+        register("ingest-test".to_string(), Arc::new(Mutex::new(logger)));
+
+        assert_eq!(
+            respond("ingest-test", &format!("[{}, {}]", EVENT, EVENT)),
+            (200, "{\"ingested\":2}".to_string())
+        );
+        assert_eq!(&*rx.try_recv().unwrap().measurement, "moisture");
+        assert_eq!(&*rx.try_recv().unwrap().location, "garden");
+
+        let (status, body) = respond(
+            "ingest-test",
+            &format!("[{}, {}]", EVENT, EVENT.replace("41.5", "true")),
+        );
+        assert_eq!(status, 400);
+        assert!(body.contains("event 1"));
+        assert!(rx.try_recv().is_err());
+
+        assert_eq!(respond("ingest-test", "{").0, 400);
+        assert_eq!(respond("unknown", EVENT).0, 404);
+    }
+}
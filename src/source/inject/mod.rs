@@ -0,0 +1,198 @@
+use crate::config::StaticEventConfig;
+use crate::data::CheckMessage;
+use crate::error::{GatewayError, Result};
+use crate::source::control;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Timelike, Utc};
+use log::info;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Schedule in the format of cron, `minute hour day month weekday` with lists, ranges and steps
+/// like `*/15 6-22 * * 1-5`. Sunday is 0 or 7. As with cron an event is due on the days matching
+/// either the day or the weekday if both are restricted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || {
+            GatewayError::config(format!(
+                "invalid schedule '{}', expected 'minute hour day month weekday'",
+                value
+            ))
+        };
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(invalid());
+        };
+        let mut weekday_bits = field(weekdays, 0, 7).ok_or_else(invalid)?;
+        if is_set(weekday_bits, 7) {
+            weekday_bits |= 1;
+        }
+        Ok(Cron {
+            minutes: field(minutes, 0, 59).ok_or_else(invalid)?,
+            hours: field(hours, 0, 23).ok_or_else(invalid)?,
+            days: field(days, 1, 31).ok_or_else(invalid)?,
+            months: field(months, 1, 12).ok_or_else(invalid)?,
+            weekdays: weekday_bits,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        let day = is_set(self.days, time.day());
+        let weekday = is_set(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        };
+        is_set(self.minutes, time.minute())
+            && is_set(self.hours, time.hour())
+            && is_set(self.months, time.month())
+            && day_matches
+    }
+}
+
+fn is_set(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// The values of a cron field as bits, `None` if it is invalid.
+fn field(value: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None if range == "*" => (min, max),
+            // `5/10` runs from 5 to the end of the range
+            None if step > 1 => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+/// Passes the events of the configuration to the logger of the source with the given prefix at
+/// the start of each minute their schedule matches, stamped with that minute.
+pub fn spawn_injection(
+    events: Vec<StaticEventConfig>,
+    prefix: String,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+) -> Result<JoinHandle<()>> {
+    let events = events
+        .into_iter()
+        .map(|event| Ok((Cron::parse(&event.schedule)?, event)))
+        .collect::<Result<Vec<_>>>()?;
+    info!(
+        "injecting {} scheduled events into {}",
+        events.len(),
+        prefix
+    );
+
+    Ok(thread::spawn(move || {
+        let mut next = Utc::now().timestamp() / 60 * 60 + 60;
+        loop {
+            let wait = next * 1000 - Utc::now().timestamp_millis();
+            if wait > 0 {
+                thread::sleep(Duration::from_millis(wait as u64));
+            }
+            let time = DateTime::from_timestamp(next, 0).expect("failed to convert timestamp");
+            if control::is_enabled(&prefix) {
+                let local = time.with_timezone(&Local).naive_local();
+                for (cron, event) in &events {
+                    if cron.matches(local) {
+                        logger.lock().unwrap().inject(&prefix, event, time);
+                    }
+                }
+            }
+            // minutes missed while the system was suspended are skipped
+            next = (next + 60).max(Utc::now().timestamp() / 60 * 60);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn time(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 is a Monday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let cron = Cron::parse("*/15 6-8,22 * * *")?;
+
+        assert_eq!(cron.minutes, 1 | (1 << 15) | (1 << 30) | (1 << 45));
+        assert_eq!(cron.hours, (1 << 6) | (1 << 7) | (1 << 8) | (1 << 22));
+        assert!(cron.any_day && cron.any_weekday);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(Cron::parse("0 0 * *").is_err());
+        assert!(Cron::parse("60 0 * * *").is_err());
+        assert!(Cron::parse("0 0 0 * *").is_err());
+        assert!(Cron::parse("*/0 0 * * *").is_err());
+        assert!(Cron::parse("0 5-3 * * *").is_err());
+    }
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        let midnight = Cron::parse("0 0 * * *")?;
+        assert!(midnight.matches(time(1, 0, 0)));
+        assert!(!midnight.matches(time(1, 0, 1)));
+
+        let weekdays = Cron::parse("30 7 * * 1-5")?;
+        assert!(weekdays.matches(time(5, 7, 30)));
+        assert!(!weekdays.matches(time(6, 7, 30)));
+
+        let sunday = Cron::parse("0 12 * * 7")?;
+        assert!(sunday.matches(time(7, 12, 0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_day_or_weekday() -> Result<()> {
+        let cron = Cron::parse("0 0 1 * 0")?;
+
+        assert!(cron.matches(time(1, 0, 0)));
+        assert!(cron.matches(time(7, 0, 0)));
+        assert!(!cron.matches(time(2, 0, 0)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::labels;
+use paho_mqtt as mqtt;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+/// User property with the ID of the gateway which republished a message.
+pub const GATEWAY_PROPERTY: &str = "mqtt-gateway";
+
+static GATEWAY_ID: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
+static LOOPS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the ID republished messages are marked with, an empty ID disables the loop protection.
+pub fn set_gateway_id(id: &str) {
+    *GATEWAY_ID.lock().unwrap() = Some(id.to_string()).filter(|id| !id.is_empty());
+}
+
+/// Properties marking a message as republished by this gateway.
+pub fn properties() -> mqtt::Properties {
+    let mut properties = mqtt::Properties::new();
+    if let Some(id) = GATEWAY_ID.lock().unwrap().as_deref() {
+        // only fails for property codes other than string pairs
+        let _ = properties.push_string_pair(mqtt::PropertyCode::UserProperty, GATEWAY_PROPERTY, id);
+    }
+    properties
+}
+
+/// Whether the message was republished by this gateway and reached it again, e.g. because a
+/// republish target writes to a topic of a source on the same or a bridged broker. Loops are
+/// counted and logged.
+pub fn is_loop(msg: &mqtt::Message) -> bool {
+    let Some(id) = GATEWAY_ID.lock().unwrap().clone() else {
+        return false;
+    };
+    let looped = msg
+        .properties()
+        .user_iter()
+        .any(|(key, value)| key == GATEWAY_PROPERTY && value == id);
+    if looped {
+        LOOPS.fetch_add(1, Ordering::Relaxed);
+        warn_deduplicated(
+            "dropping messages republished by this gateway",
+            &format!("loop via topic {}", msg.topic()),
+        );
+    }
+    looped
+}
+
+/// Number of republished messages received again.
+pub fn loops() -> u64 {
+    LOOPS.load(Ordering::Relaxed)
+}
+
+/// The loop counter in the Prometheus text format.
+pub fn metrics() -> String {
+    let mut metrics = String::new();
+    writeln!(
+        metrics,
+        "# HELP mqtt_gateway_loops_total Messages republished by the gateway and received again.\n\
+         # TYPE mqtt_gateway_loops_total counter\n\
+         mqtt_gateway_loops_total{} {}",
+        labels(&[]),
+        loops()
+    )
+    .unwrap();
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_loop() {
+        set_gateway_id("gw-loop-test");
+        let republished = mqtt::MessageBuilder::new()
+            .topic("republished/power/kitchen")
+            .payload("{}")
+            .properties(properties())
+            .finalize();
+        let mut foreign = mqtt::Properties::new();
+        foreign
+            .push_string_pair(mqtt::PropertyCode::UserProperty, GATEWAY_PROPERTY, "gw-2")
+            .unwrap();
+        let bridged = mqtt::MessageBuilder::new()
+            .topic("republished/power/kitchen")
+            .payload("{}")
+            .properties(foreign)
+            .finalize();
+
+        let before = loops();
+        assert!(is_loop(&republished));
+        assert!(!is_loop(&bridged));
+        assert!(!is_loop(&mqtt::Message::new("shellies/kitchen", "{}", 1)));
+        assert_eq!(loops(), before + 1);
+        assert!(metrics().contains("mqtt_gateway_loops_total "));
+    }
+}
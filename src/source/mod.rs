@@ -1 +1,15 @@
+pub(crate) mod broker;
+pub(crate) mod charset;
+pub(crate) mod connection;
+pub(crate) mod control;
+pub(crate) mod ingest;
+pub(crate) mod inject;
+pub(crate) mod loopback;
+pub(crate) mod modbus;
 pub(crate) mod mqtt;
+pub(crate) mod sample;
+pub(crate) mod schedule;
+#[cfg(feature = "snmp")]
+pub(crate) mod snmp;
+pub(crate) mod socket;
+pub(crate) mod warmup;
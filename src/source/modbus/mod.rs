@@ -0,0 +1,296 @@
+use crate::config::{ModbusConfig, RegisterConfig, RegisterKind, RegisterType};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::{CheckMessage, EventValue, LogEvent};
+use crate::error::Result;
+use crate::source::control;
+use crate::source::socket;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::info;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const DEFAULT_INTERVAL: u64 = 60;
+const DEFAULT_PORT: u16 = 502;
+const DEFAULT_UNIT: u8 = 1;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+
+impl RegisterType {
+    /// Number of 16 bit registers of a value.
+    fn count(self) -> u16 {
+        match self {
+            RegisterType::U16 | RegisterType::I16 => 1,
+            RegisterType::U32 | RegisterType::I32 | RegisterType::F32 => 2,
+        }
+    }
+
+    /// Value of the registers, 32 bit values with the high word first.
+    fn decode(self, words: &[u16]) -> f64 {
+        let long = || ((words[0] as u32) << 16) | words[1] as u32;
+        match self {
+            RegisterType::U16 => words[0] as f64,
+            RegisterType::I16 => words[0] as i16 as f64,
+            RegisterType::U32 => long() as f64,
+            RegisterType::I32 => long() as i32 as f64,
+            RegisterType::F32 => f32::from_bits(long()) as f64,
+        }
+    }
+}
+
+/// Modbus TCP request reading `count` registers starting at `address`.
+fn request(transaction: u16, unit: u8, function: u8, address: u16, count: u16) -> [u8; 12] {
+    let mut request = [0; 12];
+    request[0..2].copy_from_slice(&transaction.to_be_bytes());
+    // protocol identifier 0 and the length of the remaining bytes
+    request[4..6].copy_from_slice(&6u16.to_be_bytes());
+    request[6] = unit;
+    request[7] = function;
+    request[8..10].copy_from_slice(&address.to_be_bytes());
+    request[10..12].copy_from_slice(&count.to_be_bytes());
+    request
+}
+
+/// Registers of the response to a request, the bytes following the 7 byte header.
+fn parse_response(function: u8, count: u16, pdu: &[u8]) -> io::Result<Vec<u16>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    match pdu {
+        [code, exception, ..] if *code == function | 0x80 => {
+            Err(invalid(format!("exception code {}", exception)))
+        }
+        [code, length, data @ ..]
+            if *code == function
+                && *length as usize == 2 * count as usize
+                && data.len() == *length as usize =>
+        {
+            Ok(data
+                .chunks_exact(2)
+                .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                .collect())
+        }
+        _ => Err(invalid(format!("unexpected response {:02x?}", pdu))),
+    }
+}
+
+/// Connection to a Modbus TCP device.
+struct Connection {
+    stream: TcpStream,
+    unit: u8,
+    transaction: u16,
+}
+
+impl Connection {
+    fn open(address: &str, unit: u8) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(Connection {
+            stream,
+            unit,
+            transaction: 0,
+        })
+    }
+
+    fn read(&mut self, register: &RegisterConfig) -> io::Result<Vec<u16>> {
+        self.transaction = self.transaction.wrapping_add(1);
+        let function = match register.kind.unwrap_or_default() {
+            RegisterKind::Holding => READ_HOLDING_REGISTERS,
+            RegisterKind::Input => READ_INPUT_REGISTERS,
+        };
+        let count = register.register_type.count();
+        self.stream.write_all(&request(
+            self.transaction,
+            self.unit,
+            function,
+            register.address,
+            count,
+        ))?;
+        let mut header = [0; 7];
+        self.stream.read_exact(&mut header)?;
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if header[0..2] != self.transaction.to_be_bytes() || length < 2 {
+            // out of sync with the responses, so the connection is not reused
+            return Err(io::Error::other(format!(
+                "unexpected response header {:02x?}",
+                header
+            )));
+        }
+        // the length includes the unit identifier of the header
+        let mut pdu = vec![0; length - 1];
+        self.stream.read_exact(&mut pdu)?;
+        parse_response(function, count, &pdu)
+    }
+}
+
+/// The log event of a register read from the device at the given time.
+fn log_event(
+    device: &ModbusConfig,
+    register: &RegisterConfig,
+    words: &[u16],
+    time: DateTime<Utc>,
+) -> LogEvent {
+    let value = register.register_type.decode(words);
+    LogEvent {
+        host: device.host.clone(),
+        location: device.location.clone(),
+        measurement_type: register.measurement.clone(),
+        unit: register.unit.clone().unwrap_or_default(),
+        sensor: register
+            .sensor
+            .clone()
+            .unwrap_or_else(|| device.host.clone()),
+        calculated: register.scale.is_some(),
+        time: time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        value: EventValue::Float(value * register.scale.unwrap_or(1.0)),
+    }
+}
+
+/// Polls the registers of the device in the configured interval and passes the values as log
+/// events to the logger of the source with the given prefix.
+pub fn spawn_modbus(
+    device: ModbusConfig,
+    prefix: String,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+) -> Result<JoinHandle<()>> {
+    let address = if device.host.contains(':') {
+        device.host.clone()
+    } else {
+        format!("{}:{}", device.host, DEFAULT_PORT)
+    };
+    let unit = device.unit.unwrap_or(DEFAULT_UNIT);
+    let interval = Duration::from_secs(device.interval.unwrap_or(DEFAULT_INTERVAL));
+    info!(
+        "polling {} registers of {} via Modbus TCP every {:?}",
+        device.registers.len(),
+        device.host,
+        interval
+    );
+
+    Ok(thread::spawn(move || {
+        let mut connection: Option<Connection> = None;
+        loop {
+            if control::is_enabled(&prefix) {
+                for register in &device.registers {
+                    if connection.is_none() {
+                        connection = Connection::open(&address, unit)
+                            .inspect_err(|error| {
+                                warn_deduplicated(
+                                    &format!("failed to connect to {} via Modbus TCP", address),
+                                    &error.to_string(),
+                                )
+                            })
+                            .ok();
+                    }
+                    let Some(open) = connection.as_mut() else {
+                        break;
+                    };
+                    let words = match open.read(register) {
+                        Ok(words) => words,
+                        Err(error) => {
+                            warn_deduplicated(
+                                &format!("failed to read {} via Modbus TCP", device.host),
+                                &format!("register {}: {}", register.address, error),
+                            );
+                            // a complete response was read, so the connection is still usable
+                            if error.kind() != io::ErrorKind::InvalidData {
+                                connection = None;
+                            }
+                            continue;
+                        }
+                    };
+                    let event = log_event(&device, register, &words, Utc::now());
+                    match socket::to_message(&prefix, event) {
+                        Ok(msg) => logger.lock().unwrap().check_message(&msg),
+                        Err(error) => warn_deduplicated("invalid Modbus value", &error.to_string()),
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn device(register_type: RegisterType) -> ModbusConfig {
+        ModbusConfig {
+            host: "127.0.0.1:5020".to_string(),
+            unit: None,
+            interval: None,
+            location: "basement".to_string(),
+            registers: vec![RegisterConfig {
+                address: 100,
+                register_type,
+                kind: None,
+                measurement: "flow_temperature".to_string(),
+                sensor: Some("heatpump".to_string()),
+                unit: Some("°C".to_string()),
+                scale: Some(0.1),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(RegisterType::U16.decode(&[0xffff]), 65535.0);
+        assert_eq!(RegisterType::I16.decode(&[0xffff]), -1.0);
+        assert_eq!(RegisterType::U32.decode(&[0x0001, 0x0000]), 65536.0);
+        assert_eq!(RegisterType::I32.decode(&[0xffff, 0xfffe]), -2.0);
+        assert_eq!(RegisterType::F32.decode(&[0x4148, 0x0000]), 12.5);
+    }
+
+    #[test]
+    fn test_request_response() {
+        assert_eq!(
+            request(7, 1, READ_HOLDING_REGISTERS, 100, 2),
+            [0, 7, 0, 0, 0, 6, 1, 3, 0, 100, 0, 2]
+        );
+        assert_eq!(
+            parse_response(READ_HOLDING_REGISTERS, 2, &[3, 4, 0x41, 0x48, 0, 0]).unwrap(),
+            vec![0x4148, 0]
+        );
+        assert!(parse_response(READ_HOLDING_REGISTERS, 2, &[0x83, 2]).is_err());
+        assert!(parse_response(READ_HOLDING_REGISTERS, 2, &[3, 2, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 12];
+            stream.read_exact(&mut request).unwrap();
+            // echo the transaction and unit, answer a single register of 215
+            let mut response = vec![request[0], request[1], 0, 0, 0, 5, request[6], 4, 2];
+            response.extend_from_slice(&215u16.to_be_bytes());
+            stream.write_all(&response).unwrap();
+        });
+
+        let device = device(RegisterType::I16);
+        let register = RegisterConfig {
+            kind: Some(RegisterKind::Input),
+            ..device.registers[0].clone()
+        };
+        let mut connection = Connection::open(&address, 1).unwrap();
+        let words = connection.read(&register).unwrap();
+
+        let time = DateTime::from_timestamp(1704067200, 0).unwrap();
+        let event = log_event(&device, &register, &words, time);
+        assert_eq!(event.value, EventValue::Float(21.5));
+        assert_eq!(event.sensor, "heatpump");
+        assert_eq!(
+            socket::to_message("heating", event).unwrap().topic(),
+            "heating/basement/flow_temperature"
+        );
+    }
+}
@@ -1,17 +1,385 @@
-use log::{error, info};
+use crate::data::enrichment::labels;
+use crate::error::{GatewayError, Result};
+use log::{error, info, warn};
 use paho_mqtt as mqtt;
-use std::process;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
-pub fn create_mqtt_client(mqtt_url: String, mqtt_client_id: String) -> mqtt::AsyncClient {
+/// Reason codes of a SUBACK from 0x80 on refuse the subscription.
+const SUBACK_FAILURE: i32 = 0x80;
+
+static CONNECTED_BROKER: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
+/// QoS granted per subscribed topic, `None` if the broker refused the subscription.
+static GRANTED_QOS: LazyLock<Mutex<BTreeMap<String, Option<i32>>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+static REFUSED_SUBSCRIPTIONS: AtomicU64 = AtomicU64::new(0);
+/// Connects without the persistent session resumed, summed over all [`SessionMonitor`]s.
+static MISSED_INTERVALS: AtomicU64 = AtomicU64::new(0);
+static MISSED_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+pub fn create_mqtt_client(mqtt_url: String, mqtt_client_id: String) -> Result<mqtt::AsyncClient> {
     info!("Connecting to the MQTT server at '{}'...", mqtt_url);
 
-    let create_opts = mqtt::CreateOptionsBuilder::new_v3()
+    // MQTT v5 like the connect options, e.g. for the user properties of republished messages
+    let create_opts = mqtt::CreateOptionsBuilder::new()
+        .mqtt_version(mqtt::MQTT_VERSION_5)
         .server_uri(mqtt_url)
         .client_id(mqtt_client_id)
         .finalize();
 
-    mqtt::AsyncClient::new(create_opts).unwrap_or_else(|e| {
-        error!("Error creating the client: {:?}", e);
-        process::exit(1);
-    })
+    mqtt::AsyncClient::new(create_opts)
+        .map_err(|error| GatewayError::config(format!("failed to create MQTT client: {}", error)))
+}
+
+pub fn check_client_id(mqtt_client_id: &str, persistent: bool) -> Result<()> {
+    if persistent && mqtt_client_id.trim().is_empty() {
+        return Err(GatewayError::config(
+            "persistent MQTT sessions require a stable, non-empty mqttClientId",
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the broker refused the connection because of the credentials.
+pub fn is_auth_failure(error: &mqtt::Error) -> bool {
+    matches!(
+        error,
+        mqtt::Error::ReasonCode(
+            mqtt::ReasonCode::BadUserNameOrPassword | mqtt::ReasonCode::NotAuthorized
+        )
+    )
+}
+
+/// Tracks whether the broker resumed the persistent session after (re)connects.
+/// Each connect without a resumed session is counted as a missed interval, as QoS1 messages
+/// published while the gateway was offline are lost in that case.
+pub struct SessionMonitor {
+    persistent: bool,
+    disconnected_at: Option<Instant>,
+    missed_intervals: u32,
+    missed_duration: Duration,
+}
+
+impl SessionMonitor {
+    pub fn new(persistent: bool) -> Self {
+        SessionMonitor {
+            persistent,
+            disconnected_at: None,
+            missed_intervals: 0,
+            missed_duration: Duration::ZERO,
+        }
+    }
+
+    pub fn disconnected(&mut self) {
+        if self.disconnected_at.is_none() {
+            self.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    pub fn connected(&mut self, response: Option<mqtt::ServerResponse>) {
+        let session_present = response
+            .and_then(|response| response.connect_response())
+            .map(|response| response.session_present);
+        self.update(session_present);
+    }
+
+    fn update(&mut self, session_present: Option<bool>) {
+        let offline = self
+            .disconnected_at
+            .take()
+            .map(|disconnected_at| disconnected_at.elapsed())
+            .unwrap_or_default();
+
+        if !self.persistent {
+            return;
+        }
+
+        match session_present {
+            Some(true) => info!("broker resumed persistent session"),
+            Some(false) => {
+                self.missed_intervals += 1;
+                self.missed_duration += offline;
+                MISSED_INTERVALS.fetch_add(1, Ordering::Relaxed);
+                MISSED_MILLIS.fetch_add(offline.as_millis() as u64, Ordering::Relaxed);
+                warn!(
+                    "broker started a new session, messages while offline for {:.1}s are lost (missed intervals: {}, total {:.1}s)",
+                    offline.as_secs_f32(),
+                    self.missed_intervals,
+                    self.missed_duration.as_secs_f32()
+                );
+            }
+            None => warn!("no connect response, unable to check session state"),
+        }
+    }
+}
+
+/// Keeps track of the configured brokers and the one currently in use.
+/// Connect attempts start at the current broker and cycle through the remaining ones, a failed
+/// (re)connect moves the preferred position to the next broker.
+pub struct Brokers {
+    urls: Vec<String>,
+    current: usize,
+}
+
+impl Brokers {
+    pub fn new(urls: Vec<String>) -> Self {
+        Brokers { urls, current: 0 }
+    }
+
+    /// Server URIs in connect order, starting with the current broker.
+    pub fn ordered(&self) -> Vec<String> {
+        let mut urls = self.urls.clone();
+        urls.rotate_left(self.current);
+        urls
+    }
+
+    pub fn failed(&mut self) {
+        if self.urls.len() > 1 {
+            self.current = (self.current + 1) % self.urls.len();
+            info!("failing over to broker {}", self.urls[self.current]);
+        }
+    }
+
+    pub fn connected(&mut self, response: &mqtt::ServerResponse) {
+        if let Some(response) = response.connect_response() {
+            self.update(&response.server_uri);
+        }
+    }
+
+    fn update(&mut self, server_uri: &str) {
+        *CONNECTED_BROKER.lock().unwrap() = Some(broker_alias(server_uri).to_string());
+        match self.urls.iter().position(|url| url == server_uri) {
+            Some(index) => {
+                if index != self.current {
+                    warn!(
+                        "connected to broker {} instead of {}",
+                        server_uri, self.urls[self.current]
+                    );
+                    self.current = index;
+                } else {
+                    info!("connected to broker {}", server_uri);
+                }
+            }
+            None => info!("connected to broker {}", server_uri),
+        }
+    }
+}
+
+/// Host of a broker URI like `tcp://bridge.local:1883`.
+fn broker_alias(server_uri: &str) -> &str {
+    let address = server_uri
+        .split_once("://")
+        .map_or(server_uri, |(_, address)| address);
+    address
+        .split([':', '/'])
+        .next()
+        .filter(|host| !host.is_empty())
+        .unwrap_or(address)
+}
+
+/// Checks the SUBACK of the broker for topics it refused, e.g. because an ACL denies them, or
+/// granted with a lower QoS than requested. Without the check a refused topic looks like a
+/// source whose devices are silent.
+pub fn check_subscriptions(topics: &[String], qoss: &[i32], response: &mqtt::ServerResponse) {
+    match response.subscribe_many_response() {
+        Some(reason_codes) => {
+            record_subscriptions(topics, qoss, &reason_codes);
+        }
+        None => warn!("no SUBACK reason codes, unable to check subscriptions"),
+    }
+}
+
+/// Records the reason codes of a SUBACK, returns the number of refused topics.
+fn record_subscriptions(topics: &[String], qoss: &[i32], codes: &[i32]) -> usize {
+    let mut granted_qos = GRANTED_QOS.lock().unwrap();
+    let mut refused = 0;
+    for ((topic, qos), code) in topics.iter().zip(qoss).zip(codes) {
+        if *code >= SUBACK_FAILURE {
+            error!(
+                "broker refused subscription to {} with reason code {:#04x}, no messages will arrive",
+                topic, code
+            );
+            refused += 1;
+            granted_qos.insert(topic.clone(), None);
+        } else {
+            if code < qos {
+                warn!(
+                    "broker granted QoS {} instead of {} for {}",
+                    code, qos, topic
+                );
+            }
+            granted_qos.insert(topic.clone(), Some(*code));
+        }
+    }
+    REFUSED_SUBSCRIPTIONS.fetch_add(refused as u64, Ordering::Relaxed);
+    refused
+}
+
+/// The granted QoS per topic, -1 for refused topics, the number of refused subscriptions and the
+/// intervals missed because the broker did not resume the session in the Prometheus text format.
+pub fn metrics() -> String {
+    let mut metrics = String::new();
+    writeln!(
+        metrics,
+        "# HELP mqtt_gateway_subscription_granted_qos QoS granted by the broker, -1 if refused.\n\
+         # TYPE mqtt_gateway_subscription_granted_qos gauge"
+    )
+    .unwrap();
+    for (topic, qos) in GRANTED_QOS.lock().unwrap().iter() {
+        writeln!(
+            metrics,
+            "mqtt_gateway_subscription_granted_qos{} {}",
+            labels(&[("topic", topic)]),
+            qos.unwrap_or(-1)
+        )
+        .unwrap();
+    }
+    writeln!(
+        metrics,
+        "# HELP mqtt_gateway_subscription_failures_total Subscriptions refused by the broker.\n\
+         # TYPE mqtt_gateway_subscription_failures_total counter\n\
+         mqtt_gateway_subscription_failures_total{} {}",
+        labels(&[]),
+        REFUSED_SUBSCRIPTIONS.load(Ordering::Relaxed)
+    )
+    .unwrap();
+    writeln!(
+        metrics,
+        "# HELP mqtt_gateway_session_missed_intervals_total Reconnects without the persistent session resumed.\n\
+         # TYPE mqtt_gateway_session_missed_intervals_total counter\n\
+         mqtt_gateway_session_missed_intervals_total{labels} {}\n\
+         # HELP mqtt_gateway_session_missed_seconds_total Time offline before the missed intervals.\n\
+         # TYPE mqtt_gateway_session_missed_seconds_total counter\n\
+         mqtt_gateway_session_missed_seconds_total{labels} {}",
+        MISSED_INTERVALS.load(Ordering::Relaxed),
+        MISSED_MILLIS.load(Ordering::Relaxed) as f64 / 1000.0,
+        labels = labels(&[])
+    )
+    .unwrap();
+    metrics
+}
+
+/// Host of the broker the gateway is connected to.
+pub fn connected_broker() -> Option<String> {
+    CONNECTED_BROKER.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brokers() -> Brokers {
+        Brokers::new(vec![
+            "tcp://a:1883".to_string(),
+            "tcp://b:1883".to_string(),
+            "tcp://c:1883".to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_brokers_failover_cycles() {
+        let mut brokers = brokers();
+
+        brokers.failed();
+        assert_eq!(
+            brokers.ordered(),
+            vec!["tcp://b:1883", "tcp://c:1883", "tcp://a:1883"]
+        );
+
+        brokers.failed();
+        brokers.failed();
+        assert_eq!(brokers.ordered()[0], "tcp://a:1883");
+    }
+
+    #[test]
+    fn test_brokers_follow_connected_server() {
+        let mut brokers = brokers();
+
+        brokers.update("tcp://c:1883");
+
+        assert_eq!(brokers.current, 2);
+        assert_eq!(brokers.ordered()[0], "tcp://c:1883");
+    }
+
+    #[test]
+    fn test_broker_alias() {
+        assert_eq!(broker_alias("tcp://bridge.local:1883"), "bridge.local");
+        assert_eq!(broker_alias("ssl://broker/"), "broker");
+        assert_eq!(broker_alias("mqtt"), "mqtt");
+    }
+
+    #[test]
+    fn test_session_monitor_resumed_session() {
+        let mut monitor = SessionMonitor::new(true);
+
+        monitor.update(Some(true));
+        monitor.disconnected();
+        monitor.update(Some(true));
+
+        assert_eq!(monitor.missed_intervals, 0);
+    }
+
+    #[test]
+    fn test_session_monitor_counts_lost_sessions() {
+        let mut monitor = SessionMonitor::new(true);
+
+        monitor.update(Some(false));
+        monitor.disconnected();
+        monitor.update(Some(false));
+
+        assert_eq!(monitor.missed_intervals, 2);
+        assert!(MISSED_INTERVALS.load(Ordering::Relaxed) >= 2);
+        assert!(metrics().contains("# TYPE mqtt_gateway_session_missed_intervals_total counter"));
+    }
+
+    #[test]
+    fn test_session_monitor_ignores_clean_sessions() {
+        let mut monitor = SessionMonitor::new(false);
+
+        monitor.disconnected();
+        monitor.update(Some(false));
+
+        assert_eq!(monitor.missed_intervals, 0);
+    }
+
+    #[test]
+    fn test_check_client_id() {
+        assert!(check_client_id("gateway", true).is_ok());
+        assert!(check_client_id("", false).is_ok());
+
+        let error = check_client_id(" ", true).unwrap_err();
+        assert_eq!(error.exit_code(), crate::error::EXIT_CONFIG);
+    }
+
+    #[test]
+    fn test_is_auth_failure() {
+        assert!(is_auth_failure(&mqtt::Error::ReasonCode(
+            mqtt::ReasonCode::NotAuthorized
+        )));
+        assert!(!is_auth_failure(&mqtt::Error::ReasonCode(
+            mqtt::ReasonCode::ServerUnavailable
+        )));
+    }
+
+    #[test]
+    fn test_record_subscriptions() {
+        let topics = [
+            "suback-a/#".to_string(),
+            "suback-b/#".to_string(),
+            "suback-c/#".to_string(),
+        ];
+
+        let refused = record_subscriptions(&topics, &[1, 1, 1], &[1, 0, 0x87]);
+
+        assert_eq!(refused, 1);
+        let metrics = metrics();
+        assert!(metrics.contains("mqtt_gateway_subscription_granted_qos{topic=\"suback-a/#\"} 1\n"));
+        assert!(metrics.contains("mqtt_gateway_subscription_granted_qos{topic=\"suback-b/#\"} 0\n"));
+        assert!(
+            metrics.contains("mqtt_gateway_subscription_granted_qos{topic=\"suback-c/#\"} -1\n")
+        );
+    }
 }
@@ -0,0 +1,150 @@
+use crate::config::{SampleRate, StaticEventConfig};
+use crate::data::{BuildInfo, CheckMessage, SourceStats};
+use crate::error::{GatewayError, Result};
+use chrono::{DateTime, Utc};
+use log::trace;
+use paho_mqtt::Message;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Fraction of the messages of each topic to process, evenly spread and starting with the first
+/// message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampler {
+    fraction: f64,
+    credits: HashMap<String, f64>,
+}
+
+impl Sampler {
+    pub fn new(sample_rate: &SampleRate) -> Result<Self> {
+        let fraction = match sample_rate {
+            SampleRate::Every(0) => None,
+            SampleRate::Every(every) => Some(1.0 / *every as f64),
+            SampleRate::Percentage(percentage) => percentage
+                .trim()
+                .strip_suffix('%')
+                .and_then(|percentage| percentage.trim().parse::<f64>().ok())
+                .filter(|percentage| *percentage > 0.0 && *percentage <= 100.0)
+                .map(|percentage| percentage / 100.0),
+        };
+        let fraction = fraction.ok_or_else(|| {
+            GatewayError::config(format!(
+                "invalid sample rate {:?}, expected a number N > 0 or a percentage like \"10%\"",
+                sample_rate
+            ))
+        })?;
+        Ok(Sampler {
+            fraction,
+            credits: HashMap::new(),
+        })
+    }
+
+    pub fn sample(&mut self, topic: &str) -> bool {
+        let credit = self
+            .credits
+            .entry(topic.to_string())
+            .or_insert(1.0 - self.fraction);
+        *credit += self.fraction;
+        // Allow for rounding errors of the accumulated fractions.
+        if *credit >= 1.0 - 1e-9 {
+            *credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct SamplingLogger {
+    sampler: Sampler,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+    skipped: u64,
+}
+
+impl SamplingLogger {
+    pub(crate) fn new(sampler: Sampler, logger: Arc<Mutex<dyn CheckMessage>>) -> Self {
+        SamplingLogger {
+            sampler,
+            logger,
+            skipped: 0,
+        }
+    }
+}
+
+impl CheckMessage for SamplingLogger {
+    fn check_message(&mut self, msg: &Message) {
+        if self.sampler.sample(msg.topic()) {
+            self.logger.lock().unwrap().check_message(msg);
+        } else {
+            trace!("skipping '{}' by sampling", msg.topic());
+            self.skipped += 1;
+        }
+    }
+
+    fn stats(&self) -> SourceStats {
+        let mut stats = self.logger.lock().unwrap().stats();
+        stats.received += self.skipped;
+        stats.dropped += self.skipped;
+        stats
+    }
+
+    fn shutdown(&mut self) {
+        self.logger.lock().unwrap().shutdown();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        self.logger.lock().unwrap().heartbeat(source, messages);
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        self.logger.lock().unwrap().started(source, build);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        self.logger.lock().unwrap().inject(source, event, time);
+    }
+
+    fn field_stats(&self) -> Option<String> {
+        self.logger.lock().unwrap().field_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(sampler: &mut Sampler, topic: &str, count: usize) -> Vec<bool> {
+        (0..count).map(|_| sampler.sample(topic)).collect()
+    }
+
+    #[test]
+    fn test_sample_every() -> Result<()> {
+        let mut sampler = Sampler::new(&SampleRate::Every(3))?;
+
+        assert_eq!(
+            samples(&mut sampler, "shellies/a", 6),
+            vec![true, false, false, true, false, false]
+        );
+        assert_eq!(samples(&mut sampler, "shellies/b", 2), vec![true, false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_percentage() -> Result<()> {
+        let mut sampler = Sampler::new(&SampleRate::Percentage("10%".to_string()))?;
+
+        let sampled = samples(&mut sampler, "shellies/a", 100);
+        assert_eq!(sampled.iter().filter(|sampled| **sampled).count(), 10);
+        assert!(sampled[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_sample_rate() {
+        assert!(Sampler::new(&SampleRate::Every(0)).is_err());
+        assert!(Sampler::new(&SampleRate::Percentage("10".to_string())).is_err());
+        assert!(Sampler::new(&SampleRate::Percentage("150%".to_string())).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config::StaticEventConfig;
+use crate::data::{BuildInfo, CheckMessage, SourceStats};
+use crate::error::{GatewayError, Result};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use log::trace;
+use paho_mqtt::Message;
+use std::sync::{Arc, Mutex};
+
+/// Daily time window in local time, e.g. `06:00-21:30`. Windows whose end is before their start
+/// wrap around midnight (`22:00-06:00`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ActiveHours {
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || {
+            GatewayError::config(format!(
+                "invalid active hours '{}', expected HH:MM-HH:MM",
+                value
+            ))
+        };
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        Ok(ActiveHours {
+            start: NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?,
+            end: NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?,
+        })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+pub struct ScheduledLogger {
+    active_hours: ActiveHours,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+    ignored: u64,
+}
+
+impl ScheduledLogger {
+    pub(crate) fn new(active_hours: ActiveHours, logger: Arc<Mutex<dyn CheckMessage>>) -> Self {
+        ScheduledLogger {
+            active_hours,
+            logger,
+            ignored: 0,
+        }
+    }
+}
+
+impl CheckMessage for ScheduledLogger {
+    fn check_message(&mut self, msg: &Message) {
+        if self.active_hours.contains(Local::now().time()) {
+            self.logger.lock().unwrap().check_message(msg);
+        } else {
+            trace!("ignoring '{}' outside of active hours", msg.topic());
+            self.ignored += 1;
+        }
+    }
+
+    fn stats(&self) -> SourceStats {
+        let mut stats = self.logger.lock().unwrap().stats();
+        stats.received += self.ignored;
+        stats.dropped += self.ignored;
+        stats
+    }
+
+    fn shutdown(&mut self) {
+        self.logger.lock().unwrap().shutdown();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        self.logger.lock().unwrap().heartbeat(source, messages);
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        self.logger.lock().unwrap().started(source, build);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        self.logger.lock().unwrap().inject(source, event, time);
+    }
+
+    fn field_stats(&self) -> Option<String> {
+        self.logger.lock().unwrap().field_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let active_hours = ActiveHours::parse("06:00 - 21:30")?;
+
+        assert_eq!(active_hours.start, time(6, 0));
+        assert_eq!(active_hours.end, time(21, 30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(ActiveHours::parse("06:00").is_err());
+        assert!(ActiveHours::parse("06:00-25:00").is_err());
+    }
+
+    #[test]
+    fn test_contains() -> Result<()> {
+        let active_hours = ActiveHours::parse("06:00-21:30")?;
+
+        assert!(!active_hours.contains(time(5, 59)));
+        assert!(active_hours.contains(time(6, 0)));
+        assert!(active_hours.contains(time(21, 29)));
+        assert!(!active_hours.contains(time(21, 30)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contains_across_midnight() -> Result<()> {
+        let active_hours = ActiveHours::parse("22:00-06:00")?;
+
+        assert!(active_hours.contains(time(23, 0)));
+        assert!(active_hours.contains(time(2, 0)));
+        assert!(!active_hours.contains(time(12, 0)));
+
+        Ok(())
+    }
+}
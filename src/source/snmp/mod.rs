@@ -0,0 +1,220 @@
+use crate::config::{OidConfig, SnmpConfig};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::{CheckMessage, EventValue, LogEvent};
+use crate::error::{GatewayError, Result};
+use crate::source::control;
+use crate::source::socket;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::info;
+use snmp::{SyncSession, Value};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const DEFAULT_COMMUNITY: &str = "public";
+const DEFAULT_INTERVAL: u64 = 60;
+const DEFAULT_PORT: u16 = 161;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An OID of the configuration with its parsed numeric form.
+struct Oid {
+    numbers: Vec<u32>,
+    config: OidConfig,
+}
+
+impl Oid {
+    fn parse(config: OidConfig) -> Result<Self> {
+        let numbers = config
+            .oid
+            .trim_start_matches('.')
+            .split('.')
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<u32>, _>>()
+            .map_err(|_| GatewayError::config(format!("invalid OID '{}'", config.oid)))?;
+        Ok(Oid { numbers, config })
+    }
+}
+
+/// Number of a value, numeric text like `"38.5"` included.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(value) => Some(*value as f64),
+        Value::Counter32(value) | Value::Unsigned32(value) | Value::Timeticks(value) => {
+            Some(*value as f64)
+        }
+        Value::Counter64(value) => Some(*value as f64),
+        Value::OctetString(text) => std::str::from_utf8(text).ok()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// The log event of a value polled from the device at the given time.
+fn log_event(device: &SnmpConfig, oid: &OidConfig, value: f64, time: DateTime<Utc>) -> LogEvent {
+    LogEvent {
+        host: device.host.clone(),
+        location: device.location.clone(),
+        measurement_type: oid.measurement.clone(),
+        unit: oid.unit.clone().unwrap_or_default(),
+        sensor: oid.sensor.clone().unwrap_or_else(|| device.host.clone()),
+        calculated: oid.scale.is_some(),
+        time: time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        value: EventValue::Float(value * oid.scale.unwrap_or(1.0)),
+    }
+}
+
+/// Queries the OIDs of the device, values which could not be read are skipped.
+fn poll(session: &mut SyncSession, device: &SnmpConfig, oids: &[Oid]) -> Vec<LogEvent> {
+    let mut events = Vec::new();
+    for oid in oids {
+        let value = match session.get(&oid.numbers) {
+            Ok(mut response) => response
+                .varbinds
+                .next()
+                .and_then(|(_, value)| number(&value)),
+            Err(error) => {
+                warn_deduplicated(
+                    &format!("failed to poll {} via SNMP", device.host),
+                    &format!("{}: {:?}", oid.config.oid, error),
+                );
+                continue;
+            }
+        };
+        match value {
+            Some(value) => events.push(log_event(device, &oid.config, value, Utc::now())),
+            None => warn_deduplicated(
+                &format!("no numeric SNMP value from {}", device.host),
+                &oid.config.oid,
+            ),
+        }
+    }
+    events
+}
+
+/// Polls the device in the configured interval and passes the values as log events to the logger
+/// of the source with the given prefix.
+pub fn spawn_snmp(
+    device: SnmpConfig,
+    prefix: String,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+) -> Result<JoinHandle<()>> {
+    let oids = device
+        .oids
+        .iter()
+        .cloned()
+        .map(Oid::parse)
+        .collect::<Result<Vec<_>>>()?;
+    let address = if device.host.contains(':') {
+        device.host.clone()
+    } else {
+        format!("{}:{}", device.host, DEFAULT_PORT)
+    };
+    let community = device
+        .community
+        .clone()
+        .unwrap_or_else(|| DEFAULT_COMMUNITY.to_string());
+    let interval = Duration::from_secs(device.interval.unwrap_or(DEFAULT_INTERVAL));
+    info!(
+        "polling {} OIDs of {} via SNMP every {:?}",
+        oids.len(),
+        device.host,
+        interval
+    );
+
+    Ok(thread::spawn(move || {
+        let mut session = None;
+        loop {
+            if control::is_enabled(&prefix) {
+                if session.is_none() {
+                    // the address is resolved again after failures, e.g. for DHCP devices
+                    session = SyncSession::new(&address, community.as_bytes(), Some(TIMEOUT), 0)
+                        .inspect_err(|error| {
+                            warn_deduplicated(
+                                &format!("failed to open SNMP session to {}", address),
+                                &error.to_string(),
+                            )
+                        })
+                        .ok();
+                }
+                if let Some(session) = session.as_mut() {
+                    for event in poll(session, &device, &oids) {
+                        match socket::to_message(&prefix, event) {
+                            Ok(msg) => logger.lock().unwrap().check_message(&msg),
+                            Err(error) => {
+                                warn_deduplicated("invalid SNMP value", &error.to_string())
+                            }
+                        }
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> SnmpConfig {
+        SnmpConfig {
+            host: "ups.local".to_string(),
+            community: None,
+            interval: None,
+            location: "rack".to_string(),
+            oids: vec![OidConfig {
+                oid: ".1.3.6.1.2.1.33.1.4.4.1.5.1".to_string(),
+                measurement: "ups_load".to_string(),
+                sensor: None,
+                unit: Some("%".to_string()),
+                scale: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_parse_oid() {
+        let oid = Oid::parse(device().oids[0].clone()).unwrap();
+        assert_eq!(oid.numbers, vec![1, 3, 6, 1, 2, 1, 33, 1, 4, 4, 1, 5, 1]);
+
+        let invalid = OidConfig {
+            oid: "1.3.six".to_string(),
+            ..device().oids[0].clone()
+        };
+        assert!(Oid::parse(invalid).is_err());
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(&Value::Integer(-3)), Some(-3.0));
+        assert_eq!(number(&Value::Unsigned32(42)), Some(42.0));
+        assert_eq!(number(&Value::Counter64(7)), Some(7.0));
+        assert_eq!(number(&Value::OctetString(b" 38.5")), Some(38.5));
+        assert_eq!(number(&Value::OctetString(b"on")), None);
+        assert_eq!(number(&Value::Null), None);
+    }
+
+    #[test]
+    fn test_log_event() -> Result<()> {
+        let device = device();
+        let time = DateTime::from_timestamp(1704067200, 0).unwrap();
+        let oid = OidConfig {
+            scale: Some(0.1),
+            sensor: Some("poe".to_string()),
+            ..device.oids[0].clone()
+        };
+
+        let event = log_event(&device, &oid, 125.0, time);
+
+        assert_eq!(event.sensor, "poe");
+        assert_eq!(event.time, "2024-01-01T00:00:00Z");
+        assert_eq!(event.value, EventValue::Float(12.5));
+        let msg = socket::to_message("network", event)?;
+        assert_eq!(msg.topic(), "network/rack/ups_load");
+        assert_eq!(
+            log_event(&device, &device.oids[0], 42.0, time).sensor,
+            "ups.local"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::data::dedup::warn_deduplicated;
+use crate::data::envelope;
+use crate::data::{CheckMessage, EventValue, LogEvent};
+use crate::error::{GatewayError, Result};
+use crate::source::control;
+use chrono::DateTime;
+use log::{info, warn};
+use paho_mqtt::Message;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Path reading the events from stdin instead of a socket.
+pub const STDIN: &str = "-";
+
+/// The message a sensor publishing the event to `<prefix>/<location>/<type>` would send.
+pub(crate) fn to_message(prefix: &str, event: LogEvent) -> Result<Message> {
+    let EventValue::Float(value) = event.value else {
+        return Err(GatewayError::parse(
+            "log event",
+            format!("non-numeric value {:?}", event.value),
+        ));
+    };
+    let time = DateTime::parse_from_rfc3339(&event.time)
+        .map_err(|error| GatewayError::parse(format!("time '{}'", event.time), error))?;
+    Ok(Message::new(
+        format!("{}/{}/{}", prefix, event.location, event.measurement_type),
+        serde_json::json!({
+            "time": time.timestamp(),
+            "value": value,
+            "sensor": event.sensor,
+        })
+        .to_string(),
+        1,
+    ))
+}
+
+/// Passes the events of the NDJSON stream to the logger of the source until the stream ends.
+fn read_events(reader: impl BufRead, prefix: &str, logger: &Mutex<dyn CheckMessage>) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                warn!("failed to read log events for {}: {}", prefix, error);
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !control::is_enabled(prefix) {
+            continue;
+        }
+        let msg = envelope::decode(&line).and_then(|event| to_message(prefix, event));
+        match msg {
+            Ok(msg) => logger.lock().unwrap().check_message(&msg),
+            Err(error) => warn_deduplicated(
+                &format!("invalid log event for {}", prefix),
+                &error.to_string(),
+            ),
+        }
+    }
+}
+
+/// Reads NDJSON log events from the Unix socket at `path`, or stdin for [`STDIN`], and passes them
+/// to the logger of the source with the given prefix. Each connection to the socket is read by its
+/// own thread, a socket file left by a previous run is replaced.
+pub fn spawn_socket(
+    path: &str,
+    prefix: String,
+    logger: Arc<Mutex<dyn CheckMessage>>,
+) -> Result<JoinHandle<()>> {
+    if path == STDIN {
+        info!("reading log events for {} from stdin", prefix);
+        return Ok(thread::spawn(move || {
+            read_events(io::stdin().lock(), &prefix, logger.as_ref());
+            info!("end of log events for {} on stdin", prefix);
+        }));
+    }
+
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)
+        .map_err(|error| GatewayError::connect(format!("socket {}", path), error))?;
+    info!("reading log events for {} from socket {}", prefix, path);
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let prefix = prefix.clone();
+                    let logger = logger.clone();
+                    thread::spawn(move || {
+                        read_events(BufReader::new(stream), &prefix, logger.as_ref())
+                    });
+                }
+                Err(error) => warn!("failed to accept socket connection: {}", error),
+            }
+        }
+    }))
+}
+
+#[cfg(all(test, feature = "klimalogger"))]
+mod tests {
+    use super::*;
+    use crate::config::MissingTimestamp;
+    use crate::data::enrichment::Enrichment;
+    use crate::data::klimalogger::SensorLogger;
+    use crate::data::timestamp::TimestampPolicy;
+    use crate::SensorReading;
+    use std::env;
+    use std::io::{Cursor, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::{sync_channel, SyncSender};
+    use std::time::Duration;
+
+    const EVENT: &str = r#"{"host":"nas","location":"office","type":"temperature","unit":"°C","sensor":"disk0","calculated":false,"time":"2024-01-01T00:00:00Z","value":38.0}"#;
+
+    #[test]
+    fn test_to_message() -> Result<()> {
+        let msg = to_message("sensors", serde_json::from_str(EVENT)?)?;
+
+        assert_eq!(msg.topic(), "sensors/office/temperature");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(msg.payload())?,
+            serde_json::json!({"time": 1704067200, "value": 38.0, "sensor": "disk0"})
+        );
+
+        let event = serde_json::from_str(&EVENT.replace("38.0", "\"hot\""))?;
+        assert!(to_message("sensors", event).is_err());
+        Ok(())
+    }
+
+    /// Logger accepting the event of 2024, which is beyond the default maximum offset.
+    fn logger(tx: SyncSender<SensorReading>) -> SensorLogger {
+        SensorLogger::new(vec![tx], Enrichment::default())
+            .with_timestamp_policy(TimestampPolicy::new(MissingTimestamp::Drop, None))
+    }
+
+    #[test]
+    fn test_read_events() {
+        let (tx, rx) = sync_channel(10);
+        let logger = Mutex::new(logger(tx));
+
+        read_events(
+            Cursor::new(format!(
+                "{}\n\nnot json\n{{\"version\":2,\"event\":{}}}\n{{\"version\":9,\"event\":{}}}\n",
+                EVENT, EVENT, EVENT
+            )),
+            "sensors",
+            &logger,
+        );
+
+        let reading = rx.try_recv().unwrap();
+        assert_eq!(&*reading.measurement, "temperature");
+        assert_eq!(&*reading.location, "office");
+        assert_eq!(&*reading.sensor, "disk0");
+        assert_eq!(reading.value, 38.0);
+        // the same event in an envelope, the one of an unknown version is skipped
+        assert_eq!(rx.try_recv().unwrap().value, 38.0);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(logger.lock().unwrap().stats().received, 2);
+    }
+
+    #[test]
+    fn test_socket() -> Result<()> {
+        let path = env::temp_dir().join(format!("mqtt-gateway-{}.sock", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let (tx, rx) = sync_channel(10);
+        spawn_socket(
+            &path,
+            "sensors".to_string(),
+            Arc::new(Mutex::new(logger(tx))),
+        )?;
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        writeln!(stream, "{}", EVENT).unwrap();
+        drop(stream);
+
+        let reading = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(&*reading.location, "office");
+        fs::remove_file(path).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::StaticEventConfig;
+use crate::data::{BuildInfo, CheckMessage, SourceStats};
+use chrono::{DateTime, Utc};
+use log::debug;
+use paho_mqtt::Message;
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static WARMING_UP: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the message handled on the current thread is a retained message only warming up the
+/// device registry and the live values, whose events are not written to the targets.
+pub fn is_warming_up() -> bool {
+    WARMING_UP.get()
+}
+
+/// Handles retained messages, which the broker sends on subscribing and so again after every
+/// restart, without writing their events, so that restarts don't add duplicate points at the
+/// restart time. The wrapped logger still records their devices and live values.
+pub struct WarmupLogger {
+    logger: Arc<Mutex<dyn CheckMessage>>,
+}
+
+impl WarmupLogger {
+    pub(crate) fn new(logger: Arc<Mutex<dyn CheckMessage>>) -> Self {
+        WarmupLogger { logger }
+    }
+}
+
+impl CheckMessage for WarmupLogger {
+    fn check_message(&mut self, msg: &Message) {
+        if !msg.retained() {
+            self.logger.lock().unwrap().check_message(msg);
+            return;
+        }
+        debug!("warming up with retained message '{}'", msg.topic());
+        WARMING_UP.set(true);
+        self.logger.lock().unwrap().check_message(msg);
+        WARMING_UP.set(false);
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.logger.lock().unwrap().stats()
+    }
+
+    fn shutdown(&mut self) {
+        self.logger.lock().unwrap().shutdown();
+    }
+
+    fn heartbeat(&mut self, source: &str, messages: u64) {
+        self.logger.lock().unwrap().heartbeat(source, messages);
+    }
+
+    fn started(&mut self, source: &str, build: &BuildInfo) {
+        self.logger.lock().unwrap().started(source, build);
+    }
+
+    fn inject(&mut self, source: &str, event: &StaticEventConfig, time: DateTime<Utc>) {
+        self.logger.lock().unwrap().inject(source, event, time);
+    }
+
+    fn field_stats(&self) -> Option<String> {
+        self.logger.lock().unwrap().field_stats()
+    }
+}
+
+#[cfg(all(test, feature = "klimalogger"))]
+mod tests {
+    use super::*;
+    use crate::config::MissingTimestamp;
+    use crate::data::enrichment::Enrichment;
+    use crate::data::klimalogger::SensorLogger;
+    use crate::data::timestamp::TimestampPolicy;
+    use paho_mqtt::MessageBuilder;
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn test_retained_not_written() {
+        let (tx, rx) = sync_channel(10);
+        let logger = SensorLogger::new(vec![tx], Enrichment::default())
+            .with_timestamp_policy(TimestampPolicy::new(MissingTimestamp::Drop, None));
+        let mut logger = WarmupLogger::new(Arc::new(Mutex::new(logger)));
+        let message = |retained| {
+            MessageBuilder::new()
+                .topic("sensors/cellar/humidity")
+                .payload(r#"{"time": 1704067200, "value": 61.5, "sensor": "sht31"}"#)
+                .retained(retained)
+                .finalize()
+        };
+
+        logger.check_message(&message(true));
+        assert!(rx.try_recv().is_err());
+        assert!(!is_warming_up());
+
+        logger.check_message(&message(false));
+        assert_eq!(rx.try_recv().unwrap().value, 61.5);
+        assert_eq!(logger.stats().received, 2);
+    }
+}
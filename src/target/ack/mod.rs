@@ -0,0 +1,163 @@
+use crate::data::query::WriteQuery;
+use crate::target::Footprint;
+use crate::SensorReading;
+use futures::executor::block_on;
+use log::{debug, warn};
+use paho_mqtt as mqtt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+static CLIENT: LazyLock<Mutex<Option<mqtt::AsyncClient>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Registers the client acknowledgements are published with.
+pub fn register(client: &mqtt::AsyncClient) {
+    *CLIENT.lock().unwrap() = Some(client.clone());
+}
+
+fn publish(topic: &str, payload: &str) {
+    let Some(client) = CLIENT.lock().unwrap().clone() else {
+        debug!("no client to publish acknowledgement to {}", topic);
+        return;
+    };
+    let message = mqtt::Message::new(topic, payload, mqtt::QOS_1);
+    if let Err(error) = block_on(client.publish(message)) {
+        warn!("failed to publish acknowledgement to {}: {}", topic, error);
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    topic: String,
+    payload: String,
+    failed: AtomicBool,
+    publish: fn(&str, &str),
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.failed.load(Ordering::Relaxed) {
+            debug!("not acknowledging {}, a target failed", self.topic);
+        } else {
+            (self.publish)(&self.topic, &self.payload);
+        }
+    }
+}
+
+/// Acknowledgement of an event sent to several targets, published once every copy handed to a
+/// target was confirmed. A copy dropped without confirmation, e.g. after a failed write,
+/// suppresses the acknowledgement.
+#[derive(Debug)]
+pub struct Ack {
+    pending: Arc<Pending>,
+    confirmed: bool,
+}
+
+impl Ack {
+    pub fn new(topic: impl Into<String>, payload: impl Into<String>) -> Self {
+        Self::with_publisher(topic, payload, publish)
+    }
+
+    fn with_publisher(
+        topic: impl Into<String>,
+        payload: impl Into<String>,
+        publish: fn(&str, &str),
+    ) -> Self {
+        Ack {
+            pending: Arc::new(Pending {
+                topic: topic.into(),
+                payload: payload.into(),
+                failed: AtomicBool::new(false),
+                publish,
+            }),
+            confirmed: false,
+        }
+    }
+
+    /// Confirms that this copy was written successfully.
+    pub fn confirm(mut self) {
+        self.confirmed = true;
+    }
+}
+
+impl Clone for Ack {
+    fn clone(&self) -> Self {
+        Ack {
+            pending: self.pending.clone(),
+            confirmed: false,
+        }
+    }
+}
+
+impl Drop for Ack {
+    fn drop(&mut self) {
+        if !self.confirmed {
+            self.pending.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Data passed to targets, which may carry an acknowledgement to confirm after writing it.
+pub trait Acknowledged: Footprint {
+    fn take_ack(&mut self) -> Option<Ack> {
+        None
+    }
+}
+
+impl Acknowledged for WriteQuery {}
+
+impl Acknowledged for SensorReading {
+    fn take_ack(&mut self) -> Option<Ack> {
+        self.ack.take()
+    }
+}
+
+#[cfg(test)]
+impl Acknowledged for String {}
+
+#[cfg(test)]
+impl Acknowledged for f64 {}
+
+/// Confirms the acknowledgement taken from data, if any.
+pub fn confirm(ack: Option<Ack>) {
+    if let Some(ack) = ack {
+        ack.confirm();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PUBLISHED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn record(topic: &str, _payload: &str) {
+        PUBLISHED.lock().unwrap().push(topic.to_string());
+    }
+
+    fn published(topic: &str) -> bool {
+        PUBLISHED.lock().unwrap().iter().any(|item| item == topic)
+    }
+
+    #[test]
+    fn test_ack_after_all_confirmed() {
+        let ack = Ack::with_publisher("sensors/ack/confirmed", "{}", record);
+        let copies = vec![ack.clone(), ack.clone()];
+        ack.confirm();
+
+        let mut copies = copies.into_iter();
+        confirm(copies.next());
+        assert!(!published("sensors/ack/confirmed"));
+        confirm(copies.next());
+        assert!(published("sensors/ack/confirmed"));
+    }
+
+    #[test]
+    fn test_no_ack_after_failure() {
+        let ack = Ack::with_publisher("sensors/ack/failed", "{}", record);
+        let copy = ack.clone();
+        ack.confirm();
+
+        drop(copy);
+        assert!(!published("sensors/ack/failed"));
+    }
+}
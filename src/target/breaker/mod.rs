@@ -0,0 +1,166 @@
+use crate::config::{BreakerConfig, BreakerPolicy};
+use log::{info, warn};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_FAILURES: u32 = 5;
+const DEFAULT_COOLDOWN: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed,
+    /// No writes are attempted until the instant.
+    Open(Instant),
+    /// A single write checks whether the target recovered.
+    Probing,
+}
+
+/// Circuit breaker of a target writer, which stops writing to a failing target for a cooldown
+/// instead of piling up more load on it.
+#[derive(Debug)]
+pub struct Breaker {
+    name: String,
+    threshold: u32,
+    cooldown: Duration,
+    policy: BreakerPolicy,
+    failures: u32,
+    dropped: u64,
+    state: State,
+}
+
+impl Breaker {
+    /// Breaker of the named target, which never opens without configuration.
+    pub fn new(name: impl Into<String>, config: Option<&BreakerConfig>) -> Self {
+        let (threshold, cooldown, policy) = match config {
+            Some(config) => (
+                config.failures.unwrap_or(DEFAULT_FAILURES).max(1),
+                config.cooldown.unwrap_or(DEFAULT_COOLDOWN),
+                config.policy.unwrap_or_default(),
+            ),
+            None => (u32::MAX, DEFAULT_COOLDOWN, BreakerPolicy::Drop),
+        };
+        Breaker {
+            name: name.into(),
+            threshold,
+            cooldown: Duration::from_secs(cooldown),
+            policy,
+            failures: 0,
+            dropped: 0,
+            state: State::Closed,
+        }
+    }
+
+    /// Whether the next event is to be written. While the breaker is open the event is dropped
+    /// or, with the spool policy, held until the cooldown ended.
+    pub fn admit(&mut self) -> bool {
+        let State::Open(until) = self.state else {
+            return true;
+        };
+        match self.policy {
+            BreakerPolicy::Drop if Instant::now() < until => {
+                self.dropped += 1;
+                return false;
+            }
+            BreakerPolicy::Drop => {}
+            BreakerPolicy::Spool => thread::sleep(until.saturating_duration_since(Instant::now())),
+        }
+        info!("probing {} after the cooldown", self.name);
+        self.state = State::Probing;
+        true
+    }
+
+    /// Records the outcome of an admitted write.
+    pub fn record(&mut self, written: bool) {
+        if written {
+            if self.state == State::Probing {
+                info!(
+                    "{} recovered, resuming writes ({} events dropped)",
+                    self.name, self.dropped
+                );
+                self.dropped = 0;
+            }
+            self.failures = 0;
+            self.state = State::Closed;
+            return;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if self.state == State::Probing || self.failures >= self.threshold {
+            warn!(
+                "pausing writes to {} for {:?} after {} failed writes",
+                self.name, self.cooldown, self.failures
+            );
+            self.state = State::Open(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Attempts the write if the breaker admits it and records its outcome.
+    #[cfg(feature = "postgres")]
+    pub fn guard(&mut self, write: impl FnOnce() -> bool) {
+        if self.admit() {
+            let written = write();
+            self.record(written);
+        }
+    }
+
+    #[cfg(test)]
+    fn is_open(&self) -> bool {
+        matches!(self.state, State::Open(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(cooldown: u64, policy: BreakerPolicy) -> Breaker {
+        let config = BreakerConfig {
+            failures: Some(2),
+            cooldown: Some(cooldown),
+            policy: Some(policy),
+        };
+        Breaker::new("influxdb test", Some(&config))
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let mut breaker = breaker(60, BreakerPolicy::Drop);
+
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(false);
+        assert!(breaker.admit());
+        breaker.record(false);
+
+        assert!(breaker.is_open());
+        assert!(!breaker.admit());
+        assert_eq!(breaker.dropped, 1);
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut breaker = breaker(0, BreakerPolicy::Spool);
+        breaker.record(false);
+        breaker.record(false);
+
+        // a failed probe opens the breaker again right away
+        assert!(breaker.admit());
+        breaker.record(false);
+        assert!(breaker.is_open());
+
+        assert!(breaker.admit());
+        breaker.record(true);
+        assert_eq!(breaker.state, State::Closed);
+        assert_eq!(breaker.failures, 0);
+    }
+
+    #[test]
+    fn test_without_config() {
+        let mut breaker = Breaker::new("postgresql test", None);
+
+        for _ in 0..100 {
+            breaker.record(false);
+        }
+
+        assert!(breaker.admit());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::data::query::{Query, WriteQuery};
+use crate::error::{GatewayError, Result};
+use crate::source::connection;
+use crate::target::ack::{Ack, Acknowledged};
+use crate::target::mqtt::{parse_line, Event, FieldValue};
+use arrow::array::TimestampSecondArray;
+use arrow::array::{ArrayRef, Float64Array, MapBuilder, StringArray, StringBuilder};
+use arrow::record_batch::RecordBatch;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::{FlightClient, FlightDescriptor};
+use chrono::Utc;
+use futures::{stream, TryStreamExt};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tonic::transport::Channel;
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+const DEFAULT_FLUSH_INTERVAL: u64 = 5;
+
+pub struct FlightConfig {
+    url: String,
+    table: String,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl FlightConfig {
+    pub(crate) fn new(
+        url: String,
+        table: String,
+        batch_size: Option<usize>,
+        flush_interval: Option<u64>,
+    ) -> Self {
+        Self {
+            url,
+            table,
+            batch_size: batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+            flush_interval: Duration::from_secs(flush_interval.unwrap_or(DEFAULT_FLUSH_INTERVAL)),
+        }
+    }
+}
+
+/// A row of the table the events are streamed to, one per numeric field of an event.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    time: i64,
+    measurement: String,
+    field: String,
+    value: f64,
+    tags: BTreeMap<String, String>,
+}
+
+/// Rows of the numeric and boolean fields of an event, text fields are left out.
+fn rows(event: Event, now: i64) -> Vec<Row> {
+    let time = event.time.unwrap_or(now);
+    event
+        .fields
+        .into_iter()
+        .filter_map(|(field, value)| {
+            let value = match value {
+                FieldValue::Float(value) => value,
+                FieldValue::Integer(value) => value as f64,
+                FieldValue::Boolean(value) => value as u8 as f64,
+                FieldValue::Text(_) => return None,
+            };
+            Some(Row {
+                time,
+                measurement: event.measurement.clone(),
+                field,
+                value,
+                tags: event.tags.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Record batch with the columns `time`, `measurement`, `field`, `value` and `tags`, a map of
+/// the tags of each row.
+fn record_batch(rows: &[Row]) -> Result<RecordBatch> {
+    let invalid = |error| GatewayError::target("flight record batch", error);
+    let mut tags = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for row in rows {
+        for (key, value) in &row.tags {
+            tags.keys().append_value(key);
+            tags.values().append_value(value);
+        }
+        tags.append(true).map_err(invalid)?;
+    }
+    let time = TimestampSecondArray::from_iter_values(rows.iter().map(|row| row.time))
+        .with_timezone("UTC");
+    let measurement = StringArray::from_iter_values(rows.iter().map(|row| &row.measurement));
+    let field = StringArray::from_iter_values(rows.iter().map(|row| &row.field));
+    let value = Float64Array::from_iter_values(rows.iter().map(|row| row.value));
+    RecordBatch::try_from_iter([
+        ("time", Arc::new(time) as ArrayRef),
+        ("measurement", Arc::new(measurement) as ArrayRef),
+        ("field", Arc::new(field) as ArrayRef),
+        ("value", Arc::new(value) as ArrayRef),
+        ("tags", Arc::new(tags.finish()) as ArrayRef),
+    ])
+    .map_err(invalid)
+}
+
+async fn connect(url: &str) -> Result<FlightClient> {
+    let context = format!("flight {}", url);
+    let channel = Channel::from_shared(url.to_string())
+        .map_err(|error| GatewayError::config(format!("invalid url {}: {}", url, error)))?
+        .connect()
+        .await
+        .map_err(|error| {
+            connection::log_failure(&context, &error);
+            GatewayError::connect(context.as_str(), error)
+        })?;
+    connection::log_handshake(&context, "connected");
+    Ok(FlightClient::new(channel))
+}
+
+/// Streams the batch to the table with a `DoPut` request.
+async fn put(client: &mut FlightClient, table: &str, batch: RecordBatch) -> Result<()> {
+    let descriptor = FlightDescriptor::new_path(vec![table.to_string()]);
+    let data = FlightDataEncoderBuilder::new()
+        .with_flight_descriptor(Some(descriptor))
+        .build(stream::iter([Ok(batch)]));
+    client
+        .do_put(data)
+        .await
+        .map_err(|error| GatewayError::target(format!("flight put to {}", table), error))?
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|error| GatewayError::target(format!("flight put to {}", table), error))?;
+    Ok(())
+}
+
+/// Connects if not connected yet and puts the batch.
+async fn write(
+    client: &mut Option<FlightClient>,
+    config: &FlightConfig,
+    batch: RecordBatch,
+) -> Result<()> {
+    if client.is_none() {
+        *client = Some(connect(&config.url).await?);
+    }
+    match client {
+        Some(client) => put(client, &config.table, batch).await,
+        None => Ok(()),
+    }
+}
+
+struct FlightWriter {
+    config: FlightConfig,
+    runtime: Runtime,
+    /// Connected on the first flush and again after a failed one.
+    client: Option<FlightClient>,
+}
+
+impl FlightWriter {
+    /// Writes the rows, confirming the acknowledgements of their events once they are written.
+    fn flush(&mut self, rows: Vec<Row>, acks: Vec<Ack>) {
+        if rows.is_empty() {
+            acks.into_iter().for_each(Ack::confirm);
+            return;
+        }
+        let batch = match record_batch(&rows) {
+            Ok(batch) => batch,
+            Err(error) => {
+                warn!("failed to build record batch: {}", error);
+                return;
+            }
+        };
+        match self
+            .runtime
+            .block_on(write(&mut self.client, &self.config, batch))
+        {
+            Ok(()) => acks.into_iter().for_each(Ack::confirm),
+            Err(error) => {
+                warn!("dropping {} rows: {}", rows.len(), error);
+                self.client = None;
+            }
+        }
+    }
+}
+
+fn flight_writer<T: Acknowledged>(
+    rx: Receiver<T>,
+    mut writer: FlightWriter,
+    query_mapper: fn(T) -> WriteQuery,
+) {
+    let mut rows = Vec::new();
+    let mut acks = Vec::new();
+    let mut deadline = Instant::now() + writer.config.flush_interval;
+    loop {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(mut data) => {
+                super::received();
+                acks.extend(data.take_ack());
+                match query_mapper(data).build().map(|query| query.get()) {
+                    Ok(line) => match parse_line(&line) {
+                        Some(event) => rows.extend(self::rows(event, Utc::now().timestamp())),
+                        None => warn!("failed to parse event '{}'", line),
+                    },
+                    Err(error) => warn!("failed to build event: {:?}", error),
+                }
+                if rows.len() < writer.config.batch_size {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                writer.flush(mem::take(&mut rows), mem::take(&mut acks));
+                break;
+            }
+        }
+        writer.flush(mem::take(&mut rows), mem::take(&mut acks));
+        deadline = Instant::now() + writer.config.flush_interval;
+    }
+    info!("exiting flight writer");
+}
+
+pub fn spawn_flight_writer<T: Acknowledged + Send + 'static>(
+    config: FlightConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) -> Result<(SyncSender<T>, JoinHandle<()>)> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|error| GatewayError::target(format!("flight {}", config.url), error))?;
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((
+        tx,
+        thread::spawn(move || {
+            info!(
+                "starting flight writer {} with table {}",
+                config.url, config.table
+            );
+            let writer = FlightWriter {
+                config,
+                runtime,
+                client: None,
+            };
+            flight_writer(rx, writer, query_mapper);
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, MapArray};
+
+    #[test]
+    fn test_rows() {
+        let event =
+            parse_line("power,location=kitchen value=3.5,on=true,state=\"on\" 100").unwrap();
+
+        let rows = rows(event, 200);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].field, "on");
+        assert_eq!(rows[0].value, 1.0);
+        assert_eq!(rows[1].field, "value");
+        assert_eq!(rows[1].value, 3.5);
+        assert_eq!(rows[1].time, 100);
+        assert_eq!(rows[1].tags["location"], "kitchen");
+
+        let rows = self::rows(parse_line("power value=1").unwrap(), 200);
+        assert_eq!(rows[0].time, 200);
+    }
+
+    #[test]
+    fn test_record_batch() -> Result<()> {
+        let rows = rows(
+            parse_line("power,location=kitchen,phase=a value=3.5,count=2i 100").unwrap(),
+            0,
+        );
+
+        let batch = record_batch(&rows)?;
+
+        assert_eq!(batch.num_rows(), 2);
+        let schema = batch.schema();
+        let columns: Vec<&str> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(columns, ["time", "measurement", "field", "value", "tags"]);
+        let tags = batch
+            .column_by_name("tags")
+            .and_then(|tags| tags.as_any().downcast_ref::<MapArray>())
+            .unwrap();
+        assert_eq!(tags.value(0).len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use crate::data::query::{Query, WriteQuery};
+use crate::target::ack;
+use crate::target::ack::Acknowledged;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+const DEFAULT_SIZE: usize = 100;
+
+static HISTORY: LazyLock<Mutex<History>> = LazyLock::new(|| Mutex::new(History::default()));
+
+pub struct HistoryConfig {
+    size: usize,
+}
+
+impl HistoryConfig {
+    pub(crate) fn new(size: Option<usize>) -> Self {
+        Self {
+            size: size.unwrap_or(DEFAULT_SIZE).max(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Event {
+    /// Receive time in seconds since the epoch.
+    time: i64,
+    /// The event in InfluxDB line protocol.
+    line: String,
+}
+
+/// Last events per series, a series is the measurement and tags part of the line protocol.
+#[derive(Debug, Default)]
+struct History {
+    series: BTreeMap<String, VecDeque<Event>>,
+}
+
+impl History {
+    fn record(&mut self, line: String, time: i64, size: usize) {
+        let events = self.series.entry(series(&line).to_string()).or_default();
+        while events.len() >= size {
+            events.pop_front();
+        }
+        events.push_back(Event { time, line });
+    }
+
+    fn dump(&self, measurement: Option<&str>, since: i64) -> BTreeMap<&str, Vec<&Event>> {
+        self.series
+            .iter()
+            .filter(|(series, _)| {
+                measurement.is_none_or(|measurement| series.split(',').next() == Some(measurement))
+            })
+            .map(|(series, events)| {
+                (
+                    series.as_str(),
+                    events
+                        .iter()
+                        .filter(|event| event.time >= since)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .filter(|(_, events)| !events.is_empty())
+            .collect()
+    }
+}
+
+/// Measurement and tags of a line, i.e. everything up to the first unescaped space.
+fn series(line: &str) -> &str {
+    let mut escaped = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            ' ' if !escaped => return &line[..index],
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// Events seen in the last `minutes` (all if `None`) as JSON, optionally of a single measurement.
+pub fn dump(measurement: Option<&str>, minutes: Option<i64>) -> String {
+    let since = minutes
+        .map(|minutes| chrono::offset::Utc::now().timestamp() - minutes * 60)
+        .unwrap_or(i64::MIN);
+    serde_json::to_string(&HISTORY.lock().unwrap().dump(measurement, since)).unwrap()
+}
+
+fn history_writer<T: Acknowledged>(
+    rx: Receiver<T>,
+    config: HistoryConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) {
+    loop {
+        let mut data = match rx.recv() {
+            Ok(data) => {
+                super::received();
+                data
+            }
+            Err(error) => {
+                warn!("error receiving data: {:?}", error);
+                break;
+            }
+        };
+        // the history is no durable storage and does not hold back acknowledgements
+        ack::confirm(data.take_ack());
+
+        match query_mapper(data).build() {
+            Ok(query) => HISTORY.lock().unwrap().record(
+                query.get(),
+                chrono::offset::Utc::now().timestamp(),
+                config.size,
+            ),
+            Err(error) => warn!("failed to build history event: {:?}", error),
+        }
+    }
+    info!("exiting history writer");
+}
+
+pub fn spawn_history_writer<T: Acknowledged + Send + 'static>(
+    config: HistoryConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) -> crate::error::Result<(SyncSender<T>, JoinHandle<()>)> {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((
+        tx,
+        thread::spawn(move || {
+            info!("starting history writer with {} events", config.size);
+            history_writer(rx, config, query_mapper);
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series() {
+        assert_eq!(
+            series("power,location=loo-fan value=1 100"),
+            "power,location=loo-fan"
+        );
+        assert_eq!(
+            series("power,location=loo\\ fan value=1 100"),
+            "power,location=loo\\ fan"
+        );
+    }
+
+    #[test]
+    fn test_record_keeps_last_events() {
+        let mut history = History::default();
+
+        for time in [10, 20, 30] {
+            history.record(format!("power,location=loo-fan value=1 {}", time), time, 2);
+        }
+        history.record("voltage,location=loo-fan value=230 30".to_string(), 30, 2);
+
+        let dump = history.dump(None, i64::MIN);
+        assert_eq!(dump.len(), 2);
+        assert_eq!(
+            dump["power,location=loo-fan"]
+                .iter()
+                .map(|event| event.time)
+                .collect::<Vec<_>>(),
+            vec![20, 30]
+        );
+        assert_eq!(history.dump(Some("voltage"), i64::MIN).len(), 1);
+        assert_eq!(history.dump(None, 25).len(), 2);
+        assert!(history.dump(None, 31).is_empty());
+    }
+
+    #[test]
+    fn test_history_writer() {
+        fn mapper(value: f64) -> WriteQuery {
+            WriteQuery::new(crate::data::query::Timestamp::Seconds(100), "history_test")
+                .add_field("value", value)
+        }
+        let (tx, handle) = spawn_history_writer(HistoryConfig::new(Some(1)), mapper).unwrap();
+
+        tx.send(1.0).unwrap();
+        tx.send(2.0).unwrap();
+        drop(tx);
+        handle.join().unwrap();
+
+        assert_eq!(HISTORY.lock().unwrap().series["history_test"].len(), 1);
+        assert!(dump(Some("history_test"), Some(10)).starts_with("{\"history_test\":[{\"time\":"));
+    }
+}
@@ -0,0 +1,599 @@
+use async_trait::async_trait;
+//use anyhow::Result;
+use crate::config::BreakerConfig;
+use crate::data::query::{Query, WriteQuery};
+use crate::error::{GatewayError, Result};
+use crate::target::ack::{Ack, Acknowledged};
+use crate::target::breaker::Breaker;
+use futures::executor::block_on;
+use influxdb::{Client, QueryType, ValidQuery};
+use log::{error, info, warn};
+#[cfg(test)]
+use mockall::automock;
+use std::collections::HashMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Limits of the points written with a single request, large enough to write queued events
+/// quickly and below the default request size limit of InfluxDB.
+const DEFAULT_MAX_POINTS: usize = 5000;
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+
+pub struct InfluxConfig {
+    url: String,
+    database: String,
+    user: Option<String>,
+    password: Option<String>,
+    retention_policies: HashMap<String, String>,
+    buckets: HashMap<String, String>,
+    breaker: Option<BreakerConfig>,
+    max_points: usize,
+    max_bytes: usize,
+    concurrency: usize,
+}
+
+impl InfluxConfig {
+    pub fn new(
+        url: String,
+        database: String,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        Self {
+            url,
+            database,
+            user,
+            password,
+            retention_policies: HashMap::new(),
+            buckets: HashMap::new(),
+            breaker: None,
+            max_points: DEFAULT_MAX_POINTS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            concurrency: 1,
+        }
+    }
+
+    /// Writes the given measurements to a retention policy of the database, addressed as
+    /// `<database>/<retention policy>`.
+    pub fn with_retention_policies(mut self, retention_policies: HashMap<String, String>) -> Self {
+        self.retention_policies = retention_policies;
+        self
+    }
+
+    /// Writes the given measurements to another bucket (database) than the default one.
+    pub fn with_buckets(mut self, buckets: HashMap<String, String>) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Pauses the writes after repeated failures.
+    pub fn with_breaker(mut self, breaker: Option<BreakerConfig>) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Limits the number of points and the bytes of line protocol written with a single request.
+    pub fn with_batch_limits(
+        mut self,
+        max_points: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> Self {
+        self.max_points = max_points.unwrap_or(DEFAULT_MAX_POINTS).max(1);
+        self.max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+        self
+    }
+
+    /// Number of requests in flight at the same time, each writing a batch. Batches may be
+    /// written out of order with more than one.
+    pub fn with_concurrency(mut self, concurrency: Option<usize>) -> Self {
+        self.concurrency = concurrency.unwrap_or(1).max(1);
+        self
+    }
+
+    /// The database (or bucket) a measurement is written to, buckets take precedence.
+    fn database(&self, measurement: &str) -> String {
+        if let Some(bucket) = self.buckets.get(measurement) {
+            bucket.clone()
+        } else if let Some(retention_policy) = self.retention_policies.get(measurement) {
+            format!("{}/{}", self.database, retention_policy)
+        } else {
+            self.database.clone()
+        }
+    }
+
+    fn databases(&self) -> Vec<String> {
+        let mut databases: Vec<String> = self
+            .buckets
+            .keys()
+            .chain(self.retention_policies.keys())
+            .map(|measurement| self.database(measurement))
+            .chain([self.database.clone()])
+            .collect();
+        databases.sort();
+        databases.dedup();
+        databases
+    }
+}
+
+/// Measurement of a line protocol line, i.e. everything up to the first unescaped comma or space.
+fn measurement(line: &str) -> &str {
+    let mut escaped = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            ',' | ' ' if !escaped => return &line[..index],
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// Points of a database written with a single request.
+struct Batch {
+    database: String,
+    queries: Vec<WriteQuery>,
+    acks: Vec<Ack>,
+    bytes: usize,
+}
+
+/// Groups the events by database into batches within the limits of the configuration, keeping
+/// their order. A point exceeding the byte limit on its own is written alone.
+fn batches<T: Acknowledged>(
+    events: Vec<T>,
+    influx_config: &InfluxConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = Vec::new();
+    for mut event in events {
+        let ack = event.take_ack();
+        let query = query_mapper(event);
+        let (database, bytes) = match query.build() {
+            Ok(line) => {
+                let line = line.get();
+                (influx_config.database(measurement(&line)), line.len() + 1)
+            }
+            Err(_) => (influx_config.database.clone(), 0),
+        };
+        let open = batches.iter().rposition(|batch| batch.database == database);
+        let batch = match open {
+            Some(index)
+                if batches[index].queries.len() < influx_config.max_points
+                    && batches[index].bytes + bytes <= influx_config.max_bytes =>
+            {
+                &mut batches[index]
+            }
+            _ => {
+                batches.push(Batch {
+                    database,
+                    queries: Vec::new(),
+                    acks: Vec::new(),
+                    bytes: 0,
+                });
+                batches.last_mut().unwrap()
+            }
+        };
+        batch.queries.push(query);
+        batch.acks.extend(ack);
+        batch.bytes += bytes;
+    }
+    batches
+}
+
+/// Events of a batch in the form of the InfluxDB client.
+struct Lines(Vec<WriteQuery>);
+
+impl influxdb::Query for Lines {
+    fn build(&self) -> std::result::Result<ValidQuery, influxdb::Error> {
+        self.build_with_opts(false)
+    }
+
+    fn build_with_opts(&self, _use_v2: bool) -> std::result::Result<ValidQuery, influxdb::Error> {
+        let lines = self
+            .0
+            .iter()
+            .map(|query| query.build().map(|line| line.get()))
+            .collect::<Result<Vec<_>>>()
+            .map_err(|error| influxdb::Error::InvalidQueryError {
+                error: error.to_string(),
+            })?;
+        Ok(ValidQuery::from(lines.join("\n")))
+    }
+
+    fn get_type(&self) -> QueryType {
+        // all sources write timestamps in seconds, the precision of the first point applies
+        let precision = self
+            .0
+            .first()
+            .map_or("s", |query| query.timestamp().precision());
+        QueryType::WriteQuery(precision.to_string())
+    }
+}
+
+struct DefaultInfluxClient {
+    clients: HashMap<String, Client>,
+}
+
+impl DefaultInfluxClient {
+    fn new(clients: HashMap<String, Client>) -> Self {
+        DefaultInfluxClient { clients }
+    }
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+trait InfluxClient: Sync + Send {
+    async fn query(
+        &self,
+        database: &str,
+        write_queries: Vec<WriteQuery>,
+    ) -> std::result::Result<String, influxdb::Error>;
+}
+
+#[async_trait]
+impl InfluxClient for DefaultInfluxClient {
+    async fn query(
+        &self,
+        database: &str,
+        write_queries: Vec<WriteQuery>,
+    ) -> std::result::Result<String, influxdb::Error> {
+        match self.clients.get(database) {
+            Some(client) => client.query(Lines(write_queries)).await,
+            None => Err(influxdb::Error::ConnectionError {
+                error: format!("no client for database {}", database),
+            }),
+        }
+    }
+}
+
+fn create_influxdb_client(influx_config: &InfluxConfig) -> Result<Box<dyn InfluxClient>> {
+    let clients = influx_config
+        .databases()
+        .into_iter()
+        .map(|database| {
+            let influx_client = Client::new(influx_config.url.clone(), database.clone());
+            let influx_client = if let (Some(user), Some(password)) =
+                (influx_config.user.clone(), influx_config.password.clone())
+            {
+                influx_client.with_auth(user, password)
+            } else {
+                influx_client
+            };
+            (database, influx_client)
+        })
+        .collect();
+
+    Ok(Box::new(DefaultInfluxClient::new(clients)))
+}
+
+/// Writes the batches passed on by the writer until it exits. The flushers of a writer share the
+/// receiver and the breaker.
+fn flush(
+    batches: Arc<Mutex<Receiver<Batch>>>,
+    influx_client: Arc<dyn InfluxClient>,
+    breaker: Arc<Mutex<Breaker>>,
+    url: String,
+) {
+    loop {
+        let Ok(batch) = batches.lock().unwrap().recv() else {
+            break;
+        };
+        if !breaker.lock().unwrap().admit() {
+            continue;
+        }
+        let result = block_on(influx_client.query(&batch.database, batch.queries));
+        breaker.lock().unwrap().record(result.is_ok());
+        match result {
+            Ok(_) => batch.acks.into_iter().for_each(Ack::confirm),
+            Err(error) => {
+                error!(
+                    "#### Error writing to influx: {}",
+                    GatewayError::target(format!("{} {}", url, batch.database), error)
+                );
+            }
+        }
+    }
+}
+
+/// Collects the events into batches, which are written by `concurrency` flushers, so that events
+/// are still taken from the queue while requests are in flight. Up to `concurrency` batches wait
+/// for a flusher, beyond that the writer blocks.
+fn influxdb_writer<T: Acknowledged>(
+    rx: Receiver<T>,
+    influx_client: Box<dyn InfluxClient>,
+    influx_config: InfluxConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) {
+    info!(
+        "starting influx writer with {} flushers {} {}",
+        influx_config.concurrency, &influx_config.url, &influx_config.database
+    );
+    let influx_client: Arc<dyn InfluxClient> = Arc::from(influx_client);
+    let breaker = Arc::new(Mutex::new(Breaker::new(
+        format!("influxdb {}", influx_config.url),
+        influx_config.breaker.as_ref(),
+    )));
+    let (batch_tx, batch_rx) = sync_channel(influx_config.concurrency);
+    let batch_rx = Arc::new(Mutex::new(batch_rx));
+    let flushers: Vec<JoinHandle<()>> = (0..influx_config.concurrency)
+        .map(|_| {
+            let (batches, influx_client, breaker, url) = (
+                batch_rx.clone(),
+                influx_client.clone(),
+                breaker.clone(),
+                influx_config.url.clone(),
+            );
+            thread::spawn(move || flush(batches, influx_client, breaker, url))
+        })
+        .collect();
+
+    'receive: loop {
+        let result = rx.recv();
+        let mut events = match result {
+            Ok(query) => {
+                super::received();
+                vec![query]
+            }
+            Err(error) => {
+                warn!("error receiving query: {:?}", error);
+                break;
+            }
+        };
+        // events queued meanwhile are written together
+        while events.len() < influx_config.max_points {
+            let Ok(query) = rx.try_recv() else {
+                break;
+            };
+            super::received();
+            events.push(query);
+        }
+
+        for batch in batches(events, &influx_config, query_mapper) {
+            if batch_tx.send(batch).is_err() {
+                warn!("influx flushers exited");
+                break 'receive;
+            }
+        }
+    }
+    drop(batch_tx);
+    for flusher in flushers {
+        // a panicking flusher takes the writer down so that it is restarted
+        if let Err(panic) = flusher.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    info!("exiting influx writer");
+}
+
+pub fn spawn_influxdb_writer<T: Acknowledged + Send + 'static>(
+    influx_config: InfluxConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) -> Result<(SyncSender<T>, JoinHandle<()>)> {
+    let influx_client = create_influxdb_client(&influx_config)?;
+    Ok(spawn_influxdb_writer_internal(
+        influx_client,
+        influx_config,
+        query_mapper,
+    ))
+}
+
+fn spawn_influxdb_writer_internal<T: Acknowledged + Send + 'static>(
+    influx_client: Box<dyn InfluxClient>,
+    influx_config: InfluxConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) -> (SyncSender<T>, JoinHandle<()>) {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    (
+        tx,
+        thread::spawn(move || {
+            info!(
+                "starting influx writer {} {}",
+                &influx_config.url, &influx_config.database
+            );
+
+            influxdb_writer(rx, influx_client, influx_config, query_mapper)
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::query::Timestamp::Seconds;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    // A mock `WriteQuery` for testing purposes
+    fn mock_write_query(data: String) -> WriteQuery {
+        info!("mock write query {}", data);
+
+        assert_eq!(data, "test_data");
+
+        let current_timestamp = Seconds(chrono::Utc::now().timestamp() as u128);
+        WriteQuery::new(current_timestamp, "measurement")
+            .add_field("field", crate::data::query::Type::Float(1.23))
+    }
+
+    //
+    #[test]
+    fn test_influxdb_writer_internal() -> anyhow::Result<()> {
+        let influx_config = InfluxConfig::new(
+            "http://localhost:8086".to_string(),
+            "test_db".to_string(),
+            Some("user".to_string()),
+            Some("password".to_string()),
+        );
+
+        let mut mock_client = Box::new(MockInfluxClient::new());
+        mock_client
+            .expect_query()
+            .times(1)
+            .withf(|database, queries| database == "test_db" && queries.len() == 1)
+            .returning(|_, _| Ok("Success".to_string()));
+
+        // Run the `influxdb_writer` function
+        let (tx, join_handle) =
+            spawn_influxdb_writer_internal(mock_client, influx_config, mock_write_query);
+
+        // Send a test query
+        tx.send("test_data".to_string()).unwrap();
+
+        // Close the channel
+        drop(tx);
+
+        join_handle.join().expect("stopped writer");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_influxdb_writer_breaker() {
+        let influx_config = InfluxConfig::new(
+            "http://localhost:8086".to_string(),
+            "test_db".to_string(),
+            None,
+            None,
+        )
+        .with_breaker(Some(BreakerConfig {
+            failures: Some(2),
+            ..BreakerConfig::default()
+        }))
+        .with_batch_limits(Some(1), None);
+
+        // the remaining queries arrive during the cooldown and are dropped
+        let mut mock_client = Box::new(MockInfluxClient::new());
+        mock_client.expect_query().times(2).returning(|_, _| {
+            Err(influxdb::Error::ConnectionError {
+                error: "overloaded".to_string(),
+            })
+        });
+
+        let (tx, join_handle) =
+            spawn_influxdb_writer_internal(mock_client, influx_config, mock_write_query);
+        for _ in 0..5 {
+            tx.send("test_data".to_string()).unwrap();
+        }
+        drop(tx);
+
+        join_handle.join().expect("stopped writer");
+    }
+
+    /// Completes a request only once the given number of requests are in flight. The mock
+    /// client can't do this, it handles one call at a time.
+    struct BarrierClient {
+        barrier: Barrier,
+        queries: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl InfluxClient for BarrierClient {
+        async fn query(
+            &self,
+            _database: &str,
+            _write_queries: Vec<WriteQuery>,
+        ) -> std::result::Result<String, influxdb::Error> {
+            self.barrier.wait();
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            Ok("Success".to_string())
+        }
+    }
+
+    #[test]
+    fn test_influxdb_writer_concurrent_flushes() {
+        let influx_config = InfluxConfig::new(
+            "http://localhost:8086".to_string(),
+            "test_db".to_string(),
+            None,
+            None,
+        )
+        .with_batch_limits(Some(1), None)
+        .with_concurrency(Some(2));
+
+        // both requests only complete while the other one is in flight
+        let queries = Arc::new(AtomicUsize::new(0));
+        let client = Box::new(BarrierClient {
+            barrier: Barrier::new(2),
+            queries: queries.clone(),
+        });
+
+        let (tx, join_handle) =
+            spawn_influxdb_writer_internal(client, influx_config, mock_write_query);
+        tx.send("test_data".to_string()).unwrap();
+        tx.send("test_data".to_string()).unwrap();
+        drop(tx);
+
+        join_handle.join().expect("stopped writer");
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_batches() {
+        let influx_config = InfluxConfig::new(
+            "http://localhost:8086".to_string(),
+            "shelly".to_string(),
+            None,
+            None,
+        )
+        .with_buckets(HashMap::from([(
+            "energy".to_string(),
+            "shelly_energy".to_string(),
+        )]))
+        // room for two lines like `power value=1.5 1704067200\n` of 27 bytes
+        .with_batch_limits(Some(3), Some(60));
+        let query = |measurement: &str| {
+            WriteQuery::new(Seconds(1704067200), measurement).add_field("value", 1.5)
+        };
+        let events = vec![
+            query("power"),
+            query("energy"),
+            query("power"),
+            query("power"),
+            query("voltage"),
+        ];
+
+        let batches = batches(events, &influx_config, std::convert::identity);
+
+        let summary: Vec<(&str, usize)> = batches
+            .iter()
+            .map(|batch| (batch.database.as_str(), batch.queries.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("shelly", 2), ("shelly_energy", 1), ("shelly", 2)]
+        );
+        assert_eq!(batches[0].bytes, 2 * 27);
+    }
+
+    #[test]
+    fn test_database_per_measurement() {
+        let influx_config = InfluxConfig::new(
+            "http://localhost:8086".to_string(),
+            "shelly".to_string(),
+            None,
+            None,
+        )
+        .with_retention_policies(HashMap::from([("power".to_string(), "week".to_string())]))
+        .with_buckets(HashMap::from([(
+            "energy".to_string(),
+            "shelly_energy".to_string(),
+        )]));
+
+        assert_eq!(influx_config.database("power"), "shelly/week");
+        assert_eq!(influx_config.database("energy"), "shelly_energy");
+        assert_eq!(influx_config.database("voltage"), "shelly");
+        assert_eq!(
+            influx_config.databases(),
+            vec!["shelly", "shelly/week", "shelly_energy"]
+        );
+    }
+
+    #[test]
+    fn test_measurement() {
+        assert_eq!(measurement("power,location=loo-fan value=1 100"), "power");
+        assert_eq!(measurement("power value=1 100"), "power");
+        assert_eq!(measurement("active\\ power value=1 100"), "active\\ power");
+    }
+}
@@ -0,0 +1,325 @@
+use crate::config::{MeterConfig, MeterInput, MeterOperation, VirtualMetersConfig};
+use crate::data::dedup::warn_deduplicated;
+use crate::data::query::{Query, WriteQuery};
+use crate::error::{GatewayError, Result};
+use crate::source::warmup;
+use crate::target::mqtt::to_query;
+use crate::target::mqtt::{parse_line, Event, FieldValue};
+use crate::target::supervisor;
+use crate::target::Mappers;
+use crate::SensorReading;
+use log::info;
+use std::collections::BTreeMap;
+use std::sync::mpsc::SyncSender;
+use std::sync::{LazyLock, Mutex};
+use std::thread::JoinHandle;
+
+/// Seconds the latest values of the inputs of a meter may be apart.
+const DEFAULT_WINDOW: i64 = 30;
+
+impl MeterInput {
+    fn matches(&self, event: &Event) -> bool {
+        self.when.iter().all(|(key, value)| match key.as_str() {
+            "measurement" => &event.measurement == value,
+            _ => event.tags.get(key) == Some(value),
+        })
+    }
+
+    fn value(&self, event: &Event) -> Option<f64> {
+        let value = match event.fields.get(self.field.as_deref().unwrap_or("value"))? {
+            FieldValue::Float(value) => *value,
+            FieldValue::Integer(value) => *value as f64,
+            FieldValue::Boolean(_) | FieldValue::Text(_) => return None,
+        };
+        Some(value * self.factor.unwrap_or(1.0))
+    }
+}
+
+/// Latest weighted value and time of each input of a virtual meter.
+pub struct Meter {
+    config: MeterConfig,
+    latest: Vec<Option<(f64, i64)>>,
+}
+
+impl Meter {
+    pub fn new(config: MeterConfig) -> Result<Self> {
+        if config.inputs.is_empty() {
+            return Err(GatewayError::config(format!(
+                "virtual meter {} has no inputs",
+                config.measurement
+            )));
+        }
+        Ok(Meter {
+            latest: vec![None; config.inputs.len()],
+            config,
+        })
+    }
+
+    /// The event of the meter if the event updated one of its inputs and the latest values of all
+    /// inputs are within the window, at the time of the newest one.
+    pub fn update(&mut self, event: &Event) -> Option<Event> {
+        let time = event.time?;
+        let mut updated = false;
+        for (input, latest) in self.config.inputs.iter().zip(self.latest.iter_mut()) {
+            if input.matches(event) {
+                if let Some(value) = input.value(event) {
+                    *latest = Some((value, time));
+                    updated = true;
+                }
+            }
+        }
+        if !updated {
+            return None;
+        }
+
+        let latest = self.latest.iter().copied().collect::<Option<Vec<_>>>()?;
+        let first = latest.iter().map(|(_, time)| *time).min()?;
+        let last = latest.iter().map(|(_, time)| *time).max()?;
+        if last - first > self.config.window.unwrap_or(DEFAULT_WINDOW) {
+            return None;
+        }
+        let sum: f64 = latest.iter().map(|(value, _)| value).sum();
+        let value = match self.config.operation.unwrap_or_default() {
+            MeterOperation::Sum => sum,
+            MeterOperation::Average => sum / latest.len() as f64,
+        };
+        Some(Event {
+            measurement: self.config.measurement.clone(),
+            tags: self
+                .config
+                .tags
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            fields: BTreeMap::from([("value".to_string(), FieldValue::Float(value))]),
+            time: Some(last),
+        })
+    }
+}
+
+/// Events the virtual meters take their inputs from.
+pub trait Metered {
+    fn event(&self) -> Option<Event>;
+}
+
+impl Metered for WriteQuery {
+    fn event(&self) -> Option<Event> {
+        // events were validated by their source, so they always parse
+        self.build().ok().and_then(|line| parse_line(&line.get()))
+    }
+}
+
+impl Metered for SensorReading {
+    fn event(&self) -> Option<Event> {
+        let mut tags: BTreeMap<String, String> = self.tags.iter().cloned().collect();
+        tags.insert("location".to_string(), self.location.to_string());
+        tags.insert("sensor".to_string(), self.sensor.to_string());
+        Some(Event {
+            measurement: self.measurement.to_string(),
+            tags,
+            fields: BTreeMap::from([("value".to_string(), FieldValue::Float(self.value as f64))]),
+            time: Some(self.time.timestamp()),
+        })
+    }
+}
+
+/// The virtual meters of the gateway, updated by the events of all sources.
+struct MeterStage {
+    meters: Vec<Meter>,
+    txs: Vec<SyncSender<WriteQuery>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl MeterStage {
+    /// Queries of the meters updated by the event.
+    fn update(&mut self, event: &Event) -> Vec<WriteQuery> {
+        self.meters
+            .iter_mut()
+            .filter_map(|meter| meter.update(event))
+            .map(to_query)
+            .collect()
+    }
+}
+
+static STAGE: LazyLock<Mutex<Option<MeterStage>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Updates the virtual meters with the events of all sources and writes their events to the
+/// given targets.
+pub fn enable(config: &VirtualMetersConfig) -> Result<()> {
+    let meters = config
+        .meters
+        .iter()
+        .cloned()
+        .map(Meter::new)
+        .collect::<Result<Vec<_>>>()?;
+    let mut txs = Vec::new();
+    let mut handles = Vec::new();
+    for target in &config.targets {
+        let target = target.clone();
+        let (tx, handle) = supervisor::supervise(target.name(), move || {
+            super::spawn_writer(target.clone(), &Mappers::queries("virtual meters"))
+        })?;
+        txs.push(tx);
+        handles.push(handle);
+    }
+    info!("updating {} virtual meters", meters.len());
+    *STAGE.lock().unwrap() = Some(MeterStage {
+        meters,
+        txs,
+        handles,
+    });
+    Ok(())
+}
+
+/// Updates the virtual meters with an event of a source, does nothing unless enabled.
+pub fn observe<T: Metered>(data: &T) {
+    if warmup::is_warming_up() {
+        return;
+    }
+    let mut stage = STAGE.lock().unwrap();
+    let Some(stage) = stage.as_mut() else {
+        return;
+    };
+    let Some(event) = data.event() else {
+        return;
+    };
+    for query in stage.update(&event) {
+        for tx in &stage.txs {
+            // the events of the meters carry only their configured tags, nothing to redact
+            if super::enqueue(tx, query.clone()).is_err() {
+                warn_deduplicated("dropping events", "a virtual meter writer stopped");
+            }
+        }
+    }
+}
+
+/// Stops the writers of the virtual meters.
+pub fn close() {
+    let Some(stage) = STAGE.lock().unwrap().take() else {
+        return;
+    };
+    drop(stage.txs);
+    for handle in stage.handles {
+        let _ = handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn input(location: &str, factor: Option<f64>) -> MeterInput {
+        MeterInput {
+            when: HashMap::from([
+                ("measurement".to_string(), "power".to_string()),
+                ("location".to_string(), location.to_string()),
+            ]),
+            field: None,
+            factor,
+        }
+    }
+
+    fn house(operation: Option<MeterOperation>) -> Meter {
+        Meter::new(MeterConfig {
+            measurement: "house_power".to_string(),
+            tags: Some(HashMap::from([(
+                "location".to_string(),
+                "house".to_string(),
+            )])),
+            operation,
+            window: None,
+            inputs: vec![input("mains", None), input("pv", Some(-1.0))],
+        })
+        .unwrap()
+    }
+
+    fn power(location: &str, value: f64, time: i64) -> Event {
+        Event {
+            measurement: "power".to_string(),
+            tags: BTreeMap::from([("location".to_string(), location.to_string())]),
+            fields: BTreeMap::from([("value".to_string(), FieldValue::Float(value))]),
+            time: Some(time),
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut meter = house(None);
+
+        assert_eq!(meter.update(&power("mains", 1500.0, 1000)), None);
+        assert_eq!(meter.update(&power("garage", 20.0, 1005)), None);
+        let event = meter.update(&power("pv", 400.0, 1010)).unwrap();
+        assert_eq!(
+            to_query(event).build().unwrap().get(),
+            "house_power,location=house value=1100 1010"
+        );
+
+        // the mains value is outdated
+        assert_eq!(meter.update(&power("pv", 500.0, 1040)), None);
+        let event = meter.update(&power("mains", 1200.0, 1045)).unwrap();
+        assert_eq!(event.fields["value"], FieldValue::Float(700.0));
+        assert_eq!(event.time, Some(1045));
+    }
+
+    #[test]
+    fn test_average() {
+        let mut meter = house(Some(MeterOperation::Average));
+
+        meter.update(&power("mains", 1500.0, 1000));
+        let event = meter.update(&power("pv", 500.0, 1000)).unwrap();
+
+        assert_eq!(event.fields["value"], FieldValue::Float(500.0));
+    }
+
+    #[test]
+    fn test_stage_update() {
+        let mut stage = MeterStage {
+            meters: vec![house(None)],
+            txs: Vec::new(),
+            handles: Vec::new(),
+        };
+
+        assert!(stage.update(&power("mains", 1500.0, 1000)).is_empty());
+        let queries = stage.update(&power("pv", 400.0, 1010));
+        assert_eq!(
+            queries[0].build().unwrap().get(),
+            "house_power,location=house value=1100 1010"
+        );
+    }
+
+    #[test]
+    fn test_sensor_reading_event() {
+        let reading = SensorReading {
+            measurement: "power".into(),
+            time: chrono::DateTime::from_timestamp(1000, 0).unwrap(),
+            location: "mains".into(),
+            sensor: "meter".into(),
+            value: 1500.0,
+            tags: Vec::new(),
+            ack: None,
+        };
+
+        assert_eq!(
+            reading.event(),
+            Some(power("mains", 1500.0, 1000)).map(|event| Event {
+                tags: BTreeMap::from([
+                    ("location".to_string(), "mains".to_string()),
+                    ("sensor".to_string(), "meter".to_string()),
+                ]),
+                ..event
+            })
+        );
+    }
+
+    #[test]
+    fn test_without_inputs() {
+        let config = MeterConfig {
+            inputs: vec![],
+            ..house(None).config
+        };
+
+        assert!(Meter::new(config).is_err());
+    }
+}
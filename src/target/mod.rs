@@ -0,0 +1,639 @@
+pub(crate) mod ack;
+#[cfg(any(feature = "influx", feature = "postgres"))]
+pub(crate) mod breaker;
+#[cfg(feature = "flight")]
+pub(crate) mod flight;
+pub(crate) mod history;
+#[cfg(feature = "influx")]
+pub(crate) mod influx;
+pub(crate) mod meter;
+pub(crate) mod mqtt;
+#[cfg(feature = "notification")]
+pub(crate) mod notification;
+pub(crate) mod null;
+#[cfg(feature = "postgres")]
+pub(crate) mod postgres;
+#[cfg(feature = "redis")]
+pub(crate) mod redis;
+pub(crate) mod route;
+pub(crate) mod supervisor;
+#[cfg(feature = "wasm")]
+pub(crate) mod wasm;
+
+use crate::config::Target;
+use crate::data::dedup::warn_deduplicated;
+use crate::data::enrichment::labels;
+use crate::data::query::{Query, WriteQuery};
+use crate::data::redact;
+use crate::data::redact::Redact;
+use crate::error::{GatewayError, Result};
+use crate::source::warmup;
+use crate::target::ack::Acknowledged;
+#[cfg(feature = "flight")]
+use crate::target::flight::FlightConfig;
+use crate::target::history::HistoryConfig;
+#[cfg(feature = "influx")]
+use crate::target::influx::InfluxConfig;
+use crate::target::meter::Metered;
+use crate::target::mqtt::MqttConfig;
+#[cfg(feature = "notification")]
+use crate::target::notification::{NotificationConfig, NotificationService};
+use crate::target::null::NullConfig;
+#[cfg(feature = "postgres")]
+use crate::target::postgres::PostgresConfig;
+#[cfg(feature = "redis")]
+use crate::target::redis::RedisConfig;
+use crate::target::route::{Route, Tagged};
+#[cfg(feature = "wasm")]
+use crate::target::wasm::WasmConfig;
+use crate::SensorReading;
+use std::cell::Cell;
+use std::fmt::Write;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{SendError, SyncSender};
+use std::thread::JoinHandle;
+#[cfg(feature = "postgres")]
+use std::time::Duration;
+
+type Writer<T> = (SyncSender<T>, JoinHandle<()>);
+type ItemMapper<T> = fn(T) -> Vec<(String, String)>;
+/// Spawns a writer from its configuration.
+#[cfg(feature = "postgres")]
+type Spawn<C, T> = fn(C) -> Result<Writer<T>>;
+/// Spawns a writer from its configuration passing on to another writer.
+#[cfg(feature = "wasm")]
+type SpawnWrapping<C, T> = fn(C, Writer<T>) -> Result<Writer<T>>;
+
+const DEFAULT_QUEUE_SIZE: usize = 100;
+
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+static QUEUED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static QUEUE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_QUEUE_SIZE);
+static MEMORY_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static SHED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static QUEUE_SIZE_OVERRIDE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Sets the capacity of the queues of writers spawned afterwards.
+pub fn set_queue_size(size: usize) {
+    QUEUE_SIZE.store(size.max(1), Ordering::Relaxed);
+}
+
+/// Capacity of a writer queue.
+pub fn queue_size() -> usize {
+    QUEUE_SIZE_OVERRIDE
+        .get()
+        .unwrap_or_else(|| QUEUE_SIZE.load(Ordering::Relaxed))
+}
+
+/// Spawns writers with the given queue capacity on the current thread, e.g. 0 to hand events
+/// over directly.
+pub fn with_queue_size<R>(size: usize, spawn: impl FnOnce() -> R) -> R {
+    QUEUE_SIZE_OVERRIDE.set(Some(size));
+    let result = spawn();
+    QUEUE_SIZE_OVERRIDE.set(None);
+    result
+}
+
+/// Approximate number of bytes an event occupies while waiting in a writer queue.
+pub trait Footprint {
+    fn footprint(&self) -> usize;
+}
+
+impl Footprint for WriteQuery {
+    fn footprint(&self) -> usize {
+        size_of::<WriteQuery>() + self.build().map_or(0, |line| line.get().len())
+    }
+}
+
+impl Footprint for SensorReading {
+    fn footprint(&self) -> usize {
+        size_of::<SensorReading>()
+            + self.measurement.len()
+            + self.location.len()
+            + self.sensor.len()
+            + self
+                .tags
+                .iter()
+                .map(|(key, value)| size_of::<(String, String)>() + key.len() + value.len())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+impl Footprint for String {
+    fn footprint(&self) -> usize {
+        size_of::<String>() + self.len()
+    }
+}
+
+#[cfg(test)]
+impl Footprint for f64 {
+    fn footprint(&self) -> usize {
+        size_of::<f64>()
+    }
+}
+
+/// Sets the bytes of queued events above which further events are shed instead of queued.
+pub fn set_memory_limit(bytes: usize) {
+    MEMORY_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Adds the bytes to the queued bytes unless that exceeds the limit.
+fn reserve(queued_bytes: &AtomicUsize, bytes: usize, limit: usize) -> bool {
+    queued_bytes
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+            Some(queued + bytes).filter(|queued| *queued <= limit)
+        })
+        .is_ok()
+}
+
+/// Sends data to a writer queue after applying the redaction, counting it as queued until the
+/// writer received it. Waits for room in the queue, the consumer pauses reading from the broker
+/// while too many events are queued. Data is dropped only if the queued events of all writers
+/// would exceed the memory limit, withholding its acknowledgement. Data of retained messages
+/// warming up a source is dropped as well, it was written before the restart.
+pub fn send<T: Redact + Footprint>(
+    tx: &SyncSender<T>,
+    data: T,
+) -> std::result::Result<(), SendError<T>> {
+    if warmup::is_warming_up() {
+        return Ok(());
+    }
+    let data = redact::apply(data);
+    let Some(bytes) = admit(&data) else {
+        return Ok(());
+    };
+    tx.send(data).inspect_err(|_| release(bytes))
+}
+
+/// Sends data to each of the writer queues like [`send`], a writer which stopped is logged and
+/// skipped so the others still receive the data. The data also updates the virtual meters.
+pub fn send_all<T: Redact + Footprint + Clone + Metered>(txs: &[SyncSender<T>], data: &T) {
+    meter::observe(data);
+    for tx in txs {
+        if send(tx, data.clone()).is_err() {
+            warn_deduplicated("dropping events", "a writer stopped");
+        }
+    }
+}
+
+/// Sends data to a writer queue like [`send`] without applying the redaction, e.g. for events
+/// derived from redacted ones by a writer.
+pub fn enqueue<T: Footprint>(tx: &SyncSender<T>, data: T) -> std::result::Result<(), SendError<T>> {
+    let Some(bytes) = admit(&data) else {
+        return Ok(());
+    };
+    tx.send(data).inspect_err(|_| release(bytes))
+}
+
+/// Counts the data as queued, returns its footprint unless it exceeds the memory limit.
+fn admit<T: Footprint>(data: &T) -> Option<usize> {
+    let bytes = data.footprint();
+    let limit = MEMORY_LIMIT.load(Ordering::Relaxed);
+    if !reserve(&QUEUED_BYTES, bytes, limit) {
+        SHED.fetch_add(1, Ordering::Relaxed);
+        warn_deduplicated(
+            "shedding events",
+            &format!("queued events exceed the memory limit of {} bytes", limit),
+        );
+        return None;
+    }
+    QUEUED.fetch_add(1, Ordering::Relaxed);
+    Some(bytes)
+}
+
+/// Takes back data counted as queued which didn't make it into a queue.
+fn release(bytes: usize) {
+    let _ = QUEUED.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+        queued.checked_sub(1)
+    });
+    let _ = QUEUED_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+        Some(queued.saturating_sub(bytes))
+    });
+}
+
+/// Share of the queued bytes released by one of the queued items, all of them for the last one.
+fn released(queued_bytes: usize, queued: usize) -> usize {
+    queued_bytes / queued.max(1)
+}
+
+/// Marks an item as taken from a writer queue. Sizing an event again would mean building its line
+/// a second time, so each item releases an equal share of the queued bytes instead.
+pub fn received() {
+    let Ok(queued) = QUEUED.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+        queued.checked_sub(1)
+    }) else {
+        return;
+    };
+    let _ = QUEUED_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued_bytes| {
+        Some(queued_bytes - released(queued_bytes, queued))
+    });
+}
+
+/// Number of items waiting in all writer queues.
+pub fn queued() -> usize {
+    QUEUED.load(Ordering::Relaxed)
+}
+
+/// Approximate bytes of the items waiting in all writer queues.
+pub fn queued_bytes() -> usize {
+    QUEUED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Number of events dropped because of the memory limit.
+pub fn shed() -> u64 {
+    SHED.load(Ordering::Relaxed)
+}
+
+/// The queue metrics in the Prometheus text format.
+pub fn metrics() -> String {
+    let mut metrics = String::new();
+    writeln!(
+        metrics,
+        "# HELP mqtt_gateway_queued_events Events waiting in the writer queues.\n\
+         # TYPE mqtt_gateway_queued_events gauge\n\
+         mqtt_gateway_queued_events{labels} {}\n\
+         # HELP mqtt_gateway_queued_bytes Approximate bytes of the events in the writer queues.\n\
+         # TYPE mqtt_gateway_queued_bytes gauge\n\
+         mqtt_gateway_queued_bytes{labels} {}\n\
+         # HELP mqtt_gateway_shed_events_total Events dropped because of the queue memory limit.\n\
+         # TYPE mqtt_gateway_shed_events_total counter\n\
+         mqtt_gateway_shed_events_total{labels} {}",
+        queued(),
+        queued_bytes(),
+        shed(),
+        labels = labels(&[])
+    )
+    .unwrap();
+    metrics
+}
+
+/// How the events of a source are written by the writers spawned for its targets. Targets
+/// without a mapper for the events are rejected for the source.
+pub struct Mappers<T> {
+    /// Source type named in the errors for unsupported targets.
+    source: &'static str,
+    query: fn(T) -> WriteQuery,
+    items: Option<ItemMapper<T>>,
+    #[cfg(feature = "postgres")]
+    postgres: Option<Spawn<PostgresConfig, T>>,
+    #[cfg(feature = "wasm")]
+    wasm: Option<SpawnWrapping<WasmConfig, T>>,
+}
+
+impl<T> Mappers<T> {
+    pub fn new(source: &'static str, query: fn(T) -> WriteQuery) -> Self {
+        Mappers {
+            source,
+            query,
+            items: None,
+            #[cfg(feature = "postgres")]
+            postgres: None,
+            #[cfg(feature = "wasm")]
+            wasm: None,
+        }
+    }
+
+    /// Writes the events as key value pairs to Redis and the notification targets.
+    #[cfg(feature = "klimalogger")]
+    pub fn with_items(mut self, items: ItemMapper<T>) -> Self {
+        self.items = Some(items);
+        self
+    }
+
+    #[cfg(feature = "postgres")]
+    pub fn with_postgres(mut self, spawn: Spawn<PostgresConfig, T>) -> Self {
+        self.postgres = Some(spawn);
+        self
+    }
+
+    fn unsupported(&self, target: &str) -> GatewayError {
+        GatewayError::config(format!("{} not supported for {}", target, self.source))
+    }
+}
+
+impl Mappers<WriteQuery> {
+    /// Mappers of sources creating the queries themselves, which supports the Wasm transforms.
+    /// Redis and the notification targets get the tags and fields as items.
+    pub fn queries(source: &'static str) -> Self {
+        Mappers {
+            #[cfg(feature = "wasm")]
+            wasm: Some(wasm::spawn_wasm_writer),
+            items: Some(mqtt::to_items),
+            ..Mappers::new(source, std::convert::identity)
+        }
+    }
+}
+
+/// Spawns the writer for a target configuration, nested targets are spawned recursively.
+pub fn spawn_writer<T: Tagged + Acknowledged + Send + 'static>(
+    target: Target,
+    mappers: &Mappers<T>,
+) -> Result<Writer<T>> {
+    match target {
+        #[cfg(feature = "influx")]
+        Target::InfluxDB {
+            url,
+            database,
+            user,
+            password,
+            retention_policies,
+            buckets,
+            breaker,
+            max_points,
+            max_bytes,
+            concurrency,
+        } => influx::spawn_influxdb_writer(
+            InfluxConfig::new(url, database, user, password)
+                .with_retention_policies(retention_policies.unwrap_or_default())
+                .with_buckets(buckets.unwrap_or_default())
+                .with_breaker(breaker)
+                .with_batch_limits(max_points, max_bytes)
+                .with_concurrency(concurrency),
+            mappers.query,
+        ),
+        #[cfg(not(feature = "influx"))]
+        Target::InfluxDB { .. } => Err(GatewayError::config(
+            "InfluxDB support not built, enable the influx feature",
+        )),
+        #[cfg(feature = "flight")]
+        Target::Flight {
+            url,
+            table,
+            batch_size,
+            flush_interval,
+        } => flight::spawn_flight_writer(
+            FlightConfig::new(url, table, batch_size, flush_interval),
+            mappers.query,
+        ),
+        #[cfg(not(feature = "flight"))]
+        Target::Flight { .. } => Err(GatewayError::config(
+            "Flight support not built, enable the flight feature",
+        )),
+        Target::Null { report_interval } => {
+            null::spawn_null_writer(NullConfig::new(report_interval))
+        }
+        Target::Route {
+            when,
+            unless,
+            target,
+        } => route::spawn_route_writer(Route::new(when, unless), spawn_writer(*target, mappers)?),
+        #[cfg(feature = "wasm")]
+        Target::Wasm {
+            module,
+            fuel,
+            memory_limit,
+            target,
+        } => match mappers.wasm {
+            Some(spawn) => spawn(
+                WasmConfig::new(module, fuel, memory_limit),
+                spawn_writer(*target, mappers)?,
+            ),
+            None => Err(mappers.unsupported("Wasm")),
+        },
+        #[cfg(not(feature = "wasm"))]
+        Target::Wasm { .. } => Err(GatewayError::config(
+            "Wasm support not built, enable the wasm feature",
+        )),
+        Target::History { size } => {
+            history::spawn_history_writer(HistoryConfig::new(size), mappers.query)
+        }
+        Target::Mqtt {
+            url,
+            client_id,
+            topic,
+            format,
+            qos,
+        } => mqtt::spawn_mqtt_writer(
+            MqttConfig::new(url, client_id, topic, format, qos),
+            mappers.query,
+        ),
+        #[cfg(feature = "postgres")]
+        Target::Postgresql {
+            host,
+            port,
+            user,
+            password,
+            database,
+            summary_window,
+            summary_fields,
+            low_latency,
+            notify,
+            breaker,
+        } => match mappers.postgres {
+            Some(spawn) => spawn(
+                PostgresConfig::new(host, port, user, password, database)
+                    .with_summary_window(summary_window.map(Duration::from_secs))
+                    .with_summary_fields(summary_fields.unwrap_or_default())
+                    .with_low_latency(low_latency.unwrap_or_default())
+                    .with_notify(notify)
+                    .with_breaker(breaker),
+            ),
+            None => Err(mappers.unsupported("Postgresql")),
+        },
+        #[cfg(not(feature = "postgres"))]
+        Target::Postgresql { .. } => Err(GatewayError::config(
+            "Postgresql support not built, enable the postgres feature",
+        )),
+        #[cfg(feature = "redis")]
+        Target::Redis {
+            url,
+            stream,
+            max_length,
+        } => match mappers.items {
+            Some(items) => {
+                redis::spawn_redis_writer(RedisConfig::new(url, stream, max_length), items)
+            }
+            None => Err(mappers.unsupported("Redis")),
+        },
+        #[cfg(not(feature = "redis"))]
+        Target::Redis { .. } => Err(GatewayError::config(
+            "Redis support not built, enable the redis feature",
+        )),
+        #[cfg(feature = "notification")]
+        Target::Telegram {
+            token,
+            chat_id,
+            template,
+            min_interval,
+        } => spawn_notification_writer(
+            NotificationService::Telegram { token, chat_id },
+            template,
+            min_interval,
+            mappers,
+        ),
+        #[cfg(feature = "notification")]
+        Target::Pushover {
+            token,
+            user,
+            template,
+            min_interval,
+        } => spawn_notification_writer(
+            NotificationService::Pushover { token, user },
+            template,
+            min_interval,
+            mappers,
+        ),
+        #[cfg(feature = "notification")]
+        Target::Smtp {
+            host,
+            port,
+            user,
+            password,
+            from,
+            to,
+            template,
+            min_interval,
+        } => spawn_notification_writer(
+            NotificationService::Smtp {
+                host,
+                port,
+                user,
+                password,
+                from,
+                to,
+            },
+            template,
+            min_interval,
+            mappers,
+        ),
+        #[cfg(not(feature = "notification"))]
+        Target::Telegram { .. } | Target::Pushover { .. } | Target::Smtp { .. } => Err(
+            GatewayError::config("Notification support not built, enable the notification feature"),
+        ),
+    }
+}
+
+#[cfg(feature = "notification")]
+fn spawn_notification_writer<T: Acknowledged + Send + 'static>(
+    service: NotificationService,
+    template: Option<String>,
+    min_interval: Option<u64>,
+    mappers: &Mappers<T>,
+) -> Result<Writer<T>> {
+    match mappers.items {
+        Some(items) => notification::spawn_notification_writer(
+            NotificationConfig::new(service, template, min_interval),
+            items,
+        ),
+        None => Err(mappers.unsupported("Notifications")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::query::Timestamp;
+
+    #[test]
+    fn test_reserve() {
+        let queued_bytes = AtomicUsize::new(0);
+
+        assert!(reserve(&queued_bytes, 60, 100));
+        assert!(!reserve(&queued_bytes, 60, 100));
+        assert!(reserve(&queued_bytes, 40, 100));
+        assert_eq!(queued_bytes.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn test_released() {
+        assert_eq!(released(100, 4), 25);
+        assert_eq!(released(100, 1), 100);
+        assert_eq!(released(0, 0), 0);
+    }
+
+    #[test]
+    fn test_send_waits_for_room() {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let query =
+            || WriteQuery::new(Timestamp::Seconds(1701271852), "power").add_field("value", 1);
+
+        assert!(send(&tx, query()).is_ok());
+        let receiver = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            rx.iter().take(2).count()
+        });
+        // waits for the receiver instead of dropping the query
+        assert!(send(&tx, query()).is_ok());
+        assert_eq!(receiver.join().unwrap(), 2);
+
+        assert!(send(&tx, query()).is_err());
+    }
+
+    #[test]
+    fn test_send_all_skips_stopped_writer() {
+        let (stopped, _) = std::sync::mpsc::sync_channel(1);
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let query = WriteQuery::new(Timestamp::Seconds(1701271852), "power").add_field("value", 1);
+
+        send_all(&[stopped, tx], &query);
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_spawn_unsupported_writer() {
+        let postgresql = || Target::Postgresql {
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "gateway".to_string(),
+            password: "secret".to_string(),
+            database: "sensors".to_string(),
+            summary_window: None,
+            summary_fields: None,
+            low_latency: None,
+            notify: None,
+            breaker: None,
+        };
+
+        let result = spawn_writer(postgresql(), &Mappers::queries("shelly"));
+        assert_eq!(
+            result.err().map(|error| error.to_string()),
+            Some("configuration error: Postgresql not supported for shelly".to_string())
+        );
+
+        let result = spawn_writer(
+            Target::Route {
+                when: None,
+                unless: None,
+                target: Box::new(postgresql()),
+            },
+            &Mappers::queries("zwave"),
+        );
+        assert_eq!(
+            result.err().map(|error| error.to_string()),
+            Some("configuration error: Postgresql not supported for zwave".to_string())
+        );
+    }
+
+    #[cfg(feature = "notification")]
+    #[test]
+    fn test_spawn_notification_writer_for_queries() {
+        let target = Target::Telegram {
+            token: "token".to_string(),
+            chat_id: "chat".to_string(),
+            template: None,
+            min_interval: None,
+        };
+
+        let (tx, join_handle) = spawn_writer(target, &Mappers::queries("shelly")).unwrap();
+        drop(tx);
+        join_handle.join().expect("stopped writer");
+    }
+
+    #[test]
+    fn test_footprint() {
+        let query = WriteQuery::new(Timestamp::Seconds(1701271852), "power")
+            .add_tag("location", "kitchen")
+            .add_field("value", 12.5);
+
+        assert_eq!(
+            query.footprint(),
+            size_of::<WriteQuery>() + "power,location=kitchen value=12.5 1701271852".len()
+        );
+    }
+}
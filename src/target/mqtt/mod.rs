@@ -0,0 +1,418 @@
+use crate::config::PayloadFormat;
+use crate::data::query::{Query, WriteQuery};
+use crate::error::{GatewayError, Result};
+use crate::source::connection;
+use crate::source::loopback;
+use crate::target::ack;
+use crate::target::ack::Acknowledged;
+use futures::executor::block_on;
+use log::{info, warn};
+use paho_mqtt as mqtt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const DEFAULT_CLIENT_ID: &str = "mqtt-gateway-republish";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct MqttConfig {
+    url: String,
+    client_id: String,
+    topic: String,
+    format: PayloadFormat,
+    qos: i32,
+}
+
+impl MqttConfig {
+    pub(crate) fn new(
+        url: String,
+        client_id: Option<String>,
+        topic: String,
+        format: Option<PayloadFormat>,
+        qos: Option<i32>,
+    ) -> Self {
+        Self {
+            url,
+            client_id: client_id.unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string()),
+            topic,
+            format: format.unwrap_or_default(),
+            qos: qos.unwrap_or(mqtt::QOS_1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum FieldValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Boolean(value) => write!(f, "{}", value),
+            FieldValue::Integer(value) => write!(f, "{}", value),
+            FieldValue::Float(value) => write!(f, "{}", value),
+            FieldValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A normalized event as republished in the JSON and MessagePack formats.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Event {
+    pub(crate) measurement: String,
+    pub(crate) tags: BTreeMap<String, String>,
+    pub(crate) fields: BTreeMap<String, FieldValue>,
+    /// Seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) time: Option<i64>,
+}
+
+/// Splits at every `separator` which is not escaped by a backslash, with `quotes` neither inside
+/// double quotes, which only enclose string field values.
+fn split_unescaped(text: &str, separator: char, quotes: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+    for (index, character) in text.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if quotes => quoted = !quoted,
+            _ if character == separator && !quoted => {
+                parts.push(&text[start..index]);
+                start = index + separator.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut escaped = false;
+    for character in text.chars() {
+        if character == '\\' && !escaped {
+            escaped = true;
+        } else {
+            escaped = false;
+            unescaped.push(character);
+        }
+    }
+    unescaped
+}
+
+fn parse_value(value: &str) -> Option<FieldValue> {
+    if let Some(text) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return Some(FieldValue::Text(unescape(text)));
+    }
+    if let Some(integer) = value
+        .strip_suffix('i')
+        .or_else(|| value.strip_suffix('u'))
+        .and_then(|integer| integer.parse().ok())
+    {
+        return Some(FieldValue::Integer(integer));
+    }
+    match value {
+        "true" | "t" | "T" | "True" | "TRUE" => Some(FieldValue::Boolean(true)),
+        "false" | "f" | "F" | "False" | "FALSE" => Some(FieldValue::Boolean(false)),
+        _ => value.parse().ok().map(FieldValue::Float),
+    }
+}
+
+/// Splits at the first unescaped `=`, keys never contain one.
+fn key_value(pair: &str) -> Option<(String, &str)> {
+    let key = split_unescaped(pair, '=', false)[0];
+    Some((unescape(key), pair.get(key.len() + 1..)?))
+}
+
+/// Parses a line of InfluxDB line protocol with a timestamp in seconds.
+pub(crate) fn parse_line(line: &str) -> Option<Event> {
+    let line = line.trim_end();
+    let series = split_unescaped(line, ' ', false)[0];
+    let (fields, time) = match split_unescaped(line.get(series.len() + 1..)?, ' ', true).as_slice()
+    {
+        [fields] => (*fields, None),
+        [fields, time] => (*fields, Some(time.parse().ok()?)),
+        _ => return None,
+    };
+    let mut series = split_unescaped(series, ',', false).into_iter();
+    let measurement = unescape(series.next()?);
+    let tags = series
+        .map(|tag| key_value(tag).map(|(key, value)| (key, unescape(value))))
+        .collect::<Option<_>>()?;
+    let fields = split_unescaped(fields, ',', true)
+        .into_iter()
+        .map(|field| key_value(field).and_then(|(key, value)| Some((key, parse_value(value)?))))
+        .collect::<Option<_>>()?;
+    Some(Event {
+        measurement,
+        tags,
+        fields,
+        time,
+    })
+}
+
+/// The query of an event, events without time get the current time.
+pub(crate) fn to_query(event: Event) -> WriteQuery {
+    let time = event
+        .time
+        .unwrap_or_else(|| chrono::offset::Utc::now().timestamp());
+    let mut query = WriteQuery::new(
+        crate::data::query::Timestamp::Seconds(time as u128),
+        event.measurement,
+    );
+    for (key, value) in event.tags {
+        query = query.add_tag(key, value);
+    }
+    for (key, value) in event.fields {
+        query = match value {
+            FieldValue::Boolean(value) => query.add_field(key, value),
+            FieldValue::Integer(value) => query.add_field(key, value),
+            FieldValue::Float(value) => query.add_field(key, value),
+            FieldValue::Text(value) => query.add_field(key, value),
+        };
+    }
+    query
+}
+
+/// Key value pairs of a query like the items of a sensor reading, `measurement` and `time`
+/// followed by its tags and fields.
+pub(crate) fn to_items(query: WriteQuery) -> Vec<(String, String)> {
+    // events were validated by their source, so they always parse
+    let Some(event) = query.build().ok().and_then(|line| parse_line(&line.get())) else {
+        return Vec::new();
+    };
+    let mut items = vec![("measurement".to_string(), event.measurement)];
+    if let Some(time) = event
+        .time
+        .and_then(|time| chrono::DateTime::from_timestamp(time, 0))
+    {
+        items.push(("time".to_string(), time.to_rfc3339()));
+    }
+    items.extend(event.tags);
+    items.extend(
+        event
+            .fields
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string())),
+    );
+    items
+}
+
+/// Topic of an event, `{measurement}` and `{<tag>}` placeholders are replaced by its values.
+fn topic(template: &str, event: &Event) -> String {
+    event.tags.iter().fold(
+        template.replace("{measurement}", &event.measurement),
+        |topic, (tag, value)| topic.replace(&format!("{{{}}}", tag), value),
+    )
+}
+
+fn encode(format: PayloadFormat, line: String, event: &Event) -> Result<Vec<u8>> {
+    Ok(match format {
+        PayloadFormat::Json => serde_json::to_vec(event)?,
+        PayloadFormat::LineProtocol => line.into_bytes(),
+        #[cfg(feature = "msgpack")]
+        PayloadFormat::MessagePack => rmp_serde::to_vec_named(event)
+            .map_err(|error| GatewayError::parse("messagepack", error))?,
+        #[cfg(not(feature = "msgpack"))]
+        PayloadFormat::MessagePack => unreachable!("rejected when spawning the writer"),
+    })
+}
+
+/// Connects to the broker, retrying until it is reachable, and reconnects automatically later.
+fn connect(client: &mqtt::AsyncClient, url: &str) {
+    let conn_opts = mqtt::ConnectOptionsBuilder::new_v5()
+        .automatic_reconnect(RECONNECT_INTERVAL, Duration::from_secs(60))
+        .finalize();
+    loop {
+        match block_on(client.connect(conn_opts.clone())) {
+            Ok(response) => {
+                connection::log_connack(&format!("mqtt {}", url), &response);
+                break;
+            }
+            Err(error) => {
+                warn!("failed to connect to {}: {}", url, error);
+                connection::log_failure(&format!("mqtt {}", url), &error);
+                thread::sleep(RECONNECT_INTERVAL);
+            }
+        }
+    }
+}
+
+fn mqtt_writer<T: Acknowledged>(
+    rx: Receiver<T>,
+    client: mqtt::AsyncClient,
+    config: MqttConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) {
+    connect(&client, &config.url);
+    loop {
+        let mut data = match rx.recv() {
+            Ok(data) => {
+                super::received();
+                data
+            }
+            Err(error) => {
+                warn!("error receiving data: {:?}", error);
+                break;
+            }
+        };
+
+        let ack = data.take_ack();
+        let line = match query_mapper(data).build() {
+            Ok(query) => query.get(),
+            Err(error) => {
+                warn!("failed to build event: {:?}", error);
+                continue;
+            }
+        };
+        let Some(event) = parse_line(&line) else {
+            warn!("failed to parse event '{}'", line);
+            continue;
+        };
+        let topic = topic(&config.topic, &event);
+        match encode(config.format, line, &event) {
+            Ok(payload) => {
+                // marked so that sources of this gateway recognize it if it loops back
+                let message = mqtt::MessageBuilder::new()
+                    .topic(topic)
+                    .payload(payload)
+                    .qos(config.qos)
+                    .properties(loopback::properties())
+                    .finalize();
+                match block_on(client.publish(message)) {
+                    Ok(_) => ack::confirm(ack),
+                    Err(error) => warn!("failed to republish event: {}", error),
+                }
+            }
+            Err(error) => warn!("failed to encode event: {}", error),
+        }
+    }
+    let _ = block_on(client.disconnect(None::<mqtt::DisconnectOptions>));
+    info!("exiting mqtt writer");
+}
+
+pub fn spawn_mqtt_writer<T: Acknowledged + Send + 'static>(
+    config: MqttConfig,
+    query_mapper: fn(T) -> WriteQuery,
+) -> Result<(SyncSender<T>, JoinHandle<()>)> {
+    #[cfg(not(feature = "msgpack"))]
+    if config.format == PayloadFormat::MessagePack {
+        return Err(GatewayError::config(
+            "MessagePack support not built, enable the msgpack feature",
+        ));
+    }
+    let create_opts = mqtt::CreateOptionsBuilder::new()
+        .mqtt_version(mqtt::MQTT_VERSION_5)
+        .server_uri(config.url.clone())
+        .client_id(config.client_id.clone())
+        .finalize();
+    let client = mqtt::AsyncClient::new(create_opts)
+        .map_err(|error| GatewayError::connect(format!("mqtt {}", config.url), error))?;
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((
+        tx,
+        thread::spawn(move || {
+            info!(
+                "starting mqtt writer {} with topic {}",
+                config.url, config.topic
+            );
+            mqtt_writer(rx, client, config, query_mapper);
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let event = parse_line(
+            "power,location=loo\\ fan,channel=1 value=3.5,output=1i,state=\"on, off\",ok=true 100",
+        )
+        .unwrap();
+
+        assert_eq!(event.measurement, "power");
+        assert_eq!(event.tags["location"], "loo fan");
+        assert_eq!(event.tags["channel"], "1");
+        assert_eq!(event.fields["value"], FieldValue::Float(3.5));
+        assert_eq!(event.fields["output"], FieldValue::Integer(1));
+        assert_eq!(
+            event.fields["state"],
+            FieldValue::Text("on, off".to_string())
+        );
+        assert_eq!(event.fields["ok"], FieldValue::Boolean(true));
+        assert_eq!(event.time, Some(100));
+
+        let event = parse_line("btle,name=say\"hi\\ there value=1").unwrap();
+        assert_eq!(event.tags["name"], "say\"hi there");
+        assert_eq!(event.time, None);
+
+        assert!(parse_line("power").is_none());
+        assert!(parse_line("power value=foo 100").is_none());
+    }
+
+    #[cfg(feature = "shelly")]
+    #[test]
+    fn test_to_items() {
+        let query = WriteQuery::new(crate::data::query::Timestamp::Seconds(1701271852), "power")
+            .add_tag("location", "kitchen")
+            .add_field("value", 12.5)
+            .add_field("on", true);
+
+        assert_eq!(
+            to_items(query),
+            vec![
+                ("measurement".to_string(), "power".to_string()),
+                ("time".to_string(), "2023-11-29T15:30:52+00:00".to_string()),
+                ("location".to_string(), "kitchen".to_string()),
+                ("on".to_string(), "true".to_string()),
+                ("value".to_string(), "12.5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_topic() {
+        let event = parse_line("power,location=kitchen value=1 100").unwrap();
+
+        assert_eq!(
+            topic("gateway/{location}/{measurement}", &event),
+            "gateway/kitchen/power"
+        );
+    }
+
+    #[test]
+    fn test_encode() -> Result<()> {
+        let line = "power,location=kitchen value=1.5 100".to_string();
+        let event = parse_line(&line).unwrap();
+
+        assert_eq!(
+            encode(PayloadFormat::Json, line.clone(), &event)?,
+            b"{\"measurement\":\"power\",\"tags\":{\"location\":\"kitchen\"},\"fields\":{\"value\":1.5},\"time\":100}"
+        );
+        assert_eq!(
+            encode(PayloadFormat::LineProtocol, line.clone(), &event)?,
+            line.as_bytes()
+        );
+        #[cfg(feature = "msgpack")]
+        assert_eq!(encode(PayloadFormat::MessagePack, line, &event)?[0], 0x84);
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use crate::error::{GatewayError, Result};
+use crate::target::ack;
+use crate::target::ack::Acknowledged;
+use futures::executor::block_on;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use log::{debug, error, info, warn};
+#[cfg(test)]
+use mockall::automock;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TEMPLATE: &str = "{measurement} at {location} ({sensor}): {value}";
+const DEFAULT_MIN_INTERVAL: u64 = 60;
+
+pub enum NotificationService {
+    Telegram {
+        token: String,
+        chat_id: String,
+    },
+    Pushover {
+        token: String,
+        user: String,
+    },
+    Smtp {
+        host: String,
+        port: Option<u16>,
+        user: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: String,
+    },
+}
+
+impl NotificationService {
+    fn name(&self) -> &str {
+        match self {
+            NotificationService::Telegram { .. } => "telegram",
+            NotificationService::Pushover { .. } => "pushover",
+            NotificationService::Smtp { .. } => "smtp",
+        }
+    }
+}
+
+pub struct NotificationConfig {
+    service: NotificationService,
+    template: Option<String>,
+    min_interval: Duration,
+}
+
+impl NotificationConfig {
+    pub(crate) fn new(
+        service: NotificationService,
+        template: Option<String>,
+        min_interval: Option<u64>,
+    ) -> Self {
+        Self {
+            service,
+            template,
+            min_interval: Duration::from_secs(min_interval.unwrap_or(DEFAULT_MIN_INTERVAL)),
+        }
+    }
+}
+
+#[cfg_attr(test, automock)]
+pub trait NotificationClient: Send {
+    fn send(&mut self, text: &str) -> Result<()>;
+}
+
+struct TelegramClient {
+    token: String,
+    chat_id: String,
+}
+
+impl NotificationClient for TelegramClient {
+    fn send(&mut self, text: &str) -> Result<()> {
+        ureq::post(&format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.token
+        ))
+        .send_json(ureq::json!({
+            "chat_id": self.chat_id,
+            "text": text,
+        }))
+        .map_err(|error| GatewayError::target("telegram", error))?;
+        Ok(())
+    }
+}
+
+struct PushoverClient {
+    token: String,
+    user: String,
+}
+
+impl NotificationClient for PushoverClient {
+    fn send(&mut self, text: &str) -> Result<()> {
+        ureq::post("https://api.pushover.net/1/messages.json")
+            .send_form(&[
+                ("token", &self.token),
+                ("user", &self.user),
+                ("message", text),
+            ])
+            .map_err(|error| GatewayError::target("pushover", error))?;
+        Ok(())
+    }
+}
+
+struct SmtpClient {
+    transport: SmtpTransport,
+    from: String,
+    to: String,
+}
+
+impl NotificationClient for SmtpClient {
+    fn send(&mut self, text: &str) -> Result<()> {
+        let email = lettre::Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|error| GatewayError::target("smtp", error))?,
+            )
+            .to(self
+                .to
+                .parse()
+                .map_err(|error| GatewayError::target("smtp", error))?)
+            .subject("mqtt-gateway notification")
+            .body(text.to_string())
+            .map_err(|error| GatewayError::target("smtp", error))?;
+        self.transport
+            .send(&email)
+            .map_err(|error| GatewayError::target("smtp", error))?;
+        Ok(())
+    }
+}
+
+fn render(template: &str, items: &[(String, String)]) -> String {
+    items
+        .iter()
+        .fold(template.to_string(), |text, (key, value)| {
+            text.replace(&format!("{{{}}}", key), value)
+        })
+}
+
+/// Text of an event without a configured template, the default template for sensor readings and
+/// the measurement with the other items for the queries of the other sources.
+fn default_text(items: &[(String, String)]) -> String {
+    if items.iter().any(|(key, _)| key == "sensor") {
+        return render(DEFAULT_TEMPLATE, items);
+    }
+    let measurement = items
+        .iter()
+        .find(|(key, _)| key == "measurement")
+        .map_or("", |(_, value)| value.as_str());
+    let details: Vec<String> = items
+        .iter()
+        .filter(|(key, _)| key != "measurement" && key != "time")
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{}: {}", measurement, details.join(", "))
+}
+
+fn notification_writer<T: Acknowledged>(
+    rx: Receiver<T>,
+    mut client: Box<dyn NotificationClient>,
+    config: NotificationConfig,
+    mapper: fn(T) -> Vec<(String, String)>,
+) {
+    block_on(async move {
+        info!(
+            "starting notification writer async {}",
+            config.service.name()
+        );
+
+        let mut last_sent: Option<Instant> = None;
+        let mut suppressed = 0;
+
+        loop {
+            let result = rx.recv();
+            let mut data = match result {
+                Ok(data) => {
+                    super::received();
+                    data
+                }
+                Err(error) => {
+                    warn!("error receiving data: {:?}", error);
+                    break;
+                }
+            };
+            // notifications are no durable storage and do not hold back acknowledgements
+            ack::confirm(data.take_ack());
+
+            if let Some(last_sent) = last_sent {
+                if last_sent.elapsed() < config.min_interval {
+                    suppressed += 1;
+                    debug!(
+                        "notification rate limited {} ({} suppressed)",
+                        config.service.name(),
+                        suppressed
+                    );
+                    continue;
+                }
+            }
+
+            let items = mapper(data);
+            let mut text = match &config.template {
+                Some(template) => render(template, &items),
+                None => default_text(&items),
+            };
+            if suppressed > 0 {
+                text = format!("{} (+{} suppressed)", text, suppressed);
+            }
+
+            match client.send(&text) {
+                Ok(_) => {
+                    last_sent = Some(Instant::now());
+                    suppressed = 0;
+                }
+                Err(error) => {
+                    error!("#### Error sending notification: {}", error);
+                }
+            }
+        }
+        info!("exiting notification writer async");
+    });
+
+    info!("exiting notification writer");
+}
+
+pub fn spawn_notification_writer<T: Acknowledged + Send + 'static>(
+    config: NotificationConfig,
+    mapper: fn(T) -> Vec<(String, String)>,
+) -> Result<(SyncSender<T>, JoinHandle<()>)> {
+    let client = create_notification_client(&config)?;
+    Ok(spawn_notification_writer_internal(client, config, mapper))
+}
+
+fn create_notification_client(config: &NotificationConfig) -> Result<Box<dyn NotificationClient>> {
+    Ok(match &config.service {
+        NotificationService::Telegram { token, chat_id } => Box::new(TelegramClient {
+            token: token.clone(),
+            chat_id: chat_id.clone(),
+        }),
+        NotificationService::Pushover { token, user } => Box::new(PushoverClient {
+            token: token.clone(),
+            user: user.clone(),
+        }),
+        NotificationService::Smtp {
+            host,
+            port,
+            user,
+            password,
+            from,
+            to,
+        } => {
+            let mut builder = SmtpTransport::relay(host)
+                .map_err(|error| GatewayError::connect(format!("smtp {}", host), error))?;
+            if let Some(port) = port {
+                builder = builder.port(*port);
+            }
+            if let (Some(user), Some(password)) = (user, password) {
+                builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+            }
+            Box::new(SmtpClient {
+                transport: builder.build(),
+                from: from.clone(),
+                to: to.clone(),
+            })
+        }
+    })
+}
+
+fn spawn_notification_writer_internal<T: Acknowledged + Send + 'static>(
+    client: Box<dyn NotificationClient>,
+    config: NotificationConfig,
+    mapper: fn(T) -> Vec<(String, String)>,
+) -> (SyncSender<T>, JoinHandle<()>) {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    (
+        tx,
+        thread::spawn(move || {
+            info!("starting notification writer {}", config.service.name());
+            notification_writer(rx, client, config, mapper);
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock_mapper(data: String) -> Vec<(String, String)> {
+        vec![
+            ("location".to_string(), "kitchen".to_string()),
+            ("value".to_string(), data),
+        ]
+    }
+
+    #[test]
+    fn test_render() {
+        let items = mock_mapper("12.5".to_string());
+
+        assert_eq!(
+            render("{location}: {value} {unknown}", &items),
+            "kitchen: 12.5 {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_default_text() {
+        let reading = [
+            ("measurement".to_string(), "temperature".to_string()),
+            ("location".to_string(), "kitchen".to_string()),
+            ("sensor".to_string(), "BME680".to_string()),
+            ("value".to_string(), "21.5".to_string()),
+        ];
+        assert_eq!(
+            default_text(&reading),
+            "temperature at kitchen (BME680): 21.5"
+        );
+
+        let query = [
+            ("measurement".to_string(), "power".to_string()),
+            ("time".to_string(), "2023-11-29T15:30:52+00:00".to_string()),
+            ("location".to_string(), "kitchen".to_string()),
+            ("value".to_string(), "12.5".to_string()),
+        ];
+        assert_eq!(default_text(&query), "power: location=kitchen, value=12.5");
+    }
+
+    #[test]
+    fn test_notification_writer_internal_rate_limits() -> Result<()> {
+        let config = NotificationConfig::new(
+            NotificationService::Telegram {
+                token: "token".to_string(),
+                chat_id: "chat".to_string(),
+            },
+            Some("{location}: {value}".to_string()),
+            Some(3600),
+        );
+
+        let mut mock_client = Box::new(MockNotificationClient::new());
+        mock_client
+            .expect_send()
+            .times(1)
+            .withf(|text| text == "kitchen: 12.5")
+            .returning(|_| Ok(()));
+
+        let (tx, join_handle) =
+            spawn_notification_writer_internal(mock_client, config, mock_mapper);
+
+        tx.send("12.5".to_string()).unwrap();
+        tx.send("13.5".to_string()).unwrap();
+
+        drop(tx);
+
+        join_handle.join().expect("stopped writer");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use crate::error::Result;
+use crate::target::ack;
+use crate::target::ack::Acknowledged;
+use log::info;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct NullConfig {
+    report_interval: Duration,
+}
+
+impl NullConfig {
+    pub(crate) fn new(report_interval: Option<u64>) -> Self {
+        Self {
+            report_interval: report_interval
+                .map_or(DEFAULT_REPORT_INTERVAL, Duration::from_secs)
+                .max(Duration::from_secs(1)),
+        }
+    }
+}
+
+fn report(count: u64, elapsed: Duration, total: u64) {
+    info!(
+        "null writer discarded {} events in {:.1}s ({:.1}/s), {} in total",
+        count,
+        elapsed.as_secs_f64(),
+        count as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        total
+    );
+}
+
+/// Confirms and discards all events, reporting the throughput in the configured interval.
+/// Returns the number of events discarded.
+fn null_writer<T: Acknowledged>(rx: Receiver<T>, config: NullConfig) -> u64 {
+    let mut total = 0;
+    let mut count = 0;
+    let mut since = Instant::now();
+    loop {
+        let timeout = config.report_interval.saturating_sub(since.elapsed());
+        match rx.recv_timeout(timeout) {
+            Ok(mut data) => {
+                super::received();
+                ack::confirm(data.take_ack());
+                count += 1;
+                total += 1;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if since.elapsed() >= config.report_interval {
+            report(count, since.elapsed(), total);
+            count = 0;
+            since = Instant::now();
+        }
+    }
+    report(count, since.elapsed(), total);
+    info!("exiting null writer");
+    total
+}
+
+pub fn spawn_null_writer<T: Acknowledged + Send + 'static>(
+    config: NullConfig,
+) -> Result<(SyncSender<T>, JoinHandle<()>)> {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((
+        tx,
+        thread::spawn(move || {
+            info!("starting null writer");
+            null_writer(rx, config);
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_null_writer() {
+        let (tx, rx) = sync_channel(10);
+        for value in [1.0, 2.0, 3.0] {
+            tx.send(value).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(null_writer::<f64>(rx, NullConfig::new(None)), 3);
+    }
+}
@@ -0,0 +1,614 @@
+mod summary;
+
+use crate::config::BreakerConfig;
+use crate::error::{GatewayError, Result};
+use crate::source::connection;
+use crate::target::ack;
+use crate::target::ack::Ack;
+use crate::target::ack::Acknowledged;
+use crate::target::breaker::Breaker;
+use crate::SensorReading;
+use chrono::{DateTime, Utc};
+use futures::executor::block_on;
+use log::{error, info, warn};
+#[cfg(test)]
+use mockall::automock;
+use postgres::types::ToSql;
+use postgres::Client;
+use postgres::{Error, NoTls};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use summary::{SeriesKey, Summaries, Summary, SummaryField};
+
+/// How often completed summary windows are written.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct PostgresConfig {
+    host: String,
+    port: u16,
+    username: String,
+    password: String,
+    database: String,
+    summary_window: Option<Duration>,
+    summary_fields: Vec<String>,
+    low_latency: Vec<String>,
+    notify: Option<String>,
+    breaker: Option<BreakerConfig>,
+}
+
+impl PostgresConfig {
+    pub(crate) fn new(
+        host: String,
+        port: u16,
+        username: String,
+        password: String,
+        database: String,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            username,
+            password,
+            database,
+            summary_window: None,
+            summary_fields: Vec::new(),
+            low_latency: Vec::new(),
+            notify: None,
+            breaker: None,
+        }
+    }
+
+    /// Writes the minimum, maximum, mean and count of each series per window instead of the
+    /// readings.
+    pub(crate) fn with_summary_window(self, summary_window: Option<Duration>) -> Self {
+        PostgresConfig {
+            summary_window,
+            ..self
+        }
+    }
+
+    /// Writes the given statistics like `p95` or `stddev` in additional columns of the summaries.
+    pub(crate) fn with_summary_fields(self, summary_fields: Vec<String>) -> Self {
+        PostgresConfig {
+            summary_fields,
+            ..self
+        }
+    }
+
+    /// Writes the readings of the given measurements immediately instead of summarizing them,
+    /// e.g. door contacts alerts are based on.
+    pub(crate) fn with_low_latency(self, low_latency: Vec<String>) -> Self {
+        PostgresConfig {
+            low_latency,
+            ..self
+        }
+    }
+
+    /// Sends a `NOTIFY` with the key of each written row as JSON to the given channel.
+    pub(crate) fn with_notify(self, notify: Option<String>) -> Self {
+        PostgresConfig { notify, ..self }
+    }
+
+    /// Pauses the writes after repeated failures.
+    pub(crate) fn with_breaker(self, breaker: Option<BreakerConfig>) -> Self {
+        PostgresConfig { breaker, ..self }
+    }
+
+    fn breaker(&self) -> Breaker {
+        Breaker::new(
+            format!("postgresql {}:{}/{}", self.host, self.port, self.database),
+            self.breaker.as_ref(),
+        )
+    }
+}
+
+#[cfg_attr(test, automock)]
+pub trait PostgresClient: Send {
+    fn execute<'a>(
+        &mut self,
+        query: &str,
+        params: &'a [&'a (dyn ToSql + Sync)],
+    ) -> std::result::Result<u64, Error>;
+
+    /// Whether the connection is lost, which the writer cannot recover from.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+struct DefaultPostgresClient {
+    client: Client,
+}
+
+impl DefaultPostgresClient {
+    fn new(client: Client) -> Self {
+        DefaultPostgresClient { client }
+    }
+}
+
+impl DefaultPostgresClient {}
+
+impl PostgresClient for DefaultPostgresClient {
+    fn execute(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> std::result::Result<u64, Error> {
+        self.client.execute(query, params)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+}
+
+fn start_postgres_writer(
+    rx: Receiver<SensorReading>,
+    mut client: Box<dyn PostgresClient>,
+    notify: Option<String>,
+    mut breaker: Breaker,
+) {
+    block_on(async move {
+        info!("starting postgres writer async");
+
+        loop {
+            let result = rx.recv();
+            let mut query = match result {
+                Ok(query) => {
+                    super::received();
+                    query
+                }
+                Err(error) => {
+                    warn!("error receiving query: {:?}", error);
+                    break;
+                }
+            };
+
+            if !breaker.admit() {
+                continue;
+            }
+            let written = write_reading(client.as_mut(), &mut query, notify.as_deref());
+            breaker.record(written);
+            if !written && client.is_closed() {
+                error!("postgres connection closed, stopping writer");
+                break;
+            }
+        }
+        info!("exiting influx writer async");
+    });
+
+    info!("exiting influx writer");
+}
+
+/// Notifies listeners of the channel about a written row, e.g.
+/// `{"table":"temperature","location":"kitchen","sensor":"bme680","time":"2024-01-01T00:00:00+00:00"}`.
+fn notify(
+    client: &mut dyn PostgresClient,
+    channel: Option<&str>,
+    table: &str,
+    key: (&str, &str, &DateTime<Utc>),
+) {
+    let Some(channel) = channel else {
+        return;
+    };
+    let (location, sensor, time) = key;
+    let payload = serde_json::json!({
+        "table": table,
+        "location": location,
+        "sensor": sensor,
+        "time": time.to_rfc3339(),
+    })
+    .to_string();
+    if let Err(error) = client.execute("select pg_notify($1, $2);", &[&channel, &payload]) {
+        warn!("failed to notify postgres channel {}: {:?}", channel, error);
+    }
+}
+
+/// Inserts a reading into the table of its measurement, returns whether it was written.
+fn write_reading(
+    client: &mut dyn PostgresClient,
+    reading: &mut SensorReading,
+    channel: Option<&str>,
+) -> bool {
+    let statement = format!(
+        "insert into \"{}\" (time, location, sensor, value) values ($1, $2, $3, $4);",
+        reading.measurement
+    );
+    let (location, sensor) = (&*reading.location, &*reading.sensor);
+    match client.execute(
+        &statement,
+        &[&reading.time, &location, &sensor, &reading.value],
+    ) {
+        Ok(_) => {
+            ack::confirm(reading.take_ack());
+            notify(
+                client,
+                channel,
+                &reading.measurement,
+                (&reading.location, &reading.sensor, &reading.time),
+            );
+            true
+        }
+        Err(error) => {
+            error!(
+                "#### Error writing to postgres: {} {:?}",
+                reading.measurement, error
+            );
+            false
+        }
+    }
+}
+
+/// Inserts a summary into the summary table of its measurement, returns whether it was written.
+fn write_summary(
+    client: &mut dyn PostgresClient,
+    key: SeriesKey,
+    summary: Summary,
+    fields: &[SummaryField],
+    channel: Option<&str>,
+) -> bool {
+    let columns: String = fields
+        .iter()
+        .map(|field| format!(", {}", field.column()))
+        .collect();
+    let placeholders: String = (8..8 + fields.len())
+        .map(|index| format!(", ${}", index))
+        .collect();
+    let statement = format!(
+        "insert into \"{}_summary\" (time, location, sensor, min, max, mean, count{}) values ($1, $2, $3, $4, $5, $6, $7{});",
+        key.measurement, columns, placeholders
+    );
+    let mean = summary.mean();
+    let values: Vec<f64> = fields.iter().map(|field| field.value(&summary)).collect();
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![
+        &summary.start,
+        &key.location,
+        &key.sensor,
+        &summary.min,
+        &summary.max,
+        &mean,
+        &summary.count,
+    ];
+    params.extend(values.iter().map(|value| value as &(dyn ToSql + Sync)));
+    match client.execute(&statement, &params) {
+        Ok(_) => {
+            summary.acks.into_iter().for_each(Ack::confirm);
+            notify(
+                client,
+                channel,
+                &format!("{}_summary", key.measurement),
+                (&key.location, &key.sensor, &summary.start),
+            );
+            true
+        }
+        Err(error) => {
+            error!(
+                "#### Error writing summary to postgres: {} {:?}",
+                key.measurement, error
+            );
+            false
+        }
+    }
+}
+
+fn start_postgres_summary_writer(
+    rx: Receiver<SensorReading>,
+    mut client: Box<dyn PostgresClient>,
+    window: Duration,
+    fields: Vec<SummaryField>,
+    low_latency: Vec<String>,
+    notify: Option<String>,
+    mut breaker: Breaker,
+) {
+    let notify = notify.as_deref();
+    let mut summaries = Summaries::new(window).with_fields(&fields);
+    loop {
+        match rx.recv_timeout(SUMMARY_INTERVAL) {
+            Ok(mut reading)
+                if low_latency
+                    .iter()
+                    .any(|measurement| **measurement == *reading.measurement) =>
+            {
+                super::received();
+                breaker.guard(|| write_reading(client.as_mut(), &mut reading, notify));
+            }
+            Ok(reading) => {
+                super::received();
+                if let Some((key, summary)) = summaries.add(reading) {
+                    breaker.guard(|| write_summary(client.as_mut(), key, summary, &fields, notify));
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for (key, summary) in summaries.expired(chrono::Utc::now()) {
+            breaker.guard(|| write_summary(client.as_mut(), key, summary, &fields, notify));
+        }
+        if client.is_closed() {
+            error!("postgres connection closed, stopping summary writer");
+            return;
+        }
+    }
+    for (key, summary) in summaries.drain() {
+        breaker.guard(|| write_summary(client.as_mut(), key, summary, &fields, notify));
+    }
+    info!("exiting postgres summary writer");
+}
+
+pub fn spawn_postgres_writer(
+    config: PostgresConfig,
+) -> Result<(SyncSender<SensorReading>, JoinHandle<()>)> {
+    let fields = config
+        .summary_fields
+        .iter()
+        .map(|field| SummaryField::parse(field))
+        .collect::<Result<Vec<_>>>()?;
+    let client = create_postgres_client(&config)?;
+    let breaker = config.breaker();
+    Ok(match config.summary_window {
+        Some(window) => spawn_postgres_summary_writer_internal(
+            client,
+            window,
+            fields,
+            config.low_latency,
+            config.notify,
+            breaker,
+        ),
+        None => spawn_postgres_writer_internal(client, config.notify, breaker),
+    })
+}
+
+fn create_postgres_client(config: &PostgresConfig) -> Result<Box<dyn PostgresClient>> {
+    let context = format!(
+        "postgres {}:{}/{}",
+        config.host, config.port, config.database
+    );
+    let client = postgres::Config::new()
+        .host(&config.host)
+        .port(config.port)
+        .user(&config.username)
+        .password(&config.password)
+        .dbname(&config.database)
+        .connect(NoTls)
+        .map_err(|error| {
+            // includes the SQLSTATE, e.g. 28P01 for a wrong password
+            connection::log_failure(&context, &error);
+            GatewayError::connect(context.clone(), error)
+        })?;
+    connection::log_handshake(&context, &format!("connected as {}", config.username));
+    Ok(Box::new(DefaultPostgresClient::new(client)))
+}
+
+pub fn spawn_postgres_writer_internal(
+    client: Box<dyn PostgresClient>,
+    notify: Option<String>,
+    breaker: Breaker,
+) -> (SyncSender<SensorReading>, JoinHandle<()>) {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    (
+        tx,
+        thread::spawn(move || {
+            info!("starting postgres writer");
+            start_postgres_writer(rx, client, notify, breaker);
+        }),
+    )
+}
+
+fn spawn_postgres_summary_writer_internal(
+    client: Box<dyn PostgresClient>,
+    window: Duration,
+    fields: Vec<SummaryField>,
+    low_latency: Vec<String>,
+    notify: Option<String>,
+    breaker: Breaker,
+) -> (SyncSender<SensorReading>, JoinHandle<()>) {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    (
+        tx,
+        thread::spawn(move || {
+            info!("starting postgres summary writer");
+            start_postgres_summary_writer(rx, client, window, fields, low_latency, notify, breaker);
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> Breaker {
+        Breaker::new("postgresql test", None)
+    }
+
+    #[test]
+    fn test_postgres_writer_internal() -> anyhow::Result<()> {
+        let sensor_reading = SensorReading {
+            measurement: "measurement".into(),
+            time: chrono::Utc::now(),
+            location: "location".into(),
+            sensor: "sensor".into(),
+            value: 123.4,
+            tags: Vec::new(),
+            ack: None,
+        };
+
+        let sensor_reading_duplicate = sensor_reading.clone();
+
+        let mut mock_client = Box::new(MockPostgresClient::new());
+        mock_client.expect_execute()
+            .times(1)
+            .withf(move |query, parameters| {
+                let expected_parameters: [&dyn ToSql; 4] = [&sensor_reading_duplicate.time, &sensor_reading_duplicate.location.as_ref(), &sensor_reading_duplicate.sensor.as_ref(), &sensor_reading_duplicate.value];
+                query == "insert into \"measurement\" (time, location, sensor, value) values ($1, $2, $3, $4);" ||
+                    parameters.len() == expected_parameters.len() &&
+                        parameters.iter().zip(expected_parameters.iter()).all(|(a, b)| format!("{a:?}") == format!("{b:?}"))
+            })
+            .returning(|_, _| Ok(123));
+
+        let (tx, join_handle) = spawn_postgres_writer_internal(mock_client, None, breaker());
+
+        tx.send(sensor_reading).unwrap();
+
+        drop(tx);
+
+        let _ = join_handle.join();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_postgres_writer_notify() {
+        let reading = SensorReading {
+            measurement: "temperature".into(),
+            time: DateTime::from_timestamp(1704067200, 0).unwrap(),
+            location: "kitchen".into(),
+            sensor: "bme680".into(),
+            value: 21.5,
+            tags: Vec::new(),
+            ack: None,
+        };
+
+        let mut mock_client = Box::new(MockPostgresClient::new());
+        mock_client
+            .expect_execute()
+            .times(1)
+            .withf(|query, _| query.starts_with("insert into \"temperature\""))
+            .returning(|_, _| Ok(1));
+        mock_client
+            .expect_execute()
+            .times(1)
+            .withf(|query, parameters| {
+                query == "select pg_notify($1, $2);"
+                    && format!("{:?}", parameters) == format!("{:?}", [
+                        "readings",
+                        r#"{"location":"kitchen","sensor":"bme680","table":"temperature","time":"2024-01-01T00:00:00+00:00"}"#,
+                    ])
+            })
+            .returning(|_, _| Ok(1));
+
+        let (tx, join_handle) =
+            spawn_postgres_writer_internal(mock_client, Some("readings".to_string()), breaker());
+        tx.send(reading).unwrap();
+        drop(tx);
+
+        join_handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_postgres_summary_writer() {
+        let time = chrono::Utc::now();
+        let reading = |value| SensorReading {
+            measurement: "temperature".into(),
+            time,
+            location: "kitchen".into(),
+            sensor: "bme680".into(),
+            value,
+            tags: Vec::new(),
+            ack: None,
+        };
+
+        let mut mock_client = Box::new(MockPostgresClient::new());
+        mock_client
+            .expect_execute()
+            .times(1)
+            .withf(|query, parameters| {
+                query.starts_with("insert into \"temperature_summary\" (time, location, sensor, min, max, mean, count)")
+                    && format!("{:?}", &parameters[3..]) == "[19.0, 21.0, 20.0, 2]"
+            })
+            .returning(|_, _| Ok(1));
+        mock_client.expect_is_closed().returning(|| false);
+
+        let (tx, join_handle) = spawn_postgres_summary_writer_internal(
+            mock_client,
+            Duration::from_secs(60),
+            Vec::new(),
+            Vec::new(),
+            None,
+            breaker(),
+        );
+        tx.send(reading(19.0)).unwrap();
+        tx.send(reading(21.0)).unwrap();
+        drop(tx);
+
+        join_handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_postgres_summary_writer_low_latency() {
+        let door = SensorReading {
+            measurement: "door".into(),
+            time: chrono::Utc::now(),
+            location: "entrance".into(),
+            sensor: "contact".into(),
+            value: 1.0,
+            tags: Vec::new(),
+            ack: None,
+        };
+
+        let mut mock_client = Box::new(MockPostgresClient::new());
+        mock_client
+            .expect_execute()
+            .times(1)
+            .withf(|query, parameters| {
+                query == "insert into \"door\" (time, location, sensor, value) values ($1, $2, $3, $4);"
+                    && format!("{:?}", &parameters[3]) == "1.0"
+            })
+            .returning(|_, _| Ok(1));
+        mock_client.expect_is_closed().returning(|| false);
+
+        let (tx, join_handle) = spawn_postgres_summary_writer_internal(
+            mock_client,
+            Duration::from_secs(60),
+            Vec::new(),
+            vec!["door".to_string()],
+            None,
+            breaker(),
+        );
+        tx.send(door).unwrap();
+        drop(tx);
+
+        join_handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_postgres_summary_writer_fields() {
+        let reading = |value| SensorReading {
+            measurement: "voltage".into(),
+            time: chrono::Utc::now(),
+            location: "mains".into(),
+            sensor: "em".into(),
+            value,
+            tags: Vec::new(),
+            ack: None,
+        };
+
+        let mut mock_client = Box::new(MockPostgresClient::new());
+        mock_client
+            .expect_execute()
+            .times(1)
+            .withf(|query, parameters| {
+                query == "insert into \"voltage_summary\" (time, location, sensor, min, max, mean, count, p50, stddev) values ($1, $2, $3, $4, $5, $6, $7, $8, $9);"
+                    && format!("{:?}", &parameters[3..]) == "[229.0, 233.0, 231.0, 2, 231.0, 2.0]"
+            })
+            .returning(|_, _| Ok(1));
+        mock_client.expect_is_closed().returning(|| false);
+
+        let (tx, join_handle) = spawn_postgres_summary_writer_internal(
+            mock_client,
+            Duration::from_secs(60),
+            vec![SummaryField::Percentile(50), SummaryField::Stddev],
+            Vec::new(),
+            None,
+            breaker(),
+        );
+        tx.send(reading(229.0)).unwrap();
+        tx.send(reading(233.0)).unwrap();
+        drop(tx);
+
+        join_handle.join().unwrap();
+    }
+}
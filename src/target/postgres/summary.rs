@@ -0,0 +1,277 @@
+use crate::error::{GatewayError, Result};
+use crate::target::ack::{Ack, Acknowledged};
+use crate::SensorReading;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Time after the end of a window in which late readings are still added to it.
+const GRACE: TimeDelta = TimeDelta::seconds(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeriesKey {
+    pub(crate) measurement: String,
+    pub(crate) location: String,
+    pub(crate) sensor: String,
+}
+
+/// Statistic written in addition to the minimum, maximum, mean and count of a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SummaryField {
+    /// Population standard deviation, column `stddev`.
+    Stddev,
+    /// Percentile between 0 and 100, column `p<percentile>`, e.g. `p95`.
+    Percentile(u8),
+}
+
+impl SummaryField {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "stddev" => Ok(SummaryField::Stddev),
+            _ => name
+                .strip_prefix('p')
+                .and_then(|percentile| percentile.parse().ok())
+                .filter(|percentile| *percentile <= 100)
+                .map(SummaryField::Percentile)
+                .ok_or_else(|| {
+                    GatewayError::config(format!(
+                        "unknown summary field '{}', expected stddev or p0 to p100",
+                        name
+                    ))
+                }),
+        }
+    }
+
+    /// Column of the statistic in the summary table.
+    pub fn column(&self) -> String {
+        match self {
+            SummaryField::Stddev => "stddev".to_string(),
+            SummaryField::Percentile(percentile) => format!("p{}", percentile),
+        }
+    }
+
+    pub fn value(&self, summary: &Summary) -> f64 {
+        match self {
+            SummaryField::Stddev => summary.stddev(),
+            SummaryField::Percentile(percentile) => summary.percentile(*percentile),
+        }
+    }
+}
+
+/// Minimum, maximum, mean and count of the readings of a series in a window, with the readings
+/// themselves if percentiles are written.
+#[derive(Debug)]
+pub struct Summary {
+    pub(crate) start: DateTime<Utc>,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    sum: f64,
+    sum_squares: f64,
+    pub(crate) count: i64,
+    values: Option<Vec<f64>>,
+    pub(crate) acks: Vec<Ack>,
+}
+
+impl Summary {
+    fn new(start: DateTime<Utc>, keep_values: bool) -> Self {
+        Summary {
+            start,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            sum_squares: 0.0,
+            count: 0,
+            values: keep_values.then(Vec::new),
+            acks: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, value: f64, ack: Option<Ack>) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.sum_squares += value * value;
+        self.count += 1;
+        if let Some(values) = self.values.as_mut() {
+            values.push(value);
+        }
+        self.acks.extend(ack);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    pub fn stddev(&self) -> f64 {
+        let mean = self.mean();
+        (self.sum_squares / self.count as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// Percentile of the readings interpolated between the closest ranks, NaN if the readings
+    /// were not kept.
+    pub fn percentile(&self, percentile: u8) -> f64 {
+        let Some(mut values) = self.values.clone().filter(|values| !values.is_empty()) else {
+            return f64::NAN;
+        };
+        values.sort_by(f64::total_cmp);
+        let rank = percentile as f64 / 100.0 * (values.len() - 1) as f64;
+        let (lower, upper) = (values[rank.floor() as usize], values[rank.ceil() as usize]);
+        lower + (upper - lower) * rank.fract()
+    }
+}
+
+/// Open summaries of the current window of every series.
+pub struct Summaries {
+    window: i64,
+    keep_values: bool,
+    series: HashMap<SeriesKey, Summary>,
+}
+
+impl Summaries {
+    pub fn new(window: Duration) -> Self {
+        Summaries {
+            window: (window.as_secs() as i64).max(1),
+            keep_values: false,
+            series: HashMap::new(),
+        }
+    }
+
+    /// Keeps the readings of the windows, which the percentiles of the fields need.
+    pub fn with_fields(self, fields: &[SummaryField]) -> Self {
+        Summaries {
+            keep_values: fields
+                .iter()
+                .any(|field| matches!(field, SummaryField::Percentile(_))),
+            ..self
+        }
+    }
+
+    fn window_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        DateTime::from_timestamp(time.timestamp().div_euclid(self.window) * self.window, 0)
+            .unwrap_or(time)
+    }
+
+    /// Adds a reading and returns the summary completed by it: the previous window of the series
+    /// when the reading starts a new one, or a separate summary of a reading arriving after its
+    /// window was completed.
+    pub fn add(&mut self, mut reading: SensorReading) -> Option<(SeriesKey, Summary)> {
+        let ack = reading.take_ack();
+        let start = self.window_start(reading.time);
+        let key = SeriesKey {
+            measurement: reading.measurement.to_string(),
+            location: reading.location.to_string(),
+            sensor: reading.sensor.to_string(),
+        };
+        let value = reading.value as f64;
+        let keep_values = self.keep_values;
+        let current = self
+            .series
+            .entry(key.clone())
+            .or_insert_with(|| Summary::new(start, keep_values));
+        if start < current.start {
+            let mut late = Summary::new(start, keep_values);
+            late.add(value, ack);
+            return Some((key, late));
+        }
+        let completed = if start > current.start {
+            Some(std::mem::replace(current, Summary::new(start, keep_values)))
+        } else {
+            None
+        };
+        current.add(value, ack);
+        completed.map(|summary| (key, summary))
+    }
+
+    /// Removes the summaries of windows which ended more than the grace period before `now`.
+    pub fn expired(&mut self, now: DateTime<Utc>) -> Vec<(SeriesKey, Summary)> {
+        let end = TimeDelta::seconds(self.window) + GRACE;
+        let expired: Vec<SeriesKey> = self
+            .series
+            .iter()
+            .filter(|(_, summary)| summary.start + end <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.series.remove_entry(&key))
+            .collect()
+    }
+
+    /// Removes all open summaries, e.g. on shutdown.
+    pub fn drain(&mut self) -> Vec<(SeriesKey, Summary)> {
+        self.series.drain().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(time: i64, value: f32) -> SensorReading {
+        SensorReading {
+            measurement: "temperature".into(),
+            time: DateTime::from_timestamp(time, 0).unwrap(),
+            location: "kitchen".into(),
+            sensor: "bme680".into(),
+            value,
+            tags: Vec::new(),
+            ack: None,
+        }
+    }
+
+    #[test]
+    fn test_summarize_window() {
+        let mut summaries = Summaries::new(Duration::from_secs(60));
+
+        assert!(summaries.add(reading(1200, 20.0)).is_none());
+        assert!(summaries.add(reading(1230, 22.0)).is_none());
+        assert!(summaries.add(reading(1259, 24.0)).is_none());
+        let (key, summary) = summaries.add(reading(1260, 25.0)).unwrap();
+
+        assert_eq!(key.measurement, "temperature");
+        assert_eq!(summary.start.timestamp(), 1200);
+        assert_eq!(summary.min, 20.0);
+        assert_eq!(summary.max, 24.0);
+        assert_eq!(summary.mean(), 22.0);
+        assert_eq!(summary.count, 3);
+
+        let (_, late) = summaries.add(reading(1250, 19.0)).unwrap();
+        assert_eq!((late.start.timestamp(), late.count), (1200, 1));
+    }
+
+    #[test]
+    fn test_fields() {
+        let fields = ["p50", "p75", "stddev"]
+            .into_iter()
+            .map(SummaryField::parse)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let mut summaries = Summaries::new(Duration::from_secs(60)).with_fields(&fields);
+        let values = [4.0, 2.0, 5.0, 4.0, 4.0, 7.0, 5.0, 9.0];
+        for (offset, value) in values.into_iter().enumerate() {
+            summaries.add(reading(1200 + offset as i64, value));
+        }
+        let (_, summary) = summaries.add(reading(1260, 0.0)).unwrap();
+
+        let values: Vec<f64> = fields.iter().map(|field| field.value(&summary)).collect();
+        assert_eq!(values, vec![4.5, 5.5, 2.0]);
+        assert_eq!(fields[1].column(), "p75");
+        assert!(SummaryField::parse("p101").is_err());
+        assert!(SummaryField::parse("median").is_err());
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut summaries = Summaries::new(Duration::from_secs(60));
+        summaries.add(reading(1200, 20.0));
+
+        assert!(summaries
+            .expired(DateTime::from_timestamp(1265, 0).unwrap())
+            .is_empty());
+        let expired = summaries.expired(DateTime::from_timestamp(1270, 0).unwrap());
+        assert_eq!(expired.len(), 1);
+        assert!(summaries.drain().is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::error::{GatewayError, Result};
+use crate::source::connection;
+use crate::target::ack;
+use crate::target::ack::Acknowledged;
+use log::{error, info, warn};
+#[cfg(test)]
+use mockall::automock;
+use redis::streams::StreamMaxlen;
+use redis::{Commands, Connection, RedisResult};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+
+pub struct RedisConfig {
+    url: String,
+    stream: String,
+    max_length: Option<usize>,
+}
+
+impl RedisConfig {
+    pub(crate) fn new(url: String, stream: String, max_length: Option<usize>) -> Self {
+        Self {
+            url,
+            stream,
+            max_length,
+        }
+    }
+}
+
+#[cfg_attr(test, automock)]
+pub trait RedisClient: Send {
+    fn xadd(
+        &mut self,
+        stream: &str,
+        max_length: Option<usize>,
+        items: &[(String, String)],
+    ) -> RedisResult<String>;
+}
+
+struct DefaultRedisClient {
+    connection: Connection,
+}
+
+impl DefaultRedisClient {
+    fn new(connection: Connection) -> Self {
+        DefaultRedisClient { connection }
+    }
+}
+
+impl RedisClient for DefaultRedisClient {
+    fn xadd(
+        &mut self,
+        stream: &str,
+        max_length: Option<usize>,
+        items: &[(String, String)],
+    ) -> RedisResult<String> {
+        match max_length {
+            Some(max_length) => self.connection.xadd_maxlen(
+                stream,
+                StreamMaxlen::Approx(max_length),
+                "*",
+                items,
+            ),
+            None => self.connection.xadd(stream, "*", items),
+        }
+    }
+}
+
+fn redis_writer<T: Acknowledged>(
+    rx: Receiver<T>,
+    mut client: Box<dyn RedisClient>,
+    config: RedisConfig,
+    mapper: fn(T) -> Vec<(String, String)>,
+) {
+    loop {
+        let result = rx.recv();
+        let mut data = match result {
+            Ok(data) => {
+                super::received();
+                data
+            }
+            Err(error) => {
+                warn!("error receiving data: {:?}", error);
+                break;
+            }
+        };
+
+        let ack = data.take_ack();
+        let items = mapper(data);
+        match client.xadd(&config.stream, config.max_length, &items) {
+            Ok(_) => ack::confirm(ack),
+            Err(error) => error!(
+                "#### Error writing to redis: {} {}: {:?}",
+                &config.url, &config.stream, error
+            ),
+        }
+    }
+
+    info!("exiting redis writer");
+}
+
+pub fn spawn_redis_writer<T: Acknowledged + Send + 'static>(
+    config: RedisConfig,
+    mapper: fn(T) -> Vec<(String, String)>,
+) -> Result<(SyncSender<T>, JoinHandle<()>)> {
+    let client = create_redis_client(&config)?;
+    Ok(spawn_redis_writer_internal(client, config, mapper))
+}
+
+fn create_redis_client(config: &RedisConfig) -> Result<Box<dyn RedisClient>> {
+    let context = format!("redis {}", config.url);
+    let connection = redis::Client::open(config.url.as_str())
+        .and_then(|client| client.get_connection())
+        .map_err(|error| {
+            connection::log_failure(&context, &error);
+            GatewayError::connect(context.clone(), error)
+        })?;
+    connection::log_handshake(&context, "connected");
+    Ok(Box::new(DefaultRedisClient::new(connection)))
+}
+
+fn spawn_redis_writer_internal<T: Acknowledged + Send + 'static>(
+    client: Box<dyn RedisClient>,
+    config: RedisConfig,
+    mapper: fn(T) -> Vec<(String, String)>,
+) -> (SyncSender<T>, JoinHandle<()>) {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    (
+        tx,
+        thread::spawn(move || {
+            info!("starting redis writer {} {}", &config.url, &config.stream);
+            redis_writer(rx, client, config, mapper);
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock_mapper(data: String) -> Vec<(String, String)> {
+        vec![("value".to_string(), data)]
+    }
+
+    #[test]
+    fn test_redis_writer_internal() -> anyhow::Result<()> {
+        let config = RedisConfig::new(
+            "redis://localhost:6379".to_string(),
+            "events".to_string(),
+            Some(1000),
+        );
+
+        let mut mock_client = Box::new(MockRedisClient::new());
+        mock_client
+            .expect_xadd()
+            .times(1)
+            .withf(|stream, max_length, items| {
+                stream == "events"
+                    && *max_length == Some(1000)
+                    && items == [("value".to_string(), "test_data".to_string())]
+            })
+            .returning(|_, _, _| Ok("1-0".to_string()));
+
+        let (tx, join_handle) = spawn_redis_writer_internal(mock_client, config, mock_mapper);
+
+        tx.send("test_data".to_string()).unwrap();
+
+        drop(tx);
+
+        join_handle.join().expect("stopped writer");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use crate::data::query::{Query, WriteQuery};
+use crate::error::Result;
+use crate::target::ack;
+use crate::target::ack::Acknowledged;
+use crate::target::mqtt::parse_line;
+use crate::SensorReading;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+
+type Writer<T> = (SyncSender<T>, JoinHandle<()>);
+
+/// Events whose tags decide which targets they are routed to.
+pub trait Tagged {
+    /// Tags of the event, `measurement` being the measurement of the event.
+    fn tags(&self) -> HashMap<String, String>;
+}
+
+impl Tagged for WriteQuery {
+    fn tags(&self) -> HashMap<String, String> {
+        // events were validated by their source, so they always parse
+        let Some(event) = self.build().ok().and_then(|line| parse_line(&line.get())) else {
+            return HashMap::new();
+        };
+        let mut tags: HashMap<String, String> = event.tags.into_iter().collect();
+        tags.insert("measurement".to_string(), event.measurement);
+        tags
+    }
+}
+
+impl Tagged for SensorReading {
+    fn tags(&self) -> HashMap<String, String> {
+        let mut tags: HashMap<String, String> = self.tags.iter().cloned().collect();
+        tags.insert("measurement".to_string(), self.measurement.to_string());
+        tags.insert("location".to_string(), self.location.to_string());
+        tags.insert("sensor".to_string(), self.sensor.to_string());
+        tags
+    }
+}
+
+/// Condition on the tags of the events passed to a target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Route {
+    when: HashMap<String, String>,
+    unless: HashMap<String, String>,
+}
+
+impl Route {
+    pub(crate) fn new(
+        when: Option<HashMap<String, String>>,
+        unless: Option<HashMap<String, String>>,
+    ) -> Self {
+        Route {
+            when: when.unwrap_or_default(),
+            unless: unless.unwrap_or_default(),
+        }
+    }
+
+    fn has_all(tags: &HashMap<String, String>, condition: &HashMap<String, String>) -> bool {
+        condition
+            .iter()
+            .all(|(key, value)| tags.get(key) == Some(value))
+    }
+
+    /// Whether an event has all `when` tags and not all of the `unless` tags, the event is parsed
+    /// once for both.
+    pub fn matches<T: Tagged>(&self, data: &T) -> bool {
+        if self.when.is_empty() && self.unless.is_empty() {
+            return true;
+        }
+        let tags = data.tags();
+        Self::has_all(&tags, &self.when)
+            && (self.unless.is_empty() || !Self::has_all(&tags, &self.unless))
+    }
+}
+
+/// Passes the matching events on to the writer, confirming and discarding the others. Returns
+/// once the sender is closed or the writer died.
+fn route_writer<T: Tagged + Acknowledged>(rx: Receiver<T>, route: Route, writer: Writer<T>) {
+    let (tx, handle) = writer;
+    for mut data in rx {
+        if !route.matches(&data) {
+            super::received();
+            ack::confirm(data.take_ack());
+            continue;
+        }
+        // the event stays queued until the writer received it
+        if tx.send(data).is_err() {
+            super::received();
+            warn!("routed writer exited");
+            break;
+        }
+    }
+    drop(tx);
+    let _ = handle.join();
+    info!("exiting route writer");
+}
+
+/// Spawns a writer passing only the events matching the route on to the given writer.
+pub fn spawn_route_writer<T: Tagged + Acknowledged + Send + 'static>(
+    route: Route,
+    writer: Writer<T>,
+) -> Result<Writer<T>> {
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((
+        tx,
+        thread::spawn(move || {
+            info!("starting route writer");
+            route_writer(rx, route, writer);
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::query::Timestamp;
+
+    fn query(location: &str) -> WriteQuery {
+        WriteQuery::new(Timestamp::Seconds(1701271852), "temperature")
+            .add_tag("location", location)
+            .add_field("value", 21.5)
+    }
+
+    fn tags(key: &str, value: &str) -> Option<HashMap<String, String>> {
+        Some(HashMap::from([(key.to_string(), value.to_string())]))
+    }
+
+    #[test]
+    fn test_matches() {
+        let garage = Route::new(tags("location", "garage"), None);
+        let others = Route::new(None, tags("location", "garage"));
+
+        assert!(garage.matches(&query("garage")));
+        assert!(!garage.matches(&query("kitchen")));
+        assert!(!others.matches(&query("garage")));
+        assert!(others.matches(&query("kitchen")));
+        assert!(Route::new(tags("measurement", "temperature"), None).matches(&query("garage")));
+        assert!(Route::default().matches(&query("kitchen")));
+    }
+
+    #[test]
+    fn test_matches_sensor_reading() {
+        let reading = SensorReading {
+            measurement: "temperature".into(),
+            time: chrono::DateTime::from_timestamp(1701271852, 0).unwrap(),
+            location: "garage".into(),
+            sensor: "BME680".into(),
+            value: 21.5,
+            tags: vec![("floor".to_string(), "0".to_string())],
+            ack: None,
+        };
+
+        assert!(Route::new(tags("location", "garage"), tags("floor", "1")).matches(&reading));
+        assert!(Route::new(tags("sensor", "BME680"), None).matches(&reading));
+        assert!(!Route::new(None, tags("floor", "0")).matches(&reading));
+    }
+
+    #[test]
+    fn test_route_writer() {
+        let (tx, rx) = sync_channel(10);
+        let (writer_tx, writer_rx) = sync_channel(10);
+        for location in ["garage", "kitchen", "garage"] {
+            tx.send(query(location)).unwrap();
+        }
+        drop(tx);
+
+        route_writer(
+            rx,
+            Route::new(tags("location", "garage"), None),
+            (writer_tx, thread::spawn(|| {})),
+        );
+
+        let routed: Vec<_> = writer_rx.iter().collect();
+        assert_eq!(routed.len(), 2);
+        assert!(routed
+            .iter()
+            .all(|query| query.tags()["location"] == "garage"));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::error::Result;
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SendError, SyncSender};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often an idle forwarder checks whether its writer is still running.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Restarts are delayed exponentially from the initial to the maximum delay.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A writer running at least this long before dying is restarted with the initial delay again.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+/// A writer taking longer than this for an event is reported as stuck.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+static WRITERS: LazyLock<Mutex<Vec<Arc<Mutex<WriterState>>>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+type Writer<T> = (SyncSender<T>, JoinHandle<()>);
+
+#[derive(Debug)]
+struct WriterState {
+    name: String,
+    alive: bool,
+    restarts: u64,
+    forwarded: u64,
+    last_activity: Instant,
+    /// Start of the hand-over of an event the writer did not take yet.
+    sending_since: Option<Instant>,
+}
+
+impl WriterState {
+    fn new(name: String) -> Self {
+        WriterState {
+            name,
+            alive: true,
+            restarts: 0,
+            forwarded: 0,
+            last_activity: Instant::now(),
+            sending_since: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WriterStatus<'a> {
+    name: &'a str,
+    alive: bool,
+    restarts: u64,
+    forwarded: u64,
+    #[serde(rename = "idleSeconds")]
+    idle_seconds: u64,
+    /// Seconds the writer is busy with the current event.
+    #[serde(rename = "busySeconds", skip_serializing_if = "Option::is_none")]
+    busy_seconds: Option<u64>,
+}
+
+/// Exponentially growing delay between restart attempts of a writer.
+#[derive(Debug)]
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff {
+            delay: INITIAL_BACKOFF,
+        }
+    }
+
+    fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (delay * 2).min(MAX_BACKOFF);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.delay = INITIAL_BACKOFF;
+    }
+}
+
+impl<'a> From<&'a WriterState> for WriterStatus<'a> {
+    fn from(state: &'a WriterState) -> Self {
+        WriterStatus {
+            name: &state.name,
+            alive: state.alive,
+            restarts: state.restarts,
+            forwarded: state.forwarded,
+            idle_seconds: state.last_activity.elapsed().as_secs(),
+            busy_seconds: state.sending_since.map(|since| since.elapsed().as_secs()),
+        }
+    }
+}
+
+/// Spawns a writer with `spawn` behind a forwarder, which restarts the writer with a fresh
+/// connection whenever its thread died, with exponential backoff while it keeps failing. The
+/// returned sender stays valid across restarts. Events are handed over one at a time, so at most
+/// the event the writer was working on is lost.
+pub fn supervise<T, F>(name: impl Into<String>, spawn: F) -> Result<Writer<T>>
+where
+    T: Send + 'static,
+    F: Fn() -> Result<Writer<T>> + Send + 'static,
+{
+    let writer = super::with_queue_size(0, &spawn)?;
+    let state = Arc::new(Mutex::new(WriterState::new(name.into())));
+    WRITERS.lock().unwrap().push(state.clone());
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((tx, thread::spawn(move || forward(rx, writer, spawn, state))))
+}
+
+fn forward<T, F>(rx: Receiver<T>, mut writer: Writer<T>, spawn: F, state: Arc<Mutex<WriterState>>)
+where
+    F: Fn() -> Result<Writer<T>>,
+{
+    let mut backoff = Backoff::new();
+    let mut started = Instant::now();
+    loop {
+        match rx.recv_timeout(CHECK_INTERVAL) {
+            Ok(mut data) => {
+                state.lock().unwrap().sending_since = Some(Instant::now());
+                while let Err(SendError(returned)) = writer.0.send(data) {
+                    data = returned;
+                    writer = restart(writer, &spawn, &state, &mut backoff, started);
+                    started = Instant::now();
+                }
+                let mut state = state.lock().unwrap();
+                state.sending_since = None;
+                state.forwarded += 1;
+                state.last_activity = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) if writer.1.is_finished() => {
+                writer = restart(writer, &spawn, &state, &mut backoff, started);
+                started = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    let (tx, handle) = writer;
+    drop(tx);
+    let _ = handle.join();
+    state.lock().unwrap().alive = false;
+}
+
+fn restart<T, F>(
+    writer: Writer<T>,
+    spawn: &F,
+    state: &Mutex<WriterState>,
+    backoff: &mut Backoff,
+    started: Instant,
+) -> Writer<T>
+where
+    F: Fn() -> Result<Writer<T>>,
+{
+    let (tx, handle) = writer;
+    drop(tx);
+    let name = state.lock().unwrap().name.clone();
+    match handle.join() {
+        Ok(()) => warn!("writer {} exited", name),
+        Err(_) => warn!("writer {} panicked", name),
+    }
+    state.lock().unwrap().alive = false;
+    if started.elapsed() >= STABLE_AFTER {
+        backoff.reset();
+    }
+    loop {
+        let delay = backoff.next();
+        info!("restarting writer {} in {}s", name, delay.as_secs());
+        thread::sleep(delay);
+        match super::with_queue_size(0, spawn) {
+            Ok(writer) => {
+                let mut state = state.lock().unwrap();
+                state.alive = true;
+                state.restarts += 1;
+                info!("restarted writer {} ({} restarts)", name, state.restarts);
+                return writer;
+            }
+            Err(error) => warn!("failed to restart writer {}: {}", name, error),
+        }
+    }
+}
+
+/// State of all supervised writers.
+pub fn report() -> serde_json::Value {
+    let writers = WRITERS.lock().unwrap();
+    let states: Vec<_> = writers.iter().map(|state| state.lock().unwrap()).collect();
+    serde_json::to_value(
+        states
+            .iter()
+            .map(|state| WriterStatus::from(&**state))
+            .collect::<Vec<_>>(),
+    )
+    .unwrap()
+}
+
+/// State of all supervised writers as JSON.
+pub fn status() -> String {
+    report().to_string()
+}
+
+/// Periodically logs the queued events and warns about dead or stuck writers.
+pub fn spawn_watchdog(interval: Duration) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        info!("{} events queued for targets", super::queued());
+        for state in WRITERS.lock().unwrap().iter() {
+            let state = state.lock().unwrap();
+            if !state.alive {
+                warn!("writer {} is not running", state.name);
+            } else if let Some(since) = state.sending_since {
+                if since.elapsed() >= STUCK_AFTER {
+                    warn!(
+                        "writer {} is stuck for {}s",
+                        state.name,
+                        since.elapsed().as_secs()
+                    );
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_restart_dead_writer() -> Result<()> {
+        let (results_tx, results_rx) = sync_channel(10);
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let spawn_count = spawned.clone();
+
+        let (tx, handle) = supervise("test", move || {
+            let generation = spawn_count.fetch_add(1, Ordering::Relaxed);
+            let results_tx = results_tx.clone();
+            let (tx, rx) = sync_channel::<i32>(super::super::queue_size());
+            Ok((
+                tx,
+                thread::spawn(move || {
+                    for value in rx {
+                        if generation == 0 {
+                            panic!("writer failure");
+                        }
+                        results_tx.send(value).unwrap();
+                    }
+                }),
+            ))
+        })?;
+
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(results_rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        drop(tx);
+        handle.join().unwrap();
+
+        assert_eq!(spawned.load(Ordering::Relaxed), 2);
+        let report = report();
+        let writer = report
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|writer| writer["name"] == "test")
+            .unwrap();
+        assert_eq!(writer["alive"], false);
+        assert_eq!(writer["restarts"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+        assert_eq!(backoff.next(), Duration::from_secs(2));
+        assert_eq!(backoff.next(), Duration::from_secs(4));
+        for _ in 0..10 {
+            backoff.next();
+        }
+        assert_eq!(backoff.next(), MAX_BACKOFF);
+
+        backoff.reset();
+        assert_eq!(backoff.next(), INITIAL_BACKOFF);
+    }
+}
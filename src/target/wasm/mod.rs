@@ -0,0 +1,323 @@
+use crate::data::dedup::warn_deduplicated;
+use crate::data::query::{Query, WriteQuery};
+use crate::error::{GatewayError, Result};
+use crate::target::mqtt::{parse_line, to_query, Event};
+use log::{info, warn};
+use std::ops::Range;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use wasmtime::{Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::WasiCtxBuilder;
+
+/// Roughly the number of instructions a module may execute per event.
+const DEFAULT_FUEL: u64 = 10_000_000;
+/// Linear memory of a module in MiB.
+const DEFAULT_MEMORY_LIMIT: usize = 16;
+
+type Writer<T> = (SyncSender<T>, JoinHandle<()>);
+
+pub struct WasmConfig {
+    module: String,
+    fuel: u64,
+    memory_limit: usize,
+}
+
+impl WasmConfig {
+    pub(crate) fn new(module: String, fuel: Option<u64>, memory_limit: Option<usize>) -> Self {
+        Self {
+            module,
+            fuel: fuel.unwrap_or(DEFAULT_FUEL),
+            memory_limit: memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT) * 1024 * 1024,
+        }
+    }
+}
+
+/// Bytes of the module memory holding the output described by the result of `transform`. The
+/// range is checked against the memory and the configured memory limit before anything is
+/// allocated for it.
+fn output_range(result: u64, memory_size: usize, limit: usize) -> anyhow::Result<Range<usize>> {
+    let pointer = (result >> 32) as usize;
+    let length = (result & 0xffff_ffff) as usize;
+    match pointer.checked_add(length) {
+        Some(end) if end <= memory_size && length <= limit => Ok(pointer..end),
+        _ => Err(anyhow::anyhow!(
+            "output of {} bytes at {} exceeds the module memory of {} bytes",
+            length,
+            pointer,
+            memory_size.min(limit)
+        )),
+    }
+}
+
+struct State {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+/// A WebAssembly module turning a JSON array of events into a JSON array of events. The module
+/// exports its `memory`, `alloc(len: i32) -> i32` returning a buffer for the input and
+/// `transform(ptr: i32, len: i32) -> i64` returning the pointer of the output in the upper and its
+/// length in the lower 32 bits. WASI is available without access to files or the network.
+pub struct Transform {
+    config: WasmConfig,
+    module: Module,
+    linker: Linker<State>,
+    /// Instance kept across events, replaced after a trap.
+    instance: Option<(Store<State>, Instance)>,
+}
+
+impl Transform {
+    pub fn load(config: WasmConfig) -> Result<Self> {
+        let context = || format!("wasm module {}", config.module);
+        let engine = Engine::new(wasmtime::Config::new().consume_fuel(true))
+            .map_err(|error| GatewayError::parse(context(), error))?;
+        let module = Module::from_file(&engine, &config.module)
+            .map_err(|error| GatewayError::parse(context(), error))?;
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |state: &mut State| {
+            &mut state.wasi
+        })
+        .map_err(|error| GatewayError::parse(context(), error))?;
+        let mut transform = Transform {
+            config,
+            module,
+            linker,
+            instance: None,
+        };
+        // fail at startup if the module lacks an export
+        transform.instance()?;
+        Ok(transform)
+    }
+
+    fn instance(&mut self) -> Result<&mut (Store<State>, Instance)> {
+        if self.instance.is_none() {
+            let state = State {
+                wasi: WasiCtxBuilder::new().inherit_stderr().build_p1(),
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.config.memory_limit)
+                    .instances(1)
+                    .build(),
+            };
+            let mut store = Store::new(self.module.engine(), state);
+            store.limiter(|state| &mut state.limits);
+            store
+                .set_fuel(self.config.fuel)
+                .map_err(|error| self.error(error))?;
+            let instance = self
+                .linker
+                .instantiate(&mut store, &self.module)
+                .map_err(|error| self.error(error))?;
+            // reactors built for WASI initialize their runtime here
+            if let Ok(initialize) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+                initialize
+                    .call(&mut store, ())
+                    .map_err(|error| self.error(error))?;
+            }
+            for export in ["memory", "alloc", "transform"] {
+                if instance.get_export(&mut store, export).is_none() {
+                    return Err(GatewayError::config(format!(
+                        "wasm module {} does not export {}",
+                        self.config.module, export
+                    )));
+                }
+            }
+            self.instance = Some((store, instance));
+        }
+        Ok(self.instance.as_mut().unwrap())
+    }
+
+    fn error(&self, error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> GatewayError {
+        GatewayError::target(format!("wasm module {}", self.config.module), error)
+    }
+
+    fn call(&mut self, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let (fuel, limit) = (self.config.fuel, self.config.memory_limit);
+        let (store, instance) = self.instance().map_err(anyhow::Error::new)?;
+        store.set_fuel(fuel)?;
+        let memory = instance
+            .get_memory(&mut *store, "memory")
+            .ok_or_else(|| anyhow::anyhow!("missing memory export"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i64>(&mut *store, "transform")?;
+
+        let length = i32::try_from(input.len())?;
+        let pointer = alloc.call(&mut *store, length)?;
+        memory.write(&mut *store, pointer as u32 as usize, input)?;
+        let result = transform.call(&mut *store, (pointer, length))? as u64;
+        let range = output_range(result, memory.data_size(&*store), limit)?;
+        Ok(memory.data(&*store)[range].to_vec())
+    }
+
+    /// Events the module makes of the given ones, a fresh instance is used after a failure.
+    pub fn apply(&mut self, events: &[Event]) -> Result<Vec<Event>> {
+        let input = serde_json::to_vec(events)?;
+        match self.call(&input) {
+            Ok(output) => Ok(serde_json::from_slice(&output)?),
+            Err(error) => {
+                self.instance = None;
+                Err(self.error(error))
+            }
+        }
+    }
+}
+
+/// Passes the events transformed by the module on to the writer. Events the module fails on are
+/// dropped. Returns once the sender is closed or the writer died.
+fn wasm_writer(rx: Receiver<WriteQuery>, mut transform: Transform, writer: Writer<WriteQuery>) {
+    let (tx, handle) = writer;
+    'events: for query in rx {
+        super::received();
+        // events were validated by their source, so they always parse
+        let Some(event) = query.build().ok().and_then(|line| parse_line(&line.get())) else {
+            continue;
+        };
+        let events = match transform.apply(&[event]) {
+            Ok(events) => events,
+            Err(error) => {
+                warn_deduplicated("wasm transform failed", &error.to_string());
+                continue;
+            }
+        };
+        for event in events {
+            // the events were redacted before the transform already
+            if super::enqueue(&tx, to_query(event)).is_err() {
+                warn!("transformed writer exited");
+                break 'events;
+            }
+        }
+    }
+    drop(tx);
+    let _ = handle.join();
+    info!("exiting wasm writer");
+}
+
+/// Spawns a writer transforming the events with the module before passing them on to the given
+/// writer.
+pub fn spawn_wasm_writer(
+    config: WasmConfig,
+    writer: Writer<WriteQuery>,
+) -> Result<Writer<WriteQuery>> {
+    let transform = Transform::load(config)?;
+    let (tx, rx) = sync_channel(super::queue_size());
+
+    Ok((
+        tx,
+        thread::spawn(move || {
+            info!("starting wasm writer");
+            wasm_writer(rx, transform, writer);
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::mqtt::FieldValue;
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+
+    /// Returns its input, allocating from a bump pointer.
+    const IDENTITY: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len)))))
+    "#;
+
+    /// Never returns.
+    const LOOP: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param $len i32) (result i32) (i32.const 1024))
+          (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+    "#;
+
+    /// Claims an output of almost 4 GiB.
+    const OVERSIZED: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param $len i32) (result i32) (i32.const 1024))
+          (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+            (i64.const 0xffffffff)))
+    "#;
+
+    fn load(name: &str, wat: &str) -> Result<Transform> {
+        let path =
+            env::temp_dir().join(format!("mqtt-gateway-{}-{}.wat", name, std::process::id()));
+        fs::write(&path, wat).unwrap();
+        let transform = Transform::load(WasmConfig::new(
+            path.to_string_lossy().to_string(),
+            Some(100_000),
+            Some(1),
+        ));
+        fs::remove_file(path).unwrap();
+        transform
+    }
+
+    fn event() -> Event {
+        Event {
+            measurement: "power".to_string(),
+            tags: BTreeMap::from([("location".to_string(), "kitchen".to_string())]),
+            fields: BTreeMap::from([("value".to_string(), FieldValue::Float(12.5))]),
+            time: Some(1701271852),
+        }
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let mut transform = load("identity", IDENTITY)?;
+
+        assert_eq!(transform.apply(&[event()])?, vec![event()]);
+        assert_eq!(
+            to_query(event()).build().unwrap().get(),
+            "power,location=kitchen value=12.5 1701271852"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuel_limit() -> Result<()> {
+        let mut transform = load("loop", LOOP)?;
+
+        assert!(transform.apply(&[event()]).is_err());
+        assert!(transform.instance.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_range() {
+        assert_eq!(
+            output_range(1024 << 32 | 16, 65536, 65536).unwrap(),
+            1024..1040
+        );
+        assert!(output_range(65530 << 32 | 16, 65536, 65536).is_err());
+        assert!(output_range(16, 65536, 8).is_err());
+        assert!(output_range(u64::MAX, 65536, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_oversized_output() -> Result<()> {
+        let mut transform = load("oversized", OVERSIZED)?;
+
+        assert!(transform.apply(&[event()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_export() {
+        assert!(load("empty", "(module)").is_err());
+    }
+}